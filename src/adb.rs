@@ -0,0 +1,1319 @@
+use crate::adb_host::HostClient;
+use crate::error::{self, VoltaError};
+use crate::session::Session;
+use crate::worker;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use itertools::Itertools;
+use std::borrow::Cow;
+use std::env::consts::EXE_SUFFIX;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::{ErrorKind, Read};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(2 * 60);
+/// Interval between checks of a headset waiting to be authorized or to boot
+const STATE_POLL: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+const PREFLIGHT_INTERVAL: Duration = Duration::from_secs(10);
+const ADB_PROGRAM: &str = "adb";
+const SERVER_PORT: u16 = 5037;
+const SERVER_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// `--adb-connect` value which finds the headset over mDNS
+pub const AUTO_CONNECT: &str = "auto";
+
+/// Whether volta started the adb server rather than finding it running
+static STARTED_SERVER: AtomicBool = AtomicBool::new(false);
+
+/// Runs adb commands, shared between threads so queries can run concurrently
+pub trait AdbRunner: Sync {
+    /// Runs adb with `args` on the device and returns its output, failing if
+    /// adb exits unsuccessfully
+    fn run(&self, args: &[&str]) -> Result<String, VoltaError>;
+
+    /// Runs `adb shell` with `args` on the device and returns its output
+    fn shell(&self, args: &[&str]) -> Result<String, VoltaError> {
+        self.run(&[&["shell"], args].concat())
+    }
+
+    /// How many times a dump which failed to read is read again before
+    /// giving up on it
+    fn retries(&self) -> u32 {
+        0
+    }
+
+    /// Addresses the following commands to the device with `serial`, for
+    /// runners which address one
+    fn retarget(&self, _serial: &str) {}
+}
+
+/// How adb commands are run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Run the adb executable for every command
+    Exec,
+    /// Talk to the adb server directly
+    Native,
+    /// Talk to the adb server directly, running the executable for what that
+    /// can't do, like starting the server
+    Auto,
+}
+
+/// Runs adb commands, killing them if they don't finish within the
+/// timeout. Commands are addressed to the device with the given serial, adb
+/// picks the only connected device when there's no serial.
+#[derive(Clone, Debug)]
+pub struct Adb {
+    program: PathBuf,
+    /// Shared by the clones, so they all follow a device which came back at
+    /// a different address
+    serial: Arc<RwLock<Option<String>>>,
+    timeout: Duration,
+    retries: u32,
+    backend: Backend,
+    /// The shell kept open for every shell command, if there's one
+    session: Option<Arc<Mutex<Option<Session>>>>,
+    /// Consecutive timeouts after which the adb server is restarted, never
+    /// if 0
+    restart_after: u32,
+    /// Shared by the clones, as they all talk to the same server
+    timeouts: Arc<AtomicU32>,
+}
+
+impl Adb {
+    pub fn new(program: PathBuf, serial: Option<String>, timeout: Duration) -> Self {
+        Self {
+            program,
+            serial: Arc::new(RwLock::new(serial)),
+            timeout,
+            retries: 0,
+            backend: Backend::Exec,
+            session: None,
+            restart_after: 0,
+            timeouts: Arc::default(),
+        }
+    }
+
+    pub fn with_retries(self, retries: u32) -> Self {
+        Self { retries, ..self }
+    }
+
+    /// The same adb talking to the device with `serial`
+    pub fn with_serial(self, serial: Option<String>) -> Self {
+        Self {
+            serial: Arc::new(RwLock::new(serial)),
+            ..self
+        }
+    }
+
+    fn serial(&self) -> Option<String> {
+        self.serial.read().unwrap().clone()
+    }
+
+    pub fn program(&self) -> &Path {
+        &self.program
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn with_backend(self, backend: Backend) -> Self {
+        Self { backend, ..self }
+    }
+
+    /// Runs the shell commands in a single `adb shell` kept open between
+    /// them when `persistent` is set
+    pub fn with_persistent_shell(self, persistent: bool) -> Self {
+        Self {
+            session: persistent.then(Arc::default),
+            ..self
+        }
+    }
+
+    /// A clone for a reader on a thread of its own, following the same
+    /// device but running every command on its own, so it neither waits for
+    /// the battery readings' shell nor counts toward restarting the server
+    pub fn detached(&self) -> Self {
+        Self {
+            session: None,
+            restart_after: 0,
+            timeouts: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_restart_after(self, restart_after: u32) -> Self {
+        Self {
+            restart_after,
+            ..self
+        }
+    }
+
+    /// Logs which adb volta runs and its version
+    pub fn log_version(&self) {
+        let version = self
+            .run(&["version"])
+            .ok()
+            .and_then(|version| version.lines().next().map(str::to_string))
+            .unwrap_or_else(|| String::from("unknown version"));
+        info!("Using {} ({version})", self.program.display());
+    }
+
+    /// Counts a timeout, restarting the adb server once there were too many in
+    /// a row, as a wedged server rarely recovers by itself
+    fn timed_out(&self) {
+        let timeouts = self.timeouts.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.restart_after == 0 || timeouts < self.restart_after {
+            return;
+        }
+        self.timeouts.store(0, Ordering::Relaxed);
+        warn!("adb timed out {timeouts} times in a row, restarting the adb server");
+        // Without restarting again if the restart itself times out
+        let adb = Self {
+            restart_after: 0,
+            ..self.clone()
+        };
+        if let Err(error) = adb.run(&["kill-server"]) {
+            debug!("Failed to stop the adb server: {error:#}");
+        }
+        if let Err(error) = start_adb_server(&adb) {
+            warn!("{error:#}");
+        }
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        if let Some(serial) = self.serial() {
+            command.args(["-s", &serial]);
+        }
+        command
+    }
+}
+
+impl AdbRunner for Adb {
+    fn run(&self, args: &[&str]) -> Result<String, VoltaError> {
+        let native = || HostClient::new(server_port(), self.serial(), self.timeout);
+        let result = match self.backend {
+            Backend::Exec => self.exec(args),
+            Backend::Native => native().run(args),
+            Backend::Auto => match native().run(args) {
+                Err(VoltaError::AdbUnavailable(_)) => self.exec(args),
+                result => result,
+            },
+        };
+        match &result {
+            Ok(_) => self.timeouts.store(0, Ordering::Relaxed),
+            Err(VoltaError::AdbTimeout { .. }) => self.timed_out(),
+            Err(_) => {}
+        }
+
+        result
+    }
+
+    fn shell(&self, args: &[&str]) -> Result<String, VoltaError> {
+        let Some(session) = &self.session else {
+            return self.run(&[&["shell"], args].concat());
+        };
+        let command = args.join(" ");
+        let mut session = session.lock().unwrap();
+        if session.is_none() {
+            let mut shell = self.command();
+            shell.arg("shell");
+            match Session::open(shell, self.timeout) {
+                Ok(opened) => *session = Some(opened),
+                Err(error) => debug!("{error:#}"),
+            }
+        }
+
+        match session.as_mut().map(|session| session.run(&command)) {
+            Some(Ok((output, _, 0))) => {
+                self.timeouts.store(0, Ordering::Relaxed);
+                Ok(output)
+            }
+            Some(Ok((output, stderr, code))) => {
+                let message = if stderr.is_empty() {
+                    output.trim()
+                } else {
+                    &stderr
+                };
+                Err(VoltaError::AdbFailed {
+                    command: format!("shell {command}"),
+                    message: match message {
+                        "" => format!("exit code {code}"),
+                        message => format!("exit code {code}, {message}"),
+                    },
+                })
+            }
+            // It's opened again for the next command. A command which hung
+            // would only hang again on its own.
+            Some(Err(error @ VoltaError::AdbTimeout { .. })) => {
+                debug!("Restarting the adb shell session: {error:#}");
+                *session = None;
+                self.timed_out();
+                Err(error)
+            }
+            // This one runs on its own
+            Some(Err(error)) => {
+                debug!("Restarting the adb shell session: {error:#}");
+                *session = None;
+                self.run(&[&["shell"], args].concat())
+            }
+            None => self.run(&[&["shell"], args].concat()),
+        }
+    }
+
+    fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    fn retarget(&self, serial: &str) {
+        *self.serial.write().unwrap() = Some(serial.to_string());
+        // The shell was opened on the previous device
+        if let Some(session) = &self.session {
+            *session.lock().unwrap() = None;
+        }
+    }
+}
+
+impl Adb {
+    /// Runs the adb executable
+    fn exec(&self, args: &[&str]) -> Result<String, VoltaError> {
+        let mut child = self
+            .command()
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|error| match error.kind() {
+                ErrorKind::NotFound => VoltaError::AdbNotFound(self.program.display().to_string()),
+                _ => VoltaError::AdbIo {
+                    action: "Failed to run adb",
+                    source: error,
+                },
+            })?;
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            let status = child.try_wait();
+            if let Ok(Some(status)) = status {
+                break status;
+            }
+            let timed_out = Instant::now() >= deadline;
+            if status.is_err() || timed_out {
+                // Reaped right away, so no defunct adb is left behind
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            if timed_out {
+                return Err(VoltaError::AdbTimeout {
+                    command: args.join(" "),
+                    timeout: self.timeout,
+                });
+            }
+            status.map_err(|source| VoltaError::AdbIo {
+                action: "Failed to wait for adb",
+                source,
+            })?;
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        // The adb server started by `start-server` inherits the pipes, so
+        // only wait a little for them to close once adb itself has exited
+        let grace = Instant::now() + OUTPUT_GRACE;
+        while !(stdout.is_finished() && stderr.is_finished()) && Instant::now() < grace {
+            thread::sleep(POLL_INTERVAL);
+        }
+        let stdout = decode(&stdout.take());
+
+        if !status.success() {
+            let stderr = String::from_utf8_lossy(&stderr.take()).trim().to_string();
+            let message = if stderr.is_empty() {
+                stdout.trim()
+            } else {
+                &stderr
+            };
+            return Err(VoltaError::AdbFailed {
+                command: args.join(" "),
+                message: message.to_string(),
+            });
+        }
+
+        Ok(stdout)
+    }
+}
+
+/// Decodes adb output, replacing what isn't UTF-8, like the GBK encoded
+/// controller names some Pico firmware lists, which are never in the lines
+/// volta reads
+pub fn decode(output: &[u8]) -> String {
+    let decoded = String::from_utf8_lossy(output);
+    if let Cow::Owned(_) = decoded {
+        debug!("Replaced invalid UTF-8 in the adb output");
+    }
+    decoded.into_owned()
+}
+
+/// Finds adb on the PATH or in the directories SideQuest and the Android
+/// SDK install it to, falling back to `adb` which fails the preflight
+pub fn discover() -> PathBuf {
+    let path = std::env::var_os("PATH");
+    find(path.as_deref(), &known_locations(), |path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(ADB_PROGRAM))
+}
+
+/// Returns the first adb in the directories of `path`, then the first of
+/// the `known` locations, which `exists`
+fn find(
+    path: Option<&OsStr>,
+    known: &[PathBuf],
+    exists: impl Fn(&Path) -> bool,
+) -> Option<PathBuf> {
+    let program = format!("{ADB_PROGRAM}{EXE_SUFFIX}");
+    path.into_iter()
+        .flat_map(std::env::split_paths)
+        .map(|dir| dir.join(&program))
+        .chain(known.iter().cloned())
+        .find(|candidate| exists(candidate))
+}
+
+/// Where SideQuest and the Android SDK put adb on each platform
+fn known_locations() -> Vec<PathBuf> {
+    let platform_tools = |dir: Option<PathBuf>, install: &str| {
+        dir.map(|dir| {
+            dir.join(install)
+                .join("platform-tools")
+                .join(format!("{ADB_PROGRAM}{EXE_SUFFIX}"))
+        })
+    };
+
+    [
+        // SideQuest on Windows, in %LOCALAPPDATA%, and the Android SDK there
+        platform_tools(dirs::data_local_dir(), "SideQuest"),
+        platform_tools(dirs::data_local_dir(), "Android/Sdk"),
+        // SideQuest on Linux and macOS
+        platform_tools(dirs::config_dir(), "SideQuest"),
+        // The Android SDK on Linux and macOS
+        platform_tools(dirs::home_dir(), "Android/Sdk"),
+        platform_tools(dirs::home_dir(), "Library/Android/sdk"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Output of a child process pipe, collected on a separate thread so the
+/// child can't block on a full pipe
+struct Output {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Output {
+    fn is_finished(&self) -> bool {
+        self.reader.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer.lock().unwrap())
+    }
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> Output {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let reader = pipe.map(|mut pipe| {
+        let buffer = buffer.clone();
+        thread::spawn(move || {
+            let mut chunk = [0; 4096];
+            while let Ok(size @ 1..) = pipe.read(&mut chunk) {
+                buffer.lock().unwrap().extend_from_slice(&chunk[..size]);
+            }
+        })
+    });
+
+    Output { buffer, reader }
+}
+
+/// Starts the adb server unless one is already running, which only checks
+/// that adb itself runs then
+pub fn start_adb_server(adb: &impl AdbRunner) -> Result<()> {
+    if server_running(server_port()) {
+        debug!("The adb server is already running");
+        adb.run(&["version"])?;
+        return Ok(());
+    }
+
+    info!("Starting adb server...");
+    adb.run(&["start-server"])
+        .context("Failed to start adb server")?;
+    STARTED_SERVER.store(true, Ordering::Relaxed);
+    info!("Adb server started");
+    Ok(())
+}
+
+/// Stops the adb server if volta started it, leaving one which was already
+/// running alone
+pub fn stop_started_server(adb: &impl AdbRunner) {
+    if !STARTED_SERVER.load(Ordering::Relaxed) {
+        return;
+    }
+    info!("Stopping the adb server volta started");
+    if let Err(error) = adb.run(&["kill-server"]) {
+        warn!("Failed to stop the adb server: {error:#}");
+    }
+}
+
+/// The port the adb server listens on, which adb takes from
+/// `ANDROID_ADB_SERVER_PORT` as well
+pub fn server_port() -> u16 {
+    std::env::var("ANDROID_ADB_SERVER_PORT")
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(SERVER_PORT)
+}
+
+fn server_running(port: u16) -> bool {
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    TcpStream::connect_timeout(&address, SERVER_PROBE_TIMEOUT).is_ok()
+}
+
+/// Checks that adb runs and the device with `serial`, or the only one when
+/// there's no serial, is ready to use. Errors caused by adb or the headset
+/// contain a [`VoltaError`] with an exit code. Pass no `serial` and
+/// `device: false` to only check adb itself, e.g. for wireless devices which
+/// aren't connected yet.
+pub fn preflight(adb: &impl AdbRunner, serial: Option<&str>, device: bool) -> Result<()> {
+    start_adb_server(adb)?;
+    if device {
+        check_device(adb, serial)?;
+    }
+    Ok(())
+}
+
+/// Runs the preflight until it passes, reporting each new problem once.
+/// Returns false if volta was asked to stop meanwhile.
+pub fn wait_until_ready(adb: &impl AdbRunner, serial: Option<&str>, device: bool) -> bool {
+    let mut reported = None;
+    let mut started = false;
+    loop {
+        // The server keeps running once started, only the device is checked again
+        let result = match started {
+            true => check_device(adb, serial).map_err(Into::into),
+            false => preflight(adb, serial, device),
+        };
+        let error = match result {
+            Ok(()) => break,
+            Err(error) => error,
+        };
+        started = error::kind(&error).is_some_and(VoltaError::is_device_problem);
+        let message = format!("{error:#}");
+        if reported.as_ref() != Some(&message) {
+            warn!("{message}, checking again every {PREFLIGHT_INTERVAL:?}");
+        } else {
+            debug!("{message}");
+        }
+        reported = Some(message);
+        if !worker::sleep(PREFLIGHT_INTERVAL) {
+            return false;
+        }
+    }
+
+    if reported.is_some() {
+        info!("adb is ready");
+    }
+    true
+}
+
+/// Checks the state of the device with `serial`, or of any device when there's
+/// no serial, in the `adb devices` list
+fn check_device(adb: &impl AdbRunner, serial: Option<&str>) -> Result<(), VoltaError> {
+    let devices = adb.run(&["devices"]).unwrap_or_default();
+    let states: Vec<(&str, &str)> = devices
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().collect_tuple())
+        .filter(|(device, _)| serial.is_none_or(|serial| serial == *device))
+        .collect();
+
+    if states.iter().any(|(_, state)| *state == "device") {
+        return Ok(());
+    }
+    match states.first() {
+        Some((device, "unauthorized")) => Err(VoltaError::DeviceUnauthorized(device.to_string())),
+        Some((device, _)) => Err(VoltaError::DeviceOffline(device.to_string())),
+        None => Err(VoltaError::NoDevice(serial.map(str::to_string))),
+    }
+}
+
+/// State of a device in `adb devices -l`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceState {
+    Device,
+    Unauthorized,
+    Offline,
+    Recovery,
+    Sideload,
+    /// The device isn't listed
+    Missing,
+    Other(String),
+}
+
+impl fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceState::Device => f.write_str("device"),
+            DeviceState::Unauthorized => f.write_str("unauthorized"),
+            DeviceState::Offline => f.write_str("offline"),
+            DeviceState::Recovery => f.write_str("recovery"),
+            DeviceState::Sideload => f.write_str("sideload"),
+            DeviceState::Missing => f.write_str("missing"),
+            DeviceState::Other(state) => f.write_str(state),
+        }
+    }
+}
+
+/// Parses the state of the device with `serial`, or of the first device
+/// without a serial, from the output of `adb devices -l`
+pub fn parse_device_state(output: &str, serial: Option<&str>) -> DeviceState {
+    let state = output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((fields.next()?, fields.next()?))
+        })
+        .find(|(device, _)| serial.is_none_or(|serial| serial == *device))
+        .map(|(_, state)| state);
+    match state {
+        Some("device") => DeviceState::Device,
+        Some("unauthorized") => DeviceState::Unauthorized,
+        Some("offline") => DeviceState::Offline,
+        Some("recovery") => DeviceState::Recovery,
+        Some("sideload") => DeviceState::Sideload,
+        Some(state) => DeviceState::Other(state.to_string()),
+        None => DeviceState::Missing,
+    }
+}
+
+/// Waits for the given time in between the supervisor's checks of the
+/// headset, returning false to stop waiting for it as volta stops
+pub type Wait<'a> = dyn FnMut(Duration) -> bool + 'a;
+
+/// How waiting for the headset ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waited {
+    /// There was nothing to wait for
+    No,
+    /// The headset is ready after waiting for it
+    Ready,
+    /// volta was asked to stop while waiting
+    Stopped,
+}
+
+/// Keeps track of the headset so a lost device is only reported once and
+/// network devices can be reconnected
+pub struct Supervisor<A> {
+    adb: A,
+    address: Option<String>,
+    /// Whether the address is found over mDNS again on every reconnect
+    discover: bool,
+    /// Serial of the headset to find over mDNS
+    wanted: Option<String>,
+    serial: Option<String>,
+    lost: bool,
+    /// Interval between checks of a headset which isn't ready
+    state_poll: Duration,
+}
+
+impl<A: AdbRunner> Supervisor<A> {
+    /// Supervises the device reachable at `address` over wireless adb, or
+    /// whichever device adb picks when there's no address. An `auto` address
+    /// is found over mDNS.
+    pub fn new(adb: A, address: Option<String>) -> Self {
+        let discover = address.as_deref() == Some(AUTO_CONNECT);
+        Self {
+            adb,
+            address: address.filter(|_| !discover),
+            discover,
+            wanted: None,
+            serial: None,
+            lost: false,
+            state_poll: STATE_POLL,
+        }
+    }
+
+    /// Checks a headset which isn't ready every `poll` instead
+    pub fn with_state_poll(self, poll: Duration) -> Self {
+        Self {
+            state_poll: poll,
+            ..self
+        }
+    }
+
+    /// Only connects to the headset with `serial` when finding it over mDNS
+    pub fn with_wanted_serial(self, serial: Option<String>) -> Self {
+        Self {
+            wanted: serial,
+            ..self
+        }
+    }
+
+    /// Treats the device as lost on the next `ensure_connected` even if adb
+    /// still lists it, for when it stopped answering without adb noticing
+    pub fn mark_lost(&mut self) {
+        self.lost = true;
+        if let Some(address) = self.network_address() {
+            let _ = self.adb.run(&["disconnect", address]);
+        }
+    }
+
+    /// Runs adb on the supervised device
+    pub fn adb(&self) -> &A {
+        &self.adb
+    }
+
+    /// Blocks until a device is connected, backing off exponentially between
+    /// attempts with `wait`
+    pub fn ensure_connected(&mut self, wait: &mut Wait) -> Waited {
+        if !self.lost && self.is_connected() {
+            if self.serial.is_none() {
+                self.serial = get_serial(&self.adb);
+            }
+            return Waited::No;
+        }
+
+        match &self.serial {
+            Some(serial) => warn!("Device {serial} lost, reconnecting..."),
+            None => warn!("No device connected, waiting..."),
+        }
+
+        let mut backoff = INITIAL_BACKOFF;
+        let mut reported = false;
+        loop {
+            match self.reconnect() {
+                Ok(()) => break,
+                Err(error) if !reported => {
+                    warn!("{error:#}");
+                    reported = true;
+                }
+                Err(error) => debug!("{error:#}"),
+            }
+
+            debug!("Device still unavailable, retrying in {backoff:?}");
+            if !wait(backoff) {
+                return Waited::Stopped;
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+
+        self.lost = false;
+        let reconnected = self.serial.is_some();
+        self.serial = get_serial(&self.adb);
+        let serial = self.serial.as_deref().unwrap_or("unknown");
+        match reconnected {
+            true => info!("Device {serial} reconnected"),
+            false => info!("Device {serial} connected"),
+        }
+
+        Waited::Ready
+    }
+
+    /// Checks the headset's state after a reading failed, for the states it
+    /// can't be read in: an unauthorized headset is waited for until it's
+    /// authorized, one in recovery or sideload mode until it boots, and an
+    /// offline one is reconnected on the next `ensure_connected`. The checks
+    /// are spaced with `wait`, and `Waited::Ready` means the headset is ready
+    /// after waiting for it.
+    pub fn recover(&mut self, wait: &mut Wait) -> Waited {
+        let name = self
+            .address
+            .clone()
+            .or(self.serial.clone())
+            .unwrap_or_else(|| String::from("headset"));
+        let poll = self.state_poll;
+        let mut state = self.state();
+        let mut waited = false;
+        loop {
+            match &state {
+                DeviceState::Device if waited => {
+                    info!("The headset {name} is ready again");
+                    return Waited::Ready;
+                }
+                DeviceState::Unauthorized => warn!(
+                    "The headset {name} is unauthorized, put it on and accept the USB debugging \
+                     prompt, checking again every {poll:?}"
+                ),
+                DeviceState::Recovery | DeviceState::Sideload => warn!(
+                    "The headset {name} is in {state} mode, pausing until it boots, checking again \
+                     every {poll:?}"
+                ),
+                DeviceState::Offline => {
+                    warn!("The headset {name} is offline, reconnecting");
+                    self.mark_lost();
+                    return Waited::No;
+                }
+                _ if waited => return Waited::Ready,
+                _ => return Waited::No,
+            }
+
+            waited = true;
+            let previous = state;
+            state = loop {
+                if !wait(poll) {
+                    return Waited::Stopped;
+                }
+                let state = self.state();
+                if state != previous {
+                    break state;
+                }
+            };
+        }
+    }
+
+    fn state(&self) -> DeviceState {
+        let serial = self.address.as_deref().or(self.serial.as_deref());
+        match self.adb.run(&["devices", "-l"]) {
+            Ok(output) => parse_device_state(&output, serial),
+            Err(error) => {
+                debug!("Failed to list adb devices: {error:#}");
+                DeviceState::Missing
+            }
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        match &self.address {
+            Some(address) => get_device_state(&self.adb, address).as_deref() == Some("device"),
+            None if self.discover => false,
+            None => is_connected(&self.adb),
+        }
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        if self.discover {
+            // A headset which got a new address from DHCP is found there
+            let address = discover_headset(&self.adb, self.wanted.as_deref())?;
+            connect(&self.adb, &address)?;
+            self.adb.retarget(&address);
+            self.address = Some(address);
+            return Ok(());
+        }
+        match self.network_address() {
+            Some(address) => connect(&self.adb, address),
+            None if is_connected(&self.adb) => Ok(()),
+            None => Ok(check_device(&self.adb, self.serial.as_deref())?),
+        }
+    }
+}
+
+impl<A> Supervisor<A> {
+    fn network_address(&self) -> Option<&str> {
+        self.address
+            .as_deref()
+            .or(self.serial.as_deref().filter(|serial| is_network(serial)))
+    }
+}
+
+/// Network devices are listed by the address they were connected with
+fn is_network(serial: &str) -> bool {
+    serial.contains(':')
+}
+
+fn is_connected(adb: &impl AdbRunner) -> bool {
+    adb.run(&["get-state"])
+        .is_ok_and(|state| state.trim() == "device")
+}
+
+fn get_serial(adb: &impl AdbRunner) -> Option<String> {
+    adb.run(&["get-serialno"])
+        .ok()
+        .map(|serial| serial.trim().to_string())
+        .filter(|serial| !serial.is_empty())
+}
+
+/// Connects to a wireless adb device and checks that it's ready to use
+pub fn connect(adb: &impl AdbRunner, address: &str) -> Result<()> {
+    debug!("Connecting to {address}...");
+    let message = adb
+        .run(&["connect", address])
+        .unwrap_or_else(|error| format!("{error:#}"));
+    let message = message.trim();
+
+    if message.contains("refused") {
+        bail!("Connection to {address} refused, is wireless debugging enabled on the headset?");
+    }
+    if message.contains("failed") || message.contains("cannot") || message.contains("unable") {
+        bail!("Failed to connect to {address}: {message}");
+    }
+
+    match get_device_state(adb, address).as_deref() {
+        Some("device") => Ok(()),
+        Some("unauthorized") => {
+            bail!("{address} is unauthorized — accept the debugging prompt on the headset")
+        }
+        Some("offline") => bail!("{address} is offline"),
+        Some(state) => bail!("{address} is in an unexpected state `{state}`"),
+        None => bail!("{address} is not listed by adb devices: {message}"),
+    }
+}
+
+/// A wireless debugging service adb found over mDNS
+#[derive(Debug, PartialEq, Eq)]
+pub struct MdnsService {
+    /// Name of the service, which contains the device's serial, e.g.
+    /// `adb-PA7H10MGF1234-AbCdEf`
+    pub instance: String,
+    pub address: String,
+}
+
+/// Parses the output of `adb mdns services`, keeping the services which can
+/// be connected to rather than paired with
+pub fn parse_mdns_services(output: &str) -> Vec<MdnsService> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (instance, service, address) = line.split_whitespace().collect_tuple()?;
+            let connectable =
+                service.starts_with("_adb-tls-connect.") || service.starts_with("_adb._tcp");
+            (connectable && address.contains(':')).then(|| MdnsService {
+                instance: instance.to_string(),
+                address: address.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Finds the address of the headset with `serial`, or of any device, over
+/// mDNS with `adb mdns services`
+pub fn discover_headset(adb: &impl AdbRunner, serial: Option<&str>) -> Result<String> {
+    let output = adb
+        .run(&["mdns", "services"])
+        .context("Failed to look for wireless debugging over mDNS")?;
+    let services = parse_mdns_services(&output);
+    for service in &services {
+        debug!(
+            "Found {} at {} over mDNS",
+            service.instance, service.address
+        );
+    }
+
+    let mut candidates = services
+        .iter()
+        .filter(|service| serial.is_none_or(|serial| service.instance.contains(serial)));
+    let Some(chosen) = candidates.next() else {
+        match serial {
+            Some(serial) => bail!(
+                "The headset {serial} wasn't found over mDNS, is wireless debugging enabled on it?"
+            ),
+            None => bail!("No headset found over mDNS, is wireless debugging enabled on it?"),
+        }
+    };
+    // A device may be listed once per address
+    if candidates.any(|other| other.instance != chosen.instance) {
+        warn!("Found several devices over mDNS, pass --serial to pick one");
+    }
+    info!(
+        "Connecting to {} at {} found over mDNS",
+        chosen.instance, chosen.address
+    );
+
+    Ok(chosen.address.clone())
+}
+
+/// Reads the device's model name, e.g. `A8110` for a Pico 4
+pub fn model(adb: &impl AdbRunner) -> Result<String> {
+    let model = adb.shell(&["getprop", "ro.product.model"])?;
+    Ok(model.trim().to_string())
+}
+
+/// Looks up the state of `serial` in the `adb devices` list, e.g. `device`,
+/// `offline` or `unauthorized`
+fn get_device_state(adb: &impl AdbRunner, serial: &str) -> Option<String> {
+    adb.run(&["devices"])
+        .ok()?
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().collect_tuple())
+        .find(|(device, _)| *device == serial)
+        .map(|(_, state): (&str, &str)| state.to_string())
+}
+
+/// Picks the device to use when several are connected and no serial was
+/// given, preferring the only Pico among them
+pub fn select_device(adb: &impl AdbRunner) -> Result<Option<String>> {
+    let output = adb
+        .run(&["devices", "-l"])
+        .context("Failed to list adb devices")?;
+    let devices: Vec<&str> = output
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+
+    if devices.len() < 2 {
+        return Ok(None);
+    }
+
+    info!("Found {} devices:", devices.len());
+    for device in &devices {
+        info!("  {device}");
+    }
+
+    let serial = |device: &&str| {
+        device
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    };
+    let picos: Vec<&&str> = devices
+        .iter()
+        .filter(|device| {
+            device
+                .split_whitespace()
+                .filter_map(|field| field.strip_prefix("product:"))
+                .any(|product| product.to_lowercase().contains("pico"))
+        })
+        .collect();
+
+    match picos.as_slice() {
+        [pico] => {
+            let serial = serial(pico);
+            info!("Using the Pico {serial}");
+            Ok(Some(serial))
+        }
+        _ => bail!(
+            "More than one device connected, select one with --serial: {}",
+            devices.iter().map(serial).join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockAdb;
+    use super::*;
+
+    fn devices(list: &str) -> MockAdb {
+        MockAdb::default().with("devices", &format!("List of devices attached\n{list}\n"))
+    }
+
+    #[test]
+    fn finds_adb_on_the_path_first() {
+        let program = format!("adb{EXE_SUFFIX}");
+        let path = std::env::join_paths(["/opt/empty", "/opt/platform-tools"]).unwrap();
+        let known = [PathBuf::from("/sidequest/platform-tools").join(&program)];
+        // Only adb in the `installed` directories exists
+        let installed = |installed: &'static [&'static str]| {
+            move |candidate: &Path| {
+                installed
+                    .iter()
+                    .any(|dir| candidate == Path::new(dir).join(format!("adb{EXE_SUFFIX}")))
+            }
+        };
+
+        assert_eq!(
+            find(
+                Some(&path),
+                &known,
+                installed(&["/opt/platform-tools", "/sidequest/platform-tools"])
+            ),
+            Some(Path::new("/opt/platform-tools").join(&program))
+        );
+        assert_eq!(
+            find(
+                Some(&path),
+                &known,
+                installed(&["/sidequest/platform-tools"])
+            ),
+            Some(known[0].clone())
+        );
+        assert_eq!(
+            find(None, &known, installed(&["/sidequest/platform-tools"])),
+            Some(known[0].clone())
+        );
+        assert_eq!(find(Some(&path), &known, installed(&[])), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kills_hanging_commands() {
+        // `sleep 5` stands in for an adb which hangs
+        let adb = Adb::new(PathBuf::from("sleep"), None, Duration::from_millis(100));
+        let start = Instant::now();
+        let error = adb.run(&["5"]).unwrap_err();
+
+        assert!(matches!(error, VoltaError::AdbTimeout { .. }), "{error:#}");
+        assert!(start.elapsed() < Duration::from_secs(3));
+        assert_eq!(adb.timeouts.load(Ordering::Relaxed), 1);
+        // A command which finishes starts the count over
+        adb.run(&["0"]).unwrap();
+        assert_eq!(adb.timeouts.load(Ordering::Relaxed), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_a_detached_reader_apart() {
+        let adb = Adb::new(PathBuf::from("sleep"), None, Duration::from_millis(100))
+            .with_persistent_shell(true)
+            .with_restart_after(1);
+        let detached = adb.detached();
+        assert!(detached.session.is_none());
+        assert_eq!(detached.restart_after, 0);
+
+        // Its timeouts neither restart the server nor add to the count
+        detached.run(&["5"]).unwrap_err();
+        assert_eq!(adb.timeouts.load(Ordering::Relaxed), 0);
+        assert_eq!(detached.timeouts.load(Ordering::Relaxed), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn gives_up_on_a_shell_command_which_hung() {
+        // A script ignoring `shell` stands in for adb, whose shell is `sh`
+        let program = std::env::temp_dir().join(format!("vrc_volta-{}-adb", std::process::id()));
+        std::fs::write(&program, "#!/bin/sh\nexec sh\n").unwrap();
+        std::fs::set_permissions(
+            &program,
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )
+        .unwrap();
+        let adb =
+            Adb::new(program.clone(), None, Duration::from_millis(200)).with_persistent_shell(true);
+
+        assert_eq!(adb.shell(&["echo", "85"]).unwrap(), "85\n");
+        let error = adb.shell(&["sleep", "5"]).unwrap_err();
+        assert!(matches!(error, VoltaError::AdbTimeout { .. }), "{error:#}");
+        assert_eq!(adb.timeouts.load(Ordering::Relaxed), 1);
+        let error = adb.shell(&["cat", "/nonexistent"]).unwrap_err();
+        assert!(error.to_string().contains("/nonexistent"), "{error:#}");
+        std::fs::remove_file(&program).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaces_invalid_utf8() {
+        // `printf` stands in for an adb printing GBK encoded text
+        let adb = Adb::new(PathBuf::from("printf"), None, Duration::from_secs(2));
+
+        assert_eq!(
+            adb.run(&["name: \\304\\343\nbattery: 4\n"]).unwrap(),
+            "name: \u{FFFD}\u{FFFD}\nbattery: 4\n"
+        );
+    }
+
+    #[test]
+    fn probes_the_server() {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(server_running(port));
+        drop(listener);
+        assert!(!server_running(port));
+    }
+
+    #[test]
+    fn diagnoses_device_states() {
+        assert!(check_device(&devices("PA7L10MGH4030\tdevice"), None).is_ok());
+        assert!(matches!(
+            check_device(&devices("PA7L10MGH4030\tunauthorized"), None),
+            Err(VoltaError::DeviceUnauthorized(serial)) if serial == "PA7L10MGH4030"
+        ));
+        assert!(matches!(
+            check_device(&devices("PA7L10MGH4030\toffline"), None),
+            Err(VoltaError::DeviceOffline(serial)) if serial == "PA7L10MGH4030"
+        ));
+        assert!(matches!(
+            check_device(&devices(""), None),
+            Err(VoltaError::NoDevice(None))
+        ));
+    }
+
+    #[test]
+    fn parses_device_states() {
+        let output = "List of devices attached\n\
+            PA7L10MGH4030          unauthorized usb:1-1 transport_id:3\n\
+            192.168.1.50:5555      recovery product:A7H10 model:Pico_Neo_3 transport_id:4\n\
+            PA7L10MGH4031          no permissions (missing udev rules?); see [http://developer.android.com/tools/device.html] usb:1-2\n\n";
+
+        assert_eq!(parse_device_state(output, None), DeviceState::Unauthorized);
+        assert_eq!(
+            parse_device_state(output, Some("192.168.1.50:5555")),
+            DeviceState::Recovery
+        );
+        assert_eq!(
+            parse_device_state(output, Some("PA7L10MGH4031")),
+            DeviceState::Other(String::from("no"))
+        );
+        assert_eq!(
+            parse_device_state(output, Some("PA7L10MGH4032")),
+            DeviceState::Missing
+        );
+    }
+
+    #[test]
+    fn leaves_a_ready_headset_alone() {
+        let adb = MockAdb::default().with(
+            "devices -l",
+            "List of devices attached\nPA7L10MGH4030 device usb:1-1\n",
+        );
+        let mut supervisor = Supervisor::new(adb, None);
+        assert_eq!(supervisor.recover(&mut |_| true), Waited::No);
+    }
+
+    /// Lists the headset in each of its states in turn, staying in the last
+    struct ChangingState(Mutex<Vec<&'static str>>);
+
+    impl AdbRunner for ChangingState {
+        fn run(&self, args: &[&str]) -> Result<String, VoltaError> {
+            assert_eq!(args, ["devices", "-l"]);
+            let mut states = self.0.lock().unwrap();
+            let state = match states.len() {
+                1 => states[0],
+                _ => states.remove(0),
+            };
+            Ok(format!(
+                "List of devices attached\nPA7L10MGH4030 {state} usb:1-1\n"
+            ))
+        }
+    }
+
+    fn changing_state(states: &[&'static str]) -> ChangingState {
+        ChangingState(Mutex::new(states.to_vec()))
+    }
+
+    #[test]
+    fn waits_for_the_headset_to_be_ready() {
+        for states in [
+            ["unauthorized", "unauthorized", "device"],
+            ["recovery", "sideload", "device"],
+        ] {
+            let poll = Duration::from_millis(250);
+            let mut supervisor =
+                Supervisor::new(changing_state(&states), None).with_state_poll(poll);
+            let mut waits = Vec::new();
+            let waited = supervisor.recover(&mut |timeout| {
+                waits.push(timeout);
+                true
+            });
+
+            assert_eq!(waited, Waited::Ready, "{states:?}");
+            assert_eq!(waits, [poll, poll], "{states:?}");
+        }
+    }
+
+    #[test]
+    fn stops_waiting_for_an_unauthorized_headset() {
+        let mut supervisor = Supervisor::new(changing_state(&["unauthorized"]), None);
+        let mut waits = 0;
+        let waited = supervisor.recover(&mut |_| {
+            waits += 1;
+            waits < 3
+        });
+
+        assert_eq!(waited, Waited::Stopped);
+        assert_eq!(waits, 3);
+    }
+
+    #[test]
+    fn stops_waiting_for_a_lost_headset() {
+        let mut supervisor = Supervisor::new(MockAdb::default(), None);
+        let mut waits = Vec::new();
+        let waited = supervisor.ensure_connected(&mut |backoff| {
+            waits.push(backoff);
+            waits.len() < 2
+        });
+
+        assert_eq!(waited, Waited::Stopped);
+        assert_eq!(waits, [INITIAL_BACKOFF, INITIAL_BACKOFF * 2]);
+    }
+
+    #[test]
+    fn diagnoses_selected_device() {
+        let adb = devices("PA7L10MGH4030\tdevice\nPA7L10MGH4031\tunauthorized");
+
+        assert!(check_device(&adb, Some("PA7L10MGH4030")).is_ok());
+        assert!(matches!(
+            check_device(&adb, Some("PA7L10MGH4031")),
+            Err(VoltaError::DeviceUnauthorized(serial)) if serial == "PA7L10MGH4031"
+        ));
+        assert!(matches!(
+            check_device(&adb, Some("192.168.1.50:5555")),
+            Err(VoltaError::NoDevice(Some(serial))) if serial == "192.168.1.50:5555"
+        ));
+    }
+
+    const MDNS_SERVICES: &str = "List of discovered mdns services\n\
+        adb-PA7L10MGH4030-w3Xq9Z\t_adb-tls-connect._tcp.\t192.168.1.50:37421\n\
+        adb-PA7L10MGH4030-w3Xq9Z\t_adb-tls-pairing._tcp.\t192.168.1.50:41013\n\
+        adb-PA7L10MGH4031-Kd02mA\t_adb-tls-connect._tcp.\t192.168.1.51:40199\n";
+
+    #[test]
+    fn parses_connectable_mdns_services() {
+        let services = parse_mdns_services(MDNS_SERVICES);
+        let addresses: Vec<_> = services.iter().map(|s| s.address.as_str()).collect();
+        assert_eq!(addresses, ["192.168.1.50:37421", "192.168.1.51:40199"]);
+        assert_eq!(services[0].instance, "adb-PA7L10MGH4030-w3Xq9Z");
+    }
+
+    #[test]
+    fn discovers_the_wanted_headset() {
+        let adb = MockAdb::default().with("mdns services", MDNS_SERVICES);
+        assert_eq!(
+            discover_headset(&adb, Some("PA7L10MGH4031")).unwrap(),
+            "192.168.1.51:40199"
+        );
+        assert_eq!(discover_headset(&adb, None).unwrap(), "192.168.1.50:37421");
+        assert!(discover_headset(&adb, Some("PA7L10MGH4032")).is_err());
+
+        let empty = MockAdb::default().with("mdns services", "List of discovered mdns services\n");
+        assert!(discover_headset(&empty, None).is_err());
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::AdbRunner;
+    use crate::error::VoltaError;
+    use std::collections::HashMap;
+
+    /// Answers adb commands with canned output, any other command fails
+    #[derive(Clone, Default)]
+    pub struct MockAdb {
+        outputs: HashMap<String, String>,
+    }
+
+    impl MockAdb {
+        pub fn with(mut self, command: &str, output: &str) -> Self {
+            self.outputs.insert(command.to_string(), output.to_string());
+            self
+        }
+    }
+
+    impl AdbRunner for MockAdb {
+        fn run(&self, args: &[&str]) -> Result<String, VoltaError> {
+            let command = args.join(" ");
+            self.outputs
+                .get(&command)
+                .cloned()
+                .ok_or_else(|| VoltaError::AdbFailed {
+                    command,
+                    message: String::from("unknown command"),
+                })
+        }
+    }
+}
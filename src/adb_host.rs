@@ -0,0 +1,420 @@
+use crate::adb;
+use crate::error::VoltaError;
+use std::fmt::{self, Display};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// Stream ids of the shell protocol's packets
+const SHELL_STDOUT: u8 = 1;
+const SHELL_STDERR: u8 = 2;
+const SHELL_EXIT: u8 = 3;
+
+/// Why the native client can't run a command, in which case the `auto`
+/// backend runs the adb executable instead
+#[derive(Debug)]
+pub enum Unavailable {
+    /// The client doesn't speak this command, e.g. `start-server`
+    Unsupported(String),
+    /// Nothing answers on the adb server's port
+    NoServer(u16, io::Error),
+}
+
+impl Display for Unavailable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Unavailable::Unsupported(command) => {
+                write!(f, "The native adb client can't run `adb {command}`")
+            }
+            Unavailable::NoServer(port, error) => {
+                write!(f, "No adb server is running on port {port}: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Unavailable {}
+
+/// Talks to the adb server over its host protocol, which saves starting the
+/// adb executable for every command. Requests are a length in 4 hex digits
+/// followed by the request, answered by `OKAY` or by `FAIL` and a message.
+pub struct HostClient {
+    port: u16,
+    serial: Option<String>,
+    timeout: Duration,
+}
+
+impl HostClient {
+    pub fn new(port: u16, serial: Option<String>, timeout: Duration) -> Self {
+        Self {
+            port,
+            serial,
+            timeout,
+        }
+    }
+
+    /// Runs the adb command with `args`, returning the output the adb
+    /// executable would print
+    pub fn run(&self, args: &[&str]) -> Result<String, VoltaError> {
+        let command = args.join(" ");
+        match args {
+            ["version"] => self.host(&command, "host:version").map(|version| {
+                let version = u32::from_str_radix(&version, 16).unwrap_or_default();
+                format!("Android Debug Bridge version 1.0.{version}\n")
+            }),
+            ["devices"] => self.host(&command, "host:devices").map(device_list),
+            ["devices", "-l"] => self.host(&command, "host:devices-l").map(device_list),
+            ["get-state"] => self
+                .host(&command, &self.device_request("get-state"))
+                .map(line),
+            ["get-serialno"] => self
+                .host(&command, &self.device_request("get-serialno"))
+                .map(line),
+            ["connect", address] => self
+                .host(&command, &format!("host:connect:{address}"))
+                .map(line),
+            ["mdns", "services"] => self.host(&command, "host:mdns:services"),
+            ["disconnect", address] => self
+                .host(&command, &format!("host:disconnect:{address}"))
+                .map(line),
+            ["kill-server"] => self.kill(&command),
+            ["shell", shell @ ..] if !shell.is_empty() => self.shell(&command, &shell.join(" ")),
+            _ => Err(Unavailable::Unsupported(command).into()),
+        }
+    }
+
+    /// A connection for running `command`
+    fn connect(&self, command: &str) -> Result<Connection, VoltaError> {
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, self.port));
+        let stream = TcpStream::connect_timeout(&address, self.timeout)
+            .map_err(|error| Unavailable::NoServer(self.port, error))?;
+        let connection = Connection {
+            stream,
+            timeout: self.timeout,
+            command: command.to_string(),
+        };
+        connection
+            .stream
+            .set_read_timeout(Some(self.timeout))
+            .and_then(|()| connection.stream.set_write_timeout(Some(self.timeout)))
+            .map_err(|error| connection.error(error))?;
+
+        Ok(connection)
+    }
+
+    /// A request about the device with the serial, or the only one
+    fn device_request(&self, request: &str) -> String {
+        match &self.serial {
+            Some(serial) => format!("host-serial:{serial}:{request}"),
+            None => format!("host:{request}"),
+        }
+    }
+
+    /// Sends a request to the server itself, returning its answer
+    fn host(&self, command: &str, request: &str) -> Result<String, VoltaError> {
+        let mut connection = self.connect(command)?;
+        connection.request(request)?;
+        connection.read_answer()
+    }
+
+    fn kill(&self, command: &str) -> Result<String, VoltaError> {
+        let mut connection = self.connect(command)?;
+        connection.request("host:kill")?;
+        Ok(String::new())
+    }
+
+    /// Runs a shell command with the shell protocol, which tells stdout,
+    /// stderr and the exit code apart, or with the plain one on devices
+    /// without it
+    fn shell(&self, command: &str, shell: &str) -> Result<String, VoltaError> {
+        let mut connection = self.transport(command)?;
+        if let Err(error) = connection.request(&format!("shell,v2,raw:{shell}")) {
+            if matches!(error, VoltaError::AdbTimeout { .. }) {
+                return Err(error);
+            }
+            let mut connection = self.transport(command)?;
+            connection.request(&format!("shell:{shell}"))?;
+            return Ok(adb::decode(&connection.read_to_end()?));
+        }
+
+        let (mut stdout, mut stderr, mut exit) = (Vec::new(), Vec::new(), None);
+        while let Some((id, data)) = connection.read_packet()? {
+            match id {
+                SHELL_STDOUT => stdout.extend_from_slice(&data),
+                SHELL_STDERR => stderr.extend_from_slice(&data),
+                SHELL_EXIT => {
+                    exit = data.first().copied();
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        let stdout = adb::decode(&stdout);
+        match exit {
+            Some(0) | None => Ok(stdout),
+            Some(code) => {
+                let stderr = String::from_utf8_lossy(&stderr).trim().to_string();
+                let message = if stderr.is_empty() {
+                    stdout.trim().to_string()
+                } else {
+                    stderr
+                };
+                Err(connection.failed(format!("exited with {code}: {message}")))
+            }
+        }
+    }
+
+    /// A connection switched over to the device
+    fn transport(&self, command: &str) -> Result<Connection, VoltaError> {
+        let mut connection = self.connect(command)?;
+        match &self.serial {
+            Some(serial) => connection.request(&format!("host:transport:{serial}"))?,
+            None => connection.request("host:transport-any")?,
+        }
+        Ok(connection)
+    }
+}
+
+/// A connection to the server for running `command`, which its errors are
+/// about
+struct Connection {
+    stream: TcpStream,
+    timeout: Duration,
+    command: String,
+}
+
+impl Connection {
+    /// Sends `request` and waits for it to be accepted
+    fn request(&mut self, request: &str) -> Result<(), VoltaError> {
+        let message = format!("{:04x}{request}", request.len());
+        self.stream
+            .write_all(message.as_bytes())
+            .map_err(|error| self.error(error))?;
+
+        let mut status = [0; 4];
+        self.read_exact(&mut status)?;
+        match &status {
+            b"OKAY" => Ok(()),
+            b"FAIL" => {
+                let message = self.read_answer()?;
+                Err(self.failed(message))
+            }
+            _ => Err(self.failed(format!(
+                "Unexpected answer `{}` from the adb server",
+                String::from_utf8_lossy(&status)
+            ))),
+        }
+    }
+
+    /// Reads an answer prefixed by its length in 4 hex digits
+    fn read_answer(&mut self) -> Result<String, VoltaError> {
+        let mut length = [0; 4];
+        self.read_exact(&mut length)?;
+        let length = std::str::from_utf8(&length)
+            .ok()
+            .and_then(|length| usize::from_str_radix(length, 16).ok())
+            .ok_or_else(|| self.failed(String::from("Invalid length from the adb server")))?;
+        let mut answer = vec![0; length];
+        self.read_exact(&mut answer)?;
+        Ok(adb::decode(&answer))
+    }
+
+    /// Reads a packet of the shell protocol, a stream id and a little endian
+    /// length followed by the data, or nothing once the device closed it
+    fn read_packet(&mut self) -> Result<Option<(u8, Vec<u8>)>, VoltaError> {
+        let mut header = [0; 5];
+        match self.stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(self.error(error)),
+        }
+        let length = u32::from_le_bytes(header[1..].try_into().expect("4 bytes")) as usize;
+        let mut data = vec![0; length];
+        self.read_exact(&mut data)?;
+        Ok(Some((header[0], data)))
+    }
+
+    fn read_to_end(&mut self) -> Result<Vec<u8>, VoltaError> {
+        let mut output = Vec::new();
+        self.stream
+            .read_to_end(&mut output)
+            .map_err(|error| self.error(error))?;
+        Ok(output)
+    }
+
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), VoltaError> {
+        self.stream
+            .read_exact(buffer)
+            .map_err(|error| self.error(error))
+    }
+
+    /// The server or the device refused the command with `message`
+    fn failed(&self, message: String) -> VoltaError {
+        VoltaError::AdbFailed {
+            command: self.command.clone(),
+            message,
+        }
+    }
+
+    fn error(&self, error: io::Error) -> VoltaError {
+        match error.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => VoltaError::AdbTimeout {
+                command: self.command.clone(),
+                timeout: self.timeout,
+            },
+            _ => VoltaError::AdbIo {
+                action: "Failed to talk to the adb server",
+                source: error,
+            },
+        }
+    }
+}
+
+/// The server lists the devices without the header the executable prints
+fn device_list(devices: String) -> String {
+    format!("List of devices attached\n{devices}")
+}
+
+fn line(answer: String) -> String {
+    format!("{answer}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// An adb server which answers each request with `answer`, closing the
+    /// connection after answers which end it
+    fn serve(answer: fn(&str) -> (Vec<u8>, bool)) -> u16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                loop {
+                    let mut length = [0; 4];
+                    if stream.read_exact(&mut length).is_err() {
+                        break;
+                    }
+                    let length =
+                        usize::from_str_radix(std::str::from_utf8(&length).unwrap(), 16).unwrap();
+                    let mut request = vec![0; length];
+                    stream.read_exact(&mut request).unwrap();
+                    let (reply, close) = answer(std::str::from_utf8(&request).unwrap());
+                    stream.write_all(&reply).unwrap();
+                    if close {
+                        break;
+                    }
+                }
+            }
+        });
+        port
+    }
+
+    fn okay(answer: &str) -> Vec<u8> {
+        format!("OKAY{:04x}{answer}", answer.len()).into_bytes()
+    }
+
+    fn fail(message: &str) -> Vec<u8> {
+        format!("FAIL{:04x}{message}", message.len()).into_bytes()
+    }
+
+    fn packet(id: u8, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![id];
+        packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn client(port: u16, serial: &str) -> HostClient {
+        HostClient::new(port, Some(serial.to_string()), Duration::from_secs(2))
+    }
+
+    #[test]
+    fn runs_shell_commands() {
+        let port = serve(|request| match request {
+            "host:transport:PA7L10MGH4030" => (b"OKAY".to_vec(), false),
+            "shell,v2,raw:dumpsys battery" => {
+                let mut reply = b"OKAY".to_vec();
+                reply.extend(packet(SHELL_STDOUT, b"Current Battery Service state:\n"));
+                reply.extend(packet(SHELL_STDOUT, b"  level: 85\n"));
+                reply.extend(packet(SHELL_EXIT, &[0]));
+                (reply, true)
+            }
+            "shell,v2,raw:cat /missing" => {
+                let mut reply = b"OKAY".to_vec();
+                reply.extend(packet(SHELL_STDERR, b"cat: /missing: No such file\n"));
+                reply.extend(packet(SHELL_EXIT, &[1]));
+                (reply, true)
+            }
+            _ => (fail("unknown request"), true),
+        });
+        let adb = client(port, "PA7L10MGH4030");
+
+        assert_eq!(
+            adb.run(&["shell", "dumpsys", "battery"]).unwrap(),
+            "Current Battery Service state:\n  level: 85\n"
+        );
+        let error = adb.run(&["shell", "cat", "/missing"]).unwrap_err();
+        assert!(format!("{error:#}").contains("No such file"), "{error:#}");
+    }
+
+    #[test]
+    fn falls_back_to_the_plain_shell() {
+        let port = serve(|request| match request {
+            "host:transport-any" => (b"OKAY".to_vec(), false),
+            "shell:getprop ro.product.manufacturer" => (b"OKAYPico\n".to_vec(), true),
+            _ => (fail("unsupported"), true),
+        });
+        let adb = HostClient::new(port, None, Duration::from_secs(2));
+
+        assert_eq!(
+            adb.run(&["shell", "getprop", "ro.product.manufacturer"])
+                .unwrap(),
+            "Pico\n"
+        );
+    }
+
+    #[test]
+    fn answers_host_requests() {
+        let port = serve(|request| match request {
+            "host:version" => (okay("0029"), true),
+            "host:devices" => (okay("PA7L10MGH4030\tdevice\n"), true),
+            "host-serial:PA7L10MGH4030:get-state" => (okay("device"), true),
+            "host:transport:192.168.1.50:5555" => {
+                (fail("device '192.168.1.50:5555' not found"), true)
+            }
+            _ => (fail("unknown request"), true),
+        });
+        let adb = client(port, "PA7L10MGH4030");
+
+        assert!(adb.run(&["version"]).unwrap().ends_with("1.0.41\n"));
+        assert_eq!(
+            adb.run(&["devices"]).unwrap(),
+            "List of devices attached\nPA7L10MGH4030\tdevice\n"
+        );
+        assert_eq!(adb.run(&["get-state"]).unwrap(), "device\n");
+
+        let error = client(port, "192.168.1.50:5555")
+            .run(&["shell", "dumpsys", "battery"])
+            .unwrap_err();
+        assert!(
+            format!("{error:#}").contains("device '192.168.1.50:5555' not found"),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn leaves_other_commands_to_the_executable() {
+        let error = client(1, "PA7L10MGH4030")
+            .run(&["start-server"])
+            .unwrap_err();
+
+        assert!(matches!(
+            error,
+            VoltaError::AdbUnavailable(Unavailable::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::osc::LevelScale;
+use rosc::{OscMessage, OscType};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How the messages of battery levels are described, which `min_delta` is
+/// scaled for
+const LEVEL: &str = "battery level";
+
+/// Drops messages whose value hasn't changed since it was last sent, so
+/// VRChat doesn't sync identical parameters to the whole instance. The
+/// minimum change of a float is a change of a level from 0.0 to 1.0, so for
+/// the levels it's put on the scale they're sent on.
+pub struct ChangeFilter {
+    min_delta: f32,
+    keepalive: Duration,
+    sent: HashMap<String, OscType>,
+    last_keepalive: Option<Instant>,
+}
+
+impl ChangeFilter {
+    pub fn new(min_delta: f32, keepalive: Duration) -> Self {
+        Self {
+            min_delta,
+            keepalive,
+            sent: HashMap::new(),
+            last_keepalive: None,
+        }
+    }
+
+    /// Keeps the messages which changed, or all of them once the keepalive
+    /// interval has passed, the levels being sent on `scale`
+    pub fn filter<'a>(
+        &mut self,
+        mut messages: Vec<(&'a str, OscMessage)>,
+        scale: LevelScale,
+    ) -> Vec<(&'a str, OscMessage)> {
+        let now = Instant::now();
+        let keepalive = self
+            .last_keepalive
+            .is_none_or(|last| now.duration_since(last) >= self.keepalive);
+
+        if keepalive {
+            self.last_keepalive = Some(now);
+        } else {
+            messages.retain(|(name, message)| {
+                let min_delta = match name.ends_with(LEVEL) {
+                    true => scale.apply(self.min_delta),
+                    false => self.min_delta,
+                };
+                self.has_changed(message, min_delta)
+            });
+        }
+
+        for (_, message) in &messages {
+            if let Some(arg) = message.args.first() {
+                self.sent.insert(message.addr.clone(), arg.clone());
+            }
+        }
+
+        messages
+    }
+
+    /// Lets the next message to `address` through, as this one wasn't sent
+    pub fn forget(&mut self, address: &str) {
+        self.sent.remove(address);
+    }
+
+    /// Keeps every message of the next cycle, as if the keepalive was due
+    pub fn reset(&mut self) {
+        self.last_keepalive = None;
+    }
+
+    fn has_changed(&self, message: &OscMessage, min_delta: f32) -> bool {
+        match (self.sent.get(&message.addr), message.args.first()) {
+            (Some(OscType::Float(sent)), Some(OscType::Float(value))) if min_delta > 0.0 => {
+                (value - sent).abs() >= min_delta
+            }
+            (Some(sent), Some(value)) => sent != value,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::{self, ParamType};
+
+    /// Which of the left controller's `levels` get through a filter with a
+    /// minimum change of 5%
+    fn sent(scale: LevelScale, levels: &[f32]) -> Vec<bool> {
+        let mut changes = ChangeFilter::new(0.05, Duration::from_secs(3600));
+        levels
+            .iter()
+            .map(|&level| {
+                let messages = osc::level_messages(ParamType::Float, scale, "/left", level)
+                    .into_iter()
+                    .map(|message| ("left controller battery level", message))
+                    .collect();
+                !changes.filter(messages, scale).is_empty()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compares_the_levels_before_scaling() {
+        for scale in [LevelScale::Unit, LevelScale::Percent] {
+            // Controllers reporting steps of 20%
+            assert_eq!(
+                sent(scale, &[0.6, 0.6, 0.4, 0.4]),
+                [true, false, true, false],
+                "{scale:?}"
+            );
+            // Controllers reporting percentages
+            assert_eq!(
+                sent(scale, &[0.6, 0.62, 0.58, 0.66]),
+                [true, false, false, true],
+                "{scale:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_the_minimum_change_of_other_floats() {
+        let mut changes = ChangeFilter::new(0.05, Duration::from_secs(3600));
+        let voltage = |value| {
+            vec![(
+                "headset battery voltage",
+                OscMessage {
+                    addr: String::from("/voltage"),
+                    args: vec![OscType::Float(value)],
+                },
+            )]
+        };
+        changes.filter(voltage(4.0), LevelScale::Percent);
+        assert_eq!(changes.filter(voltage(4.1), LevelScale::Percent).len(), 1);
+        assert!(changes
+            .filter(voltage(4.12), LevelScale::Percent)
+            .is_empty());
+    }
+}
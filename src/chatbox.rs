@@ -0,0 +1,150 @@
+use crate::osc::OscSender;
+use crate::sink::{BatteryReport, Settings, Sink};
+use crate::source::BatteryLevels;
+use anyhow::{Context, Result};
+use rosc::{OscMessage, OscType};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+const ADDRESS: &str = "/chatbox/input";
+const MAX_LENGTH: usize = 144;
+const MIN_INTERVAL: Duration = Duration::from_secs(5);
+
+pub const DEFAULT_FORMAT: &str = "Headset {headset}% | Left {left}% | Right {right}%";
+
+/// Writes the battery levels to the VRChat chatbox, no more often than the
+/// chatbox rate limit allows
+pub struct Chatbox {
+    format: String,
+    last_sent: Option<Instant>,
+}
+
+impl Chatbox {
+    pub fn new(format: String) -> Self {
+        Self {
+            format,
+            last_sent: None,
+        }
+    }
+
+    pub fn set_format(&mut self, format: String) {
+        self.format = format;
+    }
+
+    /// Returns `None` while the previous chatbox message is too recent
+    pub fn message(&mut self, levels: &BatteryLevels) -> Option<OscMessage> {
+        let now = Instant::now();
+        if self
+            .last_sent
+            .is_some_and(|last_sent| now.duration_since(last_sent) < MIN_INTERVAL)
+        {
+            return None;
+        }
+        self.last_sent = Some(now);
+
+        Some(OscMessage {
+            addr: ADDRESS.to_string(),
+            args: vec![
+                OscType::String(
+                    render(&self.format, levels)
+                        .chars()
+                        .take(MAX_LENGTH)
+                        .collect(),
+                ),
+                // Send immediately instead of opening the keyboard, without the notification sound
+                OscType::Bool(true),
+                OscType::Bool(false),
+            ],
+        })
+    }
+}
+
+/// Publishes the battery levels to the chatbox
+pub struct ChatboxSink {
+    chatbox: Chatbox,
+    sender: Rc<RefCell<OscSender>>,
+}
+
+impl ChatboxSink {
+    pub fn new(chatbox: Chatbox, sender: Rc<RefCell<OscSender>>) -> Self {
+        Self { chatbox, sender }
+    }
+}
+
+impl Sink for ChatboxSink {
+    fn name(&self) -> &'static str {
+        "the chatbox"
+    }
+
+    fn publish(&mut self, report: &BatteryReport) -> Result<()> {
+        let Some(message) = self.chatbox.message(&report.levels) else {
+            return Ok(());
+        };
+        self.sender
+            .borrow_mut()
+            .send(vec![("chatbox", message)])
+            .context("Failed to send the chatbox message")
+    }
+
+    fn reconfigure(&mut self, settings: &Settings) {
+        self.chatbox.set_format(settings.chatbox_format.clone());
+    }
+}
+
+/// Replaces the `{headset}`, `{left}` and `{right}` placeholders with integer
+/// percentages, or `-` for a disconnected controller or a headset without a
+/// battery
+pub fn render(format: &str, levels: &BatteryLevels) -> String {
+    let percentage = |level: Option<f32>| {
+        level.map_or(String::from("-"), |level| {
+            format!("{}", (level * 100.0).round() as i32)
+        })
+    };
+
+    format
+        .replace("{headset}", &percentage(levels.headset))
+        .replace("{left}", &percentage(levels.left_controller))
+        .replace("{right}", &percentage(levels.right_controller))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::fixture;
+    use rosc::{decoder, OscPacket};
+    use std::net::{Ipv4Addr, UdpSocket};
+    use std::time::Duration;
+
+    #[test]
+    fn publishes_rate_limited_messages() {
+        let receiver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let sender = OscSender::new(
+            UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap(),
+            vec![receiver.local_addr().unwrap().to_string()],
+            false,
+            Duration::ZERO,
+        )
+        .unwrap();
+        let chatbox = Chatbox::new(String::from(DEFAULT_FORMAT));
+        let mut sink = ChatboxSink::new(chatbox, Rc::new(RefCell::new(sender)));
+
+        sink.publish(&fixture::report()).unwrap();
+        let mut buffer = [0; 1024];
+        let size = receiver.recv(&mut buffer).unwrap();
+        let Ok((_, OscPacket::Message(message))) = decoder::decode_udp(&buffer[..size]) else {
+            panic!("expected a message");
+        };
+        assert_eq!(message.addr, ADDRESS);
+        assert_eq!(
+            message.args[0],
+            OscType::String(String::from("Headset 85% | Left -% | Right 40%"))
+        );
+
+        sink.publish(&fixture::report()).unwrap();
+        assert!(receiver.recv(&mut buffer).is_err());
+    }
+}
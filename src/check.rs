@@ -0,0 +1,347 @@
+use crate::adb::{self, AdbRunner};
+use crate::osc;
+use crate::parse;
+use crate::source::{self, BatterySource};
+use anyhow::{ensure, Context, Result};
+use rosc::{encoder, OscMessage, OscPacket};
+use std::fmt::Display;
+use std::net::UdpSocket;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Address of the message sent to check the receiver, which VRChat ignores
+const CHECK_ADDRESS: &str = "/volta/check";
+
+/// How long the OS gets to report that nothing receives the check message
+const UNREACHABLE_GRACE: Duration = Duration::from_millis(100);
+
+/// What the self-test checks the setup with
+pub struct Setup<'a, A> {
+    /// Runs the adb executable itself without a device, even when the
+    /// server would answer
+    pub executable: A,
+    /// Where the executable is
+    pub program: &'a Path,
+    /// Runs adb without a device the way volta does
+    pub adb: A,
+    /// Asks the adb server for its version directly
+    pub server: Box<dyn Fn() -> Result<String> + 'a>,
+    /// Wireless adb address to connect to first
+    pub address: Option<&'a str>,
+    pub serial: Option<&'a str>,
+    /// Creates the source reading the headset through `adb`
+    pub source: Box<dyn Fn(A) -> Box<dyn BatterySource> + 'a>,
+    pub sender: &'a str,
+    pub receivers: &'a [String],
+    pub interface: Option<&'a str>,
+}
+
+/// Collects the stages which failed while printing a line for each
+#[derive(Default)]
+struct Stages {
+    failed: Vec<String>,
+}
+
+impl Stages {
+    /// Prints the outcome of a required stage
+    fn required<T, E: Into<anyhow::Error>>(
+        &mut self,
+        stage: &str,
+        result: Result<T, E>,
+        detail: impl Fn(&T) -> String,
+    ) -> Option<T> {
+        match result.map_err(Into::into) {
+            Ok(value) => {
+                println!("✅ {stage}: {}", detail(&value));
+                Some(value)
+            }
+            Err(error) => {
+                println!("❌ {stage}: {error:#}");
+                self.failed.push(stage.to_string());
+                None
+            }
+        }
+    }
+
+    /// Prints the outcome of a stage which only warns when it fails
+    fn optional<T, E: Into<anyhow::Error>>(
+        &self,
+        stage: &str,
+        result: Result<T, E>,
+        detail: impl Fn(&T) -> String,
+    ) {
+        match result.map_err(Into::into) {
+            Ok(value) => println!("✅ {stage}: {}", detail(&value)),
+            Err(error) => println!("⚠️ {stage}: {error:#}"),
+        }
+    }
+
+    /// Prints a required stage which can't run as an earlier one failed
+    fn skipped<T>(&self, stage: &str, reason: impl Display) -> Option<T> {
+        println!("❌ {stage}: skipped, {reason}");
+        None
+    }
+}
+
+/// Checks adb, the headset, the parsers and sending OSC one after the other,
+/// printing a line per stage. Returns whether every required stage passed.
+pub fn run<A: AdbRunner + Clone>(setup: Setup<A>) -> bool {
+    let failed = check(setup).len();
+    match failed {
+        0 => {}
+        1 => println!("1 check failed"),
+        failed => println!("{failed} checks failed"),
+    }
+    failed == 0
+}
+
+/// Runs the stages, returning the required ones which failed
+fn check<A: AdbRunner + Clone>(setup: Setup<A>) -> Vec<String> {
+    let mut stages = Stages::default();
+
+    let adb = &setup.adb;
+    let version = setup.executable.run(&["version"]);
+    let found = stages.required("adb", version, |version| {
+        let version = version.lines().next().unwrap_or("unknown version");
+        format!("{} ({version})", setup.program.display())
+    });
+    let server = match found {
+        Some(_) => {
+            let port = adb::server_port();
+            let running = adb::start_adb_server(adb)
+                .and_then(|()| (setup.server)().context("Failed to reach the adb server"));
+            stages.required("adb server", running, |_| format!("running on port {port}"))
+        }
+        None => stages.skipped("adb server", "as adb wasn't found"),
+    };
+    let headset = match server {
+        Some(_) => {
+            let headset = find_headset(adb, setup.address, setup.serial);
+            stages.required("Headset", headset, |(serial, model)| {
+                format!("{model} ({serial})")
+            })
+        }
+        None => stages.skipped("Headset", "as the adb server isn't running"),
+    };
+
+    match headset {
+        Some((serial, _)) => {
+            let adb = adb.clone();
+            adb.retarget(&serial);
+            let level =
+                source::get_battery_dump(&adb).and_then(|dump| parse::parse_headset_level(&dump));
+            stages.required("dumpsys battery", level, |level| {
+                format!("headset at {level}%")
+            });
+            let levels = (setup.source)(adb).get_levels();
+            stages.optional("Controllers", levels, |levels| {
+                let percent = |level: Option<f32>| {
+                    level.map_or(String::from("disconnected"), |level| {
+                        format!("{}%", source::percent(level))
+                    })
+                };
+                format!(
+                    "left {}, right {}",
+                    percent(levels.left_controller),
+                    percent(levels.right_controller)
+                )
+            });
+        }
+        None => {
+            stages.skipped::<()>("dumpsys battery", "as there's no headset to read");
+        }
+    }
+
+    // The socket only fixes the IP and family the receivers are sent from,
+    // each of them gets a connected socket of its own
+    let socket = osc::bind(setup.sender, &setup.receivers[0], setup.interface);
+    let socket = stages.required("OSC sender", socket, |socket| match socket.local_addr() {
+        Ok(address) => format!("bound to {address}"),
+        Err(_) => String::from("bound"),
+    });
+    for receiver in setup.receivers {
+        let stage = format!("OSC receiver {receiver}");
+        match (&socket, receiver.as_str()) {
+            (_, "auto") => {
+                println!("⚠️ {stage}: skipped, `auto` is only discovered once volta runs")
+            }
+            (Some(socket), receiver) => {
+                let sent = send_check(socket, receiver);
+                stages.required(&stage, sent, |_| {
+                    format!("sent a test message to {receiver}")
+                });
+            }
+            (None, _) => {
+                stages.skipped::<()>(&stage, "as the sender isn't bound");
+            }
+        }
+    }
+
+    stages.failed
+}
+
+/// Connects to the headset if it's wireless and checks that it's ready,
+/// returning its serial and model
+fn find_headset<A: AdbRunner + Clone>(
+    adb: &A,
+    address: Option<&str>,
+    serial: Option<&str>,
+) -> Result<(String, String)> {
+    let discovered = match address {
+        Some(adb::AUTO_CONNECT) => Some(adb::discover_headset(adb, serial)?),
+        _ => None,
+    };
+    let address = discovered.as_deref().or(address);
+    if let Some(address) = address {
+        adb::connect(adb, address)?;
+    }
+    // The discovered headset is listed by its address rather than its serial
+    let serial = match discovered.as_deref().or(serial).or(address) {
+        Some(serial) => Some(serial.to_string()),
+        None => adb::select_device(adb)?,
+    };
+    adb::preflight(adb, serial.as_deref(), true)?;
+
+    let adb = adb.clone();
+    if let Some(serial) = &serial {
+        adb.retarget(serial);
+    }
+    let serial = adb.run(&["get-serialno"])?.trim().to_string();
+    Ok((serial, adb::model(&adb)?))
+}
+
+fn send_check(socket: &UdpSocket, receiver: &str) -> Result<()> {
+    let address = osc::resolve(receiver, socket.local_addr()?.is_ipv6())?;
+    let packet = OscPacket::Message(OscMessage {
+        addr: CHECK_ADDRESS.to_string(),
+        args: Vec::new(),
+    });
+    let bytes = encoder::encode(&packet)?;
+    let connected = osc::connect(socket, address)?;
+    connected
+        .send(&bytes)
+        .with_context(|| format!("Failed to send to {address}"))?;
+
+    // Only platforms which report an unreachable port can tell
+    thread::sleep(UNREACHABLE_GRACE);
+    let unreachable = connected
+        .take_error()?
+        .is_some_and(|error| osc::is_unreachable(&error));
+    ensure!(
+        !unreachable,
+        "Nothing receives on {address}, is VRChat running with OSC enabled?"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb::mock::MockAdb;
+    use crate::source::{ControllerScale, Device, HeadsetSource};
+    use anyhow::anyhow;
+
+    /// adb with a Pico headset connected
+    fn headset() -> MockAdb {
+        MockAdb::default()
+            .with("version", "Android Debug Bridge version 1.0.41\n")
+            .with("start-server", "")
+            .with(
+                "devices",
+                "List of devices attached\nPA7H10MGF1234\tdevice\n",
+            )
+            .with(
+                "devices -l",
+                "List of devices attached\nPA7H10MGF1234 device product:A8110 model:Pico_4\n",
+            )
+            .with("get-serialno", "PA7H10MGF1234\n")
+            .with("shell getprop ro.product.model", "Pico 4\n")
+            .with(
+                "shell dumpsys battery",
+                include_str!("../tests/fixtures/battery.txt"),
+            )
+            .with(
+                "shell dumpsys pxrcontrollerservice",
+                include_str!("../tests/fixtures/pxrcontrollerservice.txt"),
+            )
+    }
+
+    /// The stages which fail with `adb`, whose server answers if `server`
+    fn failed(adb: MockAdb, server: bool, receivers: &[&str]) -> Vec<String> {
+        let receivers: Vec<String> = receivers
+            .iter()
+            .map(|receiver| receiver.to_string())
+            .collect();
+        check(Setup {
+            executable: adb.clone(),
+            program: Path::new("adb"),
+            adb,
+            server: Box::new(move || match server {
+                true => Ok(String::from("0029")),
+                false => Err(anyhow!("Connection refused")),
+            }),
+            address: None,
+            serial: None,
+            source: Box::new(|adb| {
+                source::create(
+                    &Device::Pico,
+                    None,
+                    adb,
+                    ControllerScale::Auto,
+                    HeadsetSource::Dumpsys,
+                    false,
+                    false,
+                )
+            }),
+            sender: "127.0.0.1:0",
+            receivers: &receivers,
+            interface: None,
+        })
+    }
+
+    /// A receiver listening on a port of its own
+    fn receiver() -> (UdpSocket, String) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        (socket, address)
+    }
+
+    #[test]
+    fn passes_with_a_headset_and_receivers() {
+        let (_first, first) = receiver();
+        let (_second, second) = receiver();
+        assert!(failed(headset(), true, &[first.as_str(), second.as_str()]).is_empty());
+    }
+
+    #[test]
+    fn fails_the_stages_which_fail() {
+        let (_listening, listening) = receiver();
+        let receivers = [listening.as_str(), "127.0.0.1:99999"];
+        assert_eq!(
+            failed(headset(), true, &receivers),
+            ["OSC receiver 127.0.0.1:99999"]
+        );
+
+        let unauthorized = headset().with(
+            "devices",
+            "List of devices attached\nPA7H10MGF1234\tunauthorized\n",
+        );
+        assert_eq!(failed(unauthorized, true, &receivers[..1]), ["Headset"]);
+        // The stages after one which failed are skipped
+        assert_eq!(failed(headset(), false, &receivers[..1]), ["adb server"]);
+        assert_eq!(failed(MockAdb::default(), true, &["auto"]), ["adb"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fails_on_an_unreachable_receiver() {
+        // Nothing listens on the port once the socket is dropped
+        let (closed, address) = receiver();
+        drop(closed);
+        assert_eq!(
+            failed(headset(), true, &[address.as_str()]),
+            [format!("OSC receiver {address}")]
+        );
+    }
+}
@@ -0,0 +1,807 @@
+use crate::profile::Profile;
+use anyhow::{bail, ensure, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+const FILE_NAME: &str = "volta.toml";
+
+/// Values read from `volta.toml`, every key is optional and only applies
+/// when the matching flag wasn't passed on the command line
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Config {
+    pub receiver: Option<Receivers>,
+    pub discovery_timeout: Option<String>,
+    pub oscquery: Option<bool>,
+    pub sender: Option<String>,
+    pub bind_interface: Option<String>,
+    pub listen: Option<String>,
+    pub no_osc_refresh: Option<bool>,
+    pub no_http_refresh: Option<bool>,
+    pub no_signal_refresh: Option<bool>,
+    pub interval: Option<String>,
+    pub detect_sleep: Option<bool>,
+    pub headset_interval: Option<String>,
+    pub controller_interval: Option<String>,
+    pub idle_interval: Option<String>,
+    pub charge_poll: Option<String>,
+    pub no_charge_poll: Option<bool>,
+    pub require_vrchat: Option<bool>,
+    pub vrchat_process: Option<Vec<String>>,
+    pub vrchat_check_interval: Option<String>,
+    pub pause_between: Option<Vec<String>>,
+    pub adb_connect: Option<String>,
+    pub serial: Option<String>,
+    pub adb_path: Option<PathBuf>,
+    pub adb_backend: Option<String>,
+    pub persistent_shell: Option<bool>,
+    pub adb_timeout: Option<String>,
+    pub retries: Option<u32>,
+    pub adb_restart_after: Option<u32>,
+    pub kill_adb_on_exit: Option<bool>,
+    pub source: Option<String>,
+    pub device: Option<Devices>,
+    pub controller_scale: Option<String>,
+    pub headset_source: Option<String>,
+    pub clamp: Option<bool>,
+    pub fake: Option<bool>,
+    pub tray: Option<bool>,
+    pub fake_sweep: Option<String>,
+    pub fake_values: Option<String>,
+    pub replay: Option<PathBuf>,
+    pub capture: Option<PathBuf>,
+    pub param_type: Option<String>,
+    pub scale: Option<String>,
+    pub offline_value: Option<String>,
+    pub no_bundle: Option<bool>,
+    pub max_rate: Option<u32>,
+    pub message_spacing: Option<u64>,
+    pub min_delta: Option<f32>,
+    pub smooth: Option<f32>,
+    pub interpolate_steps: Option<bool>,
+    pub keepalive: Option<String>,
+    pub startup_burst: Option<Vec<String>>,
+    pub no_startup_burst: Option<bool>,
+    pub stale_behavior: Option<String>,
+    pub max_data_age: Option<u32>,
+    pub warn_headset: Option<u8>,
+    pub warn_controller: Option<u8>,
+    pub critical_headset: Option<u8>,
+    pub critical_controller: Option<u8>,
+    pub dry_run: Option<bool>,
+    pub notify: Option<bool>,
+    pub alert_sound: Option<PathBuf>,
+    pub warn_sound: Option<PathBuf>,
+    pub critical_sound: Option<PathBuf>,
+    pub xsoverlay: Option<bool>,
+    pub xsoverlay_port: Option<u16>,
+    pub webhook: Option<String>,
+    pub webhook_format: Option<String>,
+    pub no_reset_on_exit: Option<bool>,
+    pub reset_value: Option<f32>,
+    pub stages: Option<u8>,
+    pub stages_only: Option<bool>,
+    pub stage_hysteresis: Option<f32>,
+    pub estimate: Option<bool>,
+    pub estimate_window: Option<String>,
+    pub temp_range: Option<String>,
+    pub chatbox: Option<bool>,
+    pub chatbox_format: Option<String>,
+    pub http: Option<String>,
+    pub mqtt: Option<String>,
+    pub mqtt_topic_prefix: Option<String>,
+    pub mqtt_discovery: Option<bool>,
+    pub status_file: Option<PathBuf>,
+    pub status_format: Option<String>,
+    pub history: Option<PathBuf>,
+    pub history_rotate_daily: Option<bool>,
+    pub session_log: Option<PathBuf>,
+    pub prefix: Option<String>,
+    pub headset_param: Option<String>,
+    pub left_param: Option<String>,
+    pub right_param: Option<String>,
+    pub charging_param: Option<String>,
+    pub temp_param: Option<String>,
+    pub voltage_param: Option<String>,
+    pub left_connected_param: Option<String>,
+    pub right_connected_param: Option<String>,
+    pub low_headset_param: Option<String>,
+    pub low_left_param: Option<String>,
+    pub low_right_param: Option<String>,
+    pub tracker_param: Option<String>,
+    pub awake_param: Option<String>,
+    pub data_age_param: Option<String>,
+    pub stage_headset_param: Option<String>,
+    pub stage_left_param: Option<String>,
+    pub stage_right_param: Option<String>,
+    pub minutes_headset_param: Option<String>,
+    pub minutes_left_param: Option<String>,
+    pub minutes_right_param: Option<String>,
+    pub drain_headset_param: Option<String>,
+    pub drain_left_param: Option<String>,
+    pub drain_right_param: Option<String>,
+    pub steamvr_param: Option<String>,
+    pub heartbeat: Option<bool>,
+    pub alive_param: Option<String>,
+    pub beat_param: Option<String>,
+    pub version_param: Option<String>,
+    pub report_storage: Option<bool>,
+    pub storage_interval: Option<String>,
+    pub storage_param: Option<String>,
+    pub report_health: Option<bool>,
+    pub health_param: Option<String>,
+    /// Parameter addresses of avatars which differ from the ones above, by
+    /// avatar ID
+    pub avatar: Option<BTreeMap<String, AvatarEntry>>,
+    /// Device profiles `device = "profile:<name>"` selects, by name
+    pub profile: Option<BTreeMap<String, Profile>>,
+
+    #[serde(flatten)]
+    unknown: BTreeMap<String, toml::Value>,
+}
+
+/// One receiver or a list of them
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Receivers {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<Receivers> for Vec<String> {
+    fn from(receivers: Receivers) -> Self {
+        match receivers {
+            Receivers::One(receiver) => vec![receiver],
+            Receivers::Many(receivers) => receivers,
+        }
+    }
+}
+
+/// The kind of headset as `device = "quest"`, or a `[[device]]` entry for
+/// every headset polled by one process
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Devices {
+    Kind(String),
+    Entries(Vec<DeviceEntry>),
+}
+
+/// A headset polled alongside the others, whose values replace the top level
+/// ones
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceEntry {
+    /// Tags the log lines, defaults to the serial or the address
+    pub name: Option<String>,
+    pub serial: Option<String>,
+    pub adb_connect: Option<String>,
+    pub prefix: String,
+    pub device: Option<String>,
+    pub warn_headset: Option<u8>,
+    pub warn_controller: Option<u8>,
+    pub critical_headset: Option<u8>,
+    pub critical_controller: Option<u8>,
+}
+
+/// Parameter addresses used while the avatar is worn, as `[avatar.avtr_...]`
+/// with the keys of the top level addresses, e.g. `headset_param`
+#[derive(Clone, Debug, Deserialize)]
+pub struct AvatarEntry {
+    /// Logged when the avatar's addresses are activated, defaults to its ID
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub addresses: BTreeMap<String, String>,
+}
+
+impl DeviceEntry {
+    pub fn name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.serial.as_deref())
+            .or(self.adb_connect.as_deref())
+            .unwrap_or(&self.prefix)
+    }
+}
+
+impl Config {
+    /// The `[[device]]` entries, empty when a single headset is polled
+    pub fn devices(&self) -> &[DeviceEntry] {
+        match &self.device {
+            Some(Devices::Entries(entries)) => entries,
+            _ => &[],
+        }
+    }
+
+    /// The config of the `index`th `[[device]]` entry, with its values in
+    /// place of the top level ones
+    pub fn for_device(mut self, index: usize) -> Result<Config> {
+        let entries = match self.device.take() {
+            Some(Devices::Entries(entries)) => entries,
+            _ => Vec::new(),
+        };
+        let count = entries.len();
+        let entry = entries.into_iter().nth(index).with_context(|| {
+            format!("The config file lists {count} devices, restart volta to change them")
+        })?;
+        ensure!(
+            entry.serial.is_some() || entry.adb_connect.is_some(),
+            "The `[[device]]` entry `{}` needs a `serial` or an `adb_connect` address",
+            entry.name()
+        );
+
+        self.serial = entry.serial;
+        self.adb_connect = entry.adb_connect;
+        self.prefix = Some(entry.prefix);
+        self.device = entry.device.map(Devices::Kind);
+        let thresholds = [
+            (&mut self.warn_headset, entry.warn_headset),
+            (&mut self.warn_controller, entry.warn_controller),
+            (&mut self.critical_headset, entry.critical_headset),
+            (&mut self.critical_controller, entry.critical_controller),
+        ];
+        for (target, value) in thresholds {
+            if value.is_some() {
+                *target = value;
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+/// Loads the config from `path`, or from the first `volta.toml` found next to
+/// the executable or in the OS config directory. Only an explicitly given
+/// path has to exist.
+pub fn load(path: Option<&Path>) -> Result<Config> {
+    match find(path) {
+        Some(path) => read(&path),
+        None => Ok(Config::default()),
+    }
+}
+
+/// The file the config is loaded from, if there is one
+pub fn find(path: Option<&Path>) -> Option<PathBuf> {
+    match path {
+        Some(path) => Some(path.to_path_buf()),
+        None => default_paths().into_iter().find(|path| path.is_file()),
+    }
+}
+
+/// Notices when the config file is edited by checking its modification time
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Watcher {
+    pub fn new(path: PathBuf) -> Self {
+        let modified = modified(&path);
+        Self { path, modified }
+    }
+
+    /// Reads the config again if the file changed since the last call. A file
+    /// which can't be read is only reported once per change.
+    pub fn changed(&mut self) -> Option<Result<Config>> {
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        Some(read(&self.path))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// The file values are stored in: `path`, or the first `volta.toml` found,
+/// or a new one in the OS config directory
+pub fn writable_path(path: Option<&Path>) -> Result<PathBuf> {
+    if let Some(path) = path {
+        return Ok(path.to_path_buf());
+    }
+    let paths = default_paths();
+    paths
+        .iter()
+        .find(|path| path.is_file())
+        .or(paths.last())
+        .cloned()
+        .context("Failed to find a location for the config file")
+}
+
+/// Sets the top level `key` of the config file at `path` to `value`, or
+/// removes it without a value. The rest of the file, including comments, is
+/// kept as it is.
+pub fn store(path: &Path, key: &str, value: Option<&str>) -> Result<()> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(error) => {
+            return Err(error).with_context(|| format!("Failed to read config file {path:?}"))
+        }
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {dir:?}"))?;
+    }
+    fs::write(path, with_value(&contents, key, value))
+        .with_context(|| format!("Failed to write config file {path:?}"))
+}
+
+fn with_value(contents: &str, key: &str, value: Option<&str>) -> String {
+    let line = value.map(|value| format!("{key} = {}", toml::Value::from(value)));
+    let mut lines: Vec<String> = Vec::new();
+    let mut stored = false;
+    let mut top_level = true;
+
+    for current in contents.lines() {
+        let trimmed = current.trim_start();
+        if top_level && trimmed.starts_with('[') {
+            // Top level keys have to come before the first table
+            top_level = false;
+            if let (false, Some(line)) = (stored, &line) {
+                lines.push(line.clone());
+                stored = true;
+            }
+        }
+        let is_key = trimmed
+            .split_once('=')
+            .is_some_and(|(name, _)| name.trim() == key);
+        if top_level && is_key {
+            if let (false, Some(line)) = (stored, &line) {
+                lines.push(line.clone());
+                stored = true;
+            }
+            continue;
+        }
+        lines.push(current.to_string());
+    }
+    if let (false, Some(line)) = (stored, line) {
+        lines.push(line);
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+/// Options which take effect before the config file is read, or which only
+/// make sense on the command line
+const COMMAND_LINE_ONLY: &[&str] = &[
+    "config",
+    "log_level",
+    "log_json",
+    "log_file",
+    "quiet",
+    "once",
+    "json",
+    "status",
+];
+
+/// A top level key of the config file, which sets the option of the same name
+#[derive(Clone, Debug)]
+pub struct Key {
+    pub name: String,
+    pub help: String,
+    pub kind: KeyKind,
+    /// `None` for options which aren't set by default
+    pub default: Option<toml::Value>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyKind {
+    Flag,
+    Value,
+    List,
+}
+
+/// Where the effective value of a key comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    CommandLine,
+    Environment,
+    File,
+    Default,
+}
+
+/// The keys of the config file, which are the options of `command` apart
+/// from the command line only ones
+pub fn schema(command: &Command) -> Vec<Key> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !COMMAND_LINE_ONLY.contains(&arg.get_id().as_str()))
+        .filter_map(|arg| {
+            let kind = match arg.get_action() {
+                ArgAction::SetTrue => KeyKind::Flag,
+                ArgAction::Set => KeyKind::Value,
+                ArgAction::Append => KeyKind::List,
+                _ => return None,
+            };
+            let name = arg.get_id().to_string();
+            let defaults: Vec<String> = arg
+                .get_default_values()
+                .iter()
+                .map(|value| value.to_string_lossy().into_owned())
+                .collect();
+            let default = match kind {
+                KeyKind::Flag => Some(toml::Value::Boolean(false)),
+                _ if defaults.is_empty() => None,
+                kind => Some(typed(&name, kind, &defaults)),
+            };
+            Some(Key {
+                help: arg.get_help().map(ToString::to_string).unwrap_or_default(),
+                name,
+                kind,
+                default,
+            })
+        })
+        .collect()
+}
+
+/// The option's values as the TOML value `key` takes: numbers and booleans
+/// where the key accepts them, strings otherwise
+fn typed(key: &str, kind: KeyKind, values: &[String]) -> toml::Value {
+    let wrap = |mut values: Vec<toml::Value>| match kind {
+        KeyKind::List => toml::Value::Array(values),
+        _ => values.swap_remove(0),
+    };
+    let strings = || wrap(values.iter().cloned().map(toml::Value::String).collect());
+    let parsed = values
+        .iter()
+        .map(|value| {
+            format!("value = {value}")
+                .parse::<toml::Table>()
+                .ok()
+                .and_then(|mut table| table.remove("value"))
+        })
+        .collect::<Option<Vec<_>>>();
+    let Some(parsed) = parsed.map(wrap) else {
+        return strings();
+    };
+
+    let table = toml::Table::from_iter([(key.to_string(), parsed.clone())]);
+    match toml::Value::Table(table).try_into::<Config>() {
+        Ok(_) => parsed,
+        Err(_) => strings(),
+    }
+}
+
+/// The effective value of every key and where it comes from: the command
+/// line, then the environment, then the config file's `table`, then the
+/// default. The values in `merged` are the ones volta runs with after
+/// merging, e.g. the addresses with the `prefix` inserted, and replace the
+/// resolved ones.
+pub fn resolve(
+    keys: &[Key],
+    matches: &ArgMatches,
+    table: &toml::Table,
+    merged: &BTreeMap<&str, toml::Value>,
+) -> Vec<(Key, Option<toml::Value>, Source)> {
+    keys.iter()
+        .map(|key| {
+            let source = match matches.value_source(&key.name) {
+                Some(ValueSource::CommandLine) => Some(Source::CommandLine),
+                Some(ValueSource::EnvVariable) => Some(Source::Environment),
+                _ => None,
+            };
+            let (value, source) = match (source, table.get(&key.name)) {
+                (Some(source), _) if key.kind == KeyKind::Flag => (
+                    Some(toml::Value::Boolean(matches.get_flag(&key.name))),
+                    source,
+                ),
+                (Some(source), _) => {
+                    let values: Vec<String> = matches
+                        .get_raw(&key.name)
+                        .into_iter()
+                        .flatten()
+                        .map(|value| value.to_string_lossy().into_owned())
+                        .collect();
+                    (Some(typed(&key.name, key.kind, &values)), source)
+                }
+                (None, Some(value)) => (Some(value.clone()), Source::File),
+                (None, None) => (key.default.clone(), Source::Default),
+            };
+            let value = merged.get(key.name.as_str()).cloned().or(value);
+            (key.clone(), value, source)
+        })
+        .collect()
+}
+
+/// The config file as a table, empty without one, for showing the values
+/// as they were written
+pub fn load_table(path: Option<&Path>) -> Result<toml::Table> {
+    let Some(path) = find(path) else {
+        return Ok(toml::Table::new());
+    };
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file {path:?}"))?;
+    contents
+        .parse()
+        .with_context(|| format!("Failed to parse config file {path:?}"))
+}
+
+/// A config file with every key commented out at its default, described by
+/// the help of its option
+pub fn annotated(keys: &[Key]) -> String {
+    let mut contents = String::from(
+        "# volta config, every key sets the option of the same name, e.g. `adb_path` sets\n\
+         # `--adb-path`. Options passed on the command line or in `VOLTA_` environment\n\
+         # variables take precedence. Uncomment a key to change it from its default.\n",
+    );
+    for key in keys {
+        contents.push('\n');
+        for line in key.help.lines() {
+            contents.push_str(&format!("# {line}\n"));
+        }
+        match &key.default {
+            Some(default) => contents.push_str(&format!("# {} = {default}\n", key.name)),
+            None => contents.push_str(&format!("# {} =\n", key.name)),
+        }
+    }
+    contents.push_str(
+        "\n# Poll several headsets, with a `[[device]]` entry each which replaces\n\
+         # `serial`, `adb_connect`, `prefix`, `device` and the thresholds above\n\
+         # [[device]]\n\
+         # serial = \"1WMHH0000\"\n\
+         # prefix = \"Mine\"\n\
+         \n# Send to other parameter addresses while an avatar is worn\n\
+         # [avatar.avtr_00000000-0000-0000-0000-000000000000]\n\
+         # name = \"Fox\"\n\
+         # headset_param = \"/avatar/parameters/FoxBattery\"\n\
+         \n# Read the controllers of another headset with `device = \"profile:focus\"`, running\n\
+         # `command` and capturing each battery in a `level` group, on a scale of 0 to `scale`\n\
+         # [profile.focus]\n\
+         # command = \"dumpsys wvrcontrollerservice\"\n\
+         # left = 'role: LEFT\\s+state: CONNECTED(?:\\s+\\w+: \\S+)*?\\s+batteryPercent: (?P<level>\\d+)'\n\
+         # right = 'role: RIGHT\\s+state: CONNECTED(?:\\s+\\w+: \\S+)*?\\s+batteryPercent: (?P<level>\\d+)'\n\
+         # scale = 100\n",
+    );
+    contents
+}
+
+/// Writes the annotated config to `path`, which has to be new unless `force`
+/// is set
+pub fn init(path: &Path, keys: &[Key], force: bool) -> Result<()> {
+    if !force && path.exists() {
+        bail!("{path:?} already exists, pass --force to overwrite it");
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {dir:?}"))?;
+    }
+    fs::write(path, annotated(keys))
+        .with_context(|| format!("Failed to write config file {path:?}"))
+}
+
+/// The resolved values as a config file, noting the ones which aren't
+/// defaults. The `[[device]]`, `[avatar]` and `[profile]` tables of the
+/// file follow as they are.
+pub fn effective(resolved: &[(Key, Option<toml::Value>, Source)], table: &toml::Table) -> String {
+    let mut contents = String::new();
+    for (key, value, source) in resolved {
+        let line = match value {
+            Some(value) => format!("{} = {value}", key.name),
+            None => format!("# {} isn't set", key.name),
+        };
+        let note = match source {
+            Source::CommandLine => " # command line",
+            Source::Environment => " # environment",
+            Source::File => " # config file",
+            Source::Default => "",
+        };
+        contents.push_str(&format!("{line}{note}\n"));
+    }
+
+    let tables: toml::Table = table
+        .iter()
+        .filter(|(name, value)| {
+            matches!(name.as_str(), "avatar" | "profile")
+                || (name.as_str() == "device" && value.is_array())
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    if !tables.is_empty() {
+        contents.push('\n');
+        contents.push_str(&toml::to_string(&tables).unwrap_or_default());
+    }
+    contents
+}
+
+fn default_paths() -> Vec<PathBuf> {
+    let executable_dir = std::env::current_exe()
+        .ok()
+        .and_then(|executable| executable.parent().map(Path::to_path_buf));
+    let config_dir = dirs::config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")));
+
+    [executable_dir, config_dir]
+        .into_iter()
+        .flatten()
+        .map(|dir| dir.join(FILE_NAME))
+        .collect()
+}
+
+fn read(path: &Path) -> Result<Config> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read config file {path:?}"))?;
+    let config: Config = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse config file {path:?}"))?;
+
+    info!("Loaded config from {path:?}");
+    for key in config.unknown.keys() {
+        warn!("Ignoring unknown config key `{key}` in {path:?}");
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_top_level_values() {
+        let contents = "# Headset\nadb_connect = \"192.168.1.2:5555\"\ninterval = \"30s\"\n";
+
+        assert_eq!(
+            with_value(contents, "adb_connect", Some("192.168.1.50:41235")),
+            "# Headset\nadb_connect = \"192.168.1.50:41235\"\ninterval = \"30s\"\n"
+        );
+        assert_eq!(
+            with_value(contents, "adb_connect", None),
+            "# Headset\ninterval = \"30s\"\n"
+        );
+    }
+
+    #[test]
+    fn reads_the_edited_file() {
+        let path =
+            std::env::temp_dir().join(format!("vrc_volta-{}-watched.toml", std::process::id()));
+        let write = |contents: &str, seconds: u64| {
+            fs::write(&path, contents).unwrap();
+            // Set explicitly, as writes right after each other can share a
+            // modification time
+            let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+
+        write("interval = \"30s\"\n", 1);
+        let mut watcher = Watcher::new(path.clone());
+        assert!(watcher.changed().is_none());
+
+        write("interval = \"10s\"\n", 2);
+        let config = watcher.changed().unwrap().unwrap();
+        assert_eq!(config.interval.as_deref(), Some("10s"));
+        assert!(watcher.changed().is_none());
+
+        write("interval = \n", 3);
+        let changed = watcher.changed();
+        fs::remove_file(&path).unwrap();
+        assert!(changed.unwrap().is_err());
+    }
+
+    #[test]
+    fn reads_device_entries() {
+        let config: Config = toml::from_str(
+            "warn_headset = 30\nwarn_controller = 25\n\n\
+             [[device]]\nserial = \"1WMHH0000\"\nprefix = \"Mine\"\nwarn_headset = 40\n\n\
+             [[device]]\nname = \"guest\"\nadb_connect = \"192.168.1.3:5555\"\nprefix = \"Guest\"\n",
+        )
+        .unwrap();
+        let names: Vec<_> = config.devices().iter().map(DeviceEntry::name).collect();
+        assert_eq!(names, ["1WMHH0000", "guest"]);
+
+        let mine = config.clone().for_device(0).unwrap();
+        assert_eq!(mine.serial.as_deref(), Some("1WMHH0000"));
+        assert_eq!(mine.prefix.as_deref(), Some("Mine"));
+        assert_eq!(
+            (mine.warn_headset, mine.warn_controller),
+            (Some(40), Some(25))
+        );
+        let guest = config.clone().for_device(1).unwrap();
+        assert_eq!(guest.adb_connect.as_deref(), Some("192.168.1.3:5555"));
+        assert_eq!(guest.warn_headset, Some(30));
+        assert!(config.for_device(2).is_err());
+
+        let config: Config = toml::from_str("device = \"quest\"\n").unwrap();
+        assert!(config.devices().is_empty());
+    }
+
+    #[test]
+    fn reads_avatar_entries() {
+        let config: Config = toml::from_str(
+            "headset_param = \"/avatar/parameters/Battery\"\n\n\
+             [avatar.avtr_1234]\nname = \"Fox\"\nheadset_param = \"/avatar/parameters/FoxBattery\"\n",
+        )
+        .unwrap();
+        let avatars = config.avatar.unwrap();
+        let fox = &avatars["avtr_1234"];
+
+        assert_eq!(fox.name.as_deref(), Some("Fox"));
+        assert_eq!(
+            fox.addresses,
+            BTreeMap::from([(
+                String::from("headset_param"),
+                String::from("/avatar/parameters/FoxBattery")
+            )])
+        );
+        assert!(config.unknown.is_empty());
+    }
+
+    #[test]
+    fn types_the_keys_of_the_options() {
+        use clap::Arg;
+
+        let command = Command::new("volta")
+            .arg(Arg::new("config").long("config"))
+            .arg(
+                Arg::new("receiver")
+                    .long("receiver")
+                    .action(ArgAction::Append)
+                    .default_value("127.0.0.1:9000"),
+            )
+            .arg(Arg::new("interval").long("interval").default_value("60s"))
+            .arg(Arg::new("retries").long("retries").default_value("3"))
+            .arg(Arg::new("serial").long("serial"))
+            .arg(Arg::new("fake").long("fake").action(ArgAction::SetTrue));
+        let keys = schema(&command);
+        let defaults: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                (
+                    key.name.as_str(),
+                    key.default.as_ref().map(ToString::to_string),
+                )
+            })
+            .collect();
+        assert_eq!(
+            defaults,
+            [
+                ("receiver", Some(String::from("[\"127.0.0.1:9000\"]"))),
+                ("interval", Some(String::from("\"60s\""))),
+                ("retries", Some(String::from("3"))),
+                ("serial", None),
+                ("fake", Some(String::from("false"))),
+            ]
+        );
+
+        let matches = command.get_matches_from(["volta", "--retries", "5"]);
+        let table: toml::Table = toml::from_str("interval = \"30s\"\nretries = 4\n").unwrap();
+        let resolved = resolve(&keys, &matches, &table, &BTreeMap::new());
+        assert_eq!(
+            effective(&resolved, &table),
+            "receiver = [\"127.0.0.1:9000\"]\n\
+             interval = \"30s\" # config file\n\
+             retries = 5 # command line\n\
+             # serial isn't set\n\
+             fake = false\n"
+        );
+    }
+
+    #[test]
+    fn adds_values_before_tables() {
+        assert_eq!(
+            with_value(
+                "interval = \"30s\"\n\n[table]\nadb_connect = 1\n",
+                "adb_connect",
+                Some("a:1")
+            ),
+            "interval = \"30s\"\n\nadb_connect = \"a:1\"\n[table]\nadb_connect = 1\n"
+        );
+        assert_eq!(
+            with_value("", "adb_connect", Some("a:1")),
+            "adb_connect = \"a:1\"\n"
+        );
+    }
+}
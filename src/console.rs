@@ -0,0 +1,212 @@
+use crate::sink::{BatteryReport, Sink};
+use crate::source;
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use std::io::{self, IsTerminal, Write};
+
+/// Characters in a level's bar
+const BAR_WIDTH: usize = 10;
+
+/// Color of a level by the thresholds it dropped to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Color {
+    Green,
+    Yellow,
+    Red,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Color::Green => "32",
+            Color::Yellow => "33",
+            Color::Red => "31",
+        }
+    }
+}
+
+/// Shows the latest levels as a block in the terminal which is redrawn in
+/// place every cycle. When stdout isn't a terminal, every cycle prints a new
+/// block without colors instead.
+pub struct Console {
+    model: String,
+    /// Low and critical percentages of the headset and of the controllers
+    headset: (u8, u8),
+    controller: (u8, u8),
+    terminal: bool,
+    color: bool,
+    /// Lines of the block drawn last, which the next one replaces
+    lines: usize,
+}
+
+impl Console {
+    pub fn new(model: String, headset: (u8, u8), controller: (u8, u8)) -> Self {
+        let terminal = io::stdout().is_terminal();
+        Self {
+            model,
+            headset,
+            controller,
+            terminal,
+            color: terminal && std::env::var_os("NO_COLOR").is_none(),
+            lines: 0,
+        }
+    }
+
+    fn render(&self, report: &BatteryReport, stale: bool) -> Vec<String> {
+        let levels = &report.levels;
+        let state = match stale {
+            false => self.paint(Color::Green, "connected"),
+            true => self.paint(
+                Color::Red,
+                &format!(
+                    "reading failed, levels from {} min ago",
+                    report.age_minutes()
+                ),
+            ),
+        };
+        let time = DateTime::<Local>::from(report.timestamp).format("%H:%M:%S");
+
+        let mut rows = vec![
+            (
+                String::from("Headset"),
+                levels.headset,
+                levels.headset_charging,
+                self.headset,
+            ),
+            (
+                String::from("Left"),
+                levels.left_controller,
+                false,
+                self.controller,
+            ),
+            (
+                String::from("Right"),
+                levels.right_controller,
+                false,
+                self.controller,
+            ),
+        ];
+        for (name, level) in &levels.trackers {
+            rows.push((name.clone(), Some(*level), false, self.controller));
+        }
+        for device in &levels.steamvr {
+            rows.push((
+                device.name.clone(),
+                Some(device.level),
+                device.charging,
+                self.controller,
+            ));
+        }
+
+        let width = rows.iter().map(|(name, ..)| name.len()).max().unwrap_or(0);
+        let mut lines = vec![format!("{} · {state} · updated {time}", self.model)];
+        lines.extend(rows.into_iter().map(|(name, level, charging, thresholds)| {
+            format!(
+                "{name:<width$}  {}",
+                self.level(level, charging, thresholds)
+            )
+        }));
+        lines
+    }
+
+    /// Renders a level as a bar and a percentage colored by its thresholds
+    fn level(&self, level: Option<f32>, charging: bool, (low, critical): (u8, u8)) -> String {
+        let Some(level) = level else {
+            return String::from("disconnected");
+        };
+        let percent = source::percent(level);
+        let color = match percent {
+            percent if percent <= i32::from(critical) => Color::Red,
+            percent if percent <= i32::from(low) => Color::Yellow,
+            _ => Color::Green,
+        };
+        let filled = (percent.clamp(0, 100) as usize * BAR_WIDTH) / 100;
+        let bar = format!(
+            "{}{} {percent:>3}%",
+            "█".repeat(filled),
+            "░".repeat(BAR_WIDTH - filled)
+        );
+        let charging = if charging { " ⚡" } else { "" };
+        format!("{}{charging}", self.paint(color, &bar))
+    }
+
+    fn paint(&self, color: Color, text: &str) -> String {
+        match self.color {
+            true => format!("\x1b[{}m{text}\x1b[0m", color.code()),
+            false => text.to_string(),
+        }
+    }
+
+    fn draw(&mut self, lines: Vec<String>) -> Result<()> {
+        let mut stdout = io::stdout().lock();
+        if self.terminal && self.lines > 0 {
+            // Back to the start of the previous block, which is cleared
+            write!(stdout, "\x1b[{}F\x1b[J", self.lines)?;
+        }
+        for line in &lines {
+            writeln!(stdout, "{line}")?;
+        }
+        if !self.terminal {
+            writeln!(stdout)?;
+        }
+        stdout.flush()?;
+        self.lines = lines.len();
+        Ok(())
+    }
+}
+
+impl Sink for Console {
+    fn name(&self) -> &'static str {
+        "the status display"
+    }
+
+    fn publish(&mut self, report: &BatteryReport) -> Result<()> {
+        self.draw(self.render(report, false))
+    }
+
+    /// Keeps the last levels, marked as failing to read with their age
+    fn publish_stale(&mut self, report: &BatteryReport) -> Result<()> {
+        self.draw(self.render(report, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::fixture;
+
+    fn console(color: bool) -> Console {
+        Console {
+            model: String::from("A8110"),
+            headset: (20, 10),
+            controller: (50, 10),
+            terminal: false,
+            color,
+            lines: 0,
+        }
+    }
+
+    #[test]
+    fn renders_the_levels() {
+        let lines = console(false).render(&fixture::report(), false);
+
+        assert!(lines[0].starts_with("A8110 · connected · updated "));
+        assert_eq!(
+            lines[1..],
+            [
+                "Headset  ████████░░  85% ⚡",
+                "Left     disconnected",
+                "Right    ████░░░░░░  40%",
+            ]
+        );
+    }
+
+    #[test]
+    fn colors_by_threshold() {
+        let lines = console(true).render(&fixture::report(), true);
+
+        assert!(lines[0].contains("\x1b[31mreading failed, levels from 0 min ago\x1b[0m"));
+        assert!(lines[1].contains("\x1b[32m████████░░  85%\x1b[0m"));
+        assert!(lines[3].contains("\x1b[33m████░░░░░░  40%\x1b[0m"));
+    }
+}
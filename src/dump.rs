@@ -0,0 +1,90 @@
+use crate::adb::AdbRunner;
+use crate::error::VoltaError;
+use crate::{parse, source};
+use anyhow::{Context, Result};
+use std::fmt::{Debug, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Writes the raw `dumpsys` output volta parses, together with what it parsed
+/// from it, to a timestamped file in `out` which can be attached to bug
+/// reports. Returns the path of the file.
+pub fn write(adb: &impl AdbRunner, out: &Path) -> Result<PathBuf> {
+    let battery = source::get_battery_dump(adb);
+    let controllers = source::get_controller_service_dump(adb);
+
+    fs::create_dir_all(out).with_context(|| format!("Failed to create {out:?}"))?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let path = out.join(format!("volta-dump-{timestamp}.txt"));
+    fs::write(&path, report(&battery, &controllers))
+        .with_context(|| format!("Failed to write {path:?}"))?;
+
+    Ok(path)
+}
+
+fn report(
+    battery: &Result<String, VoltaError>,
+    controllers: &Result<String, VoltaError>,
+) -> String {
+    let mut report = format!("{} {}\n", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+    for (command, dump) in [
+        ("dumpsys battery", battery),
+        ("dumpsys pxrcontrollerservice", controllers),
+    ] {
+        let _ = writeln!(report, "\n===== {command} =====");
+        match dump {
+            Ok(dump) => report.push_str(dump),
+            Err(error) => {
+                let _ = writeln!(report, "Failed: {error:#}");
+            }
+        }
+    }
+
+    report.push_str("\n===== parsed =====\n");
+    match battery {
+        Ok(battery) => {
+            line(
+                &mut report,
+                "Headset level",
+                parse::parse_headset_level(battery),
+            );
+            let _ = writeln!(
+                report,
+                "Headset charging: {}",
+                parse::parse_headset_charging(battery)
+            );
+            let _ = writeln!(
+                report,
+                "Headset temperature: {:?}",
+                parse::parse_headset_temperature(battery)
+            );
+            let _ = writeln!(
+                report,
+                "Headset voltage: {:?}",
+                parse::parse_headset_voltage(battery)
+            );
+        }
+        Err(_) => report.push_str("Headset: no dump\n"),
+    }
+    match controllers {
+        Ok(controllers) => {
+            line(
+                &mut report,
+                "Controllers",
+                Ok(parse::parse_controllers(controllers)),
+            );
+        }
+        Err(_) => report.push_str("Controllers: no dump\n"),
+    }
+
+    report
+}
+
+fn line(report: &mut String, name: &str, result: Result<impl Debug, VoltaError>) {
+    let _ = match result {
+        Ok(value) => writeln!(report, "{name}: {value:?}"),
+        Err(error) => writeln!(report, "{name}: failed: {error:#}"),
+    };
+}
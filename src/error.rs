@@ -0,0 +1,290 @@
+use crate::adb_host::Unavailable;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Why reading or sending the levels failed. The adb layer, the sources and
+/// the sending return it, describing what volta was doing with
+/// [`VoltaError::Read`], and [`kind`] finds the root cause in an
+/// `anyhow::Error` further up.
+#[derive(Debug, Error)]
+pub enum VoltaError {
+    /// The adb executable at this path doesn't exist
+    #[error(
+        "adb wasn't found at `{0}`, install the Android SDK platform tools from \
+         https://developer.android.com/tools/releases/platform-tools and add them to your PATH, \
+         or pass the path of adb with --adb-path"
+    )]
+    AdbNotFound(String),
+    /// adb lists no device, or not the one with this serial
+    #[error("{}", no_device(.0.as_deref()))]
+    NoDevice(Option<String>),
+    /// The headset hasn't accepted this computer's debugging key
+    #[error(
+        "The headset {0} hasn't authorized this computer, put it on and accept the USB debugging prompt"
+    )]
+    DeviceUnauthorized(String),
+    #[error("The headset {0} is offline, reconnect it or restart adb with `adb kill-server`")]
+    DeviceOffline(String),
+    /// An adb command hung and was killed, usually because the adb server
+    /// wedged
+    #[error("adb {command} timed out after {timeout:?}")]
+    AdbTimeout { command: String, timeout: Duration },
+    /// adb ran the command and it failed, with what it printed
+    #[error("adb {command} failed: {message}")]
+    AdbFailed { command: String, message: String },
+    /// Running adb or talking to its server failed
+    #[error("{action}")]
+    AdbIo {
+        action: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    /// The adb server can't be talked to directly
+    #[error(transparent)]
+    AdbUnavailable(#[from] Unavailable),
+    /// adb returned no output at all for a dump, which happens when the
+    /// connection dropped without adb noticing
+    #[error("adb returned no output for dumpsys {0}")]
+    DumpEmpty(&'static str),
+    /// `dumpsys battery` has no headset level, or one which isn't a number
+    #[error("{}", unparsable("headset", .raw))]
+    ParseHeadset { raw: String },
+    /// The controller dump lists a battery which isn't a number
+    #[error("{}", unparsable(.side, .raw))]
+    ParseController { side: String, raw: String },
+    /// A battery reading beyond the scale it's read on
+    #[error("The {name} reported `{reading}`, beyond its scale of 0 to {scale}, skipping it")]
+    OutOfScale {
+        name: String,
+        reading: u8,
+        scale: u8,
+    },
+    /// The headset answered, but not with anything volta can use
+    #[error("{0}")]
+    Unreadable(String),
+    /// SteamVR failed to list its devices
+    #[error("{0}")]
+    SteamVr(String),
+    #[error("Failed to encode the {0}")]
+    EncodeFailed(String),
+    #[error("Failed to send to {addr}")]
+    SendFailed {
+        addr: SocketAddr,
+        #[source]
+        source: io::Error,
+    },
+    /// What volta was reading when `source` failed
+    #[error("{what}")]
+    Read {
+        what: &'static str,
+        #[source]
+        source: Box<VoltaError>,
+    },
+}
+
+impl VoltaError {
+    /// What volta was reading when this failed
+    pub fn reading(self, what: &'static str) -> Self {
+        VoltaError::Read {
+            what,
+            source: Box::new(self),
+        }
+    }
+
+    /// The failure at the bottom of the [`VoltaError::Read`]s
+    pub fn root(&self) -> &Self {
+        match self {
+            VoltaError::Read { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// Exit code of `--once` mode and subcommands when they fail because of
+    /// a problem with adb or the headset
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.root() {
+            VoltaError::AdbNotFound(_) => Some(3),
+            VoltaError::NoDevice(_) => Some(4),
+            VoltaError::DeviceUnauthorized(_) => Some(5),
+            VoltaError::DeviceOffline(_) => Some(6),
+            _ => None,
+        }
+    }
+
+    /// Whether the headset isn't ready, as opposed to adb itself failing
+    pub fn is_device_problem(&self) -> bool {
+        matches!(
+            self.root(),
+            VoltaError::NoDevice(_)
+                | VoltaError::DeviceUnauthorized(_)
+                | VoltaError::DeviceOffline(_)
+        )
+    }
+
+    /// Whether the connection to the headset is probably gone, even though
+    /// adb still lists it
+    pub fn is_connection_lost(&self) -> bool {
+        matches!(
+            self.root(),
+            VoltaError::DumpEmpty(_) | VoltaError::AdbTimeout { .. }
+        )
+    }
+
+    /// Whether trying again right away might succeed, which is pointless
+    /// without adb or a headset, while adb hangs, and for dumps which fail to
+    /// parse the same way again
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.root(),
+            VoltaError::AdbFailed { .. }
+                | VoltaError::AdbIo { .. }
+                | VoltaError::DumpEmpty(_)
+                | VoltaError::SendFailed { .. }
+        )
+    }
+
+    /// Name of the kind of failure, for structured logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            VoltaError::AdbNotFound(_) => "adb_not_found",
+            VoltaError::NoDevice(_) => "no_device",
+            VoltaError::DeviceUnauthorized(_) => "device_unauthorized",
+            VoltaError::DeviceOffline(_) => "device_offline",
+            VoltaError::AdbTimeout { .. } => "adb_timeout",
+            VoltaError::AdbFailed { .. } => "adb_failed",
+            VoltaError::AdbIo { .. } => "adb_io",
+            VoltaError::AdbUnavailable(_) => "adb_unavailable",
+            VoltaError::DumpEmpty(_) => "dump_empty",
+            VoltaError::ParseHeadset { .. } => "parse_headset",
+            VoltaError::ParseController { .. } => "parse_controller",
+            VoltaError::OutOfScale { .. } => "out_of_scale",
+            VoltaError::Unreadable(_) => "unreadable",
+            VoltaError::SteamVr(_) => "steamvr",
+            VoltaError::EncodeFailed(_) => "encode_failed",
+            VoltaError::SendFailed { .. } => "send_failed",
+            VoltaError::Read { source, .. } => source.name(),
+        }
+    }
+}
+
+/// The failure which caused `error`, if volta knows its kind
+pub fn kind(error: &anyhow::Error) -> Option<&VoltaError> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<VoltaError>())
+        .map(VoltaError::root)
+}
+
+fn no_device(serial: Option<&str>) -> String {
+    match serial {
+        None => String::from(
+            "No headset found, connect it over USB and enable developer mode and USB debugging on it",
+        ),
+        Some(serial) => format!(
+            "The headset {serial} isn't connected, connect it over USB and enable developer mode \
+             and USB debugging on it"
+        ),
+    }
+}
+
+fn unparsable(device: &str, raw: &str) -> String {
+    match raw {
+        "" => format!("Failed to find the {device} battery level"),
+        raw => format!("Failed to parse the {device} battery level `{raw}`"),
+    }
+}
+
+/// Cycles between the summaries of a failure which repeats every cycle
+pub const SUMMARY_CYCLES: u64 = 10;
+
+/// What to log for a failed cycle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Repeat {
+    /// The failure is of another kind than the previous cycle's, or follows
+    /// a success
+    First,
+    /// The same kind of failure as the previous cycle
+    Again,
+    /// The same kind again, which has now failed this many cycles in a row
+    Summary(u64),
+}
+
+/// Follows the failures in a row, so a failure repeating every cycle is
+/// logged once and then summed up every [`SUMMARY_CYCLES`] cycles. Failures
+/// of the same kind are the same, even if their details like a timestamp in
+/// adb's output differ.
+#[derive(Debug, Default)]
+pub struct FailureStreak {
+    kind: Option<&'static str>,
+    cycles: u64,
+}
+
+impl FailureStreak {
+    /// Counts a failed cycle
+    pub fn failed(&mut self, error: &VoltaError) -> Repeat {
+        let kind = error.name();
+        if self.kind != Some(kind) {
+            self.kind = Some(kind);
+            self.cycles = 1;
+            return Repeat::First;
+        }
+        self.cycles += 1;
+        match self.cycles % SUMMARY_CYCLES {
+            0 => Repeat::Summary(self.cycles),
+            _ => Repeat::Again,
+        }
+    }
+
+    /// Ends the streak, returning how many cycles failed in a row before it
+    pub fn succeeded(&mut self) -> Option<u64> {
+        self.kind = None;
+        match std::mem::take(&mut self.cycles) {
+            0 => None,
+            cycles => Some(cycles),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_up_repeated_failures() {
+        let mut streak = FailureStreak::default();
+        let adb = |cycle: u64| VoltaError::AdbFailed {
+            command: String::from("shell dumpsys battery"),
+            message: format!("error at cycle {cycle}"),
+        };
+        let parse = || VoltaError::ParseHeadset { raw: String::new() };
+        assert_eq!(streak.failed(&adb(0)), Repeat::First);
+        // The same kind, even with other details
+        let repeats: Vec<_> = (1..=SUMMARY_CYCLES)
+            .map(|cycle| streak.failed(&adb(cycle).reading("Failed to get headset battery")))
+            .collect();
+        assert_eq!(repeats[0], Repeat::Again);
+        assert_eq!(repeats[8], Repeat::Summary(SUMMARY_CYCLES));
+        assert_eq!(repeats[9], Repeat::Again);
+
+        assert_eq!(streak.failed(&parse()), Repeat::First);
+        assert_eq!(streak.succeeded(), Some(1));
+        assert_eq!(streak.succeeded(), None);
+        assert_eq!(streak.failed(&parse()), Repeat::First);
+    }
+
+    #[test]
+    fn finds_the_failure_below_what_was_read() {
+        let error = VoltaError::DumpEmpty("battery")
+            .reading("Failed to get the headset battery")
+            .reading("Failed to read the levels");
+        assert!(matches!(error.root(), VoltaError::DumpEmpty(_)));
+        assert_eq!(error.name(), "dump_empty");
+        assert!(error.is_connection_lost());
+        assert_eq!(
+            format!("{:#}", anyhow::Error::from(error)),
+            "Failed to read the levels: Failed to get the headset battery: adb returned no output for dumpsys battery"
+        );
+    }
+}
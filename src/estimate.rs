@@ -0,0 +1,222 @@
+use crate::source::BatteryLevels;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long the samples have to span before a drain rate is estimated, as
+/// the levels only change by a percent every few minutes
+const MIN_SPAN: Duration = Duration::from_secs(5 * 60);
+/// Percentage points a level has to rise by without charging to count as a
+/// new battery rather than noise
+const RECHARGED: f32 = 10.0;
+
+/// How fast a battery drains and how long it's estimated to last
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    /// Percentage points per hour
+    pub drain: f32,
+    pub minutes: u32,
+}
+
+/// The estimates of the headset and controllers, where a device has none
+/// while it's charging or hasn't been read for long enough
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Estimates {
+    pub headset: Option<Estimate>,
+    pub left_controller: Option<Estimate>,
+    pub right_controller: Option<Estimate>,
+}
+
+/// The levels of one device over the window, as percentages
+#[derive(Default)]
+struct History {
+    samples: VecDeque<(Instant, f32)>,
+}
+
+impl History {
+    fn update(&mut self, now: Instant, percent: Option<f32>, charging: bool, window: Duration) {
+        let Some(percent) = percent.filter(|_| !charging) else {
+            self.samples.clear();
+            return;
+        };
+        while self
+            .samples
+            .front()
+            .is_some_and(|&(time, _)| now.duration_since(time) > window)
+        {
+            self.samples.pop_front();
+        }
+        if let Some(&(_, last)) = self.samples.back() {
+            if percent >= last + RECHARGED {
+                self.samples.clear();
+            } else if percent > last {
+                // A reading bouncing back up isn't the battery charging
+                return;
+            }
+        }
+
+        self.samples.push_back((now, percent));
+    }
+
+    /// The least squares slope through the samples, which smooths out the
+    /// steps of whole percentages
+    fn estimate(&self) -> Option<Estimate> {
+        let (&(first, _), &(last, percent)) = (self.samples.front()?, self.samples.back()?);
+        if last.duration_since(first) < MIN_SPAN {
+            return None;
+        }
+
+        let hours = |time: Instant| time.duration_since(first).as_secs_f32() / 3600.0;
+        let count = self.samples.len() as f32;
+        let mean_time = self
+            .samples
+            .iter()
+            .map(|&(time, _)| hours(time))
+            .sum::<f32>()
+            / count;
+        let mean_level = self.samples.iter().map(|&(_, level)| level).sum::<f32>() / count;
+        let (covariance, variance) =
+            self.samples
+                .iter()
+                .fold((0.0, 0.0), |(cov, var), &(time, level)| {
+                    let time = hours(time) - mean_time;
+                    (cov + time * (level - mean_level), var + time * time)
+                });
+        let drain = -covariance / variance;
+        if !drain.is_finite() || drain <= 0.0 {
+            return None;
+        }
+
+        Some(Estimate {
+            drain,
+            minutes: (percent / drain * 60.0).round() as u32,
+        })
+    }
+}
+
+/// Estimates how long the batteries last from their levels over a rolling
+/// window
+pub struct Estimator {
+    window: Duration,
+    headset: History,
+    left_controller: History,
+    right_controller: History,
+}
+
+impl Estimator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            headset: History::default(),
+            left_controller: History::default(),
+            right_controller: History::default(),
+        }
+    }
+
+    pub fn update(&mut self, levels: &BatteryLevels) -> Estimates {
+        self.update_at(Instant::now(), levels)
+    }
+
+    fn update_at(&mut self, now: Instant, levels: &BatteryLevels) -> Estimates {
+        let percent = |level: f32| level * 100.0;
+        self.headset.update(
+            now,
+            levels.headset.map(percent),
+            levels.headset_charging,
+            self.window,
+        );
+        self.left_controller
+            .update(now, levels.left_controller.map(percent), false, self.window);
+        self.right_controller.update(
+            now,
+            levels.right_controller.map(percent),
+            false,
+            self.window,
+        );
+
+        Estimates {
+            headset: self.headset.estimate(),
+            left_controller: self.left_controller.estimate(),
+            right_controller: self.right_controller.estimate(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(headset: f32, charging: bool) -> BatteryLevels {
+        BatteryLevels {
+            headset: Some(headset),
+            left_controller: Some(headset),
+            headset_charging: charging,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn estimates_minutes_remaining() {
+        let start = Instant::now();
+        let mut estimator = Estimator::new(Duration::from_secs(30 * 60));
+        let at = |minutes: u64| start + Duration::from_secs(minutes * 60);
+
+        // 1% every 2 minutes is 30% per hour
+        assert_eq!(
+            estimator.update_at(at(0), &levels(0.60, false)).headset,
+            None
+        );
+        estimator.update_at(at(2), &levels(0.59, false));
+        estimator.update_at(at(4), &levels(0.60, false));
+        let estimates = estimator.update_at(at(6), &levels(0.57, false));
+
+        let headset = estimates.headset.unwrap();
+        assert!((headset.drain - 30.0).abs() < 0.1, "{headset:?}");
+        assert_eq!(headset.minutes, 114);
+        assert_eq!(
+            estimates.left_controller.map(|estimate| estimate.minutes),
+            Some(114)
+        );
+        assert_eq!(estimates.right_controller, None);
+    }
+
+    #[test]
+    fn forgets_samples_beyond_the_window_while_bouncing() {
+        let start = Instant::now();
+        let mut estimator = Estimator::new(Duration::from_secs(10 * 60));
+        let at = |minutes: u64| start + Duration::from_secs(minutes * 60);
+
+        estimator.update_at(at(0), &levels(0.60, false));
+        estimator.update_at(at(4), &levels(0.59, false));
+        assert!(estimator
+            .update_at(at(8), &levels(0.58, false))
+            .headset
+            .is_some());
+        // Bouncing back up long after, the old drain no longer holds
+        assert_eq!(
+            estimator.update_at(at(30), &levels(0.59, false)).headset,
+            None
+        );
+        assert_eq!(
+            estimator.update_at(at(34), &levels(0.59, false)).headset,
+            None
+        );
+    }
+
+    #[test]
+    fn charging_resets_the_history() {
+        let start = Instant::now();
+        let mut estimator = Estimator::new(Duration::from_secs(30 * 60));
+        let at = |minutes: u64| start + Duration::from_secs(minutes * 60);
+
+        estimator.update_at(at(0), &levels(0.60, false));
+        estimator.update_at(at(6), &levels(0.57, false));
+        assert_eq!(
+            estimator.update_at(at(8), &levels(0.58, true)).headset,
+            None
+        );
+        assert_eq!(
+            estimator.update_at(at(10), &levels(0.58, false)).headset,
+            None
+        );
+    }
+}
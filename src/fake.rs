@@ -0,0 +1,83 @@
+use crate::error::VoltaError;
+use crate::source::{BatteryLevels, BatterySource};
+use std::time::{Duration, Instant};
+
+/// Simulated battery levels for testing avatars without a headset
+pub enum FakeSource {
+    /// Drains every battery from 100% to 0% over the duration, then charges
+    /// them back up over the same duration
+    Sweep { start: Instant, duration: Duration },
+    /// Always reports the same levels
+    Fixed(BatteryLevels),
+}
+
+impl FakeSource {
+    pub fn sweep(duration: Duration) -> Self {
+        Self::Sweep {
+            start: Instant::now(),
+            duration,
+        }
+    }
+}
+
+impl BatterySource for FakeSource {
+    fn get_levels(&self) -> Result<BatteryLevels, VoltaError> {
+        let (start, duration) = match self {
+            Self::Sweep { start, duration } => (start, duration),
+            Self::Fixed(levels) => return Ok(levels.clone()),
+        };
+
+        let progress = start.elapsed().as_secs_f32() / duration.as_secs_f32();
+        let charging = progress % 2.0 >= 1.0;
+        let level = match charging {
+            true => progress % 1.0,
+            false => 1.0 - progress % 1.0,
+        };
+
+        Ok(BatteryLevels {
+            headset: Some(level),
+            left_controller: Some(level),
+            right_controller: Some(level),
+            headset_charging: charging,
+            headset_temperature: None,
+            headset_voltage: None,
+            headset_health: None,
+            headset_cycles: None,
+            headset_awake: None,
+            trackers: Vec::new(),
+            steamvr: Vec::new(),
+        })
+    }
+}
+
+/// Parses fixed levels given as `headset,left,right`, e.g. `0.8,0.6,0.4`,
+/// where a controller given as `-` is disconnected
+pub fn parse_values(value: &str) -> Result<BatteryLevels, String> {
+    let level = |level: &str| match level.trim().parse::<f32>() {
+        Ok(level) if (0.0..=1.0).contains(&level) => Ok(level),
+        _ => Err(format!("`{level}` is not a battery level between 0 and 1")),
+    };
+    let controller = |controller: &str| match controller.trim() {
+        "-" => Ok(None),
+        controller => level(controller).map(Some),
+    };
+
+    match value.split(',').collect::<Vec<_>>().as_slice() {
+        [headset, left, right] => Ok(BatteryLevels {
+            headset: Some(level(headset)?),
+            left_controller: controller(left)?,
+            right_controller: controller(right)?,
+            headset_charging: false,
+            headset_temperature: None,
+            headset_voltage: None,
+            headset_health: None,
+            headset_cycles: None,
+            headset_awake: None,
+            trackers: Vec::new(),
+            steamvr: Vec::new(),
+        }),
+        _ => Err(format!(
+            "`{value}` is not three levels, expected e.g. `0.8,0.6,0.4`"
+        )),
+    }
+}
@@ -0,0 +1,229 @@
+use crate::sink::{BatteryReport, Sink};
+use crate::{parse, source};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate, SecondsFormat, Utc};
+use serde_json::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+const CSV_HEADER: &str = "timestamp,headset,left_controller,right_controller,headset_charging,left_connected,right_connected,headset_health,headset_cycle_count";
+
+/// Appends every reading to a file for analyzing how fast the batteries
+/// drain. Files ending in `.jsonl` get a JSON object per line, any other file
+/// a CSV row with a header.
+pub struct History {
+    path: PathBuf,
+    /// Start a file named after the day for every day
+    rotate_daily: bool,
+    /// The CSV file whose header was checked last
+    checked: Option<PathBuf>,
+}
+
+impl History {
+    pub fn new(path: PathBuf, rotate_daily: bool) -> Self {
+        Self {
+            path,
+            rotate_daily,
+            checked: None,
+        }
+    }
+
+    /// The file to append to on `date`, which has the date before its
+    /// extension when rotating daily, e.g. `battery-2024-05-01.csv`
+    fn path_on(&self, date: NaiveDate) -> PathBuf {
+        if !self.rotate_daily {
+            return self.path.clone();
+        }
+
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let name = match self.path.extension() {
+            Some(extension) => format!("{stem}-{date}.{}", extension.to_string_lossy()),
+            None => format!("{stem}-{date}"),
+        };
+        self.path.with_file_name(name)
+    }
+
+    /// Moves a CSV file aside whose header isn't [`CSV_HEADER`], e.g. one of
+    /// an older volta with fewer columns, so the rows appended now don't end
+    /// up under the wrong header. It gets the first free number before its
+    /// extension, e.g. `battery.1.csv`.
+    fn rotate_outdated(path: &Path) -> Result<()> {
+        let header = match File::open(path) {
+            Ok(file) => BufReader::new(file).lines().next().transpose(),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => Err(error),
+        }
+        .with_context(|| format!("Failed to read {path:?}"))?;
+        if header.is_none_or(|header| header == CSV_HEADER) {
+            return Ok(());
+        }
+
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extension = path
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+        let rotated = (1..)
+            .map(|number| path.with_file_name(format!("{stem}.{number}{extension}")))
+            .find(|rotated| !rotated.exists())
+            .expect("some number is free");
+        info!("{path:?} has other columns, moving it to {rotated:?}");
+        fs::rename(path, &rotated).with_context(|| format!("Failed to move {path:?} aside"))
+    }
+
+    fn is_json(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("jsonl"))
+    }
+
+    fn record(report: &BatteryReport, json: bool) -> String {
+        let levels = &report.levels;
+        let timestamp =
+            DateTime::<Utc>::from(report.timestamp).to_rfc3339_opts(SecondsFormat::Secs, true);
+        let percent = |level: Option<f32>| level.map(source::percent);
+        let health = levels.headset_health.and_then(parse::health_name);
+
+        if json {
+            return json!({
+                "timestamp": timestamp,
+                "headset": percent(levels.headset),
+                "left_controller": percent(levels.left_controller),
+                "right_controller": percent(levels.right_controller),
+                "headset_charging": levels.headset_charging,
+                "left_connected": levels.left_controller.is_some(),
+                "right_connected": levels.right_controller.is_some(),
+                "headset_health": health,
+                "headset_cycle_count": levels.headset_cycles,
+            })
+            .to_string();
+        }
+
+        let cell = |level: Option<f32>| percent(level).map_or(String::new(), |p| p.to_string());
+        format!(
+            "{timestamp},{},{},{},{},{},{},{},{}",
+            cell(levels.headset),
+            cell(levels.left_controller),
+            cell(levels.right_controller),
+            levels.headset_charging,
+            levels.left_controller.is_some(),
+            levels.right_controller.is_some(),
+            health.unwrap_or_default(),
+            levels
+                .headset_cycles
+                .map_or(String::new(), |cycles| cycles.to_string()),
+        )
+    }
+}
+
+impl Sink for History {
+    fn name(&self) -> &'static str {
+        "the history"
+    }
+
+    fn publish(&mut self, report: &BatteryReport) -> Result<()> {
+        let path = self.path_on(DateTime::<Local>::from(report.timestamp).date_naive());
+        let json = Self::is_json(&path);
+        if !json && self.checked.as_ref() != Some(&path) {
+            Self::rotate_outdated(&path)?;
+            self.checked = Some(path.clone());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {path:?}"))?;
+
+        let mut lines = String::new();
+        if !json && file.metadata()?.len() == 0 {
+            lines.push_str(CSV_HEADER);
+            lines.push('\n');
+        }
+        lines.push_str(&Self::record(report, json));
+        lines.push('\n');
+        // A single write, so a failure doesn't leave half a header behind
+        file.write_all(lines.as_bytes())
+            .with_context(|| format!("Failed to append to {path:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::fixture;
+    use serde_json::Value;
+    use std::fs;
+
+    fn history(name: &str) -> History {
+        let path = std::env::temp_dir().join(format!("vrc_volta-{}-{name}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        History::new(path, false)
+    }
+
+    #[test]
+    fn appends_csv_rows_after_a_header() {
+        let mut history = history("history.csv");
+        history.publish(&fixture::report()).unwrap();
+        history.publish(&fixture::report()).unwrap();
+
+        let contents = fs::read_to_string(&history.path).unwrap();
+        fs::remove_file(&history.path).unwrap();
+        let row = "2023-11-14T22:13:20Z,85,,40,true,false,true,good,";
+        assert_eq!(contents, format!("{CSV_HEADER}\n{row}\n{row}\n"));
+    }
+
+    #[test]
+    fn moves_a_file_with_other_columns_aside() {
+        let mut history = history("history-old.csv");
+        let old = "timestamp,headset,left_controller,right_controller,headset_charging,left_connected,right_connected\n\
+                   2023-11-14T22:13:20Z,85,,40,true,false,true\n";
+        fs::write(&history.path, old).unwrap();
+        let rotated = history.path.with_file_name(format!(
+            "vrc_volta-{}-history-old.1.csv",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&rotated);
+        history.publish(&fixture::report()).unwrap();
+        history.publish(&fixture::report()).unwrap();
+
+        let contents = fs::read_to_string(&history.path).unwrap();
+        let moved = fs::read_to_string(&rotated).unwrap();
+        fs::remove_file(&history.path).unwrap();
+        fs::remove_file(&rotated).unwrap();
+        assert_eq!(contents.lines().next(), Some(CSV_HEADER));
+        assert_eq!(contents.lines().count(), 3);
+        assert_eq!(moved, old);
+    }
+
+    #[test]
+    fn appends_json_lines() {
+        let mut history = history("history.jsonl");
+        history.publish(&fixture::report()).unwrap();
+
+        let contents = fs::read_to_string(&history.path).unwrap();
+        fs::remove_file(&history.path).unwrap();
+        let record: Value = serde_json::from_str(contents.trim_end()).unwrap();
+        assert_eq!(record["headset"], 85);
+        assert_eq!(record["left_controller"], Value::Null);
+        assert_eq!(record["right_connected"], true);
+    }
+
+    #[test]
+    fn names_daily_files_after_the_date() {
+        let history = History::new(PathBuf::from("logs/battery.csv"), true);
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+
+        assert_eq!(
+            history.path_on(date),
+            PathBuf::from("logs/battery-2024-05-01.csv")
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use crate::info::DeviceInfo;
+use crate::listener::Event;
+use crate::metrics::Metrics;
+use crate::osc::ReceiverStatus;
+use crate::source::BatteryLevels;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What the polling loop last saw, shared with the HTTP server
+pub struct Status {
+    levels: Option<BatteryLevels>,
+    device: Option<DeviceInfo>,
+    last_success: Option<(Instant, SystemTime)>,
+    consecutive_failures: u32,
+    started: Instant,
+    metrics: Metrics,
+    receivers: Vec<ReceiverStatus>,
+}
+
+impl Status {
+    pub fn new() -> Self {
+        Self {
+            levels: None,
+            device: None,
+            last_success: None,
+            consecutive_failures: 0,
+            started: Instant::now(),
+            metrics: Metrics::default(),
+            receivers: Vec::new(),
+        }
+    }
+
+    pub fn succeeded(&mut self, levels: &BatteryLevels) {
+        self.levels = Some(levels.clone());
+        self.last_success = Some((Instant::now(), SystemTime::now()));
+        self.consecutive_failures = 0;
+    }
+
+    /// Sets what the headset and controllers are, after (re)connecting
+    pub fn set_device(&mut self, device: DeviceInfo) {
+        self.device = Some(device);
+    }
+
+    /// Sets whether the receivers are reachable, after sending to them
+    pub fn set_receivers(&mut self, receivers: Vec<ReceiverStatus>) {
+        self.receivers = receivers;
+    }
+
+    pub fn failed(&mut self) {
+        self.consecutive_failures += 1;
+    }
+
+    pub fn metrics(&mut self) -> &mut Metrics {
+        &mut self.metrics
+    }
+
+    /// Whether the levels were read successfully within two intervals
+    fn is_healthy(&self, interval: Duration) -> bool {
+        self.last_success
+            .is_some_and(|(read, _)| read.elapsed() <= interval * 2)
+    }
+
+    fn to_json(&self) -> Value {
+        let unix_time = |(_, time): (Instant, SystemTime)| {
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs())
+        };
+
+        json!({
+            "levels": self.levels.as_ref().map(BatteryLevels::to_json),
+            "device": self.device.as_ref().map(DeviceInfo::to_json),
+            "last_success": self.last_success.map(unix_time),
+            "data_age": self.last_success.map(|(read, _)| read.elapsed().as_secs() / 60),
+            "consecutive_failures": self.consecutive_failures,
+            "uptime": self.started.elapsed().as_secs(),
+            "receivers": self
+                .receivers
+                .iter()
+                .map(|receiver| json!({
+                    "name": receiver.name,
+                    "address": receiver.address.to_string(),
+                    "reachable": receiver.reachable,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serves `GET /status` with the status as JSON, `GET /metrics` with the
+/// metrics for Prometheus and `GET /healthz`, which fails while the levels
+/// haven't been read within two polling intervals. `POST /refresh` reads the
+/// levels right away if there's a `refresh` channel.
+pub fn serve(
+    address: &str,
+    status: Arc<Mutex<Status>>,
+    interval: Duration,
+    refresh: Option<Sender<Event>>,
+) -> Result<()> {
+    let server = TcpListener::bind(address)
+        .with_context(|| format!("Failed to bind the HTTP server to {address}"))?;
+    info!(
+        "Serving the status on http://{}/status",
+        server.local_addr()?
+    );
+
+    thread::spawn(move || {
+        for stream in server.incoming().flatten() {
+            if let Err(error) = respond(stream, &status, interval, refresh.as_ref()) {
+                debug!("Failed to answer an HTTP request: {error:#}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn respond(
+    mut stream: TcpStream,
+    status: &Mutex<Status>,
+    interval: Duration,
+    refresh: Option<&Sender<Event>>,
+) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let mut request = request.split_whitespace();
+    let method = request.next().unwrap_or_default();
+    let path = request
+        .next()
+        .unwrap_or("/")
+        .split('?')
+        .next()
+        .unwrap_or("/");
+
+    let (code, content_type, body) = {
+        let status = status.lock().unwrap();
+        match (method, path) {
+            ("GET", "/status") => ("200 OK", "application/json", status.to_json().to_string()),
+            ("GET", "/metrics") => (
+                "200 OK",
+                "text/plain; version=0.0.4",
+                status.metrics.render(),
+            ),
+            ("GET", "/healthz") if status.is_healthy(interval) => {
+                ("200 OK", "text/plain", String::from("ok"))
+            }
+            ("GET", "/healthz") => (
+                "503 Service Unavailable",
+                "text/plain",
+                String::from("stale"),
+            ),
+            ("GET", _) => ("404 Not Found", "text/plain", String::new()),
+            ("POST", "/refresh") => match refresh {
+                Some(refresh) => {
+                    let _ = refresh.send(Event::Refresh);
+                    ("202 Accepted", "text/plain", String::from("refreshing"))
+                }
+                None => ("404 Not Found", "text/plain", String::new()),
+            },
+            _ => ("405 Method Not Allowed", "text/plain", String::new()),
+        }
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {code}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels() -> BatteryLevels {
+        BatteryLevels {
+            headset: Some(0.5),
+            right_controller: Some(0.25),
+            headset_charging: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unhealthy_until_read() {
+        let mut status = Status::new();
+        assert!(!status.is_healthy(Duration::from_secs(60)));
+
+        status.succeeded(&levels());
+        assert!(status.is_healthy(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn failures_reset_on_success() {
+        let mut status = Status::new();
+        status.failed();
+        status.failed();
+        assert_eq!(status.to_json()["consecutive_failures"], 2);
+        assert!(status.to_json()["levels"].is_null());
+        assert!(status.to_json()["device"].is_null());
+
+        status.succeeded(&levels());
+        let json = status.to_json();
+        assert_eq!(json["consecutive_failures"], 0);
+        assert_eq!(json["levels"]["right_controller"], 0.25);
+        assert_eq!(json["levels"]["left_connected"], false);
+    }
+}
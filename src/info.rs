@@ -0,0 +1,127 @@
+use crate::adb::AdbRunner;
+use crate::parse::{self, Role};
+use serde_json::{json, Value};
+use tracing::{debug, info};
+
+/// Firmware and serial of a device listed by the controller service
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControllerFirmware {
+    pub name: String,
+    pub version: Option<String>,
+    pub serial: Option<String>,
+}
+
+/// What the headset and its controllers are, which is logged once connected
+/// to help with bug reports
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub model: Option<String>,
+    /// Build of the headset's system, e.g. `5.7.1` on a Pico
+    pub os_version: Option<String>,
+    pub controllers: Vec<ControllerFirmware>,
+}
+
+impl DeviceInfo {
+    /// Reads what it can, anything adb fails to read or the headset doesn't
+    /// list is left out without failing
+    pub fn read(adb: &impl AdbRunner) -> Self {
+        // Only Pico headsets list their controllers' firmware
+        let controllers = adb
+            .shell(&["dumpsys", "pxrcontrollerservice"])
+            .map(|dump| parse::parse_controllers(&dump))
+            .inspect_err(|error| debug!("Failed to read the controllers' firmware: {error:#}"))
+            .unwrap_or_default();
+
+        Self {
+            model: property(adb, "ro.product.model"),
+            os_version: property(adb, "ro.build.display.id"),
+            controllers: controllers
+                .into_iter()
+                .filter(|controller| controller.version.is_some() || controller.serial.is_some())
+                .map(|controller| ControllerFirmware {
+                    name: match controller.role {
+                        Role::Other(name) => name,
+                        role => role.name().to_string(),
+                    },
+                    version: controller.version,
+                    serial: controller.serial,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn log(&self) {
+        let known =
+            |value: &Option<String>| value.clone().unwrap_or_else(|| String::from("unknown"));
+        info!(
+            "Headset {}, system {}",
+            known(&self.model),
+            known(&self.os_version)
+        );
+        for controller in &self.controllers {
+            info!(
+                "{}: firmware {}, serial {}",
+                controller.name,
+                known(&controller.version),
+                known(&controller.serial)
+            );
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "model": self.model,
+            "os_version": self.os_version,
+            "controllers": self.controllers.iter().map(|controller| json!({
+                "name": controller.name,
+                "version": controller.version,
+                "serial": controller.serial,
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn property(adb: &impl AdbRunner, name: &str) -> Option<String> {
+    match adb.shell(&["getprop", name]) {
+        Ok(value) if !value.trim().is_empty() => Some(value.trim().to_string()),
+        Ok(_) => None,
+        Err(error) => {
+            debug!("Failed to read {name}: {error:#}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb::mock::MockAdb;
+
+    #[test]
+    fn reads_the_firmware() {
+        let adb = MockAdb::default()
+            .with("shell getprop ro.product.model", "A8110\n")
+            .with(
+                "shell dumpsys pxrcontrollerservice",
+                include_str!("../tests/fixtures/pxrcontrollerservice_left_missing.txt"),
+            );
+        let info = DeviceInfo::read(&adb);
+
+        assert_eq!(info.model.as_deref(), Some("A8110"));
+        assert_eq!(info.os_version, None);
+        assert_eq!(
+            info.controllers,
+            [ControllerFirmware {
+                name: String::from("right controller"),
+                version: Some(String::from("3.1.12")),
+                serial: Some(String::from("PA7R10MGE0456")),
+            }]
+        );
+        assert_eq!(info.to_json()["os_version"], Value::Null);
+    }
+
+    #[test]
+    fn reads_nothing_without_a_headset() {
+        assert_eq!(DeviceInfo::read(&MockAdb::default()), DeviceInfo::default());
+    }
+}
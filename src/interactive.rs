@@ -0,0 +1,159 @@
+use crate::osc::{self, OscSender, Parameters};
+use crate::sink::BatteryReport;
+use crate::source::BatteryLevels;
+use crate::stages::Stager;
+use crate::threshold::Thresholds;
+use anyhow::Result;
+use std::io::BufRead;
+
+const USAGE: &str = "\
+Commands:
+  headset <level>   set the headset level, from 0.0 to 1.0
+  left <level>      set the left controller level, or `-` to disconnect it
+  right <level>     set the right controller level, or `-` to disconnect it
+  all <level>       set every level
+  charging on|off   set whether the headset is charging
+  quit              stop";
+
+/// A line typed into `vrc_volta test`
+#[derive(Debug, PartialEq)]
+enum Input {
+    Headset(f32),
+    Left(Option<f32>),
+    Right(Option<f32>),
+    All(f32),
+    Charging(bool),
+    Quit,
+}
+
+fn parse(line: &str) -> Result<Input, String> {
+    let words: Vec<_> = line.split_whitespace().collect();
+    let level = |value: &str| match value.parse::<f32>() {
+        Ok(level) if (0.0..=1.0).contains(&level) => Ok(level),
+        _ => Err(format!("`{value}` is not a level from 0.0 to 1.0")),
+    };
+    let controller = |value: &str| match value {
+        "-" => Ok(None),
+        value => level(value).map(Some),
+    };
+
+    match words[..] {
+        ["headset", value] => level(value).map(Input::Headset),
+        ["left", value] => controller(value).map(Input::Left),
+        ["right", value] => controller(value).map(Input::Right),
+        ["all", value] => level(value).map(Input::All),
+        ["charging", "on"] => Ok(Input::Charging(true)),
+        ["charging", "off"] => Ok(Input::Charging(false)),
+        ["quit" | "exit"] => Ok(Input::Quit),
+        _ => Err(format!("Unknown command `{}`", line.trim())),
+    }
+}
+
+fn apply(levels: &mut BatteryLevels, input: Input) {
+    match input {
+        Input::Headset(level) => levels.headset = Some(level),
+        Input::Left(level) => levels.left_controller = level,
+        Input::Right(level) => levels.right_controller = level,
+        Input::All(level) => {
+            levels.headset = Some(level);
+            levels.left_controller = Some(level);
+            levels.right_controller = Some(level);
+        }
+        Input::Charging(charging) => levels.headset_charging = charging,
+        Input::Quit => {}
+    }
+}
+
+/// What `vrc_volta test` sends with, the same as polling would
+pub struct Tester {
+    pub sender: OscSender,
+    pub parameters: Parameters,
+    pub thresholds: Thresholds,
+    pub stager: Option<Stager>,
+}
+
+impl Tester {
+    /// Sends the levels set by the commands read from `input` right away,
+    /// printing every message sent, until `quit` or the end of the input
+    pub fn run(mut self, input: impl BufRead) -> Result<()> {
+        let mut levels = BatteryLevels {
+            headset: Some(1.0),
+            left_controller: Some(1.0),
+            right_controller: Some(1.0),
+            headset_charging: false,
+            headset_temperature: None,
+            headset_voltage: None,
+            headset_health: None,
+            headset_cycles: None,
+            headset_awake: None,
+            trackers: Vec::new(),
+            steamvr: Vec::new(),
+        };
+        println!("{USAGE}");
+
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse(&line) {
+                Ok(Input::Quit) => break,
+                Ok(input) => apply(&mut levels, input),
+                Err(error) => {
+                    println!("{error}\n{USAGE}");
+                    continue;
+                }
+            }
+
+            let low = self.thresholds.update(&levels);
+            let mut report = BatteryReport::new(levels.clone(), low);
+            report.stages = self.stager.as_mut().map(|stager| stager.update(&levels));
+            let messages = self.parameters.messages(&report, &[]);
+            let sent: Vec<_> = messages
+                .iter()
+                .map(|(_, message)| osc::describe(message))
+                .collect();
+            match self.sender.send(messages) {
+                Ok(()) => {
+                    for message in sent {
+                        println!("Sent {message}");
+                    }
+                }
+                Err(error) => println!("{error}"),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse("headset 0.5"), Ok(Input::Headset(0.5)));
+        assert_eq!(parse("  left -  "), Ok(Input::Left(None)));
+        assert_eq!(parse("right 1.0"), Ok(Input::Right(Some(1.0))));
+        assert_eq!(parse("all 0.75"), Ok(Input::All(0.75)));
+        assert_eq!(parse("charging on"), Ok(Input::Charging(true)));
+        assert_eq!(parse("quit"), Ok(Input::Quit));
+        assert!(parse("headset 50").is_err());
+        assert!(parse("charging maybe").is_err());
+        assert!(parse("headset").is_err());
+    }
+
+    #[test]
+    fn applies_commands() {
+        let mut levels = crate::sink::fixture::report().levels;
+        apply(&mut levels, Input::All(0.75));
+        apply(&mut levels, Input::Right(None));
+        apply(&mut levels, Input::Charging(false));
+
+        assert_eq!(levels.headset, Some(0.75));
+        assert_eq!(levels.left_controller, Some(0.75));
+        assert_eq!(levels.right_controller, None);
+        assert!(!levels.headset_charging);
+    }
+}
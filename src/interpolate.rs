@@ -0,0 +1,199 @@
+use crate::source::BatteryLevels;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Width of the steps the Pico controllers report, from 0 to 5
+const STEP: f32 = 0.2;
+
+/// The step a controller is at and what's known about how long steps last
+#[derive(Default)]
+struct Steps {
+    /// The step read last, when it was first read, and whether the controller
+    /// dropped into it then rather than already being at it
+    current: Option<(f32, Instant, bool)>,
+    /// Total time of the whole steps seen, and their count
+    seen: (Duration, u32),
+}
+
+impl Steps {
+    fn update(&mut self, now: Instant, level: Option<f32>) -> Option<f32> {
+        let Some(level) = level else {
+            // Reconnected controllers start over
+            self.current = None;
+            return None;
+        };
+        if !is_step(level) {
+            self.current = None;
+            return Some(level);
+        }
+
+        match self.current {
+            Some((step, since, _)) if step == level => {
+                return Some(self.estimate(level, now - since))
+            }
+            Some((step, since, entered)) if level < step => {
+                // Only a step seen from start to end tells how long one lasts
+                if entered && step - level < STEP * 1.5 {
+                    self.seen.0 += now - since;
+                    self.seen.1 += 1;
+                }
+                self.current = Some((level, now, true));
+            }
+            // Rising means fresh batteries or charging
+            _ => self.current = Some((level, now, false)),
+        }
+        Some(level)
+    }
+
+    /// The step's level lowered towards the next lower step by the time spent
+    /// at it, out of the average time per step
+    fn estimate(&self, level: f32, elapsed: Duration) -> f32 {
+        let (total, count) = self.seen;
+        if count == 0 {
+            return level;
+        }
+        let per_step = total / count;
+        let fraction = (elapsed.as_secs_f32() / per_step.as_secs_f32()).min(1.0);
+        (level - STEP * fraction).clamp((level - STEP).max(0.0), level)
+    }
+}
+
+/// Whether `level` is one of the controllers' steps
+fn is_step(level: f32) -> bool {
+    let steps = level / STEP;
+    (steps - steps.round()).abs() < 1e-3
+}
+
+/// Estimates the controllers' levels in between the 20% steps the Pico
+/// controllers report, from how long the steps have lasted so far. Readings
+/// which aren't steps are left as they are.
+#[derive(Default)]
+pub struct Interpolator {
+    left_controller: Steps,
+    right_controller: Steps,
+}
+
+impl Interpolator {
+    /// Replaces the controllers' levels with their estimates
+    pub fn interpolate(&mut self, levels: &mut BatteryLevels) {
+        self.interpolate_at(Instant::now(), levels)
+    }
+
+    fn interpolate_at(&mut self, now: Instant, levels: &mut BatteryLevels) {
+        let left = self.left_controller.update(now, levels.left_controller);
+        let right = self.right_controller.update(now, levels.right_controller);
+        if (left, right) != (levels.left_controller, levels.right_controller) {
+            debug!(?left, ?right, "Interpolated the controller levels");
+        }
+        levels.left_controller = left;
+        levels.right_controller = right;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(left_controller: Option<f32>) -> BatteryLevels {
+        BatteryLevels {
+            headset: Some(0.5),
+            left_controller,
+            ..Default::default()
+        }
+    }
+
+    /// The estimates of readings at the given minutes
+    fn timeline(readings: &[(u64, Option<f32>)]) -> Vec<Option<f32>> {
+        let start = Instant::now();
+        let mut interpolator = Interpolator::default();
+        readings
+            .iter()
+            .map(|&(minute, level)| {
+                let mut levels = levels(level);
+                interpolator.interpolate_at(start + Duration::from_secs(minute * 60), &mut levels);
+                levels
+                    .left_controller
+                    .map(|level| (level * 1000.0).round() / 1000.0)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sends_the_steps_until_one_was_seen_whole() {
+        let estimates = timeline(&[
+            (0, Some(1.0)),
+            (30, Some(1.0)),
+            (60, Some(0.8)),
+            (90, Some(0.8)),
+        ]);
+        assert_eq!(estimates, [Some(1.0), Some(1.0), Some(0.8), Some(0.8)]);
+    }
+
+    #[test]
+    fn interpolates_with_the_average_time_per_step() {
+        let estimates = timeline(&[
+            (0, Some(1.0)),
+            (10, Some(0.8)),
+            // 60 minutes at 0.8
+            (70, Some(0.6)),
+            (85, Some(0.6)),
+            (100, Some(0.6)),
+            (130, Some(0.6)),
+            // 100 minutes at 0.6, the average is now 80 minutes
+            (170, Some(0.4)),
+            (190, Some(0.4)),
+        ]);
+        assert_eq!(
+            estimates,
+            [
+                Some(1.0),
+                Some(0.8),
+                Some(0.6),
+                Some(0.55),
+                Some(0.5),
+                // Clamped to the step's bounds once it lasts longer than usual
+                Some(0.4),
+                Some(0.4),
+                Some(0.35)
+            ]
+        );
+    }
+
+    #[test]
+    fn starts_over_when_the_step_rises_or_the_controller_reconnects() {
+        let estimates = timeline(&[
+            (0, Some(0.8)),
+            (60, Some(0.6)),
+            (120, Some(0.4)),
+            (150, Some(0.4)),
+            // Fresh batteries
+            (160, Some(1.0)),
+            (190, Some(1.0)),
+            (200, None),
+            (210, Some(0.4)),
+            (240, Some(0.4)),
+        ]);
+        assert_eq!(
+            estimates,
+            [
+                Some(0.8),
+                Some(0.6),
+                Some(0.4),
+                Some(0.3),
+                Some(1.0),
+                Some(0.9),
+                None,
+                Some(0.4),
+                Some(0.3)
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_percentages_alone() {
+        assert_eq!(
+            timeline(&[(0, Some(0.8)), (60, Some(0.73)), (90, Some(0.73))]),
+            [Some(0.8), Some(0.73), Some(0.73)]
+        );
+    }
+}
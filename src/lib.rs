@@ -0,0 +1,47 @@
+pub mod adb;
+pub mod adb_host;
+pub mod changes;
+pub mod chatbox;
+pub mod check;
+pub mod config;
+pub mod console;
+pub mod dump;
+pub mod error;
+pub mod estimate;
+pub mod fake;
+pub mod history;
+pub mod http;
+pub mod info;
+pub mod interactive;
+pub mod interpolate;
+pub mod listener;
+pub mod metrics;
+pub mod mqtt;
+pub mod notify;
+pub mod osc;
+pub mod oscquery;
+pub mod pair;
+pub mod params;
+pub mod parse;
+pub mod profile;
+pub mod quiet;
+pub mod rate;
+pub mod replay;
+pub mod schedule;
+pub mod service;
+pub mod session;
+pub mod sink;
+pub mod smooth;
+pub mod sound;
+pub mod source;
+pub mod stages;
+pub mod status;
+pub mod steamvr;
+pub mod storage;
+pub mod summary;
+pub mod threshold;
+pub mod tray;
+pub mod vrchat;
+pub mod webhook;
+pub mod worker;
+pub mod xsoverlay;
@@ -0,0 +1,145 @@
+use rosc::{decoder, OscPacket, OscType};
+use std::net::UdpSocket;
+use std::sync::mpsc::Sender;
+use std::thread;
+use tracing::{debug, error};
+
+const AVATAR_CHANGE: &str = "/avatar/change";
+/// Parameter an avatar or another OSC app sets to true to read the levels
+/// right away
+const REFRESH: &str = "/avatar/parameters/VoltaRefresh";
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// VRChat loaded a different avatar, carrying its ID
+    AvatarChange(String),
+    /// Read the levels right away
+    Refresh,
+    /// Stop or start publishing the levels again
+    Pause(bool),
+    /// Ctrl-C was pressed or volta was asked to terminate
+    Shutdown,
+}
+
+/// Receives OSC on `socket` in the background and forwards the messages volta
+/// reacts to as events to every `events`, which include the refresh parameter
+/// if `refresh`
+pub fn spawn(socket: UdpSocket, events: Vec<Sender<Event>>, refresh: bool) {
+    thread::spawn(move || {
+        let mut buffer = [0; decoder::MTU];
+        loop {
+            let size = match socket.recv(&mut buffer) {
+                Ok(size) => size,
+                Err(error) => {
+                    debug!("Failed to receive OSC: {error}");
+                    continue;
+                }
+            };
+
+            match decoder::decode_udp(&buffer[..size]) {
+                Ok((_, packet)) => {
+                    for event in events_of(packet, refresh) {
+                        let sent = events
+                            .iter()
+                            .filter(|events| events.send(event.clone()).is_ok());
+                        if sent.count() == 0 {
+                            return;
+                        }
+                    }
+                }
+                Err(error) => debug!("Failed to decode OSC packet: {error}"),
+            }
+        }
+    });
+}
+
+fn events_of(packet: OscPacket, refresh: bool) -> Vec<Event> {
+    match packet {
+        OscPacket::Message(message) if message.addr == AVATAR_CHANGE => {
+            match message.args.into_iter().next() {
+                Some(OscType::String(avatar)) => vec![Event::AvatarChange(avatar)],
+                args => {
+                    error!("Ignoring {AVATAR_CHANGE} with unexpected arguments {args:?}");
+                    vec![]
+                }
+            }
+        }
+        // Setting it back to false doesn't refresh again
+        OscPacket::Message(message) if refresh && message.addr == REFRESH => {
+            match message.args.first() {
+                Some(OscType::Bool(true)) => vec![Event::Refresh],
+                _ => vec![],
+            }
+        }
+        OscPacket::Message(message) => {
+            debug!("Ignoring OSC message to {}", message.addr);
+            vec![]
+        }
+        OscPacket::Bundle(bundle) => bundle
+            .content
+            .into_iter()
+            .flat_map(|packet| events_of(packet, refresh))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rosc::OscMessage;
+
+    fn message(addr: &str, args: Vec<OscType>, refresh: bool) -> Vec<Event> {
+        events_of(
+            OscPacket::Message(OscMessage {
+                addr: addr.to_string(),
+                args,
+            }),
+            refresh,
+        )
+    }
+
+    fn avatar_change(args: Vec<OscType>) -> Vec<Event> {
+        message(AVATAR_CHANGE, args, true)
+    }
+
+    #[test]
+    fn passes_on_any_avatar_id() {
+        for id in ["avtr_1234", "", "not an ID ✨"] {
+            let events = avatar_change(vec![OscType::String(id.to_string())]);
+            assert!(matches!(&events[..], [Event::AvatarChange(avatar)] if avatar == id));
+        }
+        assert!(avatar_change(vec![OscType::Int(1)]).is_empty());
+        assert!(avatar_change(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn refreshes_when_set_to_true() {
+        let refresh = |value, enabled| message(REFRESH, vec![OscType::Bool(value)], enabled);
+
+        assert!(matches!(&refresh(true, true)[..], [Event::Refresh]));
+        assert!(refresh(false, true).is_empty());
+        assert!(refresh(true, false).is_empty());
+    }
+
+    #[test]
+    fn forwards_the_events_to_every_channel() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let (first, first_events) = std::sync::mpsc::channel();
+        let (second, second_events) = std::sync::mpsc::channel();
+        spawn(socket, vec![first, second], true);
+
+        let packet = OscPacket::Message(OscMessage {
+            addr: AVATAR_CHANGE.to_string(),
+            args: vec![OscType::String(String::from("avtr_1234"))],
+        });
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .send_to(&rosc::encoder::encode(&packet).unwrap(), address)
+            .unwrap();
+        for events in [first_events, second_events] {
+            let event = events.recv_timeout(std::time::Duration::from_secs(5));
+            assert!(matches!(event, Ok(Event::AvatarChange(avatar)) if avatar == "avtr_1234"));
+        }
+    }
+}
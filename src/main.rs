@@ -1,161 +1,2831 @@
-use anyhow::{Context, Result};
-use clap::{arg, command, Parser};
-use itertools::Itertools;
-use lazy_static::lazy_static;
-use regex::Regex;
-use rosc::{encoder, OscMessage, OscPacket, OscType};
+use anyhow::{anyhow, ensure, Context, Result};
+use chrono::Local;
+use clap::builder::BoolishValueParser;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use rosc::OscMessage;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
 use std::net::UdpSocket;
-use std::process::{Command, Stdio};
-use std::{thread, time};
-use tracing::{error, info};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::level_filters::LevelFilter;
+use tracing::{debug, error, info, info_span, warn, Level};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::fmt::{self, MakeWriter};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+use vrc_volta::adb::{self, Adb, Waited};
+use vrc_volta::adb_host::HostClient;
+use vrc_volta::changes::ChangeFilter;
+use vrc_volta::chatbox::{self, Chatbox, ChatboxSink};
+use vrc_volta::check;
+use vrc_volta::config::{self, AvatarEntry, Config, Devices};
+use vrc_volta::console::Console;
+use vrc_volta::dump;
+use vrc_volta::error::{self, FailureStreak, Repeat};
+use vrc_volta::estimate::Estimator;
+use vrc_volta::fake::{self, FakeSource};
+use vrc_volta::history::History;
+use vrc_volta::http::{self, Status};
+use vrc_volta::info::DeviceInfo;
+use vrc_volta::interactive::Tester;
+use vrc_volta::interpolate::Interpolator;
+use vrc_volta::listener::{self, Event};
+use vrc_volta::metrics::SendCounts;
+use vrc_volta::mqtt::{self, Broker, Mqtt};
+use vrc_volta::notify::Notifier;
+use vrc_volta::osc::{
+    self, LevelScale, OfflineValue, OscSender, OscSink, ParamType, Parameters, StaleBehavior,
+};
+use vrc_volta::oscquery;
+use vrc_volta::pair;
+use vrc_volta::params;
+use vrc_volta::parse;
+use vrc_volta::profile::{self, Profile};
+use vrc_volta::quiet::{self, PauseWindow, QuietHours};
+use vrc_volta::rate::RateLimit;
+use vrc_volta::replay::{Capture, Replay};
+use vrc_volta::schedule::{Burst, Cadence, ChargeCheck, Debounce, Schedule};
+use vrc_volta::service;
+use vrc_volta::sink::{BatteryReport, DryRun, Settings, Sink, Sinks};
+use vrc_volta::smooth::Smoother;
+use vrc_volta::sound::{Player, Sounds};
+use vrc_volta::source::{
+    self, BatteryLevels, BatterySource, ControllerScale, Device, HeadsetSource, SourceKind,
+};
+use vrc_volta::stages::Stager;
+use vrc_volta::status::StatusFile;
+use vrc_volta::steamvr::{CombinedSource, SteamVr, SteamVrSource};
+use vrc_volta::storage::StorageReader;
+use vrc_volta::summary::SessionSummary;
+use vrc_volta::threshold::Thresholds;
+use vrc_volta::tray::Tray;
+use vrc_volta::vrchat::{self, Presence, Vrchat};
+use vrc_volta::webhook::{Webhook, WebhookFormat};
+use vrc_volta::worker;
+use vrc_volta::xsoverlay::{self, XsOverlay};
 
-const LEVEL_KEY: &str = "  level: ";
-const HANDLER_KEY: &str = "   handler: ";
-const BATTERY_KEY: &str = "   battery: ";
-
-lazy_static! {
-    static ref REGEX_CONTROLLER_LEFT: Regex =
-        Regex::new("handler: left[.\\s\\S]*?battery: ([0-9]*)").unwrap();
-    static ref REGEX_CONTROLLER_RIGHT: Regex =
-        Regex::new("handler: right[.\\s\\S]*?battery: ([0-9]*)").unwrap();
-}
-
-#[derive(Parser, Debug)]
+#[derive(Parser, Clone, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Arguments {
-    /// Receiver address
-    #[arg(short, long, default_value_t = String::from("127.0.0.1:9000"))]
-    receiver: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Config file, defaults to `volta.toml` next to the executable or in the config directory
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Least severe log level shown, one of `trace`, `debug`, `info`, `warn` or `error`. Defaults to `RUST_LOG`, or `info` without it.
+    #[arg(long)]
+    log_level: Option<Level>,
+
+    /// Only log warnings and errors, besides the summary of every cycle. `--log-level` and `RUST_LOG` take precedence.
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Log as newline-delimited JSON events
+    #[arg(long)]
+    log_json: bool,
+
+    /// File to append the log to as well
+    #[arg(long)]
+    log_file: Option<PathBuf>,
 
-    /// Sender address
+    /// Show the levels in a block redrawn every cycle instead of logging them, the log then only goes to `--log-file`
+    #[arg(long, conflicts_with = "once")]
+    status: bool,
+
+    /// Receiver address, pass it several times to send to several receivers. `auto` discovers VRChat through OSCQuery.
+    #[arg(short, long, default_value = "127.0.0.1:9000")]
+    receiver: Vec<String>,
+
+    /// Time to wait for VRChat to answer OSCQuery discovery before falling back to `127.0.0.1:9000`
+    #[arg(long, default_value = "5s", value_parser = parse_interval)]
+    discovery_timeout: Duration,
+
+    /// Sender address, whose IP every receiver is sent to from and whose port receives OSC without `--listen`. Use port 0 to let the OS pick a free port.
     #[arg(long, default_value_t = String::from("127.0.0.1:9003"))]
     sender: String,
+
+    /// Network interface to send from, e.g. `eth0`, its address in the receiver's family replaces the sender's IP
+    #[arg(long)]
+    bind_interface: Option<String>,
+
+    /// Address to receive OSC from VRChat on, e.g. `127.0.0.1:9001`, defaults to the sender address
+    #[arg(short, long)]
+    listen: Option<String>,
+
+    /// Don't read the levels right away when `/avatar/parameters/VoltaRefresh` is set to true
+    #[arg(long)]
+    no_osc_refresh: bool,
+
+    /// Don't read the levels right away on `POST /refresh` to the HTTP server
+    #[arg(long)]
+    no_http_refresh: bool,
+
+    /// Don't read the levels right away on SIGUSR1
+    #[arg(long)]
+    no_signal_refresh: bool,
+
+    /// Polling interval, e.g. `30s`, `2m` or `90` (seconds)
+    #[arg(short, long, default_value = "60s", value_parser = parse_interval)]
+    interval: Duration,
+
+    /// Read only the headset's battery while it's asleep, and send whether it's awake
+    #[arg(long)]
+    detect_sleep: bool,
+
+    /// Interval between readings of the headset, defaults to `--interval`
+    #[arg(long, value_parser = parse_interval)]
+    headset_interval: Option<Duration>,
+
+    /// Interval between readings of the controllers and trackers, defaults to `--interval`. The headset is read along with them whenever they're due.
+    #[arg(long, value_parser = parse_interval)]
+    controller_interval: Option<Duration>,
+
+    /// Polling interval while the headset is asleep, defaults to `--interval`
+    #[arg(long, value_parser = parse_interval, requires = "detect_sleep")]
+    idle_interval: Option<Duration>,
+
+    /// Interval between the quick checks of whether the headset is charging in between the readings, which read the levels right away when it changed
+    #[arg(long, default_value = "10s", value_parser = parse_interval)]
+    charge_poll: Duration,
+
+    /// Only notice the headset being plugged in or unplugged at the next reading
+    #[arg(long)]
+    no_charge_poll: bool,
+
+    /// Only read and send the levels while VRChat runs
+    #[arg(long)]
+    require_vrchat: bool,
+
+    /// Name of the VRChat process, ignoring case, pass it several times for several names, e.g. for a launch wrapper
+    #[arg(long, default_values_t = vrchat::DEFAULT_PROCESS_NAMES.map(String::from), requires = "require_vrchat")]
+    vrchat_process: Vec<String>,
+
+    /// How often to check whether VRChat started while it isn't running
+    #[arg(long, default_value = "10s", value_parser = parse_interval, requires = "require_vrchat")]
+    vrchat_check_interval: Duration,
+
+    /// Local time window in which to neither read nor send the levels, e.g. `01:00-08:00`, pass it several times for several windows
+    #[arg(long, value_name = "HH:MM-HH:MM", value_parser = quiet::parse_window)]
+    pause_between: Vec<PauseWindow>,
+
+    /// Wireless adb address of the headset, e.g. `192.168.1.50:5555`, or `auto` to find it over mDNS
+    #[arg(long)]
+    adb_connect: Option<String>,
+
+    /// Serial of the adb device to use when several are connected
+    #[arg(short, long)]
+    serial: Option<String>,
+
+    /// Path of the adb executable, found on the PATH or in SideQuest's or the Android SDK's directory by default
+    #[arg(long)]
+    adb_path: Option<PathBuf>,
+
+    /// How to run adb commands, `exec` runs the adb executable, `native` talks to the adb server directly, and `auto` talks to it directly while running the executable for what that can't do
+    #[arg(long, value_enum, default_value_t = adb::Backend::Auto)]
+    adb_backend: adb::Backend,
+
+    /// Keep a single `adb shell` open and run every command in it, rather than opening a shell for each
+    #[arg(long)]
+    persistent_shell: bool,
+
+    /// Time after which a hanging adb command is killed
+    #[arg(long, default_value = "10s", value_parser = parse_interval)]
+    adb_timeout: Duration,
+
+    /// How many times a dump which adb failed to read is read again within a cycle, after 1, 2, 4... seconds
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    /// Restart the adb server after this many adb commands timed out in a row, 0 to never restart it
+    #[arg(long, default_value_t = 3)]
+    adb_restart_after: u32,
+
+    /// Stop the adb server when volta stops, if volta started it
+    #[arg(long)]
+    kill_adb_on_exit: bool,
+
+    /// Where to read the battery levels from, `steamvr` and `both` need a build with the `steamvr` feature
+    #[arg(long, value_enum, default_value_t = SourceKind::Adb)]
+    source: SourceKind,
+
+    /// Headset model to read the controller batteries from: `pico`, `quest`, `auto` to detect it, or `profile:<name>` for a device profile like `profile:vive_focus`
+    #[arg(short, long, default_value_t = Device::Auto, value_parser = Device::from_str)]
+    device: Device,
+
+    /// Scale the Pico controllers report their battery on, `auto` infers it from the readings
+    #[arg(long, value_enum, default_value_t = ControllerScale::Auto)]
+    controller_scale: ControllerScale,
+
+    /// Where to read the headset level from, `auto` reads sysfs and falls back to `dumpsys battery`
+    #[arg(long, value_enum, default_value_t = HeadsetSource::Auto)]
+    headset_source: HeadsetSource,
+
+    /// Clamp battery readings beyond their scale instead of skipping the reading
+    #[arg(long)]
+    clamp: bool,
+
+    /// Show the levels in a tray icon, with a menu to refresh, pause sending and quit
+    #[arg(long, conflicts_with = "once")]
+    tray: bool,
+
+    /// Send simulated battery levels instead of reading them from a headset, adb isn't needed
+    #[arg(long)]
+    fake: bool,
+
+    /// Duration over which the simulated levels drain from 100% to 0%, they then charge back up
+    #[arg(long, default_value = "2m", value_parser = parse_interval, requires = "fake")]
+    fake_sweep: Duration,
+
+    /// Fixed simulated levels as `headset,left,right`, e.g. `0.8,0.6,-` where `-` is disconnected
+    #[arg(long, value_parser = fake::parse_values, requires = "fake")]
+    fake_values: Option<BatteryLevels>,
+
+    /// Directory of saved dumps to read instead of asking adb, `battery.txt` and `controllers.txt`, or numbered ones as written by `--capture`
+    #[arg(long, conflicts_with_all = ["fake", "capture"])]
+    replay: Option<PathBuf>,
+
+    /// Directory to save the dumps read every cycle to, for `--replay`
+    #[arg(long)]
+    capture: Option<PathBuf>,
+
+    /// Read and send the battery levels once, print them and exit
+    #[arg(long)]
+    once: bool,
+
+    /// Print the battery levels as JSON in `--once` mode
+    #[arg(long, requires = "once")]
+    json: bool,
+
+    /// Log every OSC message and what the other outputs would get instead of sending anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// OSC type of the battery level parameters, `both` also sends integers to the addresses with an `Int` suffix
+    #[arg(long, value_enum, default_value_t = ParamType::Float)]
+    param_type: ParamType,
+
+    /// Range the float battery levels are sent across, the thresholds stay percentages either way
+    #[arg(long, value_enum, default_value = "0-1")]
+    scale: LevelScale,
+
+    /// What to send to the level parameters of a controller which isn't connected, a number like `-1` to send as it is, `hold` to keep sending its last level or `skip` to send nothing. Its connection parameter is false either way.
+    #[arg(long, default_value = "skip", value_parser = osc::parse_offline_value)]
+    offline_value: OfflineValue,
+
+    /// Advertise volta over OSCQuery so VRChat and OSC routers can discover it
+    #[arg(long)]
+    oscquery: bool,
+
+    /// Send every parameter as its own message instead of a single bundle
+    #[arg(long)]
+    no_bundle: bool,
+
+    /// Most OSC messages to send per second, the least important are dropped when a cycle has more
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    max_rate: Option<u32>,
+
+    /// Milliseconds to wait between two OSC packets, for receivers which drop bursts
+    #[arg(long, default_value_t = 0)]
+    message_spacing: u64,
+
+    /// Minimum change of a battery level from 0.0 to 1.0 before it's sent again, whatever its --scale, 0 sends every change
+    #[arg(long, default_value_t = 0.0, value_parser = parse_min_delta)]
+    min_delta: f32,
+
+    /// Smooth the levels with an exponential moving average, weighting each new reading by this from 0.0 to 1.0
+    #[arg(long, value_parser = parse_alpha)]
+    smooth: Option<f32>,
+
+    /// Estimate the levels of controllers which report 20% steps in between the steps, from how long the steps lasted so far. The stage parameters get the steps as read.
+    #[arg(long)]
+    interpolate_steps: bool,
+
+    /// Interval after which every parameter is sent again even if it didn't change
+    #[arg(long, default_value = "5m", value_parser = parse_interval)]
+    keepalive: Duration,
+
+    /// Times after the first levels were sent to send them again, in case VRChat was still loading and dropped them
+    #[arg(long, default_values = ["10s", "20s", "30s"], value_parser = parse_interval)]
+    startup_burst: Vec<Duration>,
+
+    /// Send the first levels only once
+    #[arg(long)]
+    no_startup_burst: bool,
+
+    /// What to send while the levels can't be read, `resend` the last levels, `hold` nothing or `zero` empty levels. The minutes since they were last read are sent either way.
+    #[arg(long, value_enum, default_value_t = StaleBehavior::Hold)]
+    stale_behavior: StaleBehavior,
+
+    /// Minutes the age of the levels is capped at, VRChat syncs Ints up to 255
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u32).range(1..=255))]
+    max_data_age: u32,
+
+    /// Headset battery percentage at or below which it counts as low
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u8).range(0..=100))]
+    warn_headset: u8,
+
+    /// Controller battery percentage at or below which it counts as low, controllers report in steps of 20%
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u8).range(0..=100))]
+    warn_controller: u8,
+
+    /// Leave the avatar parameters at their last values on exit instead of resetting them
+    #[arg(long)]
+    no_reset_on_exit: bool,
+
+    /// Battery level from 0.0 to 1.0 sent to every level parameter when resetting them on exit, on `--scale`
+    #[arg(long, default_value_t = 0.0)]
+    reset_value: f32,
+
+    /// Headset battery percentage at or below which it counts as critical
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=100))]
+    critical_headset: u8,
+
+    /// Controller battery percentage at or below which it counts as critical
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=100))]
+    critical_controller: u8,
+
+    /// Show a desktop notification when a battery gets low or critical
+    #[arg(long)]
+    notify: bool,
+
+    /// Sound file to play when a battery gets low or critical, as WAV or Ogg Vorbis
+    #[arg(long)]
+    alert_sound: Option<PathBuf>,
+
+    /// Sound file to play instead when a battery gets low
+    #[arg(long)]
+    warn_sound: Option<PathBuf>,
+
+    /// Sound file to play instead when a battery gets critical
+    #[arg(long)]
+    critical_sound: Option<PathBuf>,
+
+    /// Show a notification in XSOverlay when a battery gets low or critical
+    #[arg(long)]
+    xsoverlay: bool,
+
+    /// Port XSOverlay receives notifications on
+    #[arg(long, default_value_t = xsoverlay::DEFAULT_PORT)]
+    xsoverlay_port: u16,
+
+    /// URL to POST to when a battery gets critical, e.g. a Discord webhook
+    #[arg(long)]
+    webhook: Option<String>,
+
+    /// Body the webhook is called with
+    #[arg(long, value_enum, default_value_t = WebhookFormat::Json)]
+    webhook_format: WebhookFormat,
+
+    /// Also write the battery levels to the chatbox
+    #[arg(long)]
+    chatbox: bool,
+
+    /// Chatbox text, `{headset}`, `{left}` and `{right}` are replaced with percentages
+    #[arg(long, default_value = chatbox::DEFAULT_FORMAT)]
+    chatbox_format: String,
+
+    /// Address to serve the status on over HTTP, e.g. `127.0.0.1:9080`, with `GET /status`, `GET /metrics`, `GET /healthz` and `POST /refresh`
+    #[arg(long)]
+    http: Option<String>,
+
+    /// MQTT broker to publish the battery levels to as retained messages, e.g. `mqtt://192.168.1.2:1883`
+    #[arg(long, value_parser = mqtt::parse_broker)]
+    mqtt: Option<Broker>,
+
+    /// Prefix of the MQTT topics, e.g. `vrc_volta/headset/level`
+    #[arg(long, default_value = mqtt::DEFAULT_TOPIC_PREFIX)]
+    mqtt_topic_prefix: String,
+
+    /// Publish Home Assistant MQTT discovery configs so the sensors show up automatically
+    #[arg(long, requires = "mqtt")]
+    mqtt_discovery: bool,
+
+    /// File to write the battery levels to after every reading, e.g. for an OBS text source. Files ending in `.json` get a JSON document.
+    #[arg(long)]
+    status_file: Option<PathBuf>,
+
+    /// Status file text, with the same placeholders as `--chatbox-format`
+    #[arg(long, default_value = chatbox::DEFAULT_FORMAT)]
+    status_format: String,
+
+    /// File to append every reading to, as CSV or as JSON Lines for files ending in `.jsonl`
+    #[arg(long)]
+    history: Option<PathBuf>,
+
+    /// Start a new history file every day, with the date added to its name
+    #[arg(long, requires = "history")]
+    history_rotate_daily: bool,
+
+    /// File to append a JSON line to when volta stops, with the session's start and end and every device's levels and drain rate over it
+    #[arg(long)]
+    session_log: Option<PathBuf>,
+
+    /// Prefix inserted before the name of every parameter whose address isn't set explicitly, e.g. `P2_` for `/avatar/parameters/P2_BatteryLevelHeadset`
+    #[arg(long, value_parser = parse_prefix)]
+    prefix: Option<String>,
+
+    /// Headset battery level parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryLevelHeadset", value_parser = parse_address)]
+    headset_param: String,
+
+    /// Left controller battery level parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryLevelControllerLeft", value_parser = parse_address)]
+    left_param: String,
+
+    /// Right controller battery level parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryLevelControllerRight", value_parser = parse_address)]
+    right_param: String,
+
+    /// Headset charging state parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryChargingHeadset", value_parser = parse_address)]
+    charging_param: String,
+
+    /// Headset battery temperature parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryTempHeadset", value_parser = parse_address)]
+    temp_param: String,
+
+    /// Headset battery voltage parameter address, sent in volts
+    #[arg(long, default_value = "/avatar/parameters/BatteryVoltageHeadset", value_parser = parse_address)]
+    voltage_param: String,
+
+    /// Also send the headset and controller levels quantized into this many stages, as Ints from 0
+    #[arg(long, value_parser = clap::value_parser!(u8).range(2..))]
+    stages: Option<u8>,
+
+    /// Send only the stages instead of the headset and controller levels
+    #[arg(long, requires = "stages")]
+    stages_only: bool,
+
+    /// Percentage points a level has to go beyond its stage before the stage changes
+    #[arg(long, default_value_t = 0.0, value_parser = parse_min_delta, requires = "stages")]
+    stage_hysteresis: f32,
+
+    /// Also send how fast the headset and controllers drain and how many minutes they're estimated to last
+    #[arg(long)]
+    estimate: bool,
+
+    /// How far back the drain rate is averaged over, e.g. `30m`
+    #[arg(long, default_value = "30m", value_parser = parse_interval, requires = "estimate")]
+    estimate_window: Duration,
+
+    /// Temperature range in °C mapped to 0.0 to 1.0, e.g. `20-50`, the temperature is sent in °C without it
+    #[arg(long, value_parser = parse_range)]
+    temp_range: Option<(f32, f32)>,
+
+    /// Left controller connection state parameter address
+    #[arg(long, default_value = "/avatar/parameters/ControllerLeftConnected", value_parser = parse_address)]
+    left_connected_param: String,
+
+    /// Right controller connection state parameter address
+    #[arg(long, default_value = "/avatar/parameters/ControllerRightConnected", value_parser = parse_address)]
+    right_connected_param: String,
+
+    /// Headset low battery state parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryLowHeadset", value_parser = parse_address)]
+    low_headset_param: String,
+
+    /// Left controller low battery state parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryLowControllerLeft", value_parser = parse_address)]
+    low_left_param: String,
+
+    /// Right controller low battery state parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryLowControllerRight", value_parser = parse_address)]
+    low_right_param: String,
+
+    /// Tracker battery level parameter address, numbered from 1 in the order the trackers show up
+    #[arg(long, default_value = "/avatar/parameters/BatteryLevelTracker", value_parser = parse_address)]
+    tracker_param: String,
+
+    /// Headset awake state parameter address, sent with `--detect-sleep`
+    #[arg(long, default_value = "/avatar/parameters/HeadsetAwake", value_parser = parse_address)]
+    awake_param: String,
+
+    /// Parameter address of the minutes since the levels were last read
+    #[arg(long, default_value = "/avatar/parameters/BatteryDataAge", value_parser = parse_address)]
+    data_age_param: String,
+
+    /// Headset battery stage parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryStageHeadset", value_parser = parse_address)]
+    stage_headset_param: String,
+
+    /// Left controller battery stage parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryStageControllerLeft", value_parser = parse_address)]
+    stage_left_param: String,
+
+    /// Right controller battery stage parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryStageControllerRight", value_parser = parse_address)]
+    stage_right_param: String,
+
+    /// Headset minutes remaining parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryMinutesHeadset", value_parser = parse_address)]
+    minutes_headset_param: String,
+
+    /// Left controller minutes remaining parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryMinutesControllerLeft", value_parser = parse_address)]
+    minutes_left_param: String,
+
+    /// Right controller minutes remaining parameter address
+    #[arg(long, default_value = "/avatar/parameters/BatteryMinutesControllerRight", value_parser = parse_address)]
+    minutes_right_param: String,
+
+    /// Headset drain rate parameter address, sent in percentage points per hour
+    #[arg(long, default_value = "/avatar/parameters/BatteryDrainHeadset", value_parser = parse_address)]
+    drain_headset_param: String,
+
+    /// Left controller drain rate parameter address, sent in percentage points per hour
+    #[arg(long, default_value = "/avatar/parameters/BatteryDrainControllerLeft", value_parser = parse_address)]
+    drain_left_param: String,
+
+    /// Right controller drain rate parameter address, sent in percentage points per hour
+    #[arg(long, default_value = "/avatar/parameters/BatteryDrainControllerRight", value_parser = parse_address)]
+    drain_right_param: String,
+
+    /// SteamVR device battery level parameter address, followed by the device's hand or serial
+    #[arg(long, default_value = "/avatar/parameters/BatterySteamVR", value_parser = parse_address)]
+    steamvr_param: String,
+
+    /// Also send a heartbeat counting up every cycle, whether volta runs, and its version, so the avatar can tell when volta stopped
+    #[arg(long)]
+    heartbeat: bool,
+
+    /// Parameter address of whether volta runs, sent with `--heartbeat` and set to false when volta stops
+    #[arg(long, default_value = "/avatar/parameters/VoltaAlive", value_parser = parse_address)]
+    alive_param: String,
+
+    /// Parameter address of the heartbeat, an Int counting up every cycle, sent with `--heartbeat`
+    #[arg(long, default_value = "/avatar/parameters/VoltaHeartbeat", value_parser = parse_address)]
+    beat_param: String,
+
+    /// Parameter address of volta's version as `major * 100 + minor`, sent with `--heartbeat`
+    #[arg(long, default_value = "/avatar/parameters/VoltaVersion", value_parser = parse_address)]
+    version_param: String,
+
+    /// Also send the fraction of the headset's storage which is free, read with `df` over adb
+    #[arg(long)]
+    report_storage: bool,
+
+    /// How often to read the headset's storage
+    #[arg(long, default_value = "10m", value_parser = parse_interval, requires = "report_storage")]
+    storage_interval: Duration,
+
+    /// Parameter address of the fraction of the headset's storage which is free, sent with `--report-storage`
+    #[arg(long, default_value = "/avatar/parameters/HeadsetStorageFree", value_parser = parse_address)]
+    storage_param: String,
+
+    /// Also send the health of the headset's battery as Android's code, e.g. 2 for good and 3 for overheating
+    #[arg(long)]
+    report_health: bool,
+
+    /// Parameter address of the health of the headset's battery, sent with `--report-health`
+    #[arg(long, default_value = "/avatar/parameters/BatteryHealthHeadset", value_parser = parse_address)]
+    health_param: String,
+
+    /// The `[[device]]` entry of the config file these options are for
+    #[arg(skip)]
+    device_entry: Option<usize>,
+
+    /// The `[avatar.<ID>]` entries of the config file
+    #[arg(skip)]
+    avatars: BTreeMap<String, AvatarEntry>,
+
+    /// The device profile `device` names, if it names one
+    #[arg(skip)]
+    profile: Option<Profile>,
 }
 
-fn main() {
-    tracing_subscriber::fmt::init();
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Save the raw adb output volta parses to a file for bug reports
+    Dump {
+        /// Directory to write the dump to
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Print the avatar parameters volta sends in VRChat's avatar OSC config format
+    Params {
+        /// File to write the parameters to instead of printing them
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Pair with a headset over wireless debugging and store it in the config file to connect to it from then on
+    Pair {
+        /// Address shown in the headset's "Pair device with pairing code" dialog, e.g. `192.168.1.50:37099`
+        #[arg(long, required_unless_present = "forget")]
+        host: Option<String>,
 
-    let arguments = Arguments::parse();
-    let socket = UdpSocket::bind(&arguments.sender).unwrap();
-    let sleep_duration = time::Duration::from_secs(60);
+        /// Pairing code shown in the same dialog
+        #[arg(long, required_unless_present = "forget")]
+        code: Option<String>,
 
-    start_adb_server();
+        /// Address to connect to after pairing, shown under "IP address & Port", found over mDNS by default
+        #[arg(long)]
+        connect: Option<String>,
 
-    loop {
-        if let Ok(levels) = get_levels() {
-            info!("{:?}", levels);
+        /// Remove the stored headset from the config file instead
+        #[arg(long, conflicts_with_all = ["host", "code", "connect"])]
+        forget: bool,
+    },
+    /// Check adb, the headset and sending OSC step by step, exiting with 1 if anything fails
+    Check,
+    /// Send levels typed into stdin, such as `headset 0.5` or `charging on`, for wiring up animators
+    Test,
+    /// Run volta in the background whenever you log in
+    Service {
+        #[command(subcommand)]
+        action: ServiceAction,
+    },
+    /// Write a commented config file, or show the values volta would use
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
 
-            let headset_message = OscPacket::Message(OscMessage {
-                addr: String::from("/avatar/parameters/BatteryLevelHeadset"),
-                args: vec![OscType::Float(levels.headset)],
-            });
-            let controller_left = OscPacket::Message(OscMessage {
-                addr: String::from("/avatar/parameters/BatteryLevelControllerLeft"),
-                args: vec![OscType::Float(levels.left_controller)],
-            });
-            let controller_right = OscPacket::Message(OscMessage {
-                addr: String::from("/avatar/parameters/BatteryLevelControllerRight"),
-                args: vec![OscType::Float(levels.right_controller)],
-            });
+#[derive(Subcommand, Clone, Debug)]
+enum ConfigAction {
+    /// Write a config file with every key commented out at its default
+    Init {
+        /// File to write, defaults to `--config` or `volta.toml` in the config directory
+        #[arg(long)]
+        path: Option<PathBuf>,
 
-            let headset_buffer = encoder::encode(&headset_message)
-                .expect("Failed to encode headset battery level message");
-            let controller_left_buffer = encoder::encode(&controller_left)
-                .expect("Failed to encode left controller battery level message");
-            let controller_right_buffer = encoder::encode(&controller_right)
-                .expect("Failed to encode right controller battery level message");
+        /// Overwrite the file if it exists
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the value of every key after merging the command line, the environment and the config file
+    Show,
+}
 
-            socket
-                .send_to(&headset_buffer, &arguments.receiver)
-                .expect("Failed to send headset battery level");
-            socket
-                .send_to(&controller_left_buffer, &arguments.receiver)
-                .expect("Failed to send left controller battery level");
-            socket
-                .send_to(&controller_right_buffer, &arguments.receiver)
-                .expect("Failed to send right controller battery level");
-        } else {
-            error!("Failed to retrieve battery levels");
+#[derive(Subcommand, Clone, Debug)]
+enum ServiceAction {
+    /// Register volta as a systemd user unit on Linux or a scheduled task on Windows and start it, with `--config` if it's passed
+    Install,
+    /// Stop volta and remove it
+    Uninstall,
+    /// Show whether volta is registered and running
+    Status,
+}
+
+fn parse_interval(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, multiplier) = if let Some(number) = value.strip_suffix('h') {
+        (number, 60 * 60)
+    } else if let Some(number) = value.strip_suffix('m') {
+        (number, 60)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1)
+    } else {
+        (value, 1)
+    };
+
+    let seconds: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("`{value}` is not a duration, expected e.g. `30s`, `2m` or `90`"))?;
+    let duration = Duration::try_from_secs_f64(seconds * multiplier as f64)
+        .map_err(|_| format!("`{value}` is not a valid duration"))?;
+
+    if duration < Duration::from_secs(1) {
+        return Err(format!("`{value}` is shorter than the minimum of 1 second"));
+    }
+
+    Ok(duration)
+}
+
+fn parse_min_delta(value: &str) -> Result<f32, String> {
+    match value.parse() {
+        Ok(delta) if delta >= 0.0 => Ok(delta),
+        Ok(_) => Err(format!("`{value}` is negative")),
+        Err(_) => Err(format!("`{value}` is not a number")),
+    }
+}
+
+fn parse_alpha(value: &str) -> Result<f32, String> {
+    match value.parse() {
+        Ok(alpha) if alpha > 0.0 && alpha <= 1.0 => Ok(alpha),
+        Ok(_) => Err(format!("`{value}` is not above 0.0 and at most 1.0")),
+        Err(_) => Err(format!("`{value}` is not a number")),
+    }
+}
+
+fn parse_range(value: &str) -> Result<(f32, f32), String> {
+    // Skip the first character so a negative minimum isn't taken as the separator
+    let separator = value
+        .get(1..)
+        .and_then(|rest| rest.find('-'))
+        .map(|index| index + 1);
+    let range = separator.and_then(|index| {
+        let min = value[..index].trim().parse().ok()?;
+        let max = value[index + 1..].trim().parse().ok()?;
+        Some((min, max))
+    });
+
+    match range {
+        Some((min, max)) if min < max => Ok((min, max)),
+        Some(_) => Err(format!(
+            "`{value}` is empty, the minimum must be below the maximum"
+        )),
+        None => Err(format!("`{value}` is not a range, expected e.g. `20-50`")),
+    }
+}
+
+fn parse_address(value: &str) -> Result<String, String> {
+    if !value.starts_with('/') {
+        return Err(format!(
+            "`{value}` is not an OSC address, it must start with `/`"
+        ));
+    }
+    if value.chars().any(char::is_whitespace) {
+        return Err(format!(
+            "`{value}` is not an OSC address, it must not contain whitespace"
+        ));
+    }
+
+    Ok(value.to_string())
+}
+
+/// Parameter name prefixes may only use the characters VRChat allows in
+/// parameter names
+fn parse_prefix(value: &str) -> Result<String, String> {
+    if value.is_empty()
+        || !value
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '_')
+    {
+        return Err(format!(
+            "`{value}` is not a parameter name prefix, it may only contain letters, digits and `_`"
+        ));
+    }
+
+    Ok(value.to_string())
+}
+
+/// Inserts `prefix` before the parameter name of an avatar parameter address
+fn with_prefix(address: &str, prefix: &str) -> String {
+    match address.strip_prefix(params::PARAMETERS_PREFIX) {
+        Some(name) => format!("{}{prefix}{name}", params::PARAMETERS_PREFIX),
+        None => address.to_string(),
+    }
+}
+
+impl Arguments {
+    /// Fills in every value which was left at its default with the value from
+    /// the config file, so command line flags and environment variables always
+    /// take precedence
+    fn merge(&mut self, config: Config, matches: &ArgMatches) -> Result<()> {
+        let is_default = |id: &str| matches.value_source(id) == Some(ValueSource::DefaultValue);
+        let address = |key: &str, value: String| {
+            parse_address(&value)
+                .map_err(|error| anyhow!("Invalid config value for `{key}`: {error}"))
+        };
+
+        if let (true, Some(receiver)) = (is_default("receiver"), config.receiver) {
+            self.receiver = receiver.into();
+            ensure!(
+                !self.receiver.is_empty(),
+                "Invalid config value for `receiver`: the list of receivers is empty"
+            );
+        }
+        if let (true, Some(timeout)) = (is_default("discovery_timeout"), config.discovery_timeout) {
+            self.discovery_timeout = parse_interval(&timeout).map_err(|error| {
+                anyhow!("Invalid config value for `discovery_timeout`: {error}")
+            })?;
+        }
+        if let (true, Some(oscquery)) = (is_default("oscquery"), config.oscquery) {
+            self.oscquery = oscquery;
+        }
+        if let (true, Some(sender)) = (is_default("sender"), config.sender) {
+            self.sender = sender;
+        }
+        if let (None, Some(interface)) = (&self.bind_interface, config.bind_interface) {
+            self.bind_interface = Some(interface);
+        }
+        if let (None, Some(listen)) = (&self.listen, config.listen) {
+            self.listen = Some(listen);
+        }
+        for (key, value, target) in [
+            (
+                "no_osc_refresh",
+                config.no_osc_refresh,
+                &mut self.no_osc_refresh,
+            ),
+            (
+                "no_http_refresh",
+                config.no_http_refresh,
+                &mut self.no_http_refresh,
+            ),
+            (
+                "no_signal_refresh",
+                config.no_signal_refresh,
+                &mut self.no_signal_refresh,
+            ),
+        ] {
+            if let (true, Some(value)) = (is_default(key), value) {
+                *target = value;
+            }
+        }
+        if let (true, Some(interval)) = (is_default("interval"), config.interval) {
+            self.interval = parse_interval(&interval)
+                .map_err(|error| anyhow!("Invalid config value for `interval`: {error}"))?;
+        }
+        if let (true, Some(detect_sleep)) = (is_default("detect_sleep"), config.detect_sleep) {
+            self.detect_sleep = detect_sleep;
+        }
+        if let (true, Some(require)) = (is_default("require_vrchat"), config.require_vrchat) {
+            self.require_vrchat = require;
+        }
+        if let (true, Some(names)) = (is_default("vrchat_process"), config.vrchat_process) {
+            ensure!(
+                !names.is_empty(),
+                "Invalid config value for `vrchat_process`: the list of names is empty"
+            );
+            self.vrchat_process = names;
+        }
+        if let (true, Some(interval)) = (
+            is_default("vrchat_check_interval"),
+            config.vrchat_check_interval,
+        ) {
+            self.vrchat_check_interval = parse_interval(&interval).map_err(|error| {
+                anyhow!("Invalid config value for `vrchat_check_interval`: {error}")
+            })?;
+        }
+        if let (true, Some(windows)) = (self.pause_between.is_empty(), config.pause_between) {
+            self.pause_between = windows
+                .iter()
+                .map(|window| quiet::parse_window(window))
+                .collect::<Result<_, _>>()
+                .map_err(|error| anyhow!("Invalid config value for `pause_between`: {error}"))?;
+        }
+        for (key, value, target) in [
+            (
+                "headset_interval",
+                config.headset_interval,
+                &mut self.headset_interval,
+            ),
+            (
+                "controller_interval",
+                config.controller_interval,
+                &mut self.controller_interval,
+            ),
+        ] {
+            if let (None, Some(interval)) = (&target, value) {
+                *target = Some(
+                    parse_interval(&interval)
+                        .map_err(|error| anyhow!("Invalid config value for `{key}`: {error}"))?,
+                );
+            }
+        }
+        if let (None, Some(interval)) = (self.idle_interval, config.idle_interval) {
+            self.idle_interval =
+                Some(parse_interval(&interval).map_err(|error| {
+                    anyhow!("Invalid config value for `idle_interval`: {error}")
+                })?);
+        }
+        if let (true, Some(interval)) = (is_default("charge_poll"), config.charge_poll) {
+            self.charge_poll = parse_interval(&interval)
+                .map_err(|error| anyhow!("Invalid config value for `charge_poll`: {error}"))?;
+        }
+        if let (true, Some(no_poll)) = (is_default("no_charge_poll"), config.no_charge_poll) {
+            self.no_charge_poll = no_poll;
+        }
+        if let (None, Some(address)) = (&self.adb_connect, config.adb_connect) {
+            self.adb_connect = Some(address);
+        }
+        if let (None, Some(serial)) = (&self.serial, config.serial) {
+            self.serial = Some(serial);
+        }
+        if let (true, Some(no_bundle)) = (is_default("no_bundle"), config.no_bundle) {
+            self.no_bundle = no_bundle;
+        }
+        if let (None, Some(rate)) = (&self.max_rate, config.max_rate) {
+            ensure!(
+                rate > 0,
+                "Invalid config value for `max_rate`: `0` is less than 1"
+            );
+            self.max_rate = Some(rate);
+        }
+        if let (true, Some(spacing)) = (is_default("message_spacing"), config.message_spacing) {
+            self.message_spacing = spacing;
+        }
+        if let (true, Some(min_delta)) = (is_default("min_delta"), config.min_delta) {
+            ensure!(
+                min_delta >= 0.0,
+                "Invalid config value for `min_delta`: `{min_delta}` is negative"
+            );
+            self.min_delta = min_delta;
+        }
+        if let (None, Some(alpha)) = (self.smooth, config.smooth) {
+            ensure!(
+                alpha > 0.0 && alpha <= 1.0,
+                "Invalid config value for `smooth`: `{alpha}` is not above 0.0 and at most 1.0"
+            );
+            self.smooth = Some(alpha);
+        }
+        if let (true, Some(interpolate)) =
+            (is_default("interpolate_steps"), config.interpolate_steps)
+        {
+            self.interpolate_steps = interpolate;
+        }
+        if let (true, Some(keepalive)) = (is_default("keepalive"), config.keepalive) {
+            self.keepalive = parse_interval(&keepalive)
+                .map_err(|error| anyhow!("Invalid config value for `keepalive`: {error}"))?;
+        }
+        if let (true, Some(offsets)) = (is_default("startup_burst"), config.startup_burst) {
+            self.startup_burst = offsets
+                .iter()
+                .map(|offset| parse_interval(offset))
+                .collect::<Result<_, _>>()
+                .map_err(|error| anyhow!("Invalid config value for `startup_burst`: {error}"))?;
+        }
+        if let (true, Some(no_burst)) = (is_default("no_startup_burst"), config.no_startup_burst) {
+            self.no_startup_burst = no_burst;
+        }
+        for (key, value, target) in [
+            ("warn_headset", config.warn_headset, &mut self.warn_headset),
+            (
+                "warn_controller",
+                config.warn_controller,
+                &mut self.warn_controller,
+            ),
+            (
+                "critical_headset",
+                config.critical_headset,
+                &mut self.critical_headset,
+            ),
+            (
+                "critical_controller",
+                config.critical_controller,
+                &mut self.critical_controller,
+            ),
+        ] {
+            if let (true, Some(value)) = (is_default(key), value) {
+                ensure!(
+                    value <= 100,
+                    "Invalid config value for `{key}`: `{value}` is not a percentage"
+                );
+                *target = value;
+            }
+        }
+        if let (true, Some(no_reset)) = (is_default("no_reset_on_exit"), config.no_reset_on_exit) {
+            self.no_reset_on_exit = no_reset;
+        }
+        if let (true, Some(value)) = (is_default("reset_value"), config.reset_value) {
+            self.reset_value = value;
+        }
+        if let (true, Some(dry_run)) = (is_default("dry_run"), config.dry_run) {
+            self.dry_run = dry_run;
+        }
+        if let (true, Some(notify)) = (is_default("notify"), config.notify) {
+            self.notify = notify;
+        }
+        if let (None, Some(path)) = (&self.alert_sound, config.alert_sound) {
+            self.alert_sound = Some(path);
+        }
+        if let (None, Some(path)) = (&self.warn_sound, config.warn_sound) {
+            self.warn_sound = Some(path);
+        }
+        if let (None, Some(path)) = (&self.critical_sound, config.critical_sound) {
+            self.critical_sound = Some(path);
+        }
+        if let (true, Some(xsoverlay)) = (is_default("xsoverlay"), config.xsoverlay) {
+            self.xsoverlay = xsoverlay;
+        }
+        if let (true, Some(port)) = (is_default("xsoverlay_port"), config.xsoverlay_port) {
+            self.xsoverlay_port = port;
+        }
+        if let (None, Some(url)) = (&self.webhook, config.webhook) {
+            self.webhook = Some(url);
+        }
+        if let (true, Some(format)) = (is_default("webhook_format"), config.webhook_format) {
+            self.webhook_format = WebhookFormat::from_str(&format, true)
+                .map_err(|error| anyhow!("Invalid config value for `webhook_format`: {error}"))?;
+        }
+        if let (true, Some(chatbox)) = (is_default("chatbox"), config.chatbox) {
+            self.chatbox = chatbox;
+        }
+        if let (true, Some(format)) = (is_default("chatbox_format"), config.chatbox_format) {
+            self.chatbox_format = format;
+        }
+        if let (None, Some(address)) = (&self.http, config.http) {
+            self.http = Some(address);
+        }
+        if let (None, Some(url)) = (&self.mqtt, config.mqtt) {
+            self.mqtt = Some(
+                mqtt::parse_broker(&url)
+                    .map_err(|error| anyhow!("Invalid config value for `mqtt`: {error}"))?,
+            );
+        }
+        if let (true, Some(prefix)) = (is_default("mqtt_topic_prefix"), config.mqtt_topic_prefix) {
+            self.mqtt_topic_prefix = prefix;
+        }
+        if let (true, Some(discovery)) = (is_default("mqtt_discovery"), config.mqtt_discovery) {
+            self.mqtt_discovery = discovery;
+        }
+        if let (None, Some(path)) = (&self.status_file, config.status_file) {
+            self.status_file = Some(path);
+        }
+        if let (None, Some(path)) = (&self.session_log, config.session_log) {
+            self.session_log = Some(path);
+        }
+        if let (None, Some(path)) = (&self.history, config.history) {
+            self.history = Some(path);
+        }
+        if let (true, Some(rotate)) = (
+            is_default("history_rotate_daily"),
+            config.history_rotate_daily,
+        ) {
+            self.history_rotate_daily = rotate;
+        }
+        if let (true, Some(format)) = (is_default("status_format"), config.status_format) {
+            self.status_format = format;
+        }
+        if let (None, Some(path)) = (&self.adb_path, config.adb_path) {
+            self.adb_path = Some(path);
+        }
+        if let (true, Some(retries)) = (is_default("retries"), config.retries) {
+            self.retries = retries;
+        }
+        if let (true, Some(kill)) = (is_default("kill_adb_on_exit"), config.kill_adb_on_exit) {
+            self.kill_adb_on_exit = kill;
         }
+        if let (true, Some(restart_after)) =
+            (is_default("adb_restart_after"), config.adb_restart_after)
+        {
+            self.adb_restart_after = restart_after;
+        }
+        if let (true, Some(backend)) = (is_default("adb_backend"), config.adb_backend) {
+            self.adb_backend = adb::Backend::from_str(&backend, true)
+                .map_err(|error| anyhow!("Invalid config value for `adb_backend`: {error}"))?;
+        }
+        if let (true, Some(persistent)) = (is_default("persistent_shell"), config.persistent_shell)
+        {
+            self.persistent_shell = persistent;
+        }
+        if let (true, Some(timeout)) = (is_default("adb_timeout"), config.adb_timeout) {
+            self.adb_timeout = parse_interval(&timeout)
+                .map_err(|error| anyhow!("Invalid config value for `adb_timeout`: {error}"))?;
+        }
+        if let (true, Some(tray)) = (is_default("tray"), config.tray) {
+            self.tray = tray;
+        }
+        if let (true, Some(fake)) = (is_default("fake"), config.fake) {
+            self.fake = fake;
+        }
+        if let (true, Some(sweep)) = (is_default("fake_sweep"), config.fake_sweep) {
+            self.fake_sweep = parse_interval(&sweep)
+                .map_err(|error| anyhow!("Invalid config value for `fake_sweep`: {error}"))?;
+        }
+        if let (None, Some(dir)) = (&self.replay, config.replay) {
+            self.replay = Some(dir);
+        }
+        if let (None, Some(dir)) = (&self.capture, config.capture) {
+            self.capture = Some(dir);
+        }
+        if let (None, Some(values)) = (&self.fake_values, config.fake_values) {
+            self.fake_values = Some(
+                fake::parse_values(&values)
+                    .map_err(|error| anyhow!("Invalid config value for `fake_values`: {error}"))?,
+            );
+        }
+        if let (None, Some(stages)) = (&self.stages, config.stages) {
+            ensure!(
+                stages >= 2,
+                "Invalid config value for `stages`: `{stages}` is less than 2"
+            );
+            self.stages = Some(stages);
+        }
+        if let (true, Some(stages_only)) = (is_default("stages_only"), config.stages_only) {
+            self.stages_only = stages_only;
+        }
+        if let (true, Some(hysteresis)) = (is_default("stage_hysteresis"), config.stage_hysteresis)
+        {
+            ensure!(
+                hysteresis >= 0.0,
+                "Invalid config value for `stage_hysteresis`: `{hysteresis}` is negative"
+            );
+            self.stage_hysteresis = hysteresis;
+        }
+        ensure!(
+            self.stages.is_some() || !self.stages_only,
+            "`stages_only` needs `stages`"
+        );
+        if let (true, Some(heartbeat)) = (is_default("heartbeat"), config.heartbeat) {
+            self.heartbeat = heartbeat;
+        }
+        if let (true, Some(report)) = (is_default("report_storage"), config.report_storage) {
+            self.report_storage = report;
+        }
+        if let (true, Some(interval)) = (is_default("storage_interval"), config.storage_interval) {
+            self.storage_interval = parse_interval(&interval)
+                .map_err(|error| anyhow!("Invalid config value for `storage_interval`: {error}"))?;
+        }
+        if let (true, Some(report)) = (is_default("report_health"), config.report_health) {
+            self.report_health = report;
+        }
+        if let (true, Some(estimate)) = (is_default("estimate"), config.estimate) {
+            self.estimate = estimate;
+        }
+        if let (true, Some(window)) = (is_default("estimate_window"), config.estimate_window) {
+            self.estimate_window = parse_interval(&window)
+                .map_err(|error| anyhow!("Invalid config value for `estimate_window`: {error}"))?;
+        }
+        if let (None, Some(range)) = (&self.temp_range, config.temp_range) {
+            self.temp_range = Some(
+                parse_range(&range)
+                    .map_err(|error| anyhow!("Invalid config value for `temp_range`: {error}"))?,
+            );
+        }
+        if let (true, Some(behavior)) = (is_default("stale_behavior"), config.stale_behavior) {
+            self.stale_behavior = StaleBehavior::from_str(&behavior, true)
+                .map_err(|error| anyhow!("Invalid config value for `stale_behavior`: {error}"))?;
+        }
+        if let (true, Some(max)) = (is_default("max_data_age"), config.max_data_age) {
+            ensure!(
+                (1..=255).contains(&max),
+                "Invalid config value for `max_data_age`: `{max}` is not from 1 to 255"
+            );
+            self.max_data_age = max;
+        }
+        if let (true, Some(param_type)) = (is_default("param_type"), config.param_type) {
+            self.param_type = ParamType::from_str(&param_type, true)
+                .map_err(|error| anyhow!("Invalid config value for `param_type`: {error}"))?;
+        }
+        if let (true, Some(scale)) = (is_default("scale"), config.scale) {
+            self.scale = LevelScale::from_str(&scale, true)
+                .map_err(|error| anyhow!("Invalid config value for `scale`: {error}"))?;
+        }
+        if let (true, Some(value)) = (is_default("offline_value"), config.offline_value) {
+            self.offline_value = osc::parse_offline_value(&value)
+                .map_err(|error| anyhow!("Invalid config value for `offline_value`: {error}"))?;
+        }
+        if let (true, Some(scale)) = (is_default("controller_scale"), config.controller_scale) {
+            self.controller_scale = ControllerScale::from_str(&scale, true)
+                .map_err(|error| anyhow!("Invalid config value for `controller_scale`: {error}"))?;
+        }
+        if let (true, Some(source)) = (is_default("headset_source"), config.headset_source) {
+            self.headset_source = HeadsetSource::from_str(&source, true)
+                .map_err(|error| anyhow!("Invalid config value for `headset_source`: {error}"))?;
+        }
+        if let (true, Some(clamp)) = (is_default("clamp"), config.clamp) {
+            self.clamp = clamp;
+        }
+        if let (true, Some(source)) = (is_default("source"), config.source) {
+            self.source = SourceKind::from_str(&source, true)
+                .map_err(|error| anyhow!("Invalid config value for `source`: {error}"))?;
+        }
+        // The `[[device]]` entries are merged one by one by `for_entry`
+        if let (true, Some(Devices::Kind(device))) = (is_default("device"), config.device) {
+            self.device = Device::from_str(&device)
+                .map_err(|error| anyhow!("Invalid config value for `device`: {error}"))?;
+        }
+        if let (None, Some(prefix)) = (&self.prefix, config.prefix) {
+            self.prefix = Some(
+                parse_prefix(&prefix)
+                    .map_err(|error| anyhow!("Invalid config value for `prefix`: {error}"))?,
+            );
+        }
+        // Addresses set explicitly are taken as they are
+        let prefix = self.prefix.clone();
+        // In the order of `addresses`
+        let values = [
+            config.headset_param,
+            config.left_param,
+            config.right_param,
+            config.charging_param,
+            config.temp_param,
+            config.voltage_param,
+            config.left_connected_param,
+            config.right_connected_param,
+            config.low_headset_param,
+            config.low_left_param,
+            config.low_right_param,
+            config.tracker_param,
+            config.awake_param,
+            config.data_age_param,
+            config.stage_headset_param,
+            config.stage_left_param,
+            config.stage_right_param,
+            config.minutes_headset_param,
+            config.minutes_left_param,
+            config.minutes_right_param,
+            config.drain_headset_param,
+            config.drain_left_param,
+            config.drain_right_param,
+            config.steamvr_param,
+            config.alive_param,
+            config.beat_param,
+            config.version_param,
+            config.storage_param,
+            config.health_param,
+        ];
+        for ((key, target), value) in self.addresses().into_iter().zip(values) {
+            match (is_default(key), value, &prefix) {
+                (true, Some(value), _) => *target = address(key, value)?,
+                (true, None, Some(prefix)) => *target = with_prefix(target, prefix),
+                _ => {}
+            }
+        }
+
+        if let Some(avatars) = config.avatar {
+            for (avatar, entry) in &avatars {
+                ensure!(
+                    avatar.starts_with("avtr_"),
+                    "Invalid config value for `avatar`: `{avatar}` isn't an avatar ID, which starts with `avtr_`"
+                );
+                self.for_avatar(entry).map_err(|error| {
+                    anyhow!("Invalid config value for `avatar.{avatar}`: {error:#}")
+                })?;
+            }
+            self.avatars = avatars;
+        }
+        self.profile = match &self.device {
+            Device::Profile(name) => {
+                Some(profile::find(name, &config.profile.unwrap_or_default())?)
+            }
+            _ => None,
+        };
+
+        Ok(())
+    }
+
+    /// The options with the addresses of an `[avatar.<ID>]` entry in place of
+    /// the top level ones
+    fn for_avatar(&self, entry: &AvatarEntry) -> Result<Arguments> {
+        let mut arguments = self.clone();
+        let mut addresses: BTreeMap<_, _> = arguments.addresses().into_iter().collect();
+        for (key, value) in &entry.addresses {
+            let target = addresses
+                .get_mut(key.as_str())
+                .with_context(|| format!("unknown parameter `{key}`"))?;
+            **target = parse_address(value).map_err(|error| anyhow!("`{key}`: {error}"))?;
+        }
+        Ok(arguments)
+    }
+
+    /// Every parameter address by its config key
+    fn addresses(&mut self) -> [(&'static str, &mut String); 29] {
+        [
+            ("headset_param", &mut self.headset_param),
+            ("left_param", &mut self.left_param),
+            ("right_param", &mut self.right_param),
+            ("charging_param", &mut self.charging_param),
+            ("temp_param", &mut self.temp_param),
+            ("voltage_param", &mut self.voltage_param),
+            ("left_connected_param", &mut self.left_connected_param),
+            ("right_connected_param", &mut self.right_connected_param),
+            ("low_headset_param", &mut self.low_headset_param),
+            ("low_left_param", &mut self.low_left_param),
+            ("low_right_param", &mut self.low_right_param),
+            ("tracker_param", &mut self.tracker_param),
+            ("awake_param", &mut self.awake_param),
+            ("data_age_param", &mut self.data_age_param),
+            ("stage_headset_param", &mut self.stage_headset_param),
+            ("stage_left_param", &mut self.stage_left_param),
+            ("stage_right_param", &mut self.stage_right_param),
+            ("minutes_headset_param", &mut self.minutes_headset_param),
+            ("minutes_left_param", &mut self.minutes_left_param),
+            ("minutes_right_param", &mut self.minutes_right_param),
+            ("drain_headset_param", &mut self.drain_headset_param),
+            ("drain_left_param", &mut self.drain_left_param),
+            ("drain_right_param", &mut self.drain_right_param),
+            ("steamvr_param", &mut self.steamvr_param),
+            ("alive_param", &mut self.alive_param),
+            ("beat_param", &mut self.beat_param),
+            ("version_param", &mut self.version_param),
+            ("storage_param", &mut self.storage_param),
+            ("health_param", &mut self.health_param),
+        ]
+    }
+
+    /// Intervals between readings of the headset and of the controllers
+    fn intervals(&self) -> (Duration, Duration) {
+        (
+            self.headset_interval.unwrap_or(self.interval),
+            self.controller_interval.unwrap_or(self.interval),
+        )
+    }
 
-        thread::sleep(sleep_duration);
+    /// Interval of the poll cycles, often enough for both the headset and the
+    /// controllers
+    fn cycle(&self) -> Duration {
+        let (headset, controllers) = self.intervals();
+        headset.min(controllers)
     }
 }
 
-fn start_adb_server() {
-    info!("Starting adb server...");
-    Command::new("adb")
-        .arg("start-server")
-        .stderr(Stdio::null())
-        .stdout(Stdio::null())
-        .status()
-        .expect("Failed to start adb server");
-    info!("Adb server started");
+/// Target of the summary of every cycle, which `--quiet` still shows
+const CYCLE_TARGET: &str = "volta::cycle";
+
+/// Logs the summary of a cycle which read the levels. The receivers are
+/// reachable unless the OS reported one of them unreachable.
+fn log_cycle(levels: &BatteryLevels, took: Duration, sent: SendCounts, reachable: Option<bool>) {
+    let percent = |level: f32| (level * 100.0).round() as u8;
+    info!(
+        target: CYCLE_TARGET,
+        headset_pct = levels.headset.map(percent),
+        left_pct = levels.left_controller.map(percent),
+        right_pct = levels.right_controller.map(percent),
+        charging = levels.headset_charging,
+        duration_ms = took.as_millis() as u64,
+        messages_sent = sent.messages,
+        receiver_reachable = reachable,
+        "Read the battery levels"
+    );
 }
 
-#[derive(Debug)]
-struct BatteryLevels {
-    pub headset: f32,
-    pub left_controller: f32,
-    pub right_controller: f32,
+/// Logs to stderr, or stdout in the polling loop, and to the log file if one
+/// is set. The returned guard flushes the log file when it's dropped.
+fn init_logging(arguments: &Arguments) -> Result<Option<WorkerGuard>> {
+    let filter = match arguments.log_level {
+        Some(level) => EnvFilter::new(level.as_str()),
+        None if arguments.quiet && std::env::var_os(EnvFilter::DEFAULT_ENV).is_none() => {
+            EnvFilter::new(format!("warn,{CYCLE_TARGET}=info"))
+        }
+        None => EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .from_env_lossy(),
+    };
+
+    // Keep stdout for the output itself in `--once` mode and subcommands,
+    // and leave the terminal to the status display
+    let mut layers = Vec::new();
+    if arguments.once || arguments.command.is_some() {
+        layers.push(log_layer(std::io::stderr, arguments.log_json, true));
+    } else if !arguments.status {
+        layers.push(log_layer(std::io::stdout, arguments.log_json, true));
+    }
+    let mut guard = None;
+    if let Some(path) = &arguments.log_file {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open the log file {path:?}"))?;
+        // Written on a thread of its own, so a slow disk can't hold up sending
+        let (writer, file_guard) = tracing_appender::non_blocking(file);
+        layers.push(log_layer(writer, arguments.log_json, false));
+        guard = Some(file_guard);
+    }
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .init();
+
+    Ok(guard)
 }
 
-fn get_levels() -> Result<BatteryLevels> {
-    let headset: u8 = get_battery_dump()?
-        .lines()
-        .find(|line| line.starts_with(LEVEL_KEY))
-        .context("Failed to find headset battery level")?
-        .replace(LEVEL_KEY, "")
-        .parse()
-        .context("Failed to detach the important thing")?;
+fn log_layer<W>(writer: W, json: bool, ansi: bool) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    match json {
+        true => layer.json().boxed(),
+        false => layer.boxed(),
+    }
+}
 
-    let controllers: String = get_controller_service_dump()?
-        .lines()
-        .filter(|line| line.starts_with(HANDLER_KEY) || line.starts_with(BATTERY_KEY))
-        .intersperse("\n")
+/// Lets every option be set with a `VOLTA_` environment variable named after
+/// it, e.g. `VOLTA_RECEIVER` for `--receiver`. Lists take comma-separated
+/// values.
+fn command() -> clap::Command {
+    let command = Arguments::command();
+    let ids: Vec<_> = command
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
         .collect();
-    let left_controller: u8 = REGEX_CONTROLLER_LEFT
-        .captures_iter(&controllers)
-        .next()
-        .context("Failed to capture left controller battery level")?[1]
-        .to_string()
-        .parse()
-        .context("Failed to parse left controller battery level")?;
-    let right_controller: u8 = REGEX_CONTROLLER_RIGHT
-        .captures_iter(&controllers)
-        .next()
-        .context("Failed to capture right controller battery level")?[1]
-        .to_string()
-        .parse()
-        .context("Failed to parse right controller battery level")?;
+    ids.into_iter()
+        .fold(command, |command, id| {
+            command.mut_arg(&id, |arg| {
+                let arg = match arg.get_action() {
+                    ArgAction::Append => arg.value_delimiter(','),
+                    // So `VOLTA_ONCE=1` works as well as `VOLTA_ONCE=true`
+                    ArgAction::SetTrue => arg.value_parser(BoolishValueParser::new()),
+                    _ => arg,
+                };
+                arg.env(format!("VOLTA_{}", id.to_uppercase()))
+            })
+        })
+        .after_help(
+            "Every option is taken from the command line, then from its environment variable, \
+             then from the config file, and falls back to its default.",
+        )
+}
+
+/// Writes or shows the config file, showing the values `arguments` take
+/// once merged with it
+fn config_command(
+    action: &ConfigAction,
+    arguments: &Arguments,
+    matches: &ArgMatches,
+) -> Result<()> {
+    let keys = config::schema(&command());
+    match action {
+        ConfigAction::Init { path, force } => {
+            let path = match path {
+                Some(path) => path.clone(),
+                None => config::writable_path(arguments.config.as_deref())?,
+            };
+            config::init(&path, &keys, *force)?;
+            println!("Wrote the config to {}", path.display());
+        }
+        ConfigAction::Show => {
+            let table = config::load_table(arguments.config.as_deref())?;
+            let mut merged = arguments.clone();
+            merged.merge(config::load(arguments.config.as_deref())?, matches)?;
+            let mut values = BTreeMap::new();
+            for (key, address) in merged.addresses() {
+                values.insert(key, toml::Value::String(address.clone()));
+            }
+            let resolved = config::resolve(&keys, matches, &table, &values);
+            print!("{}", config::effective(&resolved, &table));
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let matches = command().get_matches();
+    let mut arguments = Arguments::from_arg_matches(&matches)?;
+
+    let _log_guard = init_logging(&arguments)?;
+
+    // Before loading the config file, so a broken one can be replaced
+    if let Some(Command::Config { action }) = &arguments.command {
+        return config_command(action, &arguments, &matches);
+    }
+
+    let config = config::load(arguments.config.as_deref())?;
+    arguments.merge(config.clone(), &matches)?;
+
+    match &arguments.command {
+        Some(Command::Dump { out }) => {
+            let Some(adb) = open_adb(&arguments)? else {
+                return Ok(());
+            };
+            let path = dump::write(&adb, out)?;
+            println!("{}", path.display());
+            return Ok(());
+        }
+        Some(Command::Params { out }) => {
+            let config = params::avatar_config(&sent_parameters(&arguments));
+            let config = serde_json::to_string_pretty(&config)?;
+            match out {
+                Some(out) => {
+                    fs::write(out, config).with_context(|| format!("Failed to write {out:?}"))?
+                }
+                None => println!("{config}"),
+            }
+            return Ok(());
+        }
+        Some(Command::Check) => {
+            let program = adb_program(&arguments);
+            let executable = Adb::new(program.clone(), None, arguments.adb_timeout);
+            let adb = executable.clone().with_backend(arguments.adb_backend);
+            let setup = check::Setup {
+                executable,
+                program: &program,
+                adb,
+                server: Box::new(|| {
+                    HostClient::new(adb::server_port(), None, arguments.adb_timeout)
+                        .run(&["version"])
+                        .map_err(Into::into)
+                }),
+                address: arguments.adb_connect.as_deref(),
+                serial: arguments.serial.as_deref(),
+                source: Box::new(|adb| {
+                    source::create(
+                        &arguments.device,
+                        arguments.profile.as_ref(),
+                        adb,
+                        arguments.controller_scale,
+                        arguments.headset_source,
+                        arguments.clamp,
+                        false,
+                    )
+                }),
+                sender: &arguments.sender,
+                interface: arguments.bind_interface.as_deref(),
+                receivers: &arguments.receiver,
+            };
+            if !check::run(setup) {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Test) => {
+            let socket = osc::bind(
+                &arguments.sender,
+                &arguments.receiver[0],
+                arguments.bind_interface.as_deref(),
+            )?;
+            let tester = Tester {
+                sender: osc_sender(&arguments, socket)?,
+                parameters: parameters(&arguments),
+                thresholds: Thresholds::new(
+                    (arguments.warn_headset, arguments.critical_headset),
+                    (arguments.warn_controller, arguments.critical_controller),
+                ),
+                stager: arguments
+                    .stages
+                    .map(|count| Stager::new(count, arguments.stage_hysteresis)),
+            };
+            return tester.run(std::io::stdin().lock());
+        }
+        Some(Command::Service { action }) => {
+            let message = match action {
+                ServiceAction::Install => service::install(arguments.config.as_deref())?,
+                ServiceAction::Uninstall => service::uninstall()?,
+                ServiceAction::Status => service::status()?,
+            };
+            println!("{message}");
+            return Ok(());
+        }
+        Some(Command::Pair {
+            host,
+            code,
+            connect,
+            forget,
+        }) => {
+            let path = config::writable_path(arguments.config.as_deref())?;
+            let address = match (host, code) {
+                (Some(host), Some(code)) if !forget => {
+                    let adb = Adb::new(adb_program(&arguments), None, arguments.adb_timeout);
+                    check_adb(&adb, &arguments, false)?;
+                    Some(pair::pair(&adb, host, code, connect.as_deref())?)
+                }
+                _ => None,
+            };
+            config::store(&path, "adb_connect", address.as_deref())?;
+            match address {
+                Some(address) => println!("Stored {address} in {}", path.display()),
+                None => println!("Removed the stored headset from {}", path.display()),
+            }
+            return Ok(());
+        }
+        // Handled before the config file was loaded
+        Some(Command::Config { .. }) | None => {}
+    }
+
+    // Only the adb server volta started itself is stopped
+    let adb = arguments
+        .kill_adb_on_exit
+        .then(|| Adb::new(adb_program(&arguments), None, arguments.adb_timeout));
+    let result = run(arguments, config, matches);
+    if let Some(adb) = adb {
+        adb::stop_started_server(&adb);
+    }
+
+    result
+}
+
+/// Polls the levels of one headset, optionally with the tray, or of every
+/// `[[device]]` entry
+fn run(arguments: Arguments, config: Config, matches: ArgMatches) -> Result<()> {
+    if !config.devices().is_empty() {
+        return poll_devices(arguments, config, matches);
+    }
+
+    let (events_sender, events) = mpsc::channel();
+    if !arguments.once {
+        stop_on_ctrlc(vec![events_sender.clone()])?;
+    }
+    if !arguments.once && !arguments.no_signal_refresh {
+        refresh_on_sigusr1(vec![events_sender.clone()])?;
+    }
+    if arguments.tray {
+        // The tray has to run on the main thread, so the polling moves to
+        // another one and the tray runs until it finishes
+        let (reports_sender, reports) = mpsc::channel();
+        let tray = Tray::new(reports, events_sender.clone())?;
+        let worker = Worker::new(events_sender, events, Some(reports_sender));
+        let poll = thread::spawn(move || supervised_poll(arguments, matches, worker));
+        tray.run();
+        return poll.join().expect("the polling thread panicked");
+    }
+
+    supervised_poll(arguments, matches, Worker::new(events_sender, events, None))
+}
 
-    Ok(BatteryLevels {
-        headset: headset as f32 / 100.0,
-        left_controller: left_controller as f32 / 5.0,
-        right_controller: right_controller as f32 / 5.0,
+/// Polls every `[[device]]` entry of the config file on its own thread, so a
+/// headset which is offline doesn't hold up the others. The log lines of each
+/// are tagged with its name.
+fn poll_devices(arguments: Arguments, config: Config, matches: ArgMatches) -> Result<()> {
+    ensure!(
+        !arguments.tray,
+        "The tray shows a single headset, it can't be used with `[[device]]` entries"
+    );
+    ensure!(
+        !arguments.status,
+        "The status display shows a single headset, it can't be used with `[[device]]` entries"
+    );
+    for key in ["serial", "adb_connect", "prefix"] {
+        ensure!(
+            matches.value_source(key).is_none(),
+            "`--{}` can't be used with `[[device]]` entries, set it in the entries instead",
+            key.replace('_', "-")
+        );
+    }
+
+    let mut devices = Vec::new();
+    for (index, entry) in config.devices().iter().enumerate() {
+        let arguments = for_entry(config.clone(), &matches, index)
+            .with_context(|| format!("Invalid `[[device]]` entry `{}`", entry.name()))?;
+        devices.push((entry.name().to_string(), arguments, mpsc::channel()));
+    }
+    let senders = || -> Vec<_> {
+        devices
+            .iter()
+            .map(|(_, _, (events_sender, _))| events_sender.clone())
+            .collect()
+    };
+    if !arguments.once {
+        stop_on_ctrlc(senders())?;
+    }
+    if !arguments.once && !arguments.no_signal_refresh {
+        refresh_on_sigusr1(senders())?;
+    }
+
+    // The first entry's listener is the only one, so VRChat's events reach
+    // every device through it
+    let mut listener_events = Some(senders());
+
+    let count = devices.len();
+    let workers: Vec<_> = devices
+        .into_iter()
+        .map(|(name, arguments, (events_sender, events))| {
+            let matches = matches.clone();
+            let listener_events = listener_events.take();
+            thread::spawn(move || {
+                let _span = info_span!("device", name).entered();
+                let mut worker = Worker::new(events_sender, events, None);
+                if let Some(listener_events) = listener_events {
+                    worker.listener_events = listener_events;
+                }
+                let result = supervised_poll(arguments, matches, worker);
+                if let Err(error) = &result {
+                    error!("{error:#}");
+                }
+                result
+            })
+        })
+        .collect();
+    let failed = workers
+        .into_iter()
+        .map(|worker| worker.join())
+        .filter(|result| !matches!(result, Ok(Ok(()))))
+        .count();
+    ensure!(failed == 0, "{failed} of the {count} devices failed");
+
+    Ok(())
+}
+
+/// The options for the `index`th `[[device]]` entry of `config`. Only the
+/// first entry listens for VRChat, forwarding its events to the others, and
+/// runs the sinks which can't be shared. The others send from a port of
+/// their own.
+fn for_entry(config: Config, matches: &ArgMatches, index: usize) -> Result<Arguments> {
+    let mut arguments = Arguments::from_arg_matches(matches)?;
+    arguments.merge(config.for_device(index)?, matches)?;
+    arguments.device_entry = Some(index);
+
+    if let Some(prefix) = &arguments.prefix {
+        arguments.mqtt_topic_prefix = format!("{}/{prefix}", arguments.mqtt_topic_prefix);
+    }
+    if index > 0 {
+        let host = arguments
+            .sender
+            .rsplit_once(':')
+            .map_or("127.0.0.1", |(host, _)| host);
+        arguments.sender = format!("{host}:0");
+        arguments.listen = None;
+        arguments.oscquery = false;
+        arguments.http = None;
+        arguments.chatbox = false;
+        arguments.status_file = None;
+        arguments.history = None;
+        arguments.session_log = None;
+    }
+
+    Ok(arguments)
+}
+
+/// Sends a shutdown to every `events` on the first Ctrl-C, and quits right
+/// away on the second
+fn stop_on_ctrlc(events: Vec<Sender<Event>>) -> Result<()> {
+    let mut stopping = false;
+    ctrlc::set_handler(move || {
+        if stopping {
+            std::process::exit(130);
+        }
+        stopping = true;
+        warn!("Stopping, press Ctrl-C again to quit immediately");
+        worker::request_stop();
+        for events in &events {
+            let _ = events.send(Event::Shutdown);
+        }
     })
+    .context("Failed to install the Ctrl-C handler")
 }
 
-fn get_battery_dump() -> Result<String> {
-    Ok(String::from_utf8(
-        Command::new("adb")
-            .args(["shell", "dumpsys", "battery"])
-            .stderr(Stdio::null())
-            .output()
-            .context("Failed to get headset battery")?
-            .stdout,
-    )
-    .context("Failed to convert headset battery output to a string")?)
+/// Sends a refresh to every `events` on SIGUSR1
+#[cfg(unix)]
+fn refresh_on_sigusr1(events: Vec<Sender<Event>>) -> Result<()> {
+    use signal_hook::consts::SIGUSR1;
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([SIGUSR1]).context("Failed to install the SIGUSR1 handler")?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            info!("Received SIGUSR1, reading the levels right away");
+            for events in &events {
+                let _ = events.send(Event::Refresh);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Windows has no SIGUSR1
+#[cfg(not(unix))]
+fn refresh_on_sigusr1(_events: Vec<Sender<Event>>) -> Result<()> {
+    Ok(())
+}
+
+/// What the polling keeps when it's restarted after a panic, which includes
+/// the sockets and servers which can't be bound again while the old ones live
+struct Worker {
+    events_sender: Sender<Event>,
+    events: Receiver<Event>,
+    tray: Option<Sender<BatteryReport>>,
+    /// Where the listener forwards its events, which are the events of every
+    /// device sharing it
+    listener_events: Vec<Sender<Event>>,
+    /// The socket sending OSC, once it's bound
+    socket: Option<UdpSocket>,
+    /// Port of the listener, once it's started
+    listening: Option<u16>,
+    /// What the HTTP server serves, once it's started
+    status: Option<Arc<Mutex<Status>>>,
+    last_report: Option<BatteryReport>,
+}
+
+impl Worker {
+    fn new(
+        events_sender: Sender<Event>,
+        events: Receiver<Event>,
+        tray: Option<Sender<BatteryReport>>,
+    ) -> Self {
+        Self {
+            listener_events: vec![events_sender.clone()],
+            events_sender,
+            events,
+            tray,
+            socket: None,
+            listening: None,
+            status: None,
+            last_report: None,
+        }
+    }
 }
 
-fn get_controller_service_dump() -> Result<String> {
-    Ok(String::from_utf8(
-        Command::new("adb")
-            .args(["shell", "dumpsys", "pxrcontrollerservice"])
-            .stderr(Stdio::null())
-            .output()
-            .context("Failed to get controller batteries")?
-            .stdout,
+/// Polls on a worker thread which is started again when it panics
+fn supervised_poll(arguments: Arguments, matches: ArgMatches, mut worker: Worker) -> Result<()> {
+    worker::supervise(
+        &mut worker,
+        |worker| &worker.events,
+        |worker| poll(arguments.clone(), matches.clone(), worker),
     )
-    .context("Failed to convert controller batteries output to a string")?)
+}
+
+/// Polls the levels and publishes them until volta is stopped, sending every
+/// report to the tray as well if there is one
+fn poll(mut arguments: Arguments, matches: ArgMatches, worker: &mut Worker) -> Result<()> {
+    // The socket only fixes the IP and family the receivers are sent from,
+    // each of them gets a connected socket of its own, and the port OSC is
+    // received on without `--listen`
+    let socket = match &worker.socket {
+        Some(socket) => socket.try_clone()?,
+        None => {
+            let socket = osc::bind(
+                &arguments.sender,
+                &arguments.receiver[0],
+                arguments.bind_interface.as_deref(),
+            )?;
+            worker.socket = Some(socket.try_clone()?);
+            socket
+        }
+    };
+
+    let (headset_interval, controller_interval) = arguments.intervals();
+    match headset_interval == controller_interval {
+        true => info!("Polling every {headset_interval:?}"),
+        false => info!(
+            "Reading the headset every {headset_interval:?} and the controllers every {controller_interval:?}"
+        ),
+    }
+
+    let mut model = String::from("Headset");
+    let mut device = None;
+    let mut storage = None;
+    let (source, mut supervisor): (Box<dyn BatterySource>, _) = if arguments.fake {
+        info!("Sending simulated battery levels");
+        model = String::from("Simulated headset");
+        let source = match arguments.fake_values.clone() {
+            Some(levels) => FakeSource::Fixed(levels),
+            None => FakeSource::sweep(arguments.fake_sweep),
+        };
+        (Box::new(source), None)
+    } else if let Some(dir) = &arguments.replay {
+        model = String::from("Replay");
+        // The replayed controller dumps are Pico's
+        let source = source::create(
+            &Device::Pico,
+            None,
+            Replay::open(dir)?,
+            arguments.controller_scale,
+            arguments.headset_source,
+            arguments.clamp,
+            arguments.detect_sleep,
+        );
+        (source, None)
+    } else if arguments.source == SourceKind::SteamVr {
+        info!("Reading the battery levels from SteamVR");
+        model = String::from("SteamVR");
+        (Box::new(SteamVrSource(SteamVr::new()?)), None)
+    } else {
+        // Fail before touching adb when SteamVR isn't available
+        let steamvr = match arguments.source {
+            SourceKind::Both => Some(SteamVr::new()?),
+            _ => None,
+        };
+        let Some(adb) = open_adb(&arguments)? else {
+            return Ok(());
+        };
+        let mut supervisor = adb::Supervisor::new(adb.clone(), arguments.adb_connect.clone())
+            .with_wanted_serial(arguments.serial.clone());
+        if !arguments.once && supervisor.ensure_connected(&mut worker::sleep) == Waited::Stopped {
+            return Ok(());
+        }
+        let info = DeviceInfo::read(&adb);
+        info.log();
+        if let Some(name) = &info.model {
+            model = name.clone();
+        }
+        device = Some(info);
+        if arguments.report_storage && !arguments.once {
+            storage = Some(StorageReader::spawn(
+                adb.detached(),
+                arguments.storage_interval,
+            ));
+        }
+        let source = match &arguments.capture {
+            Some(dir) => source::create(
+                &arguments.device,
+                arguments.profile.as_ref(),
+                Capture::new(adb, dir.clone())?,
+                arguments.controller_scale,
+                arguments.headset_source,
+                arguments.clamp,
+                arguments.detect_sleep,
+            ),
+            None => source::create(
+                &arguments.device,
+                arguments.profile.as_ref(),
+                adb,
+                arguments.controller_scale,
+                arguments.headset_source,
+                arguments.clamp,
+                arguments.detect_sleep,
+            ),
+        };
+        let source: Box<dyn BatterySource> = match steamvr {
+            Some(steamvr) => Box::new(CombinedSource::new(source, steamvr)),
+            None => source,
+        };
+        (source, Some(supervisor))
+    };
+    if arguments.report_storage && device.is_none() {
+        warn!("The storage is read over adb, ignoring `--report-storage` without a headset");
+    }
+    let mut thresholds = Thresholds::new(
+        (arguments.warn_headset, arguments.critical_headset),
+        (arguments.warn_controller, arguments.critical_controller),
+    );
+    let mut stager = arguments
+        .stages
+        .map(|count| Stager::new(count, arguments.stage_hysteresis));
+    let mut sender = osc_sender(&arguments, socket)?;
+
+    let parameters = parameters(&arguments);
+
+    if arguments.once {
+        let levels = source
+            .get_levels()
+            .context("Failed to retrieve battery levels")?;
+        let low = thresholds.update(&levels);
+        let trackers: Vec<String> = levels
+            .trackers
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        let mut report = BatteryReport::new(levels.clone(), low);
+        report.stages = stager.as_mut().map(|stager| stager.update(&levels));
+        let sent = sender.send(parameters.messages(&report, &trackers));
+        print_levels(&levels, device.as_ref(), arguments.json)?;
+        sent.context("Failed to send the battery levels")?;
+        return Ok(());
+    }
+
+    // The listener of a previous run still forwards the events
+    let port = match (worker.listening, &arguments.listen) {
+        (Some(port), _) => port,
+        (None, listen) => {
+            let listener = match listen {
+                Some(listen) => UdpSocket::bind(listen)
+                    .with_context(|| format!("Failed to listen on {listen}"))?,
+                None => worker.socket.as_ref().expect("bound above").try_clone()?,
+            };
+            let port = listener.local_addr()?.port();
+            listener::spawn(
+                listener,
+                worker.listener_events.clone(),
+                !arguments.no_osc_refresh,
+            );
+            worker.listening = Some(port);
+            port
+        }
+    };
+    let _advertisement = match (arguments.oscquery, arguments.dry_run) {
+        (true, true) => {
+            info!("Would advertise the OSCQuery service for port {port} over mDNS");
+            None
+        }
+        (true, false) => Some(oscquery::advertise(port, &sent_parameters(&arguments))?),
+        (false, _) => None,
+    };
+    let notifier = arguments.notify.then(Notifier::spawn);
+    let sounds = Sounds {
+        low: arguments
+            .warn_sound
+            .clone()
+            .or(arguments.alert_sound.clone()),
+        critical: arguments
+            .critical_sound
+            .clone()
+            .or(arguments.alert_sound.clone()),
+    };
+    let player = match (&sounds.low, &sounds.critical) {
+        (None, None) => None,
+        _ => Some(Player::spawn(sounds)?),
+    };
+    let webhook = arguments
+        .webhook
+        .clone()
+        .map(|url| Webhook::spawn(url, arguments.webhook_format, arguments.dry_run));
+    let mut xsoverlay = match (arguments.xsoverlay, arguments.dry_run) {
+        (true, true) => Some(XsOverlay::dry_run(arguments.xsoverlay_port)),
+        (true, false) => Some(
+            XsOverlay::new(arguments.xsoverlay_port)
+                .context("Failed to bind the XSOverlay socket")?,
+        ),
+        (false, _) => None,
+    };
+    let status = match &worker.status {
+        Some(status) => {
+            // The panic may have happened while it was locked
+            status.clear_poison();
+            status.clone()
+        }
+        None => {
+            let status = Arc::new(Mutex::new(Status::new()));
+            if let Some(address) = &arguments.http {
+                let refresh = (!arguments.no_http_refresh).then(|| worker.events_sender.clone());
+                http::serve(address, status.clone(), arguments.interval, refresh)?;
+            }
+            worker.status = Some(status.clone());
+            status
+        }
+    };
+    if let Some(device) = device {
+        status.lock().unwrap().set_device(device);
+    }
+
+    let sender = Rc::new(RefCell::new(sender));
+    let mut sinks: Vec<Box<dyn Sink>> = vec![Box::new(OscSink::new(
+        sender.clone(),
+        parameters,
+        ChangeFilter::new(arguments.min_delta, arguments.keepalive),
+        (!arguments.no_reset_on_exit).then_some(arguments.reset_value),
+    ))];
+    if arguments.chatbox {
+        let chatbox = Chatbox::new(arguments.chatbox_format.clone());
+        sinks.push(Box::new(ChatboxSink::new(chatbox, sender.clone())));
+    }
+    if let Some(broker) = &arguments.mqtt {
+        sinks.push(match arguments.dry_run {
+            true => Box::new(DryRun("MQTT")),
+            false => Box::new(Mqtt::connect(
+                broker,
+                arguments.mqtt_topic_prefix.clone(),
+                arguments.mqtt_discovery,
+            )),
+        });
+    }
+    if let Some(path) = &arguments.status_file {
+        sinks.push(match arguments.dry_run {
+            true => Box::new(DryRun("the status file")),
+            false => Box::new(StatusFile::new(
+                path.clone(),
+                arguments.status_format.clone(),
+            )),
+        });
+    }
+    if let Some(path) = &arguments.history {
+        sinks.push(match arguments.dry_run {
+            true => Box::new(DryRun("the history")),
+            false => Box::new(History::new(path.clone(), arguments.history_rotate_daily)),
+        });
+    }
+    let session_log = match (&arguments.session_log, arguments.dry_run) {
+        (Some(_), true) => {
+            sinks.push(Box::new(DryRun("the session log")));
+            None
+        }
+        (session_log, _) => session_log.clone(),
+    };
+    sinks.push(Box::new(SessionSummary::new(
+        session_log,
+        arguments.warn_headset,
+        arguments.warn_controller,
+    )));
+    if arguments.status {
+        sinks.push(Box::new(Console::new(
+            model,
+            (arguments.warn_headset, arguments.critical_headset),
+            (arguments.warn_controller, arguments.critical_controller),
+        )));
+    }
+    let mut sinks = Sinks::new(sinks);
+    let mut estimator = arguments
+        .estimate
+        .then(|| Estimator::new(arguments.estimate_window));
+    let mut smoother = arguments.smooth.map(Smoother::new);
+    let mut interpolator = arguments.interpolate_steps.then(Interpolator::default);
+    let mut schedule = Schedule::new(arguments.cycle());
+    let mut headset = Cadence::new();
+    let mut controllers = Cadence::new();
+    // Only a headset read over adb has a charging state to check quickly
+    let mut charge = (supervisor.is_some() && !arguments.no_charge_poll)
+        .then(|| ChargeCheck::new(arguments.charge_poll));
+    let mut burst = Burst::new(match arguments.no_startup_burst {
+        true => Vec::new(),
+        false => arguments.startup_burst.clone(),
+    });
+    let events = &worker.events;
+    let tray = &worker.tray;
+    let last_report = &mut worker.last_report;
+    let mut last_levels: Option<BatteryLevels> =
+        last_report.as_ref().map(|report| report.levels.clone());
+    let mut free_storage = last_report.as_ref().and_then(|report| report.storage);
+    // The avatar worn, and the options of the config file edited last
+    let mut avatar: Option<String> = None;
+    let mut latest: Option<Arguments> = None;
+    let mut ready = false;
+    let mut paused = false;
+    let mut streak = FailureStreak::default();
+    let mut vrchat = arguments
+        .require_vrchat
+        .then(|| Vrchat::new(arguments.vrchat_process.clone()));
+    let mut quiet = QuietHours::new(arguments.pause_between.clone());
+    let mut forced = false;
+    // A button may send several requests
+    let mut refreshes = Debounce::new(Duration::from_secs(2));
+    let mut watcher = config::find(arguments.config.as_deref()).map(config::Watcher::new);
+
+    'poll: loop {
+        if let Some(config) = watcher.as_mut().and_then(config::Watcher::changed) {
+            match config.and_then(|config| reload(&arguments, config, &matches)) {
+                Ok(edited) => {
+                    arguments.interval = edited.interval;
+                    arguments.headset_interval = edited.headset_interval;
+                    arguments.controller_interval = edited.controller_interval;
+                    arguments.idle_interval = edited.idle_interval;
+                    arguments.vrchat_check_interval = edited.vrchat_check_interval;
+                    quiet.set_windows(edited.pause_between.clone());
+                    schedule.set_interval(arguments.cycle());
+                    thresholds.set_percentages(
+                        (edited.warn_headset, edited.critical_headset),
+                        (edited.warn_controller, edited.critical_controller),
+                    );
+                    sinks.reconfigure(&settings(&arguments, &edited, avatar.as_deref()));
+                    latest = Some(edited);
+                    info!("Applied the edited config file");
+                }
+                Err(error) => error!("Keeping the previous config: {error:#}"),
+            }
+        }
+        match vrchat.as_mut().map(Vrchat::check) {
+            Some(Presence::Absent) => {
+                // Leave adb alone until VRChat starts
+                match events.recv_timeout(arguments.vrchat_check_interval) {
+                    Ok(Event::AvatarChange(id)) => {
+                        change_avatar(id, &arguments, latest.as_ref(), &mut sinks, &mut avatar)
+                    }
+                    Ok(Event::Pause(pause)) => paused = pause,
+                    Ok(Event::Shutdown) => break 'poll,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        thread::sleep(arguments.vrchat_check_interval)
+                    }
+                    _ => {}
+                }
+                continue 'poll;
+            }
+            Some(Presence::Started) => {
+                schedule.restart();
+                headset.reset();
+                controllers.reset();
+                burst.arm();
+            }
+            _ => {}
+        }
+        // A refresh reads the levels once even during the quiet hours
+        if !std::mem::take(&mut forced) && quiet.check(Local::now().time()) {
+            schedule.restart();
+            headset.reset();
+            controllers.reset();
+            match events.recv_timeout(arguments.cycle()) {
+                Ok(Event::Refresh) if refreshes.take() => {
+                    sinks.resend();
+                    forced = true;
+                }
+                Ok(Event::AvatarChange(id)) => {
+                    change_avatar(id, &arguments, latest.as_ref(), &mut sinks, &mut avatar)
+                }
+                Ok(Event::Pause(pause)) => paused = pause,
+                Ok(Event::Shutdown) => break 'poll,
+                Err(RecvTimeoutError::Disconnected) => thread::sleep(arguments.cycle()),
+                _ => {}
+            }
+            continue 'poll;
+        }
+        if let Some(supervisor) = &mut supervisor {
+            let mut deferred = Vec::new();
+            let cycle = arguments.cycle();
+            let waited = supervisor.ensure_connected(&mut |timeout| {
+                wait_stale(
+                    events,
+                    timeout,
+                    cycle,
+                    &mut sinks,
+                    last_report,
+                    &mut paused,
+                    &mut deferred,
+                )
+            });
+            // What can't be handled without the headset is handled once it's back
+            for event in deferred {
+                let _ = worker.events_sender.send(event);
+            }
+            if waited == Waited::Stopped {
+                break 'poll;
+            }
+            if waited == Waited::Ready {
+                // The controllers may have been paired or updated meanwhile
+                let device = DeviceInfo::read(supervisor.adb());
+                device.log();
+                let mut status = status.lock().unwrap();
+                status.set_device(device);
+                status.metrics().reconnected();
+                source.reconnected();
+                schedule.restart();
+                headset.reset();
+                controllers.reset();
+            }
+        }
+
+        // In between their readings the headset or the controllers keep
+        // their last levels, which the change filter doesn't send again until
+        // the keepalive
+        let (headset_interval, controller_interval) = arguments.intervals();
+        let reading = Instant::now();
+        let levels = match (
+            &last_levels,
+            headset.take(headset_interval, arguments.cycle()),
+            controllers.take(controller_interval, arguments.cycle()),
+        ) {
+            (Some(last), true, false) => source
+                .get_headset_levels()
+                .map(|levels| levels.with_controllers_of(last)),
+            (Some(last), false, true) => source.get_controller_levels(last),
+            _ => source.get_levels(),
+        };
+        let took = reading.elapsed();
+        let failure = levels.as_ref().err().map(|error| error.root().name());
+        status.lock().unwrap().metrics().cycle(took, failure);
+        let succeeded = levels.is_ok();
+        let mut recovered = false;
+        match levels {
+            Ok(mut levels) => {
+                // The steps as read are what the controllers keep in between
+                // their readings, and what the stages are sent from
+                let steps = interpolator.as_mut().map(|interpolator| {
+                    let steps = levels.clone();
+                    interpolator.interpolate(&mut levels);
+                    steps
+                });
+                if let Some(smoother) = &mut smoother {
+                    smoother.smooth(&mut levels);
+                }
+                last_levels = Some(steps.clone().unwrap_or_else(|| levels.clone()));
+                if let Some(cycles) = streak.succeeded() {
+                    let plural = if cycles == 1 { "" } else { "s" };
+                    info!("Read the battery levels again after {cycles} failed cycle{plural}");
+                }
+                debug!(
+                    headset = levels.headset,
+                    left = levels.left_controller,
+                    right = levels.right_controller,
+                    charging = levels.headset_charging,
+                    temperature = levels.headset_temperature,
+                    voltage = levels.headset_voltage,
+                    trackers = ?levels.trackers,
+                    ?took,
+                    "Read the battery levels"
+                );
+                if !ready {
+                    service::notify_ready();
+                    ready = true;
+                }
+                schedule.set_interval(match levels.headset_awake {
+                    Some(false) => arguments.idle_interval.unwrap_or(arguments.cycle()),
+                    _ => arguments.cycle(),
+                });
+                status.lock().unwrap().succeeded(&levels);
+
+                let low = thresholds.update(&levels);
+                for alert in thresholds.take_alerts() {
+                    if let Some(notifier) = &notifier {
+                        notifier.notify(&alert);
+                    }
+                    if let Some(player) = &player {
+                        player.notify(&alert);
+                    }
+                    if let Some(webhook) = &webhook {
+                        webhook.notify(&alert);
+                    }
+                    if let Some(xsoverlay) = &mut xsoverlay {
+                        xsoverlay.notify(&alert);
+                    }
+                }
+                let stages = stager
+                    .as_mut()
+                    .map(|stager| stager.update(steps.as_ref().unwrap_or(&levels)));
+                let estimates = estimator
+                    .as_mut()
+                    .map(|estimator| estimator.update(&levels));
+                let mut report = BatteryReport::new(levels, low);
+                report.stages = stages;
+                report.estimates = estimates;
+                if let Some(free) = storage.as_ref().and_then(StorageReader::take) {
+                    free_storage = Some(free);
+                }
+                report.storage = free_storage;
+                if let Some(tray) = &tray {
+                    let _ = tray.send(report.clone());
+                }
+                if !paused {
+                    sinks.publish(&report);
+                    burst.sent();
+                }
+                *last_report = Some(report);
+            }
+            Err(error) => {
+                let name = error.root().name();
+                // A failure repeating every cycle is only summed up now and then
+                match streak.failed(&error) {
+                    Repeat::First => error!(
+                        ?took,
+                        kind = name,
+                        "Failed to retrieve battery levels: {error:#}"
+                    ),
+                    Repeat::Again => debug!(
+                        ?took,
+                        kind = name,
+                        "Failed to retrieve battery levels again: {error:#}"
+                    ),
+                    Repeat::Summary(cycles) => warn!(
+                        kind = name,
+                        cycles, "Failed to retrieve battery levels for {cycles} cycles: {error:#}"
+                    ),
+                }
+                status.lock().unwrap().failed();
+                headset.reset();
+                controllers.reset();
+                publish_stale(&mut sinks, last_report, paused);
+                // A hanging adb is treated like a lost connection as well,
+                // other failures may come from a headset which isn't ready
+                if let Some(supervisor) = &mut supervisor {
+                    if error.is_connection_lost() {
+                        supervisor.mark_lost();
+                    } else {
+                        let mut deferred = Vec::new();
+                        let cycle = arguments.cycle();
+                        let waited = supervisor.recover(&mut |timeout| {
+                            wait_stale(
+                                events,
+                                timeout,
+                                cycle,
+                                &mut sinks,
+                                last_report,
+                                &mut paused,
+                                &mut deferred,
+                            )
+                        });
+                        for event in deferred {
+                            let _ = worker.events_sender.send(event);
+                        }
+                        if waited == Waited::Stopped {
+                            break 'poll;
+                        }
+                        recovered = waited == Waited::Ready;
+                    }
+                }
+            }
+        }
+
+        let sent = sender.borrow_mut().take_counts();
+        let receivers = sender.borrow_mut().receivers();
+        let reachable = receivers
+            .iter()
+            .filter_map(|receiver| receiver.reachable)
+            .reduce(|all, reachable| all && reachable);
+        {
+            let mut status = status.lock().unwrap();
+            status.metrics().add_sent(sent);
+            status.set_receivers(receivers);
+        }
+        if let (true, Some(levels)) = (succeeded, &last_levels) {
+            log_cycle(levels, took, sent, reachable);
+        }
+        if recovered {
+            source.reconnected();
+            schedule.restart();
+            headset.reset();
+            controllers.reset();
+            continue 'poll;
+        }
+
+        schedule.check_overrun();
+        // An asleep headset isn't woken up, and a paused volta sends nothing
+        let asleep = last_levels
+            .as_ref()
+            .is_some_and(|levels| levels.headset_awake == Some(false));
+        let mut checks = charge.as_mut().filter(|_| !asleep && !paused);
+        if let Some(checks) = &mut checks {
+            checks.restart();
+        }
+        while let Some(timeout) = schedule.deadline().checked_duration_since(Instant::now()) {
+            let due = [
+                burst.due(),
+                checks
+                    .as_ref()
+                    .and_then(|checks| checks.due(schedule.deadline())),
+            ];
+            let timeout = due.into_iter().flatten().fold(timeout, |timeout, due| {
+                timeout.min(due.saturating_duration_since(Instant::now()))
+            });
+            match events.recv_timeout(timeout) {
+                Ok(Event::AvatarChange(id)) => {
+                    change_avatar(id, &arguments, latest.as_ref(), &mut sinks, &mut avatar);
+                    // VRChat is evidently ready to receive them
+                    burst.stop();
+                }
+                Ok(Event::Refresh) if refreshes.take() => {
+                    debug!("Reading the levels right away");
+                    sinks.resend();
+                    schedule.restart();
+                    headset.reset();
+                    controllers.reset();
+                    continue 'poll;
+                }
+                Ok(Event::Refresh) => debug!("Merging the refresh into the previous one"),
+                Ok(Event::Pause(pause)) => {
+                    paused = pause;
+                    info!("{} sending", if paused { "Paused" } else { "Resumed" });
+                }
+                Ok(Event::Shutdown) => break 'poll,
+                Err(error) => {
+                    if error == RecvTimeoutError::Disconnected {
+                        thread::sleep(timeout);
+                    }
+                    if burst.take() {
+                        if !paused {
+                            debug!("Sending the first levels again");
+                            sinks.avatar_changed();
+                        }
+                    } else if checks
+                        .as_mut()
+                        .is_some_and(|checks| checks.take(schedule.deadline()))
+                    {
+                        match source.get_headset_charging() {
+                            Some(Ok(charging))
+                                if last_levels
+                                    .as_ref()
+                                    .is_some_and(|levels| levels.headset_charging != charging) =>
+                            {
+                                info!(
+                                    charging,
+                                    "The headset's charging state changed, reading the levels right away"
+                                );
+                                schedule.restart();
+                                continue 'poll;
+                            }
+                            Some(Err(error)) => debug!("{error:#}"),
+                            _ => {}
+                        }
+                    } else if error == RecvTimeoutError::Timeout {
+                        break;
+                    }
+                }
+            }
+        }
+        schedule.advance();
+    }
+
+    sinks.shutdown();
+    info!("Stopped");
+
+    Ok(())
+}
+
+/// Reads the options again with the edited config file, where the command
+/// line and the environment still take precedence, and warns about changes
+/// which only apply after a restart
+fn reload(arguments: &Arguments, config: Config, matches: &ArgMatches) -> Result<Arguments> {
+    let edited = match arguments.device_entry {
+        Some(index) => for_entry(config, matches, index)?,
+        None => {
+            let mut edited = Arguments::from_arg_matches(matches)?;
+            edited.merge(config, matches)?;
+            edited
+        }
+    };
+
+    for (key, changed) in [
+        ("receiver", edited.receiver != arguments.receiver),
+        ("sender", edited.sender != arguments.sender),
+        (
+            "bind_interface",
+            edited.bind_interface != arguments.bind_interface,
+        ),
+        ("listen", edited.listen != arguments.listen),
+        ("max_rate", edited.max_rate != arguments.max_rate),
+        (
+            "message_spacing",
+            edited.message_spacing != arguments.message_spacing,
+        ),
+        ("source", edited.source != arguments.source),
+        ("device", edited.device != arguments.device),
+        ("profile", edited.profile != arguments.profile),
+        ("adb_connect", edited.adb_connect != arguments.adb_connect),
+        ("serial", edited.serial != arguments.serial),
+        ("adb_path", edited.adb_path != arguments.adb_path),
+        ("adb_backend", edited.adb_backend != arguments.adb_backend),
+        (
+            "persistent_shell",
+            edited.persistent_shell != arguments.persistent_shell,
+        ),
+        ("adb_timeout", edited.adb_timeout != arguments.adb_timeout),
+        (
+            "adb_restart_after",
+            edited.adb_restart_after != arguments.adb_restart_after,
+        ),
+    ] {
+        if changed {
+            warn!("Restart volta to apply the changed `{key}`");
+        }
+    }
+
+    Ok(edited)
+}
+
+/// Publishes the last report again with its age, unless sending is paused
+fn publish_stale(sinks: &mut Sinks, last_report: &mut Option<BatteryReport>, paused: bool) {
+    if let (Some(report), false) = (last_report.as_mut(), paused) {
+        report.age = report.timestamp.elapsed().unwrap_or_default();
+        sinks.publish_stale(report);
+    }
+}
+
+/// Waits for `timeout` for the headset like `wait_for_headset`, while the
+/// stale levels keep aging and volta keeps beating every `cycle`
+fn wait_stale(
+    events: &Receiver<Event>,
+    timeout: Duration,
+    cycle: Duration,
+    sinks: &mut Sinks,
+    last_report: &mut Option<BatteryReport>,
+    paused: &mut bool,
+    deferred: &mut Vec<Event>,
+) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        publish_stale(sinks, last_report, *paused);
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            return true;
+        }
+        if !wait_for_headset(events, timeout.min(cycle), paused, deferred) {
+            return false;
+        }
+    }
+}
+
+/// Waits for `timeout` while the headset is waited for, keeping up with
+/// pauses and returning false on a shutdown. Other events are put aside in
+/// `deferred`, as they need the headset.
+fn wait_for_headset(
+    events: &Receiver<Event>,
+    timeout: Duration,
+    paused: &mut bool,
+    deferred: &mut Vec<Event>,
+) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match events.recv_timeout(timeout) {
+            Ok(Event::Shutdown) => return false,
+            Ok(Event::Pause(pause)) => *paused = pause,
+            Ok(event) => deferred.push(event),
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return worker::sleep(timeout),
+        }
+    }
+}
+
+/// Starts the adb server and picks the device to talk to, `None` if volta
+/// was stopped while waiting for adb
+fn open_adb(arguments: &Arguments) -> Result<Option<Adb>> {
+    let program = adb_program(arguments);
+    let adb =
+        Adb::new(program.clone(), None, arguments.adb_timeout).with_backend(arguments.adb_backend);
+    // Wireless devices are only listed once the supervisor connected them
+    if !check_adb(&adb, arguments, arguments.adb_connect.is_none())? {
+        return Ok(None);
+    }
+    let serial = match arguments.adb_connect.as_deref() {
+        // A single reading has no supervisor to find the headset
+        Some(adb::AUTO_CONNECT) if arguments.once => {
+            let address = adb::discover_headset(&adb, arguments.serial.as_deref())?;
+            adb::connect(&adb, &address)?;
+            Some(address)
+        }
+        // The supervisor addresses the headset once it found it
+        Some(adb::AUTO_CONNECT) => None,
+        address => match arguments.serial.as_deref().or(address) {
+            Some(serial) => Some(serial.to_string()),
+            None => adb::select_device(&adb)?,
+        },
+    };
+
+    let adb = Adb::new(program, serial, arguments.adb_timeout)
+        .with_backend(arguments.adb_backend)
+        .with_persistent_shell(arguments.persistent_shell)
+        .with_retries(arguments.retries)
+        .with_restart_after(arguments.adb_restart_after);
+    adb.log_version();
+
+    Ok(Some(adb))
+}
+
+fn adb_program(arguments: &Arguments) -> PathBuf {
+    arguments.adb_path.clone().unwrap_or_else(adb::discover)
+}
+
+/// Runs the adb preflight, checking the headset too if `device` is set.
+/// Polling waits for adb and the headset to become ready, returning false if
+/// volta is stopped meanwhile, anything else exits with the problem's exit
+/// code.
+fn check_adb(adb: &Adb, arguments: &Arguments, device: bool) -> Result<bool> {
+    let serial = arguments.serial.as_deref();
+    if !arguments.once && arguments.command.is_none() {
+        return Ok(adb::wait_until_ready(adb, serial, device));
+    }
+
+    match adb::preflight(adb, serial, device) {
+        Err(error) => match error::kind(&error).and_then(|kind| Some((kind, kind.exit_code()?))) {
+            Some((kind, code)) => {
+                error!("{kind}");
+                std::process::exit(code);
+            }
+            None => Err(error),
+        },
+        Ok(()) => Ok(true),
+    }
+}
+
+fn print_levels(levels: &BatteryLevels, device: Option<&DeviceInfo>, json: bool) -> Result<()> {
+    if json {
+        let mut json = levels.to_json();
+        if let Some(device) = device {
+            json["device"] = device.to_json();
+        }
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
+
+    let percentage = |level: Option<f32>| {
+        level.map_or(String::from("disconnected"), |level| {
+            format!("{:.0}%", level * 100.0)
+        })
+    };
+    println!(
+        "Headset: {}{}",
+        match levels.headset {
+            Some(level) => percentage(Some(level)),
+            None => String::from("no battery"),
+        },
+        if levels.headset_charging {
+            " (charging)"
+        } else {
+            ""
+        }
+    );
+    if let Some(temperature) = levels.headset_temperature {
+        println!("Headset temperature: {temperature:.1} °C");
+    }
+    if let Some(voltage) = levels.headset_voltage {
+        println!("Headset voltage: {voltage:.2} V");
+    }
+    if let Some(health) = levels.headset_health.and_then(parse::health_name) {
+        println!("Headset battery health: {health}");
+    }
+    if let Some(cycles) = levels.headset_cycles {
+        println!("Headset battery cycles: {cycles}");
+    }
+    println!("Left controller: {}", percentage(levels.left_controller));
+    println!("Right controller: {}", percentage(levels.right_controller));
+
+    Ok(())
+}
+
+/// Creates the sender to the receivers from `socket`
+fn osc_sender(arguments: &Arguments, socket: UdpSocket) -> Result<OscSender> {
+    let mut sender = match arguments.dry_run {
+        true => {
+            info!("Dry run, the messages are logged instead of sent");
+            OscSender::dry_run(!arguments.no_bundle)
+        }
+        false => OscSender::new(
+            socket,
+            arguments.receiver.clone(),
+            !arguments.no_bundle,
+            arguments.discovery_timeout,
+        )?,
+    };
+    if arguments.max_rate.is_some() || arguments.message_spacing > 0 {
+        sender = sender.with_rate_limit(RateLimit::new(
+            arguments.max_rate,
+            Duration::from_millis(arguments.message_spacing),
+            arguments.cycle(),
+        ));
+    }
+
+    Ok(sender)
+}
+
+/// The avatar parameters from the options
+fn parameters(arguments: &Arguments) -> Parameters {
+    Parameters {
+        param_type: arguments.param_type,
+        scale: arguments.scale,
+        offline: arguments.offline_value,
+        temp_range: arguments.temp_range,
+        headset: arguments.headset_param.clone(),
+        left: arguments.left_param.clone(),
+        right: arguments.right_param.clone(),
+        charging: arguments.charging_param.clone(),
+        temperature: arguments.temp_param.clone(),
+        voltage: arguments.voltage_param.clone(),
+        left_connected: arguments.left_connected_param.clone(),
+        right_connected: arguments.right_connected_param.clone(),
+        low_headset: arguments.low_headset_param.clone(),
+        low_left: arguments.low_left_param.clone(),
+        low_right: arguments.low_right_param.clone(),
+        tracker: arguments.tracker_param.clone(),
+        steamvr: arguments.steamvr_param.clone(),
+        awake: arguments.awake_param.clone(),
+        sleep: arguments.detect_sleep,
+        stage_headset: arguments.stage_headset_param.clone(),
+        stage_left: arguments.stage_left_param.clone(),
+        stage_right: arguments.stage_right_param.clone(),
+        stages: arguments.stages,
+        stages_only: arguments.stages_only,
+        minutes_headset: arguments.minutes_headset_param.clone(),
+        minutes_left: arguments.minutes_left_param.clone(),
+        minutes_right: arguments.minutes_right_param.clone(),
+        drain_headset: arguments.drain_headset_param.clone(),
+        drain_left: arguments.drain_left_param.clone(),
+        drain_right: arguments.drain_right_param.clone(),
+        estimate: arguments.estimate,
+        data_age: arguments.data_age_param.clone(),
+        max_data_age: arguments.max_data_age,
+        stale: arguments.stale_behavior,
+        heartbeat: arguments.heartbeat,
+        alive: arguments.alive_param.clone(),
+        beat: arguments.beat_param.clone(),
+        version: arguments.version_param.clone(),
+        storage: arguments.storage_param.clone(),
+        report_storage: arguments.report_storage,
+        health: arguments.health_param.clone(),
+        report_health: arguments.report_health,
+    }
+}
+
+/// Records the avatar VRChat switched to as `avatar` and sends to its
+/// addresses from now on, with the `edited` options if the config changed
+fn change_avatar(
+    id: String,
+    arguments: &Arguments,
+    edited: Option<&Arguments>,
+    sinks: &mut Sinks,
+    avatar: &mut Option<String>,
+) {
+    info!("Avatar changed to {id}");
+    let edited = edited.unwrap_or(arguments);
+    if !edited.avatars.is_empty() {
+        match edited.avatars.get(&id) {
+            Some(entry) => info!(
+                "Sending to the addresses of `{}`",
+                entry.name.as_deref().unwrap_or(&id)
+            ),
+            None => info!("Sending to the default addresses"),
+        }
+        sinks.reconfigure(&settings(arguments, edited, Some(&id)));
+    }
+    *avatar = Some(id);
+    sinks.avatar_changed();
+}
+
+/// What the sinks publish while `avatar` is worn, with the edited options
+/// except for the ones which only apply after a restart
+fn settings(arguments: &Arguments, edited: &Arguments, avatar: Option<&str>) -> Settings {
+    let entry = avatar.and_then(|avatar| edited.avatars.get(avatar));
+    // Validated while merging the config
+    let edited = match entry.map(|entry| edited.for_avatar(entry)) {
+        Some(Ok(arguments)) => arguments,
+        _ => edited.clone(),
+    };
+    // What is sent at all only changes with a restart, as the stages and
+    // estimates are set up once
+    let parameters = Parameters {
+        sleep: arguments.detect_sleep,
+        stages: arguments.stages,
+        stages_only: arguments.stages_only,
+        estimate: arguments.estimate,
+        report_storage: arguments.report_storage,
+        report_health: arguments.report_health,
+        ..parameters(&edited)
+    };
+    Settings {
+        parameters,
+        chatbox_format: edited.chatbox_format,
+    }
+}
+
+/// Messages to every address volta can send to, for describing them over
+/// OSCQuery and in the avatar config
+fn sent_parameters(arguments: &Arguments) -> Vec<OscMessage> {
+    let mut messages = parameters(arguments).all();
+    if arguments.chatbox {
+        let levels = BatteryLevels {
+            headset: Some(1.0),
+            left_controller: Some(1.0),
+            right_controller: Some(1.0),
+            headset_charging: false,
+            headset_temperature: None,
+            headset_voltage: None,
+            headset_health: None,
+            headset_cycles: None,
+            headset_awake: None,
+            trackers: Vec::new(),
+            steamvr: Vec::new(),
+        };
+        messages.extend(Chatbox::new(arguments.chatbox_format.clone()).message(&levels));
+    }
+
+    messages
 }
@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Readings the rolling average of their duration covers
+const AVERAGED: usize = 20;
+
+/// OSC messages sent and sends which failed since they were last taken
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SendCounts {
+    pub messages: u64,
+    pub failures: u64,
+}
+
+/// Counters and gauges of the polling, which are always kept as they're
+/// cheap, and exposed for Prometheus by the HTTP server
+#[derive(Debug, Default)]
+pub struct Metrics {
+    cycles: u64,
+    /// Failed cycles by the name of the kind of failure
+    failed: BTreeMap<&'static str, u64>,
+    /// How long the last readings took, the latest last
+    queries: VecDeque<Duration>,
+    sent: SendCounts,
+    reconnects: u64,
+}
+
+impl Metrics {
+    /// Counts a cycle whose reading took `took`, and the kind of failure if
+    /// it failed
+    pub fn cycle(&mut self, took: Duration, failure: Option<&'static str>) {
+        self.cycles += 1;
+        if let Some(kind) = failure {
+            *self.failed.entry(kind).or_default() += 1;
+        }
+        if self.queries.len() == AVERAGED {
+            self.queries.pop_front();
+        }
+        self.queries.push_back(took);
+    }
+
+    pub fn add_sent(&mut self, sent: SendCounts) {
+        self.sent.messages += sent.messages;
+        self.sent.failures += sent.failures;
+    }
+
+    pub fn reconnected(&mut self) {
+        self.reconnects += 1;
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(text, "# HELP {name} {help}\n# TYPE {name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(text, "{name}{labels} {value}");
+            }
+        };
+        let sample = |value: String| [(String::new(), value)];
+        let seconds = |duration: Duration| duration.as_secs_f64().to_string();
+
+        metric(
+            "volta_cycles_total",
+            "counter",
+            "Polling cycles run",
+            &sample(self.cycles.to_string()),
+        );
+        let failed: Vec<_> = self
+            .failed
+            .iter()
+            .map(|(kind, count)| (format!("{{kind=\"{kind}\"}}"), count.to_string()))
+            .collect();
+        metric(
+            "volta_cycles_failed_total",
+            "counter",
+            "Polling cycles which failed to read the levels, by the kind of failure",
+            &failed,
+        );
+        if let Some(&last) = self.queries.back() {
+            let average = self.queries.iter().sum::<Duration>() / self.queries.len() as u32;
+            metric(
+                "volta_adb_query_seconds",
+                "gauge",
+                "How long reading the levels took in the last cycle",
+                &sample(seconds(last)),
+            );
+            metric(
+                "volta_adb_query_average_seconds",
+                "gauge",
+                &format!(
+                    "How long reading the levels took on average in the last {AVERAGED} cycles"
+                ),
+                &sample(seconds(average)),
+            );
+        }
+        metric(
+            "volta_osc_messages_sent_total",
+            "counter",
+            "OSC messages sent",
+            &sample(self.sent.messages.to_string()),
+        );
+        metric(
+            "volta_osc_send_failures_total",
+            "counter",
+            "Sends of OSC messages which failed",
+            &sample(self.sent.failures.to_string()),
+        );
+        metric(
+            "volta_reconnects_total",
+            "counter",
+            "Times the connection to the headset was lost and restored",
+            &sample(self.reconnects.to_string()),
+        );
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_metrics() {
+        let mut metrics = Metrics::default();
+        metrics.cycle(Duration::from_millis(100), None);
+        metrics.cycle(Duration::from_millis(300), Some("dump_empty"));
+        metrics.add_sent(SendCounts {
+            messages: 12,
+            failures: 1,
+        });
+
+        let text = metrics.render();
+        for line in [
+            "# TYPE volta_cycles_total counter",
+            "volta_cycles_total 2",
+            "volta_cycles_failed_total{kind=\"dump_empty\"} 1",
+            "volta_adb_query_seconds 0.3",
+            "volta_adb_query_average_seconds 0.2",
+            "volta_osc_messages_sent_total 12",
+            "volta_osc_send_failures_total 1",
+            "volta_reconnects_total 0",
+        ] {
+            assert!(
+                text.lines().any(|candidate| candidate == line),
+                "{line} in {text}"
+            );
+        }
+    }
+
+    #[test]
+    fn averages_the_last_readings() {
+        let mut metrics = Metrics::default();
+        metrics.cycle(Duration::from_secs(10), None);
+        for _ in 0..AVERAGED {
+            metrics.cycle(Duration::from_secs(1), None);
+        }
+
+        assert!(metrics
+            .render()
+            .contains("volta_adb_query_average_seconds 1\n"));
+    }
+}
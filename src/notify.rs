@@ -0,0 +1,47 @@
+use crate::threshold::Alert;
+use notify_rust::Notification;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use tracing::debug;
+
+/// Shows desktop notifications for alerts on a background thread, so a
+/// notification daemon which is slow or missing can't hold up the loop
+pub struct Notifier {
+    alerts: Sender<Alert>,
+}
+
+impl Notifier {
+    pub fn spawn() -> Self {
+        let (alerts, receiver) = mpsc::channel::<Alert>();
+        thread::spawn(move || {
+            for alert in receiver {
+                if let Err(error) = show(&alert) {
+                    debug!("Failed to show a notification: {error}");
+                }
+            }
+        });
+
+        Self { alerts }
+    }
+
+    /// Shows a notification for the alert, on a best-effort basis
+    pub fn notify(&self, alert: &Alert) {
+        let _ = self.alerts.send(alert.clone());
+    }
+}
+
+fn show(alert: &Alert) -> Result<(), notify_rust::error::Error> {
+    let mut notification = Notification::new();
+    notification
+        .appname("VRC Volta")
+        .summary("VRC Volta")
+        .body(&alert.to_string());
+
+    // Only the freedesktop notification spec knows about urgency
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if alert.severity == crate::threshold::Severity::Critical {
+        notification.urgency(notify_rust::Urgency::Critical);
+    }
+
+    notification.show().map(drop)
+}
@@ -0,0 +1,1909 @@
+use crate::changes::ChangeFilter;
+use crate::error::VoltaError;
+use crate::estimate::{Estimate, Estimates};
+use crate::metrics::SendCounts;
+use crate::parse::HEALTH_GOOD;
+use crate::rate::RateLimit;
+use crate::sink::{BatteryReport, Settings, Sink};
+use crate::source::BatteryLevels;
+use crate::stages::{self, Stages};
+use crate::threshold::LowBattery;
+use crate::{oscquery, source};
+use anyhow::{anyhow, ensure, Context, Result};
+use clap::ValueEnum;
+use rosc::{encoder, OscBundle, OscMessage, OscPacket, OscTime, OscType};
+use std::cell::RefCell;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// The special OSC time tag for bundles which should be applied on receipt
+const IMMEDIATELY: OscTime = OscTime {
+    seconds: 0,
+    fractional: 1,
+};
+
+/// Consecutive failures after which further failures are only logged at
+/// debug level, so a receiver which isn't running doesn't flood the log
+const MAX_LOGGED_FAILURES: u32 = 10;
+
+const BIND_ATTEMPTS: u32 = 3;
+const BIND_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Interval after which the receiver's host name is resolved again, so a host
+/// whose address changed keeps working
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Consecutive failures after which the receiver is resolved again
+const RESOLVE_FAILURES: u32 = 3;
+
+/// Receiver used when VRChat can't be discovered through OSCQuery
+const FALLBACK_RECEIVER: &str = "127.0.0.1:9000";
+
+/// OSC type the battery levels are sent as
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ParamType {
+    /// Floats from 0.0 to 1.0
+    Float,
+    /// Integer percentages from 0 to 100
+    Int,
+    /// Floats, and integer percentages to the address with an `Int` suffix
+    Both,
+}
+
+/// Range the float battery levels are sent across
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LevelScale {
+    /// From 0.0 for empty to 1.0 for full
+    #[value(name = "0-1")]
+    Unit,
+    /// From 0.0 for empty to 100.0 for full, as some avatar prefabs expect
+    #[value(name = "0-100")]
+    Percent,
+}
+
+impl LevelScale {
+    /// A level of 0.0 to 1.0 on this scale
+    pub fn apply(self, level: f32) -> f32 {
+        match self {
+            LevelScale::Unit => level,
+            LevelScale::Percent => level * 100.0,
+        }
+    }
+}
+
+/// What is sent to the level parameters of a controller which isn't
+/// connected, besides false to its connection parameter
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OfflineValue {
+    /// This value, as it is
+    Value(f32),
+    /// The controller's last known level
+    Hold,
+    /// Nothing, leaving the parameters at their last value
+    Skip,
+}
+
+/// Parses an offline value, `hold`, `skip` or a float like `-1`
+pub fn parse_offline_value(value: &str) -> Result<OfflineValue, String> {
+    match value.trim() {
+        "hold" => Ok(OfflineValue::Hold),
+        "skip" => Ok(OfflineValue::Skip),
+        value => value
+            .parse()
+            .ok()
+            .filter(|value: &f32| value.is_finite())
+            .map(OfflineValue::Value)
+            .ok_or_else(|| format!("`{value}` isn't `hold`, `skip` or a number")),
+    }
+}
+
+/// What is sent while the levels can't be read, besides their age
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum StaleBehavior {
+    /// Keep sending the last levels
+    Resend,
+    /// Send nothing until the levels are read again
+    Hold,
+    /// Send every level as empty and every device as disconnected
+    Zero,
+}
+
+/// Builds the messages for a battery level sent to `addr` as `param_type`,
+/// the floats on `scale`
+pub fn level_messages(
+    param_type: ParamType,
+    scale: LevelScale,
+    addr: &str,
+    level: f32,
+) -> Vec<OscMessage> {
+    let float = || OscMessage {
+        addr: addr.to_string(),
+        args: vec![OscType::Float(scale.apply(level))],
+    };
+    let int = |addr: String| OscMessage {
+        addr,
+        args: vec![OscType::Int(source::percent(level))],
+    };
+
+    match param_type {
+        ParamType::Float => vec![float()],
+        ParamType::Int => vec![int(addr.to_string())],
+        ParamType::Both => vec![float(), int(format!("{addr}Int"))],
+    }
+}
+
+/// The addresses of every avatar parameter and how levels are sent to them
+#[derive(Clone, Debug)]
+pub struct Parameters {
+    pub param_type: ParamType,
+    pub scale: LevelScale,
+    pub offline: OfflineValue,
+    /// Range of °C the temperature is mapped to 0.0 to 1.0 across, it's
+    /// sent in °C without one
+    pub temp_range: Option<(f32, f32)>,
+    pub headset: String,
+    pub left: String,
+    pub right: String,
+    pub charging: String,
+    pub temperature: String,
+    pub voltage: String,
+    pub storage: String,
+    /// Whether the free storage is sent
+    pub report_storage: bool,
+    pub health: String,
+    /// Whether the battery health is sent
+    pub report_health: bool,
+    pub left_connected: String,
+    pub right_connected: String,
+    pub low_headset: String,
+    pub low_left: String,
+    pub low_right: String,
+    /// Prefix of the tracker level addresses, which are numbered from 1
+    pub tracker: String,
+    /// Prefix of the SteamVR device addresses, followed by the device's name
+    pub steamvr: String,
+    pub awake: String,
+    /// Whether the headset's awake state is sent
+    pub sleep: bool,
+    pub stage_headset: String,
+    pub stage_left: String,
+    pub stage_right: String,
+    /// Number of stages the levels are quantized into, if they're sent
+    pub stages: Option<u8>,
+    /// Send only the stages of the headset and controllers, not their levels
+    pub stages_only: bool,
+    pub minutes_headset: String,
+    pub minutes_left: String,
+    pub minutes_right: String,
+    pub drain_headset: String,
+    pub drain_left: String,
+    pub drain_right: String,
+    /// Whether the estimates are sent
+    pub estimate: bool,
+    /// Minutes since the levels were last read
+    pub data_age: String,
+    /// Minutes the age is capped at
+    pub max_data_age: u32,
+    pub stale: StaleBehavior,
+    /// Whether volta's heartbeat, alive state and version are sent
+    pub heartbeat: bool,
+    pub alive: String,
+    pub beat: String,
+    pub version: String,
+}
+
+impl Parameters {
+    /// Builds a message for every parameter, paired with a description for
+    /// logs. Trackers are numbered by their position in `trackers`.
+    pub fn messages(
+        &self,
+        report: &BatteryReport,
+        trackers: &[String],
+    ) -> Vec<(&'static str, OscMessage)> {
+        let BatteryReport {
+            levels,
+            low,
+            stages,
+            estimates,
+            storage,
+            ..
+        } = report;
+
+        let mut messages = Vec::new();
+        if !self.stages_only {
+            // A headset without a battery has no level to send, not even an
+            // offline one
+            if let Some(headset) = levels.headset {
+                messages.extend(self.level_messages(
+                    "headset battery level",
+                    &self.headset,
+                    headset,
+                ));
+            }
+            for (description, addr, level) in [
+                (
+                    "left controller battery level",
+                    &self.left,
+                    levels.left_controller,
+                ),
+                (
+                    "right controller battery level",
+                    &self.right,
+                    levels.right_controller,
+                ),
+            ] {
+                match (level, self.offline) {
+                    (Some(level), _) => {
+                        messages.extend(self.level_messages(description, addr, level))
+                    }
+                    (None, OfflineValue::Value(value)) => {
+                        messages.extend(self.offline_messages(description, addr, value))
+                    }
+                    // The last levels are held by the sink, which knows them
+                    (None, OfflineValue::Hold | OfflineValue::Skip) => {}
+                }
+            }
+        }
+        if let Some(stages) = stages {
+            messages.extend(self.stage_messages(stages));
+        }
+        if let Some(estimates) = estimates {
+            messages.extend(self.estimate_messages(estimates));
+        }
+        messages.extend([
+            (
+                "left controller connection state",
+                message(
+                    &self.left_connected,
+                    OscType::Bool(levels.left_controller.is_some()),
+                ),
+            ),
+            (
+                "right controller connection state",
+                message(
+                    &self.right_connected,
+                    OscType::Bool(levels.right_controller.is_some()),
+                ),
+            ),
+            (
+                "headset charging state",
+                message(&self.charging, OscType::Bool(levels.headset_charging)),
+            ),
+            (
+                "headset low battery state",
+                message(&self.low_headset, OscType::Bool(low.headset)),
+            ),
+        ]);
+        messages.push(self.age_message(report));
+        if let Some(awake) = levels.headset_awake {
+            messages.push((
+                "headset awake state",
+                message(&self.awake, OscType::Bool(awake)),
+            ));
+        }
+        if let Some(temperature) = levels.headset_temperature {
+            let temperature = match self.temp_range {
+                Some((min, max)) => ((temperature - min) / (max - min)).clamp(0.0, 1.0),
+                None => temperature,
+            };
+            messages.push((
+                "headset battery temperature",
+                message(&self.temperature, OscType::Float(temperature)),
+            ));
+        }
+        if let Some(voltage) = levels.headset_voltage {
+            messages.push((
+                "headset battery voltage",
+                message(&self.voltage, OscType::Float(voltage)),
+            ));
+        }
+        if let Some(free) = storage {
+            messages.push((
+                "headset free storage",
+                message(&self.storage, OscType::Float(*free)),
+            ));
+        }
+        if let Some(health) = levels.headset_health.filter(|_| self.report_health) {
+            messages.push((
+                "headset battery health",
+                message(&self.health, OscType::Int(health.into())),
+            ));
+        }
+        if let Some(low) = low.left_controller {
+            messages.push((
+                "left controller low battery state",
+                message(&self.low_left, OscType::Bool(low)),
+            ));
+        }
+        if let Some(low) = low.right_controller {
+            messages.push((
+                "right controller low battery state",
+                message(&self.low_right, OscType::Bool(low)),
+            ));
+        }
+        for (name, level) in &levels.trackers {
+            if let Some(number) = trackers.iter().position(|tracker| tracker == name) {
+                messages.extend(self.level_messages(
+                    "tracker battery level",
+                    &self.tracker_address(number),
+                    *level,
+                ));
+            }
+        }
+        for device in &levels.steamvr {
+            let address = format!("{}{}", self.steamvr, device.name);
+            messages.extend(self.level_messages(
+                "SteamVR device battery level",
+                &address,
+                device.level,
+            ));
+            messages.push((
+                "SteamVR device charging state",
+                message(
+                    &format!("{address}Charging"),
+                    OscType::Bool(device.charging),
+                ),
+            ));
+        }
+
+        messages
+    }
+
+    fn stage_messages(&self, stages: &Stages) -> Vec<(&'static str, OscMessage)> {
+        let stage = |addr, stage: u8| message(addr, OscType::Int(i32::from(stage)));
+        let mut messages = Vec::new();
+        if let Some(headset) = stages.headset {
+            messages.push(("headset battery stage", stage(&self.stage_headset, headset)));
+        }
+        if let Some(left) = stages.left_controller {
+            messages.push((
+                "left controller battery stage",
+                stage(&self.stage_left, left),
+            ));
+        }
+        if let Some(right) = stages.right_controller {
+            messages.push((
+                "right controller battery stage",
+                stage(&self.stage_right, right),
+            ));
+        }
+
+        messages
+    }
+
+    /// Builds the messages for the devices which have an estimate, with the
+    /// minutes capped at 255, the largest Int VRChat syncs
+    fn estimate_messages(&self, estimates: &Estimates) -> Vec<(&'static str, OscMessage)> {
+        let devices = [
+            (
+                ("headset minutes remaining", &self.minutes_headset),
+                ("headset drain rate", &self.drain_headset),
+                estimates.headset,
+            ),
+            (
+                ("left controller minutes remaining", &self.minutes_left),
+                ("left controller drain rate", &self.drain_left),
+                estimates.left_controller,
+            ),
+            (
+                ("right controller minutes remaining", &self.minutes_right),
+                ("right controller drain rate", &self.drain_right),
+                estimates.right_controller,
+            ),
+        ];
+
+        devices
+            .into_iter()
+            .filter_map(|(minutes, drain, estimate)| Some((minutes, drain, estimate?)))
+            .flat_map(|((minutes, minutes_addr), (drain, drain_addr), estimate)| {
+                [
+                    (
+                        minutes,
+                        message(minutes_addr, OscType::Int(estimate.minutes.min(255) as i32)),
+                    ),
+                    (drain, message(drain_addr, OscType::Float(estimate.drain))),
+                ]
+            })
+            .collect()
+    }
+
+    fn age_message(&self, report: &BatteryReport) -> (&'static str, OscMessage) {
+        let minutes = report.age_minutes().min(u64::from(self.max_data_age));
+        (
+            "data age",
+            message(&self.data_age, OscType::Int(minutes as i32)),
+        )
+    }
+
+    /// Builds the messages for a report published again after reading the
+    /// levels failed, depending on the stale behavior
+    pub fn stale_messages(
+        &self,
+        report: &BatteryReport,
+        trackers: &[String],
+        steamvr: &[String],
+    ) -> Vec<(&'static str, OscMessage)> {
+        match self.stale {
+            StaleBehavior::Resend => self.messages(report, trackers),
+            StaleBehavior::Hold => vec![self.age_message(report)],
+            StaleBehavior::Zero => {
+                let mut messages = self.reset_messages(0.0, trackers.len(), steamvr);
+                messages.push(self.age_message(report));
+                messages
+            }
+        }
+    }
+
+    /// Builds the messages showing that volta runs, with `beat` counting up
+    /// every cycle so a stalled volta shows as a beat which stopped changing
+    pub fn heartbeat_messages(&self, beat: u8) -> Vec<(&'static str, OscMessage)> {
+        if !self.heartbeat {
+            return Vec::new();
+        }
+        vec![
+            ("alive state", message(&self.alive, OscType::Bool(true))),
+            (
+                "heartbeat",
+                message(&self.beat, OscType::Int(i32::from(beat))),
+            ),
+            ("version", message(&self.version, OscType::Int(version()))),
+        ]
+    }
+
+    fn tracker_address(&self, index: usize) -> String {
+        format!("{}{}", self.tracker, index + 1)
+    }
+
+    /// Builds messages which show every device as disconnected with every
+    /// level at `value`, including the first `trackers` tracker levels and
+    /// the `steamvr` devices, for when volta stops
+    pub fn reset_messages(
+        &self,
+        value: f32,
+        trackers: usize,
+        steamvr: &[String],
+    ) -> Vec<(&'static str, OscMessage)> {
+        let mut messages = Vec::new();
+        if !self.stages_only {
+            messages.extend(self.level_messages("headset battery level", &self.headset, value));
+            messages.extend(self.level_messages(
+                "left controller battery level",
+                &self.left,
+                value,
+            ));
+            messages.extend(self.level_messages(
+                "right controller battery level",
+                &self.right,
+                value,
+            ));
+        }
+        if let Some(count) = self.stages {
+            let stage = stages::quantize(value, count, 0.0, None);
+            messages.extend(self.stage_messages(&Stages {
+                headset: Some(stage),
+                left_controller: Some(stage),
+                right_controller: Some(stage),
+            }));
+        }
+        if self.estimate {
+            messages.extend(self.estimate_messages(&empty_estimates()));
+        }
+        messages.extend([
+            (
+                "left controller connection state",
+                message(&self.left_connected, OscType::Bool(false)),
+            ),
+            (
+                "right controller connection state",
+                message(&self.right_connected, OscType::Bool(false)),
+            ),
+            (
+                "headset charging state",
+                message(&self.charging, OscType::Bool(false)),
+            ),
+            (
+                "headset low battery state",
+                message(&self.low_headset, OscType::Bool(false)),
+            ),
+            (
+                "left controller low battery state",
+                message(&self.low_left, OscType::Bool(false)),
+            ),
+            (
+                "right controller low battery state",
+                message(&self.low_right, OscType::Bool(false)),
+            ),
+        ]);
+        if self.sleep {
+            messages.push((
+                "headset awake state",
+                message(&self.awake, OscType::Bool(false)),
+            ));
+        }
+        for number in 0..trackers {
+            messages.extend(self.level_messages(
+                "tracker battery level",
+                &self.tracker_address(number),
+                value,
+            ));
+        }
+        for name in steamvr {
+            messages.extend(self.level_messages(
+                "SteamVR device battery level",
+                &format!("{}{name}", self.steamvr),
+                value,
+            ));
+        }
+
+        messages
+    }
+
+    /// A message to every address volta can send to, for describing them
+    /// over OSCQuery and in the avatar config
+    pub fn all(&self) -> Vec<OscMessage> {
+        let levels = BatteryLevels {
+            headset: Some(1.0),
+            left_controller: Some(1.0),
+            right_controller: Some(1.0),
+            headset_charging: false,
+            headset_temperature: Some(0.0),
+            headset_voltage: Some(0.0),
+            headset_health: self.report_health.then_some(HEALTH_GOOD),
+            headset_cycles: None,
+            headset_awake: self.sleep.then_some(true),
+            trackers: Vec::new(),
+            steamvr: Vec::new(),
+        };
+        let low = LowBattery {
+            headset: false,
+            left_controller: Some(false),
+            right_controller: Some(false),
+        };
+
+        let mut report = BatteryReport::new(levels, low);
+        report.stages = self.stages.map(|_| Stages {
+            headset: Some(0),
+            left_controller: Some(0),
+            right_controller: Some(0),
+        });
+
+        report.estimates = self.estimate.then(empty_estimates);
+        report.storage = self.report_storage.then_some(1.0);
+
+        let mut messages = self.messages(&report, &[]);
+        messages.extend(self.heartbeat_messages(0));
+        messages.into_iter().map(|(_, message)| message).collect()
+    }
+
+    /// Builds the messages for a battery level in the configured parameter
+    /// type
+    fn level_messages(
+        &self,
+        description: &'static str,
+        addr: &str,
+        level: f32,
+    ) -> Vec<(&'static str, OscMessage)> {
+        level_messages(self.param_type, self.scale, addr, level)
+            .into_iter()
+            .map(|message| (description, message))
+            .collect()
+    }
+
+    /// Builds the messages for the offline `value` of a level, which is sent
+    /// as it is rather than on the scale, and rounded for Int parameters
+    fn offline_messages(
+        &self,
+        description: &'static str,
+        addr: &str,
+        value: f32,
+    ) -> Vec<(&'static str, OscMessage)> {
+        let float = || (description, message(addr, OscType::Float(value)));
+        let int = |addr: &str| {
+            (
+                description,
+                message(addr, OscType::Int(value.round() as i32)),
+            )
+        };
+        match self.param_type {
+            ParamType::Float => vec![float()],
+            ParamType::Int => vec![int(addr)],
+            ParamType::Both => vec![float(), int(&format!("{addr}Int"))],
+        }
+    }
+
+    /// Builds the level messages of the controllers which aren't connected
+    /// in `levels` from their `held` last known levels, when they're held
+    fn held_messages(
+        &self,
+        levels: &BatteryLevels,
+        held: (Option<f32>, Option<f32>),
+    ) -> Vec<(&'static str, OscMessage)> {
+        if self.offline != OfflineValue::Hold || self.stages_only {
+            return Vec::new();
+        }
+        let mut messages = Vec::new();
+        if let (None, Some(level)) = (levels.left_controller, held.0) {
+            messages.extend(self.level_messages(
+                "left controller battery level",
+                &self.left,
+                level,
+            ));
+        }
+        if let (None, Some(level)) = (levels.right_controller, held.1) {
+            messages.extend(self.level_messages(
+                "right controller battery level",
+                &self.right,
+                level,
+            ));
+        }
+        messages
+    }
+}
+
+fn message(addr: &str, arg: OscType) -> OscMessage {
+    OscMessage {
+        addr: addr.to_string(),
+        args: vec![arg],
+    }
+}
+
+/// volta's version as `major * 100 + minor`, capped at 255 as the largest Int
+/// VRChat syncs
+fn version() -> i32 {
+    let part = |part: &str| part.parse::<i32>().unwrap_or(0);
+    let version =
+        part(env!("CARGO_PKG_VERSION_MAJOR")) * 100 + part(env!("CARGO_PKG_VERSION_MINOR"));
+    version.min(255)
+}
+
+/// Estimates of no drain and no time remaining for every device
+fn empty_estimates() -> Estimates {
+    let estimate = Some(Estimate {
+        drain: 0.0,
+        minutes: 0,
+    });
+
+    Estimates {
+        headset: estimate,
+        left_controller: estimate,
+        right_controller: estimate,
+    }
+}
+
+/// Publishes the battery levels to the avatar parameters, skipping the ones
+/// which haven't changed since they were last sent
+pub struct OscSink {
+    sender: Rc<RefCell<OscSender>>,
+    parameters: Parameters,
+    changes: ChangeFilter,
+    /// Level every parameter is reset to when volta stops, if it resets them
+    reset_value: Option<f32>,
+    last_messages: Option<Vec<(&'static str, OscMessage)>>,
+    /// The report the last messages were built from, and whether it was stale
+    last_report: Option<(BatteryReport, bool)>,
+    /// Every tracker seen so far, numbered in the order they showed up so a
+    /// tracker keeps its address while others come and go
+    trackers: Vec<String>,
+    /// The heartbeat sent with the next messages
+    beat: u8,
+    /// Every SteamVR device seen so far, to reset when volta stops
+    steamvr: Vec<String>,
+    /// The last known levels of the left and right controller
+    held: (Option<f32>, Option<f32>),
+}
+
+impl OscSink {
+    pub fn new(
+        sender: Rc<RefCell<OscSender>>,
+        parameters: Parameters,
+        changes: ChangeFilter,
+        reset_value: Option<f32>,
+    ) -> Self {
+        Self {
+            sender,
+            parameters,
+            changes,
+            reset_value,
+            last_messages: None,
+            last_report: None,
+            trackers: Vec::new(),
+            beat: 0,
+            steamvr: Vec::new(),
+            held: (None, None),
+        }
+    }
+
+    /// Adds the heartbeat to the messages of a cycle and advances it
+    fn with_heartbeat(
+        &mut self,
+        mut messages: Vec<(&'static str, OscMessage)>,
+    ) -> Vec<(&'static str, OscMessage)> {
+        messages.extend(self.parameters.heartbeat_messages(self.beat));
+        self.beat = self.beat.wrapping_add(1);
+        messages
+    }
+
+    /// Sends the messages, and forgets the ones the rate limit dropped so
+    /// the change filter lets them through next cycle
+    fn send(&mut self, messages: Vec<(&str, OscMessage)>) -> Result<()> {
+        let mut sender = self.sender.borrow_mut();
+        let sent = sender.send(messages);
+        for address in sender.take_dropped() {
+            self.changes.forget(&address);
+        }
+        sent.context("Failed to send the battery levels")
+    }
+}
+
+impl Sink for OscSink {
+    fn name(&self) -> &'static str {
+        "OSC"
+    }
+
+    fn publish(&mut self, report: &BatteryReport) -> Result<()> {
+        for (name, _) in &report.levels.trackers {
+            if !self.trackers.contains(name) {
+                info!(
+                    "Sending the {name} battery to {}",
+                    self.parameters.tracker_address(self.trackers.len())
+                );
+                self.trackers.push(name.clone());
+            }
+        }
+        for device in &report.levels.steamvr {
+            if !self.steamvr.contains(&device.name) {
+                info!(
+                    "Sending the SteamVR {} battery to {}{}",
+                    device.name, self.parameters.steamvr, device.name
+                );
+                self.steamvr.push(device.name.clone());
+            }
+        }
+
+        let levels = &report.levels;
+        let mut messages = self.parameters.messages(report, &self.trackers);
+        messages.extend(self.parameters.held_messages(levels, self.held));
+        self.held = (
+            levels.left_controller.or(self.held.0),
+            levels.right_controller.or(self.held.1),
+        );
+        let messages = self.with_heartbeat(messages);
+        self.last_messages = Some(messages.clone());
+        self.last_report = Some((report.clone(), false));
+
+        let messages = self.changes.filter(messages, self.parameters.scale);
+        if messages.is_empty() {
+            return Ok(());
+        }
+        self.send(messages)
+    }
+
+    /// VRChat resets the parameters with the avatar, so every last value is
+    /// sent again
+    fn avatar_changed(&mut self) -> Result<()> {
+        match self.last_messages.clone() {
+            Some(messages) => self.send(messages),
+            None => Ok(()),
+        }
+    }
+
+    fn resend(&mut self) {
+        self.changes.reset();
+    }
+
+    /// New addresses get their values with the next reading, as the change
+    /// filter hasn't sent anything to them yet, or with an avatar change,
+    /// which sends the last values to the new addresses
+    fn reconfigure(&mut self, settings: &Settings) {
+        self.parameters = settings.parameters.clone();
+        if let Some((report, stale)) = &self.last_report {
+            let mut messages = match stale {
+                true => self
+                    .parameters
+                    .stale_messages(report, &self.trackers, &self.steamvr),
+                false => {
+                    let mut messages = self.parameters.messages(report, &self.trackers);
+                    messages.extend(self.parameters.held_messages(&report.levels, self.held));
+                    messages
+                }
+            };
+            messages.extend(
+                self.parameters
+                    .heartbeat_messages(self.beat.wrapping_sub(1)),
+            );
+            self.last_messages = Some(messages);
+        }
+    }
+
+    fn publish_stale(&mut self, report: &BatteryReport) -> Result<()> {
+        let messages = self
+            .parameters
+            .stale_messages(report, &self.trackers, &self.steamvr);
+        // volta still runs while the levels can't be read
+        let messages = self.with_heartbeat(messages);
+        if self.parameters.stale != StaleBehavior::Hold {
+            self.last_messages = Some(messages.clone());
+            self.last_report = Some((report.clone(), true));
+        }
+
+        let messages = self.changes.filter(messages, self.parameters.scale);
+        if messages.is_empty() {
+            return Ok(());
+        }
+        self.send(messages)
+    }
+
+    /// Resets the parameters if volta resets them, and shows that volta
+    /// stopped if the heartbeat is sent
+    fn shutdown(&mut self) -> Result<()> {
+        let mut messages = Vec::new();
+        if let Some(value) = self.reset_value {
+            info!("Resetting the avatar parameters");
+            messages = self
+                .parameters
+                .reset_messages(value, self.trackers.len(), &self.steamvr);
+        }
+        if self.parameters.heartbeat {
+            messages.push((
+                "alive state",
+                message(&self.parameters.alive, OscType::Bool(false)),
+            ));
+        }
+        match messages.is_empty() {
+            true => Ok(()),
+            false => self.send(messages),
+        }
+    }
+}
+
+/// Sends OSC messages to every receiver, either bundled or one by one
+pub struct OscSender {
+    receivers: Vec<Receiver>,
+    bundle: bool,
+    discovery_timeout: Duration,
+    /// Logs the messages instead of sending them
+    dry_run: bool,
+    limit: Option<RateLimit>,
+    /// Addresses the limit dropped since the last call of `take_dropped`
+    dropped: Vec<String>,
+    counts: SendCounts,
+}
+
+/// A receiver along with its resolved address and how many sends to it
+/// failed in a row, which is tracked separately so one receiver which isn't
+/// running doesn't affect the others
+struct Receiver {
+    name: String,
+    address: SocketAddr,
+    /// Connected to the address, so the OS reports when nothing listens there
+    socket: UdpSocket,
+    /// Unknown until the OS had a chance to report it unreachable
+    reachable: Option<bool>,
+    /// Whether the sends since the last check found it reachable
+    observed: Option<bool>,
+    resolved_at: Instant,
+    failures: u32,
+}
+
+/// Whether a receiver is reachable, for the status
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceiverStatus {
+    pub name: String,
+    pub address: SocketAddr,
+    /// Unknown before the first send, and on platforms which don't report
+    /// an unreachable port
+    pub reachable: Option<bool>,
+}
+
+impl OscSender {
+    /// Creates a sender from the IP `socket` is bound to to `receivers`,
+    /// failing if any of them can't be resolved. An `auto` receiver is
+    /// discovered through OSCQuery, waiting up to `discovery_timeout` for
+    /// VRChat to answer.
+    pub fn new(
+        socket: UdpSocket,
+        receivers: Vec<String>,
+        bundle: bool,
+        discovery_timeout: Duration,
+    ) -> Result<Self> {
+        let receivers = receivers
+            .into_iter()
+            .map(|name| {
+                let address = lookup(&name, is_ipv6(&socket), discovery_timeout)?;
+                info!("Sending to {name} ({address})");
+
+                Ok(Receiver {
+                    socket: connect(&socket, address)?,
+                    name,
+                    address,
+                    reachable: None,
+                    observed: None,
+                    resolved_at: Instant::now(),
+                    failures: 0,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            receivers,
+            bundle,
+            discovery_timeout,
+            dry_run: false,
+            limit: None,
+            dropped: Vec::new(),
+            counts: SendCounts::default(),
+        })
+    }
+
+    /// Creates a sender which logs every message at the info level instead of
+    /// sending it, without resolving any receiver
+    pub fn dry_run(bundle: bool) -> Self {
+        Self {
+            receivers: Vec::new(),
+            bundle,
+            discovery_timeout: Duration::ZERO,
+            dry_run: true,
+            limit: None,
+            dropped: Vec::new(),
+            counts: SendCounts::default(),
+        }
+    }
+
+    /// Whether every receiver is reachable, as far as the OS reported, also
+    /// noticing the port unreachable of the last send
+    pub fn receivers(&mut self) -> Vec<ReceiverStatus> {
+        self.receivers
+            .iter_mut()
+            .map(|receiver| {
+                receiver.check_reachable();
+                ReceiverStatus {
+                    name: receiver.name.clone(),
+                    address: receiver.address,
+                    reachable: receiver.reachable,
+                }
+            })
+            .collect()
+    }
+
+    /// The messages sent and the failed sends since the last call
+    pub fn take_counts(&mut self) -> SendCounts {
+        std::mem::take(&mut self.counts)
+    }
+
+    /// The addresses of the messages the limit dropped since the last call
+    pub fn take_dropped(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.dropped)
+    }
+
+    /// Limits the messages sent and spaces them out
+    pub fn with_rate_limit(self, limit: RateLimit) -> Self {
+        Self {
+            limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// Sends the messages to every receiver, which are paired with a
+    /// description for logs, and returns the first failure after trying all
+    /// of them. Failures are logged as well, the next cycle simply tries
+    /// again.
+    pub fn send(&mut self, messages: Vec<(&str, OscMessage)>) -> Result<(), VoltaError> {
+        let messages = match &mut self.limit {
+            Some(limit) => {
+                let (kept, dropped) = limit.admit(messages, !self.bundle, Instant::now());
+                self.dropped
+                    .extend(dropped.into_iter().map(|(_, message)| message.addr));
+                kept
+            }
+            None => messages,
+        };
+        if messages.is_empty() {
+            return Ok(());
+        }
+        if self.dry_run {
+            match self.bundle {
+                true => {
+                    info!("Would send a bundle of {} messages", messages.len());
+                    for (_, message) in &messages {
+                        info!("  {}", describe(message));
+                    }
+                }
+                false => {
+                    for (_, message) in &messages {
+                        info!("Would send {}", describe(message));
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        let count = messages.len() as u64;
+        let packets = self.encode(messages).inspect_err(|error| {
+            error!("{error}");
+            self.counts.failures += 1;
+        })?;
+
+        // Spaced packets are sent one at a time to every receiver
+        let group = match &self.limit {
+            Some(_) => 1,
+            None => packets.len(),
+        };
+        let mut result = Ok(());
+        for packets in packets.chunks(group) {
+            if let Some(limit) = &mut self.limit {
+                thread::sleep(limit.wait(Instant::now()));
+            }
+            for receiver in &mut self.receivers {
+                let sent = receiver.send(packets, self.discovery_timeout);
+                result = result.and(sent);
+            }
+        }
+        match result {
+            Ok(()) => self.counts.messages += count,
+            Err(_) => self.counts.failures += 1,
+        }
+
+        result
+    }
+
+    /// Encodes the messages once for all receivers, paired with a description
+    fn encode<'a>(
+        &self,
+        messages: Vec<(&'a str, OscMessage)>,
+    ) -> Result<Vec<(&'a str, Vec<u8>)>, VoltaError> {
+        if !self.bundle {
+            return messages
+                .into_iter()
+                .map(|(name, message)| {
+                    let buffer = encoder::encode(&OscPacket::Message(message))
+                        .map_err(|_| VoltaError::EncodeFailed(format!("{name} message")))?;
+                    Ok((name, buffer))
+                })
+                .collect();
+        }
+
+        let bundle = OscPacket::Bundle(OscBundle {
+            timetag: IMMEDIATELY,
+            content: messages
+                .into_iter()
+                .map(|(_, message)| OscPacket::Message(message))
+                .collect(),
+        });
+        let buffer = encoder::encode(&bundle)
+            .map_err(|_| VoltaError::EncodeFailed(String::from("battery levels bundle")))?;
+
+        Ok(vec![("battery levels bundle", buffer)])
+    }
+}
+
+impl Receiver {
+    fn send(
+        &mut self,
+        packets: &[(&str, Vec<u8>)],
+        discovery_timeout: Duration,
+    ) -> Result<(), VoltaError> {
+        // Discovery waits for VRChat to answer, so an `auto` receiver is only
+        // discovered again once sending to it fails
+        let outdated =
+            self.name != oscquery::AUTO && self.resolved_at.elapsed() >= RESOLVE_INTERVAL;
+        if outdated || (self.failures > 0 && self.failures.is_multiple_of(RESOLVE_FAILURES)) {
+            self.resolve(discovery_timeout);
+        }
+
+        // The port unreachable of an earlier send is pending on the socket,
+        // and fails the send after it otherwise
+        let mut unreachable = self.take_unreachable();
+        let errors: Vec<_> = packets
+            .iter()
+            .filter_map(|(name, buffer)| {
+                let error = match self.socket.send(buffer) {
+                    Err(error) if is_unreachable(&error) => {
+                        unreachable = true;
+                        self.socket.send(buffer).err()?
+                    }
+                    sent => sent.err()?,
+                };
+                Some((name, error))
+            })
+            .collect();
+        self.observed = Some(self.observed.unwrap_or(true) && !unreachable);
+
+        if errors.is_empty() {
+            if self.failures >= MAX_LOGGED_FAILURES {
+                info!("Sending to {} recovered", self.name);
+            }
+            self.failures = 0;
+            return Ok(());
+        }
+
+        self.failures += 1;
+        for (name, error) in &errors {
+            match self.failures {
+                ..=MAX_LOGGED_FAILURES => error!("Failed to send {name} to {}: {error}", self.name),
+                _ => debug!("Failed to send {name} to {}: {error}", self.name),
+            }
+        }
+        if self.failures == MAX_LOGGED_FAILURES {
+            error!(
+                "Sending to {} failed {} times in a row, is it running? Further failures are only logged at debug level",
+                self.name, self.failures
+            );
+        }
+
+        let (_, source) = errors.into_iter().next().expect("a send failed");
+        Err(VoltaError::SendFailed {
+            addr: self.address,
+            source,
+        })
+    }
+
+    /// Whether the OS reported the port unreachable since the last call
+    fn take_unreachable(&self) -> bool {
+        self.socket
+            .take_error()
+            .ok()
+            .flatten()
+            .is_some_and(|error| is_unreachable(&error))
+    }
+
+    /// Updates whether the receiver is reachable from the sends since the
+    /// last check, and the port unreachable the last of them may have caused
+    fn check_reachable(&mut self) {
+        let reachable = match (self.take_unreachable(), self.observed.take()) {
+            (true, _) | (false, Some(false)) => false,
+            (false, Some(true)) => true,
+            (false, None) => return,
+        };
+        match (self.reachable, reachable) {
+            (Some(true) | None, false) => warn!(
+                "{} is unreachable, is VRChat running with OSC enabled?",
+                self.name
+            ),
+            (Some(false), true) => info!("{} is reachable again", self.name),
+            _ => {}
+        }
+        self.reachable = Some(reachable);
+    }
+
+    fn resolve(&mut self, discovery_timeout: Duration) {
+        self.resolved_at = Instant::now();
+
+        // The address VRChat was found at is kept when it doesn't answer,
+        // rather than falling back to the default port
+        let address = match self.name.as_str() {
+            oscquery::AUTO => oscquery::discover(discovery_timeout),
+            name => resolve(name, is_ipv6(&self.socket)),
+        };
+        match address {
+            Ok(address) if address != self.address => {
+                info!("{} now resolves to {address}", self.name);
+                if let Err(error) = self.socket.connect(address) {
+                    debug!("Failed to connect the socket to {address}: {error}");
+                }
+                self.address = address;
+                self.reachable = None;
+                self.observed = None;
+            }
+            Ok(_) => {}
+            Err(error) => debug!("{error:#}"),
+        }
+    }
+}
+
+/// Describes a message as its address followed by its arguments, e.g.
+/// `/avatar/parameters/BatteryLevelHeadset Float(0.82)`
+pub fn describe(message: &OscMessage) -> String {
+    let mut description = message.addr.clone();
+    for arg in &message.args {
+        description.push_str(&format!(" {arg:?}"));
+    }
+    description
+}
+
+/// Resolves a receiver, discovering VRChat through OSCQuery for `auto` and
+/// falling back to the default port if it can't be found
+fn lookup(receiver: &str, ipv6: bool, discovery_timeout: Duration) -> Result<SocketAddr> {
+    if receiver != oscquery::AUTO {
+        return resolve(receiver, ipv6);
+    }
+
+    match oscquery::discover(discovery_timeout) {
+        Ok(address) => Ok(address),
+        Err(error) => {
+            warn!("{error:#}, falling back to {FALLBACK_RECEIVER}");
+            resolve(FALLBACK_RECEIVER, ipv6)
+        }
+    }
+}
+
+/// Resolves a `host:port` receiver, where the host is an IP address, a
+/// bracketed IPv6 address or a host name. Prefers an IPv6 address if `ipv6`.
+pub fn resolve(receiver: &str, ipv6: bool) -> Result<SocketAddr> {
+    let (host, port) = receiver.rsplit_once(':').with_context(|| {
+        format!("Receiver `{receiver}` is missing a port, expected `host:port`")
+    })?;
+    ensure!(
+        !host.is_empty(),
+        "Receiver `{receiver}` is missing a host, expected `host:port`"
+    );
+    port.parse::<u16>()
+        .map_err(|_| anyhow!("Receiver `{receiver}` has an invalid port `{port}`"))?;
+
+    let addresses: Vec<SocketAddr> = receiver
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve receiver `{receiver}`"))?
+        .collect();
+
+    addresses
+        .iter()
+        .find(|address| address.is_ipv6() == ipv6)
+        .or(addresses.first())
+        .copied()
+        .with_context(|| format!("Receiver `{receiver}` resolved to no addresses"))
+}
+
+/// A socket of the receiver's own, on the IP of the sender's `socket` and
+/// connected to `address`. Sending with it, the OS reports an ICMP port
+/// unreachable as an error, which it can't for a socket which isn't connected.
+/// The sender's socket itself stays unconnected, as it receives OSC from any
+/// app without `--listen`.
+pub fn connect(socket: &UdpSocket, address: SocketAddr) -> Result<UdpSocket> {
+    let local = SocketAddr::new(socket.local_addr()?.ip(), 0);
+    let connected = UdpSocket::bind(local)
+        .with_context(|| format!("Failed to bind a socket to send to {address} from"))?;
+    connected
+        .connect(address)
+        .with_context(|| format!("Failed to connect the socket to {address}"))?;
+    Ok(connected)
+}
+
+/// Whether a send failed because nothing listens on the receiver's port
+pub fn is_unreachable(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset
+    )
+}
+
+fn is_ipv6(socket: &UdpSocket) -> bool {
+    socket.local_addr().is_ok_and(|local| local.is_ipv6())
+}
+
+/// Binds the socket to send from. A loopback or unspecified sender address is
+/// swapped for its equivalent in the receiver's address family, and a port
+/// which stays in use is replaced with one picked by the OS. With an
+/// `interface`, the socket is bound to its address in the receiver's family
+/// instead of the sender's IP, for hosts with several networks.
+pub fn bind(sender: &str, receiver: &str, interface: Option<&str>) -> Result<UdpSocket> {
+    let mut address = sender
+        .to_socket_addrs()
+        .with_context(|| format!("Invalid sender address `{sender}`"))?
+        .next()
+        .with_context(|| format!("Sender `{sender}` resolved to no addresses"))?;
+    // `auto` is only discovered once the socket is bound
+    let receiver = resolve(receiver, address.is_ipv6()).ok();
+
+    if let Some(interface) = interface {
+        let ipv6 = receiver.map_or(address.is_ipv6(), |receiver| receiver.is_ipv6());
+        address.set_ip(interface_ip(interface, ipv6)?);
+    }
+    if let Some(receiver) = receiver {
+        if receiver.is_ipv6() != address.is_ipv6() {
+            if let Some(ip) = equivalent_ip(address.ip()) {
+                debug!("Sending from {ip} to reach {receiver}");
+                address.set_ip(ip);
+            }
+        }
+        ensure!(
+            receiver.is_ipv6() == address.is_ipv6(),
+            "The sender {address} is {} but the receiver {receiver} is {}, pass a sender of the same family",
+            family(address),
+            family(receiver)
+        );
+    }
+
+    for attempt in 1..=BIND_ATTEMPTS {
+        match UdpSocket::bind(address) {
+            Ok(socket) => return Ok(socket),
+            Err(error) if error.kind() == ErrorKind::AddrInUse && address.port() != 0 => {
+                debug!(
+                    "Port {} is in use, attempt {attempt} of {BIND_ATTEMPTS}",
+                    address.port()
+                );
+                thread::sleep(BIND_RETRY_DELAY);
+            }
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Failed to bind the sender socket to {address}"))
+            }
+        }
+    }
+
+    let port = address.port();
+    address.set_port(0);
+    let socket = UdpSocket::bind(address).with_context(|| {
+        format!(
+            "Port {port} is already in use and binding any other port on {} failed",
+            address.ip()
+        )
+    })?;
+    warn!(
+        "Port {port} is already in use by another application, sending from {} instead",
+        socket.local_addr()?
+    );
+
+    Ok(socket)
+}
+
+fn family(address: SocketAddr) -> &'static str {
+    match address.is_ipv6() {
+        true => "IPv6",
+        false => "IPv4",
+    }
+}
+
+/// The address of the network interface `name` in the IPv6 or IPv4 family
+fn interface_ip(name: &str, ipv6: bool) -> Result<IpAddr> {
+    let interfaces = if_addrs::get_if_addrs().context("Failed to list the network interfaces")?;
+    let addresses: Vec<IpAddr> = interfaces
+        .into_iter()
+        .filter(|interface| interface.name == name)
+        .map(|interface| interface.ip())
+        .collect();
+    ensure!(
+        !addresses.is_empty(),
+        "There's no network interface `{name}`"
+    );
+
+    addresses
+        .into_iter()
+        .find(|ip| ip.is_ipv6() == ipv6)
+        .with_context(|| {
+            format!(
+                "The network interface `{name}` has no {} address",
+                if ipv6 { "IPv6" } else { "IPv4" }
+            )
+        })
+}
+
+/// Maps loopback and unspecified addresses to the other address family
+fn equivalent_ip(ip: IpAddr) -> Option<IpAddr> {
+    match ip {
+        IpAddr::V4(ip) if ip.is_loopback() => Some(Ipv6Addr::LOCALHOST.into()),
+        IpAddr::V4(ip) if ip.is_unspecified() => Some(Ipv6Addr::UNSPECIFIED.into()),
+        IpAddr::V6(ip) if ip.is_loopback() => Some(Ipv4Addr::LOCALHOST.into()),
+        IpAddr::V6(ip) if ip.is_unspecified() => Some(Ipv4Addr::UNSPECIFIED.into()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::fixture;
+    use crate::source::TrackedDevice;
+    use rosc::decoder;
+
+    fn parameters() -> Parameters {
+        let param = |name: &str| format!("/avatar/parameters/{name}");
+        Parameters {
+            param_type: ParamType::Float,
+            scale: LevelScale::Unit,
+            offline: OfflineValue::Skip,
+            temp_range: None,
+            headset: param("BatteryLevelHeadset"),
+            left: param("BatteryLevelControllerLeft"),
+            right: param("BatteryLevelControllerRight"),
+            charging: param("BatteryChargingHeadset"),
+            temperature: param("BatteryTempHeadset"),
+            voltage: param("BatteryVoltageHeadset"),
+            storage: param("HeadsetStorageFree"),
+            report_storage: false,
+            health: param("BatteryHealthHeadset"),
+            report_health: false,
+            left_connected: param("ControllerLeftConnected"),
+            right_connected: param("ControllerRightConnected"),
+            low_headset: param("BatteryLowHeadset"),
+            low_left: param("BatteryLowControllerLeft"),
+            low_right: param("BatteryLowControllerRight"),
+            tracker: param("BatteryLevelTracker"),
+            steamvr: param("BatterySteamVR"),
+            awake: param("HeadsetAwake"),
+            sleep: false,
+            stage_headset: param("BatteryStageHeadset"),
+            stage_left: param("BatteryStageControllerLeft"),
+            stage_right: param("BatteryStageControllerRight"),
+            stages: None,
+            stages_only: false,
+            minutes_headset: param("BatteryMinutesHeadset"),
+            minutes_left: param("BatteryMinutesControllerLeft"),
+            minutes_right: param("BatteryMinutesControllerRight"),
+            drain_headset: param("BatteryDrainHeadset"),
+            drain_left: param("BatteryDrainControllerLeft"),
+            drain_right: param("BatteryDrainControllerRight"),
+            estimate: false,
+            data_age: param("BatteryDataAge"),
+            max_data_age: 60,
+            stale: StaleBehavior::Hold,
+            heartbeat: false,
+            alive: param("VoltaAlive"),
+            beat: param("VoltaHeartbeat"),
+            version: param("VoltaVersion"),
+        }
+    }
+
+    /// A sink sending bundles to a socket the test receives them on
+    fn sink() -> (OscSink, UdpSocket) {
+        limited_sink(None)
+    }
+
+    fn limited_sink(limit: Option<RateLimit>) -> (OscSink, UdpSocket) {
+        let receiver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let sender = OscSender::new(
+            UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap(),
+            vec![receiver.local_addr().unwrap().to_string()],
+            true,
+            Duration::ZERO,
+        )
+        .unwrap();
+        let sender = match limit {
+            Some(limit) => sender.with_rate_limit(limit),
+            None => sender,
+        };
+        let sink = OscSink::new(
+            Rc::new(RefCell::new(sender)),
+            parameters(),
+            ChangeFilter::new(0.0, Duration::from_secs(60)),
+            Some(0.0),
+        );
+
+        (sink, receiver)
+    }
+
+    /// Receives a bundle and returns its messages by address
+    fn receive(socket: &UdpSocket) -> Vec<(String, OscType)> {
+        let mut buffer = [0; 4096];
+        let size = socket.recv(&mut buffer).unwrap();
+        let Ok((_, OscPacket::Bundle(bundle))) = decoder::decode_udp(&buffer[..size]) else {
+            panic!("expected a bundle");
+        };
+
+        bundle
+            .content
+            .into_iter()
+            .map(|packet| match packet {
+                OscPacket::Message(mut message) => (message.addr, message.args.remove(0)),
+                OscPacket::Bundle(_) => panic!("unexpected nested bundle"),
+            })
+            .collect()
+    }
+
+    fn value<'a>(messages: &'a [(String, OscType)], name: &str) -> Option<&'a OscType> {
+        messages
+            .iter()
+            .find(|(addr, _)| addr == &format!("/avatar/parameters/{name}"))
+            .map(|(_, value)| value)
+    }
+
+    #[test]
+    fn sends_what_the_rate_limit_dropped_next_cycle() {
+        let cycle = Duration::from_millis(300);
+        let (mut sink, receiver) =
+            limited_sink(Some(RateLimit::new(Some(10), Duration::ZERO, cycle)));
+        let report = fixture::report();
+
+        sink.publish(&report).unwrap();
+        let first = receive(&receiver);
+        assert_eq!(first.len(), 3);
+        thread::sleep(cycle);
+        sink.publish(&report).unwrap();
+        let second = receive(&receiver);
+        assert_eq!(second.len(), 3);
+        assert!(second.iter().all(|message| !first.contains(message)));
+    }
+
+    #[test]
+    fn publishes_parameters() {
+        let (mut sink, receiver) = sink();
+        sink.publish(&fixture::report()).unwrap();
+        let messages = receive(&receiver);
+
+        assert_eq!(
+            value(&messages, "BatteryLevelHeadset"),
+            Some(&OscType::Float(0.85))
+        );
+        assert_eq!(value(&messages, "BatteryLevelControllerLeft"), None);
+        assert_eq!(
+            value(&messages, "ControllerLeftConnected"),
+            Some(&OscType::Bool(false))
+        );
+        assert_eq!(
+            value(&messages, "BatteryLevelControllerRight"),
+            Some(&OscType::Float(0.4))
+        );
+        assert_eq!(
+            value(&messages, "BatteryChargingHeadset"),
+            Some(&OscType::Bool(true))
+        );
+    }
+
+    #[test]
+    fn sends_the_offline_value_until_the_controller_reconnects() {
+        let (mut sink, receiver) = sink();
+        sink.parameters.offline = OfflineValue::Value(-1.0);
+        sink.publish(&fixture::report()).unwrap();
+        let messages = receive(&receiver);
+
+        assert_eq!(
+            value(&messages, "BatteryLevelControllerLeft"),
+            Some(&OscType::Float(-1.0))
+        );
+        assert_eq!(
+            value(&messages, "ControllerLeftConnected"),
+            Some(&OscType::Bool(false))
+        );
+
+        let mut report = fixture::report();
+        report.levels.left_controller = Some(0.6);
+        sink.publish(&report).unwrap();
+        let messages = receive(&receiver);
+        assert_eq!(
+            value(&messages, "BatteryLevelControllerLeft"),
+            Some(&OscType::Float(0.6))
+        );
+    }
+
+    #[test]
+    fn holds_the_last_level_of_a_disconnected_controller() {
+        let (mut sink, receiver) = sink();
+        sink.parameters.offline = OfflineValue::Hold;
+        sink.publish(&fixture::report()).unwrap();
+        receive(&receiver);
+
+        let mut report = fixture::report();
+        report.levels.right_controller = None;
+        sink.publish(&report).unwrap();
+        receive(&receiver);
+        // The held level goes out again with the avatar change
+        sink.avatar_changed().unwrap();
+        let messages = receive(&receiver);
+
+        assert_eq!(
+            value(&messages, "BatteryLevelControllerRight"),
+            Some(&OscType::Float(0.4))
+        );
+        assert_eq!(
+            value(&messages, "ControllerRightConnected"),
+            Some(&OscType::Bool(false))
+        );
+        // A controller which was never connected has no level to hold
+        assert_eq!(value(&messages, "BatteryLevelControllerLeft"), None);
+    }
+
+    #[test]
+    fn resends_on_avatar_change_and_resets() {
+        let (mut sink, receiver) = sink();
+        sink.publish(&fixture::report()).unwrap();
+        let published = receive(&receiver);
+
+        // Unchanged levels aren't sent again until the avatar changes
+        sink.publish(&fixture::report()).unwrap();
+        sink.avatar_changed().unwrap();
+        assert_eq!(receive(&receiver), published);
+
+        sink.shutdown().unwrap();
+        let reset = receive(&receiver);
+        assert_eq!(
+            value(&reset, "BatteryLevelControllerLeft"),
+            Some(&OscType::Float(0.0))
+        );
+        assert_eq!(
+            value(&reset, "BatteryChargingHeadset"),
+            Some(&OscType::Bool(false))
+        );
+    }
+
+    #[test]
+    fn sends_the_heartbeat() {
+        let (mut sink, receiver) = sink();
+        sink.parameters.heartbeat = true;
+        sink.publish(&fixture::report()).unwrap();
+        let messages = receive(&receiver);
+        assert_eq!(value(&messages, "VoltaAlive"), Some(&OscType::Bool(true)));
+        assert_eq!(value(&messages, "VoltaHeartbeat"), Some(&OscType::Int(0)));
+        assert_eq!(
+            value(&messages, "VoltaVersion"),
+            Some(&OscType::Int(version()))
+        );
+
+        // Only the heartbeat changes while the levels stay the same
+        sink.publish(&fixture::report()).unwrap();
+        assert_eq!(
+            receive(&receiver),
+            [(
+                String::from("/avatar/parameters/VoltaHeartbeat"),
+                OscType::Int(1)
+            )]
+        );
+
+        sink.shutdown().unwrap();
+        assert_eq!(
+            value(&receive(&receiver), "VoltaAlive"),
+            Some(&OscType::Bool(false))
+        );
+    }
+
+    #[test]
+    fn sends_the_age_of_stale_levels() {
+        let (mut sink, receiver) = sink();
+        let mut report = fixture::report();
+        sink.publish(&report).unwrap();
+        assert_eq!(
+            value(&receive(&receiver), "BatteryDataAge"),
+            Some(&OscType::Int(0))
+        );
+
+        // Held, only the age is sent, capped at the maximum
+        report.age = Duration::from_secs(2 * 60 * 60);
+        sink.publish_stale(&report).unwrap();
+        let messages = receive(&receiver);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(value(&messages, "BatteryDataAge"), Some(&OscType::Int(60)));
+    }
+
+    #[test]
+    fn resends_stale_levels() {
+        let (mut sink, receiver) = sink();
+        sink.parameters.stale = StaleBehavior::Resend;
+        let mut report = fixture::report();
+        sink.publish(&report).unwrap();
+        let published = receive(&receiver);
+        assert!(value(&published, "BatteryLevelControllerRight").is_some());
+
+        // The unchanged levels wait for the keepalive, but stay the last values
+        report.age = Duration::from_secs(3 * 60);
+        sink.publish_stale(&report).unwrap();
+        assert_eq!(
+            receive(&receiver),
+            [(
+                String::from("/avatar/parameters/BatteryDataAge"),
+                OscType::Int(3)
+            )]
+        );
+        sink.avatar_changed().unwrap();
+        let resent = receive(&receiver);
+        assert_eq!(
+            value(&resent, "BatteryLevelControllerRight"),
+            value(&published, "BatteryLevelControllerRight")
+        );
+        assert_eq!(value(&resent, "BatteryDataAge"), Some(&OscType::Int(3)));
+    }
+
+    #[test]
+    fn zeroes_stale_levels() {
+        let (mut sink, receiver) = sink();
+        sink.parameters.stale = StaleBehavior::Zero;
+        let mut report = fixture::report();
+        sink.publish(&report).unwrap();
+        let published = receive(&receiver);
+
+        report.age = Duration::from_secs(3 * 60);
+        sink.publish_stale(&report).unwrap();
+        let zeroed = receive(&receiver);
+        assert_eq!(
+            value(&zeroed, "BatteryLevelControllerRight"),
+            Some(&OscType::Float(0.0))
+        );
+        assert_eq!(
+            value(&zeroed, "ControllerRightConnected"),
+            Some(&OscType::Bool(false))
+        );
+        assert_eq!(value(&zeroed, "BatteryDataAge"), Some(&OscType::Int(3)));
+
+        // Read again, the levels are back
+        report.age = Duration::ZERO;
+        sink.publish(&report).unwrap();
+        let messages = receive(&receiver);
+        assert_eq!(
+            value(&messages, "BatteryLevelControllerRight"),
+            value(&published, "BatteryLevelControllerRight")
+        );
+    }
+
+    #[test]
+    fn trackers_keep_their_number() {
+        let (mut sink, receiver) = sink();
+        let mut report = fixture::report();
+        report.levels.trackers = vec![
+            (String::from("tracker0"), 0.6),
+            (String::from("tracker1"), 0.8),
+        ];
+        sink.publish(&report).unwrap();
+        let messages = receive(&receiver);
+        assert_eq!(
+            value(&messages, "BatteryLevelTracker2"),
+            Some(&OscType::Float(0.8))
+        );
+
+        report.levels.trackers.remove(0);
+        report.levels.trackers[0].1 = 0.6;
+        sink.publish(&report).unwrap();
+        let messages = receive(&receiver);
+        assert_eq!(value(&messages, "BatteryLevelTracker1"), None);
+        assert_eq!(
+            value(&messages, "BatteryLevelTracker2"),
+            Some(&OscType::Float(0.6))
+        );
+    }
+
+    #[test]
+    fn sends_steamvr_devices_by_name() {
+        let (mut sink, receiver) = sink();
+        let mut report = fixture::report();
+        report.levels.steamvr = vec![TrackedDevice {
+            name: String::from("LHR1A2B3C4D"),
+            level: 0.75,
+            charging: true,
+        }];
+        sink.publish(&report).unwrap();
+        let messages = receive(&receiver);
+        assert_eq!(
+            value(&messages, "BatterySteamVRLHR1A2B3C4D"),
+            Some(&OscType::Float(0.75))
+        );
+        assert_eq!(
+            value(&messages, "BatterySteamVRLHR1A2B3C4DCharging"),
+            Some(&OscType::Bool(true))
+        );
+
+        sink.shutdown().unwrap();
+        let reset = receive(&receiver);
+        assert_eq!(
+            value(&reset, "BatterySteamVRLHR1A2B3C4D"),
+            Some(&OscType::Float(0.0))
+        );
+    }
+
+    #[test]
+    fn parses_offline_values() {
+        assert_eq!(parse_offline_value("hold"), Ok(OfflineValue::Hold));
+        assert_eq!(parse_offline_value("skip"), Ok(OfflineValue::Skip));
+        assert_eq!(parse_offline_value("-1"), Ok(OfflineValue::Value(-1.0)));
+        assert!(parse_offline_value("zero").is_err());
+        assert!(parse_offline_value("NaN").is_err());
+    }
+
+    #[test]
+    fn sends_int_suffix_with_both_types() {
+        let messages = level_messages(
+            ParamType::Both,
+            LevelScale::Unit,
+            "/avatar/parameters/Level",
+            0.456,
+        );
+
+        assert_eq!(messages[0].args, [OscType::Float(0.456)]);
+        assert_eq!(messages[1].addr, "/avatar/parameters/LevelInt");
+        assert_eq!(messages[1].args, [OscType::Int(46)]);
+    }
+
+    #[test]
+    fn scales_only_the_floats() {
+        let messages = level_messages(
+            ParamType::Both,
+            LevelScale::Percent,
+            "/avatar/parameters/Level",
+            0.8,
+        );
+
+        assert_eq!(messages[0].args, [OscType::Float(80.0)]);
+        assert_eq!(messages[1].args, [OscType::Int(80)]);
+
+        // The right controller of the fixture reports steps of 0 to 5
+        let parameters = Parameters {
+            scale: LevelScale::Percent,
+            ..parameters()
+        };
+        let messages = parameters.messages(&fixture::report(), &[]);
+        let level = |addr: &str| {
+            messages
+                .iter()
+                .find(|(_, message)| message.addr.ends_with(addr))
+                .map(|(_, message)| message.args.clone())
+        };
+        assert_eq!(level("LevelHeadset"), Some(vec![OscType::Float(85.0)]));
+        assert_eq!(
+            level("LevelControllerRight"),
+            Some(vec![OscType::Float(40.0)])
+        );
+    }
+
+    #[test]
+    fn describes_messages() {
+        let messages = level_messages(
+            ParamType::Float,
+            LevelScale::Unit,
+            "/avatar/parameters/Level",
+            0.82,
+        );
+
+        assert_eq!(
+            describe(&messages[0]),
+            "/avatar/parameters/Level Float(0.82)"
+        );
+    }
+
+    #[test]
+    fn binds_in_the_receivers_family() {
+        let socket = bind("127.0.0.1:0", "127.0.0.1:9000", None).unwrap();
+        assert!(socket.local_addr().unwrap().is_ipv4());
+
+        let socket = bind("[::1]:0", "[::1]:9000", None).unwrap();
+        assert!(socket.local_addr().unwrap().is_ipv6());
+
+        // Loopback is swapped for the receiver's
+        let socket = bind("127.0.0.1:0", "[::1]:9000", None).unwrap();
+        assert!(socket.local_addr().unwrap().is_ipv6());
+    }
+
+    #[test]
+    fn fails_on_a_family_mismatch() {
+        let error = bind("192.0.2.1:0", "[fd00::5]:9000", None).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "The sender 192.0.2.1:0 is IPv4 but the receiver [fd00::5]:9000 is IPv6, pass a sender of the same family"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn notices_an_unreachable_receiver() {
+        // Nothing listens on the port once the socket is dropped
+        let closed = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = closed.local_addr().unwrap();
+        drop(closed);
+        let mut sender = OscSender::new(
+            UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap(),
+            vec![address.to_string()],
+            true,
+            Duration::ZERO,
+        )
+        .unwrap();
+        let send = |sender: &mut OscSender| {
+            let message = message(
+                "/avatar/parameters/BatteryLevelHeadset",
+                OscType::Float(0.5),
+            );
+            sender.send(vec![("headset level", message)]).unwrap();
+            thread::sleep(Duration::from_millis(50));
+            sender.receivers()[0].reachable
+        };
+        assert_eq!(sender.receivers()[0].reachable, None);
+        assert_eq!(send(&mut sender), Some(false));
+        assert_eq!(send(&mut sender), Some(false));
+
+        let _receiver = UdpSocket::bind(address).unwrap();
+        assert_eq!(send(&mut sender), Some(true));
+    }
+
+    #[test]
+    fn binds_to_an_interface() {
+        let loopback = if_addrs::get_if_addrs()
+            .unwrap()
+            .into_iter()
+            .find(|interface| interface.ip() == IpAddr::from(Ipv4Addr::LOCALHOST))
+            .unwrap();
+
+        let socket = bind("0.0.0.0:0", "127.0.0.1:9000", Some(&loopback.name)).unwrap();
+        assert_eq!(socket.local_addr().unwrap().ip(), Ipv4Addr::LOCALHOST);
+        assert!(bind("0.0.0.0:0", "127.0.0.1:9000", Some("volta-missing")).is_err());
+    }
+}
@@ -0,0 +1,362 @@
+use anyhow::{bail, ensure, Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use rosc::{OscMessage, OscType};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+/// Receiver which is discovered through OSCQuery instead of given as an address
+pub const AUTO: &str = "auto";
+
+const SERVICE_TYPE: &str = "_oscjson._tcp.local.";
+const OSC_SERVICE_TYPE: &str = "_osc._udp.local.";
+
+/// VRChat advertises its OSCQuery service as `VRChat-Client-<id>`
+const VRCHAT_PREFIX: &str = "VRChat-Client";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// OSCQuery access values
+const ACCESS_NONE: u8 = 0;
+const ACCESS_READ: u8 = 1;
+const ACCESS_WRITE: u8 = 2;
+
+/// The part of an OSCQuery `HOST_INFO` response describing the OSC endpoint
+#[derive(Debug, Deserialize)]
+struct HostInfo {
+    #[serde(rename = "OSC_IP")]
+    ip: Option<IpAddr>,
+    #[serde(rename = "OSC_PORT")]
+    port: u16,
+    #[serde(rename = "OSC_TRANSPORT")]
+    transport: Option<String>,
+}
+
+/// Browses mDNS for VRChat's OSCQuery service and asks it for the address
+/// VRChat receives OSC on, giving up after `timeout`
+pub fn discover(timeout: Duration) -> Result<SocketAddr> {
+    let mdns = ServiceDaemon::new().context("Failed to start mDNS")?;
+    let result = browse(&mdns, timeout);
+    let _ = mdns.shutdown();
+    result
+}
+
+fn browse(mdns: &ServiceDaemon, timeout: Duration) -> Result<SocketAddr> {
+    let events = mdns.browse(SERVICE_TYPE).context("Failed to browse mDNS")?;
+    let deadline = Instant::now() + timeout;
+
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        let Ok(event) = events.recv_timeout(remaining) else {
+            break;
+        };
+        let ServiceEvent::ServiceResolved(service) = event else {
+            continue;
+        };
+        if !service.get_fullname().starts_with(VRCHAT_PREFIX) {
+            debug!("Ignoring OSCQuery service {}", service.get_fullname());
+            continue;
+        }
+
+        // VRChat runs on this machine in the usual setup, so prefer loopback
+        let mut addresses: Vec<IpAddr> = service
+            .get_addresses()
+            .iter()
+            .map(|address| address.to_ip_addr())
+            .collect();
+        addresses.sort_by_key(|address| (!address.is_loopback(), address.is_ipv6()));
+        let Some(&ip) = addresses.first() else {
+            continue;
+        };
+        let server = SocketAddr::new(ip, service.get_port());
+
+        match host_info(server) {
+            Ok(info) => {
+                debug!("{} at {server} reported {info:?}", service.get_fullname());
+                ensure!(
+                    info.transport
+                        .as_deref()
+                        .is_none_or(|transport| transport == "UDP"),
+                    "VRChat reported an unsupported OSC transport {:?}",
+                    info.transport
+                );
+                return Ok(SocketAddr::new(info.ip.unwrap_or(ip), info.port));
+            }
+            Err(error) => debug!("{error:#}"),
+        }
+    }
+
+    bail!("Found no VRChat OSCQuery service within {timeout:?}")
+}
+
+fn host_info(server: SocketAddr) -> Result<HostInfo> {
+    let body = get(server, "/?HOST_INFO")?;
+    serde_json::from_str(&body).with_context(|| format!("Invalid HOST_INFO from {server}"))
+}
+
+/// Makes a minimal HTTP GET request and returns the body
+fn get(server: SocketAddr, path: &str) -> Result<String> {
+    let mut stream = TcpStream::connect_timeout(&server, REQUEST_TIMEOUT)
+        .with_context(|| format!("Failed to connect to OSCQuery server {server}"))?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: {server}\r\nConnection: close\r\n\r\n"
+    )?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .with_context(|| format!("Failed to read from OSCQuery server {server}"))?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .with_context(|| format!("Invalid HTTP response from {server}"))?;
+    ensure!(
+        head.split_whitespace().nth(1) == Some("200"),
+        "OSCQuery server {server} answered {}",
+        head.lines().next().unwrap_or_default()
+    );
+
+    Ok(body.to_string())
+}
+
+/// Keeps volta advertised over OSCQuery until it's dropped
+pub struct Advertisement {
+    mdns: ServiceDaemon,
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        let _ = self.mdns.shutdown();
+    }
+}
+
+/// Serves the OSCQuery tree of the `sent` addresses and of `/avatar/change`,
+/// which volta receives on `listen_port`, and advertises it over mDNS so
+/// VRChat and OSC routers can find volta
+pub fn advertise(listen_port: u16, sent: &[OscMessage]) -> Result<Advertisement> {
+    let server = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .context("Failed to start the OSCQuery server")?;
+    let http_port = server.local_addr()?.port();
+
+    let name = instance_name();
+    let host_info = describe_host(&name, listen_port);
+    let tree = tree(sent);
+    thread::spawn(move || serve(server, host_info, tree));
+
+    let mdns = ServiceDaemon::new().context("Failed to start mDNS")?;
+    let host = format!("{name}.local.");
+    for (service_type, port) in [(SERVICE_TYPE, http_port), (OSC_SERVICE_TYPE, listen_port)] {
+        let service = ServiceInfo::new(
+            service_type,
+            &name,
+            &host,
+            Ipv4Addr::LOCALHOST.to_string(),
+            port,
+            None::<std::collections::HashMap<String, String>>,
+        )
+        .context("Failed to describe the OSCQuery service")?;
+        mdns.register(service)
+            .context("Failed to advertise the OSCQuery service")?;
+    }
+    info!("Advertising {name} over OSCQuery on port {http_port}");
+
+    Ok(Advertisement { mdns })
+}
+
+/// The `HOST_INFO` of volta, which receives OSC on `listen_port`
+fn describe_host(name: &str, listen_port: u16) -> Value {
+    json!({
+        "NAME": name,
+        "OSC_IP": Ipv4Addr::LOCALHOST.to_string(),
+        "OSC_PORT": listen_port,
+        "OSC_TRANSPORT": "UDP",
+        "EXTENSIONS": {"ACCESS": true, "TYPE": true, "VALUE": false, "RANGE": false},
+    })
+}
+
+/// The OSCQuery tree of the `sent` addresses and of `/avatar/change`
+fn tree(sent: &[OscMessage]) -> Value {
+    let mut tree = node("/", ACCESS_NONE, None);
+    insert(&mut tree, "/avatar/change", "s", ACCESS_WRITE);
+    for message in sent {
+        insert(
+            &mut tree,
+            &message.addr,
+            &type_tag(&message.args),
+            ACCESS_READ,
+        );
+    }
+    tree
+}
+
+/// A name which doesn't collide with other volta instances
+fn instance_name() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.subsec_nanos());
+    format!("VRC-Volta-{:06X}", (std::process::id() ^ nanos) & 0xFFFFFF)
+}
+
+fn type_tag(args: &[OscType]) -> String {
+    args.iter()
+        .map(|arg| match arg {
+            OscType::Float(_) => 'f',
+            OscType::Int(_) => 'i',
+            // OSCQuery describes booleans as `T` regardless of their value
+            OscType::Bool(_) => 'T',
+            OscType::String(_) => 's',
+            _ => 'N',
+        })
+        .collect()
+}
+
+fn node(path: &str, access: u8, type_tag: Option<&str>) -> Value {
+    let mut node = json!({"FULL_PATH": path, "ACCESS": access});
+    if let Some(type_tag) = type_tag {
+        node["TYPE"] = type_tag.into();
+    }
+    node
+}
+
+/// Adds the node for `address` to the tree, along with the container nodes
+/// leading to it
+fn insert(tree: &mut Value, address: &str, type_tag: &str, access: u8) {
+    let parts: Vec<&str> = address.split('/').filter(|part| !part.is_empty()).collect();
+    let mut current = tree;
+    for (index, part) in parts.iter().enumerate() {
+        let path = format!("/{}", parts[..=index].join("/"));
+        let leaf = index == parts.len() - 1;
+        let contents = current
+            .as_object_mut()
+            .expect("OSCQuery nodes are objects")
+            .entry("CONTENTS")
+            .or_insert_with(|| Value::Object(Map::new()));
+        current = contents
+            .as_object_mut()
+            .expect("OSCQuery contents are objects")
+            .entry(part.to_string())
+            .or_insert_with(|| node(&path, ACCESS_NONE, None));
+        if leaf {
+            current["ACCESS"] = access.into();
+            current["TYPE"] = type_tag.into();
+        }
+    }
+}
+
+fn serve(server: TcpListener, host_info: Value, tree: Value) {
+    for stream in server.incoming().flatten() {
+        if let Err(error) = respond(stream, &host_info, &tree) {
+            debug!("Failed to answer an OSCQuery request: {error:#}");
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, host_info: &Value, tree: &Value) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+
+    let body = if target.ends_with("HOST_INFO") {
+        Some(host_info)
+    } else {
+        find(tree, target.split('?').next().unwrap_or("/"))
+    };
+    let (status, body) = match body {
+        Some(body) => ("200 OK", body.to_string()),
+        None => ("404 Not Found", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    Ok(())
+}
+
+fn find<'a>(tree: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('/')
+        .filter(|part| !part.is_empty())
+        .try_fold(tree, |node, part| node.get("CONTENTS")?.get(part))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(addr: &str, arg: OscType) -> OscMessage {
+        OscMessage {
+            addr: addr.to_string(),
+            args: vec![arg],
+        }
+    }
+
+    #[test]
+    fn describes_where_volta_receives() {
+        let host_info = describe_host("VRC-Volta-00002A", 9001);
+        assert_eq!(host_info["NAME"], "VRC-Volta-00002A");
+        assert_eq!(host_info["EXTENSIONS"]["ACCESS"], true);
+
+        let parsed: HostInfo = serde_json::from_value(host_info).unwrap();
+        assert_eq!(parsed.ip, Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert_eq!(parsed.port, 9001);
+        assert_eq!(parsed.transport.as_deref(), Some("UDP"));
+    }
+
+    #[test]
+    fn builds_the_tree_of_the_sent_addresses() {
+        let tree = tree(&[
+            message("/avatar/parameters/volta/headset", OscType::Float(0.5)),
+            message("/avatar/parameters/volta/charging", OscType::Bool(true)),
+            message("/avatar/parameters/volta/percent", OscType::Int(50)),
+        ]);
+
+        assert_eq!(
+            tree["CONTENTS"]["avatar"],
+            json!({
+                "FULL_PATH": "/avatar",
+                "ACCESS": ACCESS_NONE,
+                "CONTENTS": {
+                    "change": {"FULL_PATH": "/avatar/change", "ACCESS": ACCESS_WRITE, "TYPE": "s"},
+                    "parameters": {
+                        "FULL_PATH": "/avatar/parameters",
+                        "ACCESS": ACCESS_NONE,
+                        "CONTENTS": {
+                            "volta": {
+                                "FULL_PATH": "/avatar/parameters/volta",
+                                "ACCESS": ACCESS_NONE,
+                                "CONTENTS": {
+                                    "headset": {
+                                        "FULL_PATH": "/avatar/parameters/volta/headset",
+                                        "ACCESS": ACCESS_READ,
+                                        "TYPE": "f",
+                                    },
+                                    "charging": {
+                                        "FULL_PATH": "/avatar/parameters/volta/charging",
+                                        "ACCESS": ACCESS_READ,
+                                        "TYPE": "T",
+                                    },
+                                    "percent": {
+                                        "FULL_PATH": "/avatar/parameters/volta/percent",
+                                        "ACCESS": ACCESS_READ,
+                                        "TYPE": "i",
+                                    },
+                                },
+                            },
+                        },
+                    },
+                },
+            })
+        );
+        assert_eq!(
+            find(&tree, "/avatar/parameters/volta/headset").unwrap()["TYPE"],
+            "f"
+        );
+        assert!(find(&tree, "/avatar/parameters/missing").is_none());
+    }
+}
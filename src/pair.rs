@@ -0,0 +1,128 @@
+use crate::adb::{self, AdbRunner};
+use anyhow::{bail, Context, Result};
+use tracing::{debug, info};
+
+/// Service wireless debugging advertises its connect port as over mDNS
+const CONNECT_SERVICE: &str = "_adb-tls-connect._tcp";
+
+/// Pairs with a headset using the code from its "Pair device with pairing
+/// code" dialog, then connects to it at `connect`, or at the address it
+/// advertises over mDNS. Returns the address it connected to.
+pub fn pair(adb: &impl AdbRunner, host: &str, code: &str, connect: Option<&str>) -> Result<String> {
+    info!("Pairing with {host}...");
+    let output = adb
+        .run(&["pair", host, code])
+        .unwrap_or_else(|error| format!("{error:#}"));
+    check_paired(host, output.trim())?;
+    info!("Paired with {host}");
+
+    let address = match connect {
+        Some(address) => address.to_string(),
+        None => find_connect_address(adb, host)?,
+    };
+    adb::connect(adb, &address)?;
+    info!("Connected to {address}");
+
+    Ok(address)
+}
+
+fn check_paired(host: &str, output: &str) -> Result<()> {
+    debug!("adb pair: {output}");
+    if output.contains("Successfully paired") {
+        return Ok(());
+    }
+    if output.contains("Wrong password") {
+        bail!("The pairing code was wrong, check the code shown on the headset and try again");
+    }
+    if output.contains("Unable to start pairing client")
+        || output.contains("refused")
+        || output.contains("protocol fault")
+    {
+        bail!(
+            "Nothing is waiting for pairing at {host}, the port changes and closes with the pairing \
+             dialog, open it again on the headset and use the new address and code"
+        );
+    }
+    bail!("Failed to pair with {host}: {output}");
+}
+
+/// Looks up the port the paired headset accepts connections on, which
+/// differs from the pairing port
+fn find_connect_address(adb: &impl AdbRunner, host: &str) -> Result<String> {
+    let ip = host.rsplit_once(':').map_or(host, |(ip, _)| ip);
+    let services = adb
+        .run(&["mdns", "services"])
+        .context("Failed to list the wireless debugging services")?;
+
+    services
+        .lines()
+        .filter(|line| line.contains(CONNECT_SERVICE))
+        .filter_map(|line| line.split_whitespace().last())
+        .find(|address| address.rsplit_once(':').is_some_and(|(host, _)| host == ip))
+        .map(str::to_string)
+        .with_context(|| {
+            format!(
+                "The headset at {ip} doesn't advertise its connect address, pass --connect with the \
+                 IP address and port shown under Wireless debugging"
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb::mock::MockAdb;
+
+    const HOST: &str = "192.168.1.50:37099";
+
+    fn adb(pair: &str) -> MockAdb {
+        MockAdb::default()
+            .with("pair 192.168.1.50:37099 123456", pair)
+            .with(
+                "mdns services",
+                "List of discovered mdns services\n\
+                 adb-PA7L10MGH4030-AbCdEf\t_adb-tls-pairing._tcp\t192.168.1.50:37099\n\
+                 adb-PA7L10MGH4030-AbCdEf\t_adb-tls-connect._tcp\t192.168.1.50:41235\n",
+            )
+            .with(
+                "connect 192.168.1.50:41235",
+                "connected to 192.168.1.50:41235\n",
+            )
+            .with(
+                "devices",
+                "List of devices attached\n192.168.1.50:41235\tdevice\n",
+            )
+    }
+
+    #[test]
+    fn pairs_and_connects_to_the_advertised_address() {
+        let adb =
+            adb("Successfully paired to 192.168.1.50:37099 [guid=adb-PA7L10MGH4030-AbCdEf]\n");
+
+        assert_eq!(
+            pair(&adb, HOST, "123456", None).unwrap(),
+            "192.168.1.50:41235"
+        );
+    }
+
+    #[test]
+    fn explains_pairing_failures() {
+        let error = pair(
+            &adb("Failed: Wrong password or connection was dropped.\n"),
+            HOST,
+            "123456",
+            None,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("pairing code was wrong"));
+
+        let error = pair(
+            &adb("Failed: Unable to start pairing client.\n"),
+            HOST,
+            "123456",
+            None,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("open it again"));
+    }
+}
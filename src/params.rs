@@ -0,0 +1,68 @@
+use rosc::{OscMessage, OscType};
+use serde_json::{json, Value};
+
+pub const PARAMETERS_PREFIX: &str = "/avatar/parameters/";
+
+/// Builds the `parameters` fragment of VRChat's avatar OSC config describing
+/// the avatar parameters among `messages`, so they can be added to the avatar
+/// with exactly the names volta sends to
+pub fn avatar_config(messages: &[OscMessage]) -> Value {
+    let parameters: Vec<Value> = messages
+        .iter()
+        .filter_map(|message| {
+            let name = message.addr.strip_prefix(PARAMETERS_PREFIX)?;
+            let kind = match message.args.first()? {
+                OscType::Float(_) => "Float",
+                OscType::Int(_) => "Int",
+                OscType::Bool(_) => "Bool",
+                _ => return None,
+            };
+
+            Some(json!({
+                "name": name,
+                "input": {"address": message.addr, "type": kind},
+            }))
+        })
+        .collect();
+
+    json!({ "parameters": parameters })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_the_avatar_parameters() {
+        let message = |addr: &str, arg| OscMessage {
+            addr: addr.to_string(),
+            args: vec![arg],
+        };
+        let config = avatar_config(&[
+            message("/avatar/parameters/volta/headset", OscType::Float(0.5)),
+            message("/avatar/parameters/volta/percent", OscType::Int(50)),
+            message("/avatar/parameters/volta/charging", OscType::Bool(false)),
+            // Not an avatar parameter, and a type avatar parameters can't have
+            message("/chatbox/input", OscType::Bool(true)),
+            message("/avatar/parameters/volta/name", OscType::String("x".into())),
+        ]);
+
+        assert_eq!(
+            config,
+            json!({"parameters": [
+                {
+                    "name": "volta/headset",
+                    "input": {"address": "/avatar/parameters/volta/headset", "type": "Float"},
+                },
+                {
+                    "name": "volta/percent",
+                    "input": {"address": "/avatar/parameters/volta/percent", "type": "Int"},
+                },
+                {
+                    "name": "volta/charging",
+                    "input": {"address": "/avatar/parameters/volta/charging", "type": "Bool"},
+                },
+            ]})
+        );
+    }
+}
@@ -0,0 +1,606 @@
+use crate::error::VoltaError;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+use tracing::warn;
+
+const LEVEL_KEY: &str = "  level: ";
+const AC_POWERED_KEY: &str = "  AC powered: ";
+const USB_POWERED_KEY: &str = "  USB powered: ";
+const STATUS_KEY: &str = "  status: ";
+const STATUS_CHARGING: &str = "2";
+const TEMPERATURE_KEY: &str = "  temperature: ";
+const VOLTAGE_KEY: &str = "  voltage: ";
+const HEALTH_KEY: &str = "  health: ";
+const HANDLER_KEY: &str = "handler";
+const BATTERY_KEY: &str = "battery";
+const CONNECTED_KEY: &str = "connected";
+const VERSION_KEY: &str = "version";
+const SERIAL_KEY: &str = "sn";
+const WAKEFULNESS_KEY: &str = "mWakefulness=";
+const AWAKE: &str = "Awake";
+const MISSING_SERVICE: &str = "Can't find service";
+const BONDED_DEVICES: &str = "Bonded devices:";
+
+/// Android's `BATTERY_HEALTH_GOOD`
+pub const HEALTH_GOOD: u8 = 2;
+
+lazy_static! {
+    static ref REGEX_CYCLES: Regex =
+        Regex::new("(?im)^\\s*(?:battery |charge |charging )?cycle[ _]count: *([0-9]+)\\s*$")
+            .unwrap();
+    static ref REGEX_QUEST_LEFT: Regex = Regex::new("(?im)^.*left.*?battery\\D*([0-9]+)").unwrap();
+    static ref REGEX_QUEST_RIGHT: Regex =
+        Regex::new("(?im)^.*right.*?battery\\D*([0-9]+)").unwrap();
+    /// A Bluetooth address, which newer Android masks as `XX:XX:XX:XX:1B:2C`
+    static ref REGEX_BT_ADDRESS: Regex =
+        Regex::new("(?i)\\b(?:[0-9a-fx]{2}:){5}[0-9a-fx]{2}\\b").unwrap();
+    static ref REGEX_BT_BATTERY: Regex =
+        Regex::new("(?i)battery[ _]?level\\s*[:=]\\s*(-?[0-9]+)").unwrap();
+}
+
+/// Parses the headset battery percentage from `dumpsys battery`
+pub fn parse_headset_level(dump: &str) -> Result<u8, VoltaError> {
+    let level = field(dump, LEVEL_KEY).unwrap_or_default();
+    level.parse().map_err(|_| VoltaError::ParseHeadset {
+        raw: level.to_string(),
+    })
+}
+
+/// Treats the headset as charging if `dumpsys battery` reports the charging
+/// status or any power source, a missing field simply counts as not charging
+pub fn parse_headset_charging(dump: &str) -> bool {
+    field(dump, STATUS_KEY).is_some_and(|status| status == STATUS_CHARGING)
+        || field(dump, AC_POWERED_KEY).is_some_and(|powered| powered == "true")
+        || field(dump, USB_POWERED_KEY).is_some_and(|powered| powered == "true")
+}
+
+/// Parses the headset battery temperature in degrees Celsius from
+/// `dumpsys battery`, which reports it in tenths of a degree
+pub fn parse_headset_temperature(dump: &str) -> Option<f32> {
+    field(dump, TEMPERATURE_KEY)?
+        .parse()
+        .ok()
+        .map(|tenths: f32| tenths / 10.0)
+}
+
+/// Parses the headset battery voltage in volts from `dumpsys battery`, which
+/// reports it in millivolts
+pub fn parse_headset_voltage(dump: &str) -> Option<f32> {
+    field(dump, VOLTAGE_KEY)?
+        .parse()
+        .ok()
+        .map(|millivolts: f32| millivolts / 1000.0)
+}
+
+/// Parses Android's code of the battery's health from `dumpsys battery`,
+/// leaving out codes it doesn't define
+pub fn parse_headset_health(dump: &str) -> Option<u8> {
+    field(dump, HEALTH_KEY)?
+        .parse()
+        .ok()
+        .filter(|code| health_name(*code).is_some())
+}
+
+/// The name of a battery health code of Android's `BatteryManager`
+pub fn health_name(code: u8) -> Option<&'static str> {
+    match code {
+        1 => Some("unknown"),
+        HEALTH_GOOD => Some("good"),
+        3 => Some("overheat"),
+        4 => Some("dead"),
+        5 => Some("over_voltage"),
+        6 => Some("failure"),
+        7 => Some("cold"),
+        _ => None,
+    }
+}
+
+/// Parses the charge cycles of the battery from `dumpsys battery`, which
+/// only some builds report
+pub fn parse_headset_cycles(dump: &str) -> Option<u32> {
+    REGEX_CYCLES.captures(dump)?[1].parse().ok()
+}
+
+/// Parses whether the headset is awake from `dumpsys power`, where dozing
+/// and dreaming count as asleep
+pub fn parse_awake(dump: &str) -> Result<bool, VoltaError> {
+    let wakefulness = dump
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(WAKEFULNESS_KEY))
+        .ok_or_else(|| {
+            VoltaError::Unreadable(String::from("Failed to find the headset's wakefulness"))
+        })?;
+
+    Ok(wakefulness.trim() == AWAKE)
+}
+
+fn field<'a>(dump: &'a str, key: &str) -> Option<&'a str> {
+    dump.lines()
+        .find_map(|line| line.strip_prefix(key))
+        .map(str::trim)
+}
+
+/// What a handler of `dumpsys pxrcontrollerservice` is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    Left,
+    Right,
+    /// Any other device by its handler name, e.g. a motion tracker
+    Other(String),
+}
+
+impl Role {
+    fn of(handler: &str) -> Self {
+        match handler.to_ascii_lowercase().as_str() {
+            "left" => Role::Left,
+            "right" => Role::Right,
+            _ => Role::Other(handler.to_string()),
+        }
+    }
+
+    /// Name of the device in logs and errors
+    pub fn name(&self) -> &str {
+        match self {
+            Role::Left => "left controller",
+            Role::Right => "right controller",
+            Role::Other(name) => name,
+        }
+    }
+}
+
+/// A handler's section of `dumpsys pxrcontrollerservice`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControllerInfo {
+    pub role: Role,
+    /// Battery step (0 to 5) or percentage, `None` while it isn't listed
+    pub battery: Option<u8>,
+    pub connected: Option<bool>,
+    /// Firmware version of the device
+    pub version: Option<String>,
+    pub serial: Option<String>,
+}
+
+impl ControllerInfo {
+    fn new(handler: &str) -> Self {
+        Self {
+            role: Role::of(handler),
+            battery: None,
+            connected: None,
+            version: None,
+            serial: None,
+        }
+    }
+}
+
+/// Splits `dumpsys pxrcontrollerservice` into the sections of its handlers
+/// in the order listed. Firmware differs in indentation and capitalization,
+/// e.g. `   handler: left` on the Neo 3 and `    Handler: LEFT` on the Pico 4
+/// Ultra, so keys are matched case-insensitively after any whitespace. A
+/// section ends at the next handler or at a new section like `Gamepad:`, so
+/// battery lines outside of them, like the headset's before the controller
+/// list or the gamepad's after it, are ignored. A battery which isn't a
+/// number is left out with a warning, so the other handlers are still read.
+pub fn parse_controllers(dump: &str) -> Vec<ControllerInfo> {
+    let mut controllers = Vec::new();
+    let mut section: Option<ControllerInfo> = None;
+    for line in dump.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+
+        if key.eq_ignore_ascii_case(HANDLER_KEY) {
+            controllers.extend(section.replace(ControllerInfo::new(value)));
+            continue;
+        }
+        if value.is_empty() {
+            controllers.extend(section.take());
+            continue;
+        }
+        let Some(controller) = section.as_mut() else {
+            continue;
+        };
+        if key.eq_ignore_ascii_case(BATTERY_KEY) {
+            controller.battery = value
+                .parse()
+                .inspect_err(|_| {
+                    let error = VoltaError::ParseController {
+                        side: controller.role.name().to_string(),
+                        raw: value.to_string(),
+                    };
+                    warn!("{error}, skipping it");
+                })
+                .ok();
+        } else if key.eq_ignore_ascii_case(CONNECTED_KEY) {
+            controller.connected = match value {
+                "1" | "true" => Some(true),
+                "0" | "false" => Some(false),
+                _ => None,
+            };
+        } else if key.eq_ignore_ascii_case(VERSION_KEY) {
+            controller.version = Some(value.to_string());
+        } else if key.eq_ignore_ascii_case(SERIAL_KEY) {
+            controller.serial = Some(value.to_string());
+        }
+    }
+    controllers.extend(section);
+
+    controllers
+}
+
+/// Battery of the device with `role`, `None` if it isn't listed, which
+/// happens while it's disconnected or asleep
+pub fn level(controllers: &[ControllerInfo], role: &Role) -> Option<u8> {
+    controllers
+        .iter()
+        .find(|controller| controller.role == *role)
+        .and_then(|controller| controller.battery)
+}
+
+/// Batteries of the devices which aren't controllers, e.g. motion
+/// trackers, by their handler name in the order listed
+pub fn tracker_levels(controllers: &[ControllerInfo]) -> Vec<(String, u8)> {
+    controllers
+        .iter()
+        .filter_map(|controller| match &controller.role {
+            Role::Other(name) => Some((name.clone(), controller.battery?)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parses the left and right controller battery percentages from
+/// `dumpsys OVRRemoteService`, a controller which isn't listed is `None`
+pub fn parse_quest_controller_levels(dump: &str) -> Result<(Option<u8>, Option<u8>), VoltaError> {
+    Ok((
+        capture_level(&REGEX_QUEST_LEFT, dump, "left controller")?,
+        capture_level(&REGEX_QUEST_RIGHT, dump, "right controller")?,
+    ))
+}
+
+/// Whether dumpsys printed that the service doesn't exist on the headset
+pub fn is_missing_service(dump: &str) -> bool {
+    dump.trim_start().starts_with(MISSING_SERVICE)
+}
+
+/// Parses the left and right controller battery percentages from `dumpsys
+/// bluetooth_manager`, for firmware without `pxrcontrollerservice`. The Pico
+/// controllers are found among the bonded devices by name, their side by the
+/// name's suffix, e.g. `PICO 4 Controller-L`, and their level is the one the
+/// battery profile lists after their address. A controller without a level,
+/// which Android reports as -1 while it's disconnected, is `None`.
+pub fn parse_bluetooth_controllers(dump: &str) -> (Option<u8>, Option<u8>) {
+    let mut levels = HashMap::new();
+    let mut device = None;
+    for line in dump.lines() {
+        if let Some(address) = REGEX_BT_ADDRESS.find(line) {
+            device = Some(address.as_str().to_ascii_uppercase());
+        }
+        let level = REGEX_BT_BATTERY
+            .captures(line)
+            .and_then(|captures| captures[1].parse::<u8>().ok());
+        if let (Some(device), Some(level)) = (&device, level) {
+            levels.entry(device.clone()).or_insert(level);
+        }
+    }
+
+    let (mut left, mut right) = (None, None);
+    let bonded = dump
+        .lines()
+        .skip_while(|line| line.trim() != BONDED_DEVICES)
+        .skip(1)
+        .take_while(|line| !line.trim().is_empty());
+    for line in bonded {
+        let Some(address) = REGEX_BT_ADDRESS.find(line) else {
+            continue;
+        };
+        // The device type in brackets precedes the name
+        let name = line[address.end()..].trim();
+        let name = name
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once(']'))
+            .map_or(name, |(_, name)| name.trim());
+        let level = levels.get(&address.as_str().to_ascii_uppercase()).copied();
+        match bluetooth_controller(name) {
+            Some(Role::Left) => left = left.or(level),
+            Some(Role::Right) => right = right.or(level),
+            _ => {}
+        }
+    }
+
+    (left, right)
+}
+
+/// The side of a Pico controller by its Bluetooth name, `None` for any other
+/// device
+fn bluetooth_controller(name: &str) -> Option<Role> {
+    let name = name.to_ascii_lowercase();
+    if !(name.contains("pico") && name.contains("controller")) {
+        return None;
+    }
+    let suffix = name
+        .trim_end_matches(|c: char| !c.is_ascii_alphanumeric())
+        .rsplit(|c: char| !c.is_ascii_alphanumeric())
+        .next()?;
+    match suffix {
+        "l" | "left" => Some(Role::Left),
+        "r" | "right" => Some(Role::Right),
+        _ => None,
+    }
+}
+
+/// Returns `None` if the controller isn't listed, which happens while it's
+/// disconnected or asleep
+fn capture_level(regex: &Regex, controllers: &str, side: &str) -> Result<Option<u8>, VoltaError> {
+    regex
+        .captures(controllers)
+        .map(|captures| {
+            captures[1]
+                .parse()
+                .map_err(|_| VoltaError::ParseController {
+                    side: side.to_string(),
+                    raw: captures[1].to_string(),
+                })
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BATTERY: &str = include_str!("../tests/fixtures/battery.txt");
+    const BATTERY_DISCHARGING: &str = include_str!("../tests/fixtures/battery_discharging.txt");
+    const CONTROLLERS: &str = include_str!("../tests/fixtures/pxrcontrollerservice.txt");
+    const CONTROLLERS_LEFT_MISSING: &str =
+        include_str!("../tests/fixtures/pxrcontrollerservice_left_missing.txt");
+    const BLUETOOTH: &str = include_str!("../tests/fixtures/bluetooth_manager.txt");
+    const BLUETOOTH_RIGHT_MISSING: &str =
+        include_str!("../tests/fixtures/bluetooth_manager_right_missing.txt");
+
+    fn controller_levels(dump: &str) -> (Option<u8>, Option<u8>) {
+        let controllers = parse_controllers(dump);
+        (
+            level(&controllers, &Role::Left),
+            level(&controllers, &Role::Right),
+        )
+    }
+
+    fn trackers(dump: &str) -> Vec<(String, u8)> {
+        tracker_levels(&parse_controllers(dump))
+    }
+
+    #[test]
+    fn parses_around_invalid_utf8() {
+        let dump = include_bytes!("../tests/fixtures/pxrcontrollerservice_gbk.txt");
+        let dump = crate::adb::decode(dump);
+
+        assert_eq!(controller_levels(&dump), (Some(4), Some(3)));
+        assert!(trackers(&dump).is_empty());
+    }
+
+    #[test]
+    fn parses_headset_level() {
+        assert_eq!(parse_headset_level(BATTERY).unwrap(), 85);
+        assert_eq!(parse_headset_level(BATTERY_DISCHARGING).unwrap(), 42);
+    }
+
+    #[test]
+    fn fails_without_headset_level() {
+        assert!(matches!(
+            parse_headset_level(""),
+            Err(VoltaError::ParseHeadset { raw }) if raw.is_empty()
+        ));
+        assert!(matches!(
+            parse_headset_level("Current Battery Service state:\n  level: full\n"),
+            Err(VoltaError::ParseHeadset { raw }) if raw == "full"
+        ));
+    }
+
+    #[test]
+    fn skips_a_battery_which_isnt_a_number() {
+        let controllers = parse_controllers(
+            "   handler: left\n   battery: low\n   handler: right\n   battery: 3\n",
+        );
+        assert_eq!(level(&controllers, &Role::Left), None);
+        assert_eq!(level(&controllers, &Role::Right), Some(3));
+    }
+
+    #[test]
+    fn parses_wakefulness() {
+        assert!(parse_awake("  mWakefulness=Awake\n").unwrap());
+        assert!(!parse_awake("  mWakefulness=Asleep\n").unwrap());
+        assert!(!parse_awake("  mWakefulness=Dozing\n").unwrap());
+        assert!(parse_awake("").is_err());
+    }
+
+    #[test]
+    fn parses_headset_charging() {
+        assert!(parse_headset_charging(BATTERY));
+        assert!(!parse_headset_charging(BATTERY_DISCHARGING));
+        assert!(!parse_headset_charging(""));
+        assert!(parse_headset_charging("  AC powered: true\n"));
+    }
+
+    #[test]
+    fn parses_headset_temperature_and_voltage() {
+        assert_eq!(parse_headset_temperature(BATTERY), Some(31.0));
+        assert_eq!(parse_headset_voltage(BATTERY), Some(4.213));
+        assert_eq!(parse_headset_temperature("  level: 85\n"), None);
+        assert_eq!(parse_headset_voltage("  level: 85\n"), None);
+    }
+
+    #[test]
+    fn parses_headset_health() {
+        assert_eq!(parse_headset_health(BATTERY), Some(HEALTH_GOOD));
+        assert_eq!(
+            parse_headset_health("  health: 3\n").and_then(health_name),
+            Some("overheat")
+        );
+        assert_eq!(parse_headset_health("  health: 12\n"), None);
+        assert_eq!(parse_headset_health("  health: Good\n"), None);
+        assert_eq!(parse_headset_health("  level: 85\n"), None);
+    }
+
+    #[test]
+    fn parses_bluetooth_controllers() {
+        assert_eq!(parse_bluetooth_controllers(BLUETOOTH), (Some(85), Some(40)));
+        assert_eq!(
+            parse_bluetooth_controllers(BLUETOOTH_RIGHT_MISSING),
+            (Some(60), None)
+        );
+        assert_eq!(parse_bluetooth_controllers(""), (None, None));
+    }
+
+    #[test]
+    fn ignores_other_bluetooth_devices() {
+        assert_eq!(
+            bluetooth_controller("PICO 4 Controller-L"),
+            Some(Role::Left)
+        );
+        assert_eq!(
+            bluetooth_controller("PICO Neo3 Controller (R)"),
+            Some(Role::Right)
+        );
+        assert_eq!(bluetooth_controller("WH-1000XM4"), None);
+        assert_eq!(bluetooth_controller("Xbox Wireless Controller"), None);
+        assert_eq!(bluetooth_controller("PICO Controller"), None);
+    }
+
+    #[test]
+    fn detects_missing_services() {
+        assert!(is_missing_service(
+            "Can't find service: pxrcontrollerservice\n"
+        ));
+        assert!(!is_missing_service(CONTROLLERS));
+    }
+
+    #[test]
+    fn parses_headset_cycles() {
+        assert_eq!(parse_headset_cycles(BATTERY), None);
+        assert_eq!(parse_headset_cycles("  cycle count: 231\n"), Some(231));
+        assert_eq!(
+            parse_headset_cycles("  Battery cycle count: 17\n"),
+            Some(17)
+        );
+        assert_eq!(parse_headset_cycles("  cycle_count: many\n"), None);
+    }
+
+    #[test]
+    fn parses_controller_levels() {
+        assert_eq!(controller_levels(CONTROLLERS), (Some(4), Some(3)));
+    }
+
+    #[test]
+    fn ignores_unrelated_battery_lines() {
+        let dump = "   battery: 99\n   handler: right\n   connected: 1\n   battery: 5\n";
+
+        assert_eq!(controller_levels(dump), (None, Some(5)));
+    }
+
+    #[test]
+    fn parses_missing_controller() {
+        assert_eq!(controller_levels(CONTROLLERS_LEFT_MISSING), (None, Some(2)));
+        assert_eq!(controller_levels(""), (None, None));
+    }
+
+    #[test]
+    fn parses_trackers_between_controllers() {
+        let dump = include_str!("../tests/fixtures/pxrcontrollerservice_trackers.txt");
+
+        assert_eq!(controller_levels(dump), (Some(4), Some(3)));
+        assert_eq!(
+            trackers(dump),
+            [(String::from("tracker0"), 5), (String::from("tracker1"), 2)]
+        );
+        assert!(trackers(CONTROLLERS).is_empty());
+    }
+
+    #[test]
+    fn parses_every_handler_block() {
+        let dump = include_str!("../tests/fixtures/pxrcontrollerservice_trackers.txt");
+        let controllers = parse_controllers(dump);
+
+        assert_eq!(
+            controllers
+                .iter()
+                .map(|controller| (controller.role.name(), controller.battery))
+                .collect::<Vec<_>>(),
+            [
+                ("left controller", Some(4)),
+                ("tracker0", Some(5)),
+                ("right controller", Some(3)),
+                ("tracker1", Some(2)),
+            ]
+        );
+        assert_eq!(
+            controllers[2],
+            ControllerInfo {
+                role: Role::Right,
+                battery: Some(3),
+                connected: Some(true),
+                version: Some(String::from("3.1.12")),
+                serial: Some(String::from("PA7R10MGE0456")),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_blocks_in_any_order() {
+        let dump = "\
+  Controller list:
+    Handler: Tracker0
+    Battery: 30
+    Handler: RIGHT
+    Connected: 0
+    Handler: LEFT
+    Connected: 1
+    Battery: 100
+";
+        let controllers = parse_controllers(dump);
+
+        assert_eq!(controller_levels(dump), (Some(100), None));
+        assert_eq!(controllers[1].connected, Some(false));
+        assert_eq!(controllers[0].version, None);
+        assert_eq!(trackers(dump), [(String::from("Tracker0"), 30)]);
+    }
+
+    #[test]
+    fn parses_firmware_formats() {
+        // Neo 3: three spaces, lowercase, steps of 0 to 5
+        assert_eq!(controller_levels(CONTROLLERS), (Some(4), Some(3)));
+
+        // Pico 4: deeper indentation, percentages
+        let dump = include_str!("../tests/fixtures/pxrcontrollerservice_pico4.txt");
+        assert_eq!(controller_levels(dump), (Some(80), Some(45)));
+        assert!(trackers(dump).is_empty());
+
+        // Pico 4 Ultra: capitalized keys and uppercase handlers
+        let dump = include_str!("../tests/fixtures/pxrcontrollerservice_pico4_ultra.txt");
+        assert_eq!(controller_levels(dump), (Some(100), Some(65)));
+        assert_eq!(trackers(dump), [(String::from("Tracker0"), 30)]);
+    }
+
+    #[test]
+    fn ignores_battery_outside_handler_sections() {
+        let dump = "   handler: left\n   connected: 0\n  Gamepad:\n    battery: 100\n";
+
+        assert_eq!(controller_levels(dump), (None, None));
+    }
+
+    #[test]
+    fn parses_quest_controller_levels() {
+        let dump = "\
+Paired devices:
+  Left Controller: connected, battery: 70%
+  Right Controller: connected, battery: 15%
+";
+
+        assert_eq!(
+            parse_quest_controller_levels(dump).unwrap(),
+            (Some(70), Some(15))
+        );
+    }
+}
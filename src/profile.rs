@@ -0,0 +1,243 @@
+use crate::error::VoltaError;
+use anyhow::{anyhow, ensure, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Name of the capture group holding a controller's battery reading
+const LEVEL_GROUP: &str = "level";
+
+/// Profiles shipped with volta, in the format of the config file's
+/// `[profile.<name>]` tables
+const BUILT_IN: &[(&str, &str)] = &[(
+    "vive_focus",
+    r#"
+    command = "dumpsys wvrcontrollerservice"
+    left = 'role: LEFT(?:\s+\w+: \S+)*?\s+batteryPercent: (?P<level>[0-9]+)'
+    right = 'role: RIGHT(?:\s+\w+: \S+)*?\s+batteryPercent: (?P<level>[0-9]+)'
+    left_connected = 'role: LEFT\s+state: CONNECTED'
+    right_connected = 'role: RIGHT\s+state: CONNECTED'
+    "#,
+)];
+
+/// A `[profile.<name>]` table as it's written in the config file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileEntry {
+    command: String,
+    left: String,
+    right: String,
+    left_connected: Option<String>,
+    right_connected: Option<String>,
+    scale: Option<u8>,
+}
+
+/// How to read the controllers of a headset volta has no backend for: the
+/// shell command listing them, and patterns capturing each controller's
+/// battery in a `level` group. A controller counts as disconnected when its
+/// pattern doesn't match, or its `connected` pattern is given and doesn't.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "ProfileEntry")]
+pub struct Profile {
+    pub command: Vec<String>,
+    left: Regex,
+    right: Regex,
+    left_connected: Option<Regex>,
+    right_connected: Option<Regex>,
+    /// The readings are on a scale of 0 to this, 100 by default
+    pub scale: u8,
+}
+
+impl TryFrom<ProfileEntry> for Profile {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: ProfileEntry) -> Result<Self> {
+        let command: Vec<String> = entry.command.split_whitespace().map(String::from).collect();
+        ensure!(!command.is_empty(), "`command` is empty");
+        let scale = entry.scale.unwrap_or(100);
+        ensure!(scale > 0, "`scale` has to be at least 1");
+        let connected = |key, pattern: Option<String>| {
+            pattern.map(|pattern| compile(key, &pattern)).transpose()
+        };
+
+        Ok(Self {
+            command,
+            left: level_pattern("left", &entry.left)?,
+            right: level_pattern("right", &entry.right)?,
+            left_connected: connected("left_connected", entry.left_connected)?,
+            right_connected: connected("right_connected", entry.right_connected)?,
+            scale,
+        })
+    }
+}
+
+impl PartialEq for Profile {
+    fn eq(&self, other: &Self) -> bool {
+        let pattern = |regex: &Option<Regex>| regex.as_ref().map(Regex::as_str).map(String::from);
+        self.command == other.command
+            && self.left.as_str() == other.left.as_str()
+            && self.right.as_str() == other.right.as_str()
+            && pattern(&self.left_connected) == pattern(&other.left_connected)
+            && pattern(&self.right_connected) == pattern(&other.right_connected)
+            && self.scale == other.scale
+    }
+}
+
+impl Profile {
+    /// Parses the raw readings of the left and right controller from the
+    /// output of `command`, `None` for disconnected ones
+    pub fn parse(&self, dump: &str) -> Result<(Option<u8>, Option<u8>), VoltaError> {
+        Ok((
+            reading(&self.left, self.left_connected.as_ref(), dump, "left")?,
+            reading(&self.right, self.right_connected.as_ref(), dump, "right")?,
+        ))
+    }
+}
+
+/// The profile called `name`, from the config file's `profiles` or else the
+/// built-in ones, so the config file can replace a built-in profile
+pub fn find(name: &str, profiles: &BTreeMap<String, Profile>) -> Result<Profile> {
+    if let Some(profile) = profiles.get(name) {
+        return Ok(profile.clone());
+    }
+    let (_, built_in) = BUILT_IN
+        .iter()
+        .find(|(built_in, _)| *built_in == name)
+        .ok_or_else(|| {
+            let mut names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+            names.extend(BUILT_IN.iter().map(|(name, _)| *name));
+            names.sort_unstable();
+            names.dedup();
+            anyhow!(
+                "There's no device profile `{name}`, add it as `[profile.{name}]` to the config file or use one of {}",
+                names.join(", ")
+            )
+        })?;
+
+    toml::from_str(built_in).with_context(|| format!("The built-in profile `{name}` is invalid"))
+}
+
+fn compile(key: &str, pattern: &str) -> Result<Regex> {
+    // Only the outermost error reaches the config file's error message
+    Regex::new(pattern).map_err(|error| anyhow!("`{key}` isn't a valid pattern: {error}"))
+}
+
+fn level_pattern(key: &str, pattern: &str) -> Result<Regex> {
+    let regex = compile(key, pattern)?;
+    ensure!(
+        regex
+            .capture_names()
+            .flatten()
+            .any(|name| name == LEVEL_GROUP),
+        "`{key}` has no `(?P<{LEVEL_GROUP}>...)` group capturing the battery"
+    );
+    Ok(regex)
+}
+
+fn reading(
+    level: &Regex,
+    connected: Option<&Regex>,
+    dump: &str,
+    side: &str,
+) -> Result<Option<u8>, VoltaError> {
+    if connected.is_some_and(|connected| !connected.is_match(dump)) {
+        return Ok(None);
+    }
+    let Some(captures) = level.captures(dump) else {
+        return Ok(None);
+    };
+    let raw = captures.name(LEVEL_GROUP).map_or("", |raw| raw.as_str());
+    raw.parse()
+        .map(Some)
+        .map_err(|_| VoltaError::ParseController {
+            side: side.to_string(),
+            raw: raw.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn built_in(name: &str) -> Profile {
+        find(name, &BTreeMap::new()).unwrap()
+    }
+
+    fn invalid(contents: &str) -> String {
+        let error = toml::from_str::<Profile>(contents).unwrap_err();
+        error.to_string()
+    }
+
+    #[test]
+    fn reads_the_vive_focus_controllers() {
+        let profile = built_in("vive_focus");
+        assert_eq!(profile.command, ["dumpsys", "wvrcontrollerservice"]);
+
+        let dump = include_str!("../tests/fixtures/vive_focus_controllers.txt");
+        assert_eq!(profile.parse(dump).unwrap(), (Some(76), Some(31)));
+        let dump = include_str!("../tests/fixtures/vive_focus_controllers_right_missing.txt");
+        assert_eq!(profile.parse(dump).unwrap(), (Some(100), None));
+    }
+
+    #[test]
+    fn reads_a_profile_of_the_config_file() {
+        let profile: Profile = toml::from_str(
+            "command = \"dumpsys pxrcontrollerservice\"\n\
+             left = 'handler: left\\s+connected: 1\\s+battery: (?P<level>\\d+)'\n\
+             right = 'handler: right\\s+connected: 1\\s+battery: (?P<level>\\d+)'\n\
+             scale = 5\n",
+        )
+        .unwrap();
+        assert_eq!(profile.scale, 5);
+
+        let dump = include_str!("../tests/fixtures/pxrcontrollerservice.txt");
+        assert_eq!(profile.parse(dump).unwrap(), (Some(4), Some(3)));
+        let dump = include_str!("../tests/fixtures/pxrcontrollerservice_left_missing.txt");
+        assert_eq!(profile.parse(dump).unwrap().0, None);
+    }
+
+    #[test]
+    fn replaces_a_built_in_profile() {
+        let mut profiles = BTreeMap::new();
+        let mut profile = built_in("vive_focus");
+        profile.scale = 5;
+        profiles.insert(String::from("vive_focus"), profile.clone());
+        assert_eq!(find("vive_focus", &profiles).unwrap(), profile);
+
+        let error = find("focus", &profiles).unwrap_err().to_string();
+        assert!(error.contains("`[profile.focus]`"), "{error}");
+    }
+
+    #[test]
+    fn rejects_invalid_profiles() {
+        let error = invalid(
+            "command = \"dumpsys x\"\nleft = 'battery: (\\d+)'\nright = '(?P<level>\\d+)'\n",
+        );
+        assert!(
+            error.contains("`left` has no `(?P<level>...)` group"),
+            "{error}"
+        );
+        let error = invalid(
+            "command = \"dumpsys x\"\nleft = '(?P<level>\\d+)'\nright = '(?P<level>\\d+'\n",
+        );
+        assert!(error.contains("`right` isn't a valid pattern"), "{error}");
+        let error =
+            invalid("command = \" \"\nleft = '(?P<level>\\d+)'\nright = '(?P<level>\\d+)'\n");
+        assert!(error.contains("`command` is empty"), "{error}");
+        let error = invalid("command = \"x\"\nleft = '(?P<level>\\d+)'\nright = '(?P<level>\\d+)'\nleft_connected = '('\n");
+        assert!(
+            error.contains("`left_connected` isn't a valid pattern"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn fails_on_a_level_which_isnt_a_number() {
+        let profile: Profile = toml::from_str(
+            "command = \"x\"\nleft = 'left: (?P<level>\\w+)'\nright = 'right: (?P<level>\\w+)'\n",
+        )
+        .unwrap();
+        assert!(profile.parse("left: full\n").is_err());
+        assert_eq!(profile.parse("right: 80\n").unwrap(), (None, Some(80)));
+    }
+}
@@ -0,0 +1,134 @@
+use chrono::NaiveTime;
+use std::fmt;
+use tracing::info;
+
+/// A daily window of local time, which ends the next day if it ends before
+/// it starts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PauseWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl PauseWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        match self.start <= self.end {
+            true => self.start <= time && time < self.end,
+            false => self.start <= time || time < self.end,
+        }
+    }
+}
+
+impl fmt::Display for PauseWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// Parses a window as `HH:MM-HH:MM`, e.g. `01:00-08:00` or `22:30-06:00`
+pub fn parse_window(value: &str) -> Result<PauseWindow, String> {
+    let invalid = || format!("`{value}` is not a time window, expected e.g. `01:00-08:00`");
+    let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+    let time = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid());
+    let window = PauseWindow {
+        start: time(start)?,
+        end: time(end)?,
+    };
+    match window.start == window.end {
+        true => Err(format!("`{value}` starts when it ends")),
+        false => Ok(window),
+    }
+}
+
+/// Pauses the polling during the windows, logging when a pause starts and
+/// ends. Overlapping windows make up a single pause.
+pub struct QuietHours {
+    windows: Vec<PauseWindow>,
+    paused: bool,
+}
+
+impl QuietHours {
+    pub fn new(windows: Vec<PauseWindow>) -> Self {
+        Self {
+            windows,
+            paused: false,
+        }
+    }
+
+    pub fn set_windows(&mut self, windows: Vec<PauseWindow>) {
+        self.windows = windows;
+    }
+
+    /// Whether the polling is paused at the local time `now`
+    pub fn check(&mut self, now: NaiveTime) -> bool {
+        let window = self.windows.iter().find(|window| window.contains(now));
+        match (window, self.paused) {
+            (Some(window), false) => info!("Pausing during the quiet hours {window}"),
+            (None, true) => info!("The quiet hours are over, polling again"),
+            _ => {}
+        }
+        self.paused = window.is_some();
+        self.paused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn parses_windows() {
+        let window = parse_window("01:00-08:30").unwrap();
+        assert_eq!(window.to_string(), "01:00-08:30");
+        assert_eq!(
+            parse_window(" 22:00 - 06:00 ").unwrap().to_string(),
+            "22:00-06:00"
+        );
+
+        assert!(parse_window("01:00").is_err());
+        assert!(parse_window("25:00-08:00").is_err());
+        assert!(parse_window("08:00-08:00").is_err());
+    }
+
+    #[test]
+    fn contains_the_times_of_the_window() {
+        let night = parse_window("01:00-08:00").unwrap();
+        assert!(!night.contains(at("00:59")));
+        assert!(night.contains(at("01:00")));
+        assert!(night.contains(at("07:59")));
+        assert!(!night.contains(at("08:00")));
+    }
+
+    #[test]
+    fn crosses_midnight() {
+        let night = parse_window("22:00-06:00").unwrap();
+        assert!(night.contains(at("23:30")));
+        assert!(night.contains(at("00:00")));
+        assert!(night.contains(at("05:59")));
+        assert!(!night.contains(at("06:00")));
+        assert!(!night.contains(at("12:00")));
+    }
+
+    #[test]
+    fn pauses_during_overlapping_windows() {
+        let mut quiet = QuietHours::new(vec![
+            parse_window("23:00-02:00").unwrap(),
+            parse_window("01:00-08:00").unwrap(),
+        ]);
+
+        let paused: Vec<_> = ["22:59", "23:00", "01:30", "02:00", "07:59", "08:00"]
+            .into_iter()
+            .map(|time| quiet.check(at(time)))
+            .collect();
+        assert_eq!(paused, [false, true, true, true, true, false]);
+    }
+}
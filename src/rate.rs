@@ -0,0 +1,196 @@
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Messages which are dropped first when a cycle's messages don't fit the
+/// limit, as they matter least and are sent again next cycle anyway
+const LOW_PRIORITY: [&str; 4] = ["alive state", "heartbeat", "version", "chatbox"];
+
+/// Messages paired with their names
+type Messages<'a, T> = Vec<(&'a str, T)>;
+
+/// Caps the outgoing OSC messages with a token bucket holding a cycle's
+/// worth of messages, and spaces out the packets, at least by the time one
+/// message takes at the rate, for OSC middlewares which drop bursts. A
+/// cycle's messages are thereby paced over the cycle, and the ones which
+/// don't fit are dropped instead of delayed, so a cycle never runs into the
+/// next one.
+pub struct RateLimit {
+    /// Messages per second
+    rate: Option<f64>,
+    /// Time between two packets
+    spacing: Duration,
+    /// How long sending a cycle's messages may take, the polling interval
+    budget: Duration,
+    /// Messages the bucket holds, a cycle's worth at the rate
+    capacity: f64,
+    tokens: f64,
+    refilled: Instant,
+    sent: Option<Instant>,
+}
+
+impl RateLimit {
+    pub fn new(rate: Option<u32>, spacing: Duration, budget: Duration) -> Self {
+        let rate = rate.map(f64::from);
+        let capacity = rate.map_or(0.0, |rate| (rate * budget.as_secs_f64()).max(1.0));
+        Self {
+            rate,
+            spacing: rate.map_or(spacing, |rate| {
+                spacing.max(Duration::from_secs_f64(1.0 / rate))
+            }),
+            budget,
+            capacity,
+            tokens: capacity,
+            refilled: Instant::now(),
+            sent: None,
+        }
+    }
+
+    /// Splits the messages into the ones which fit the limit at `now` and
+    /// the dropped ones, dropping the low priority ones first and then the
+    /// ones sent last. `spaced` is whether every message is a packet of its
+    /// own, which the spacing applies to.
+    pub fn admit<'a, T>(
+        &mut self,
+        messages: Messages<'a, T>,
+        spaced: bool,
+        now: Instant,
+    ) -> (Messages<'a, T>, Messages<'a, T>) {
+        let mut capacity = messages.len();
+        if let Some(rate) = self.rate {
+            let elapsed = now.duration_since(self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate).min(self.capacity);
+            self.refilled = now;
+            capacity = capacity.min(self.tokens as usize);
+        }
+        if spaced && !self.spacing.is_zero() {
+            let packets = self.budget.as_nanos() / self.spacing.as_nanos();
+            capacity = capacity.min(usize::try_from(packets).unwrap_or(usize::MAX).max(1));
+        }
+
+        let mut keep = vec![true; messages.len()];
+        let mut excess = messages.len() - capacity;
+        for low_priority in [true, false] {
+            for (index, (name, _)) in messages.iter().enumerate().rev() {
+                if excess > 0 && keep[index] && LOW_PRIORITY.contains(name) == low_priority {
+                    keep[index] = false;
+                    excess -= 1;
+                }
+            }
+        }
+        if self.rate.is_some() {
+            self.tokens -= capacity as f64;
+        }
+
+        let (kept, skipped): (Vec<_>, Vec<_>) =
+            messages.into_iter().zip(keep).partition(|(_, keep)| *keep);
+        if !skipped.is_empty() {
+            let names: Vec<_> = skipped.iter().map(|((name, _), _)| *name).collect();
+            debug!("Rate limited, skipping {}", names.join(", "));
+        }
+        let messages =
+            |messages: Vec<(_, bool)>| messages.into_iter().map(|(message, _)| message).collect();
+        (messages(kept), messages(skipped))
+    }
+
+    /// How long to wait at `now` before sending the next packet, which is
+    /// counted as sent once the wait is over
+    pub fn wait(&mut self, now: Instant) -> Duration {
+        let wait = self.sent.map_or(Duration::ZERO, |sent| {
+            (sent + self.spacing).saturating_duration_since(now)
+        });
+        self.sent = Some(now + wait);
+        wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(messages: &[(&str, ())]) -> Vec<String> {
+        messages.iter().map(|(name, _)| name.to_string()).collect()
+    }
+
+    fn messages() -> Vec<(&'static str, ())> {
+        vec![
+            ("headset battery level", ()),
+            ("heartbeat", ()),
+            ("left controller battery level", ()),
+            ("right controller battery level", ()),
+            ("chatbox", ()),
+        ]
+    }
+
+    #[test]
+    fn drops_low_priority_messages_first() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(Some(3), Duration::ZERO, Duration::from_secs(1));
+
+        let (kept, dropped) = limit.admit(messages(), false, start);
+        assert_eq!(
+            names(&kept),
+            [
+                "headset battery level",
+                "left controller battery level",
+                "right controller battery level"
+            ]
+        );
+        assert_eq!(names(&dropped), ["heartbeat", "chatbox"]);
+        // The bucket is empty until it refills
+        assert!(limit.admit(messages(), false, start).0.is_empty());
+        assert_eq!(
+            names(
+                &limit
+                    .admit(messages(), false, start + Duration::from_millis(700))
+                    .0
+            ),
+            ["headset battery level", "left controller battery level"]
+        );
+    }
+
+    #[test]
+    fn holds_a_cycles_worth_of_messages() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(Some(1), Duration::ZERO, Duration::from_secs(5));
+
+        assert_eq!(limit.admit(messages(), false, start).0.len(), 5);
+        // Refilled at the rate, and never beyond a cycle's worth
+        assert_eq!(
+            limit
+                .admit(messages(), false, start + Duration::from_secs(2))
+                .0
+                .len(),
+            2
+        );
+        assert_eq!(
+            limit
+                .admit(messages(), false, start + Duration::from_secs(60))
+                .0
+                .len(),
+            5
+        );
+    }
+
+    #[test]
+    fn paces_the_messages_at_the_rate() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(Some(4), Duration::ZERO, Duration::from_secs(1));
+
+        assert_eq!(limit.admit(messages(), true, start).0.len(), 4);
+        assert_eq!(limit.wait(start), Duration::ZERO);
+        assert_eq!(limit.wait(start), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn spaces_packets_within_the_cycle() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(None, Duration::from_millis(400), Duration::from_secs(1));
+
+        assert_eq!(limit.admit(messages(), true, start).0.len(), 2);
+        // A bundle is a single packet
+        assert_eq!(limit.admit(messages(), false, start).0.len(), 5);
+        assert_eq!(limit.wait(start), Duration::ZERO);
+        assert_eq!(limit.wait(start), Duration::from_millis(400));
+        assert_eq!(limit.wait(start + Duration::from_secs(2)), Duration::ZERO);
+    }
+}
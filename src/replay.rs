@@ -0,0 +1,220 @@
+use crate::adb::{self, AdbRunner};
+use crate::error::VoltaError;
+use anyhow::{ensure, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+/// The dumps which are replayed and captured, by the name of their files
+const DUMPS: [(&str, &str); 2] = [
+    ("battery", "battery"),
+    ("pxrcontrollerservice", "controllers"),
+];
+
+/// The files of one dump in the order they're replayed
+struct Files {
+    paths: Vec<PathBuf>,
+    next: AtomicUsize,
+}
+
+impl Files {
+    /// Finds `<name>_<number>.txt` in `dir`, ordered by number, or else
+    /// `<name>.txt`
+    fn find(dir: &Path, name: &str) -> Result<Self> {
+        let mut numbered = Vec::new();
+        for entry in fs::read_dir(dir)
+            .with_context(|| format!("Failed to read the replay directory {}", dir.display()))?
+        {
+            let path = entry?.path();
+            let number = path
+                .file_name()
+                .and_then(|file| file.to_str())
+                .and_then(|file| file.strip_prefix(name)?.strip_prefix('_'))
+                .and_then(|file| file.strip_suffix(".txt")?.parse::<u64>().ok());
+            if let Some(number) = number {
+                numbered.push((number, path));
+            }
+        }
+        numbered.sort();
+        let mut paths: Vec<_> = numbered.into_iter().map(|(_, path)| path).collect();
+        let single = dir.join(format!("{name}.txt"));
+        if paths.is_empty() && single.exists() {
+            paths.push(single);
+        }
+
+        Ok(Self {
+            paths,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Reads the next file, or the last one again once all were read, as
+    /// the output of `command`
+    fn read(&self, command: &str) -> Result<String, VoltaError> {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        let path = &self.paths[index.min(self.paths.len() - 1)];
+        if index == self.paths.len() && self.paths.len() > 1 {
+            info!("Replayed every dump, repeating {}", path.display());
+        }
+        debug!("Replaying {}", path.display());
+        let dump = fs::read(path).map_err(|error| VoltaError::AdbFailed {
+            command: command.to_string(),
+            message: format!("Failed to read {}: {error}", path.display()),
+        })?;
+        Ok(adb::decode(&dump))
+    }
+}
+
+/// Answers the dumps from files saved earlier instead of asking a headset,
+/// advancing to the next file of a dump every time it's read
+pub struct Replay {
+    battery: Files,
+    controllers: Files,
+}
+
+impl Replay {
+    pub fn open(dir: &Path) -> Result<Self> {
+        let battery = Files::find(dir, "battery")?;
+        ensure!(
+            !battery.paths.is_empty(),
+            "{} has no battery.txt or battery_<number>.txt to replay",
+            dir.display()
+        );
+        let controllers = Files::find(dir, "controllers")?;
+        info!(
+            "Replaying {} battery and {} controller dumps from {}",
+            battery.paths.len(),
+            controllers.paths.len(),
+            dir.display()
+        );
+
+        Ok(Self {
+            battery,
+            controllers,
+        })
+    }
+}
+
+impl AdbRunner for Replay {
+    fn run(&self, args: &[&str]) -> Result<String, VoltaError> {
+        let command = args.join(" ");
+        match args {
+            ["shell", "dumpsys", "battery"] => self.battery.read(&command),
+            // Without controller dumps no controller is listed
+            ["shell", "dumpsys", "pxrcontrollerservice"] if self.controllers.paths.is_empty() => {
+                Ok(String::new())
+            }
+            ["shell", "dumpsys", "pxrcontrollerservice"] => self.controllers.read(&command),
+            _ => Err(VoltaError::AdbFailed {
+                command,
+                message: String::from("it can't be replayed"),
+            }),
+        }
+    }
+}
+
+/// Passes every command on to `adb` and saves the dumps to a directory on
+/// the way, as `battery_<unix ms>.txt` and `controllers_<unix ms>.txt`, which
+/// [`Replay`] reads in the same order
+pub struct Capture<A> {
+    adb: A,
+    dir: PathBuf,
+}
+
+impl<A: AdbRunner> Capture<A> {
+    pub fn new(adb: A, dir: PathBuf) -> Result<Self> {
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create the capture directory {}", dir.display()))?;
+        info!("Capturing the dumps to {}", dir.display());
+        Ok(Self { adb, dir })
+    }
+
+    fn save(&self, args: &[&str], output: &Result<String, VoltaError>) {
+        let (Ok(output), ["dumpsys", service]) = (output, args) else {
+            return;
+        };
+        let Some((_, name)) = DUMPS.iter().find(|(dump, _)| dump == service) else {
+            return;
+        };
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_millis());
+        let path = self.dir.join(format!("{name}_{time}.txt"));
+        if let Err(error) = fs::write(&path, output) {
+            debug!("Failed to capture {}: {error}", path.display());
+        }
+    }
+}
+
+impl<A: AdbRunner> AdbRunner for Capture<A> {
+    fn run(&self, args: &[&str]) -> Result<String, VoltaError> {
+        let output = self.adb.run(args);
+        if let ["shell", command @ ..] = args {
+            self.save(command, &output);
+        }
+        output
+    }
+
+    fn shell(&self, args: &[&str]) -> Result<String, VoltaError> {
+        let output = self.adb.shell(args);
+        self.save(args, &output);
+        output
+    }
+
+    fn retries(&self) -> u32 {
+        self.adb.retries()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb::mock::MockAdb;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vrc_volta-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn replays_what_was_captured() {
+        let dir = dir("capture");
+        let battery = |level| format!("  level: {level}\n");
+        let capture = Capture::new(
+            MockAdb::default()
+                .with("shell dumpsys battery", &battery(85))
+                .with("shell getprop ro.product.model", "A8110"),
+            dir.clone(),
+        )
+        .unwrap();
+        capture.shell(&["dumpsys", "battery"]).unwrap();
+        capture.shell(&["getprop", "ro.product.model"]).unwrap();
+        // Captured files are numbered by the millisecond
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        fs::write(dir.join("battery_99999999999999.txt"), battery(84)).unwrap();
+
+        let replay = Replay::open(&dir).unwrap();
+        assert_eq!(replay.shell(&["dumpsys", "battery"]).unwrap(), battery(85));
+        assert_eq!(replay.shell(&["dumpsys", "battery"]).unwrap(), battery(84));
+        // The last dump is repeated
+        assert_eq!(replay.shell(&["dumpsys", "battery"]).unwrap(), battery(84));
+        assert_eq!(
+            replay.shell(&["dumpsys", "pxrcontrollerservice"]).unwrap(),
+            ""
+        );
+        assert!(replay.shell(&["getprop", "ro.product.model"]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn needs_a_battery_dump() {
+        let dir = dir("replay-empty");
+
+        assert!(Replay::open(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Keeps the poll cycles on a fixed cadence by computing every deadline from
+/// the previous one, so the time spent talking to the headset doesn't add up
+/// to the interval
+pub struct Schedule {
+    interval: Duration,
+    deadline: Instant,
+}
+
+impl Schedule {
+    /// Starts the schedule with a cycle right now
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            deadline: Instant::now() + interval,
+        }
+    }
+
+    /// End of the current cycle, at which the next one starts
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Warns if the work of the current cycle took longer than the interval,
+    /// in which case the schedule is realigned to start the next cycle now
+    pub fn check_overrun(&mut self) {
+        let now = Instant::now();
+        if now > self.deadline {
+            warn!(
+                "Cycle overran the interval of {:?} by {:?}, adb is responding slowly",
+                self.interval,
+                now - self.deadline
+            );
+            self.deadline = now;
+        }
+    }
+
+    /// Moves on to the next cycle
+    pub fn advance(&mut self) {
+        self.deadline += self.interval;
+    }
+
+    /// Changes the interval from the next cycle on
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Starts over with a cycle right now, e.g. after waiting for the device
+    pub fn restart(&mut self) {
+        self.deadline = Instant::now() + self.interval;
+    }
+}
+
+/// Tracks when a reading which happens less often than every cycle, like the
+/// controllers', is due next
+#[derive(Default)]
+pub struct Cadence {
+    next: Option<Instant>,
+}
+
+impl Cadence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the reading is due in the cycle starting now, which counts it
+    /// as done if so. Readings due within half a `cycle` happen early rather
+    /// than a whole cycle late.
+    pub fn take(&mut self, interval: Duration, cycle: Duration) -> bool {
+        self.take_at(Instant::now(), interval, cycle)
+    }
+
+    fn take_at(&mut self, now: Instant, interval: Duration, cycle: Duration) -> bool {
+        if self.next.is_some_and(|next| now + cycle / 2 < next) {
+            return false;
+        }
+        self.next = Some(now + interval);
+        true
+    }
+
+    /// Makes the reading due in the next cycle, e.g. after it failed
+    pub fn reset(&mut self) {
+        self.next = None;
+    }
+}
+
+/// Sends the first levels again a few times after they were first sent, in
+/// case VRChat was still loading and dropped them
+pub struct Burst {
+    /// When to send again, counted from the first send
+    offsets: Vec<Duration>,
+    armed: bool,
+    started: Option<Instant>,
+    sent: usize,
+}
+
+impl Burst {
+    pub fn new(mut offsets: Vec<Duration>) -> Self {
+        offsets.sort();
+        Self {
+            offsets,
+            armed: true,
+            started: None,
+            sent: 0,
+        }
+    }
+
+    /// Starts over with the next levels sent, e.g. once VRChat started
+    pub fn arm(&mut self) {
+        self.armed = true;
+        self.started = None;
+    }
+
+    /// Starts the burst if it's armed, as the levels were just sent
+    pub fn sent(&mut self) {
+        self.sent_at(Instant::now());
+    }
+
+    fn sent_at(&mut self, now: Instant) {
+        if self.armed {
+            self.armed = false;
+            self.started = Some(now);
+            self.sent = 0;
+        }
+    }
+
+    /// When the levels are sent again next
+    pub fn due(&self) -> Option<Instant> {
+        let offset = self.offsets.get(self.sent)?;
+        Some(self.started? + *offset)
+    }
+
+    /// Whether the levels are due to be sent again, which counts them as sent
+    /// if so
+    pub fn take(&mut self) -> bool {
+        self.take_at(Instant::now())
+    }
+
+    fn take_at(&mut self, now: Instant) -> bool {
+        if self.due().is_some_and(|due| due <= now) {
+            self.sent += 1;
+            return true;
+        }
+        false
+    }
+
+    /// Ends the burst early, e.g. once VRChat shows it's ready
+    pub fn stop(&mut self) {
+        self.started = None;
+    }
+}
+
+/// Merges the requests to refresh which come within a couple of seconds of
+/// each other, e.g. from a button which sends several messages
+pub struct Debounce {
+    window: Duration,
+    last: Option<Instant>,
+}
+
+impl Debounce {
+    pub fn new(window: Duration) -> Self {
+        Self { window, last: None }
+    }
+
+    /// Whether a request made now runs, as it's not within the window of the
+    /// last one which ran
+    pub fn take(&mut self) -> bool {
+        self.take_at(Instant::now())
+    }
+
+    fn take_at(&mut self, now: Instant) -> bool {
+        if self
+            .last
+            .is_some_and(|last| now.duration_since(last) < self.window)
+        {
+            return false;
+        }
+        self.last = Some(now);
+        true
+    }
+}
+
+/// Checks the headset's charging state in between the cycles, so plugging it
+/// in shows without waiting for the next cycle
+pub struct ChargeCheck {
+    interval: Duration,
+    next: Instant,
+}
+
+impl ChargeCheck {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Instant::now() + interval,
+        }
+    }
+
+    /// Starts counting the interval over, as the levels were just read
+    pub fn restart(&mut self) {
+        self.next = Instant::now() + self.interval;
+    }
+
+    /// When the state is checked next before the cycle ending at `deadline`.
+    /// A check due within half an interval of the deadline is left to the
+    /// cycle, which reads the state anyway.
+    pub fn due(&self, deadline: Instant) -> Option<Instant> {
+        (self.next + self.interval / 2 < deadline).then_some(self.next)
+    }
+
+    /// Whether the state is due to be checked, which counts it as checked if
+    /// so
+    pub fn take(&mut self, deadline: Instant) -> bool {
+        self.take_at(Instant::now(), deadline)
+    }
+
+    fn take_at(&mut self, now: Instant, deadline: Instant) -> bool {
+        if self.due(deadline).is_some_and(|due| due <= now) {
+            self.next = now + self.interval;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_every_few_cycles() {
+        let start = Instant::now();
+        let cycle = Duration::from_secs(30);
+        let mut cadence = Cadence::new();
+        // The cycles start a little late, as they wait for the headset
+        let due: Vec<_> = (0..9)
+            .map(|index| {
+                let now = start + cycle * index + Duration::from_millis(300 * u64::from(index));
+                cadence.take_at(now, Duration::from_secs(90), cycle)
+            })
+            .collect();
+
+        assert_eq!(
+            due,
+            [true, false, false, true, false, false, true, false, false]
+        );
+    }
+
+    #[test]
+    fn sends_again_after_the_first_send() {
+        let start = Instant::now();
+        let seconds = |seconds| start + Duration::from_secs(seconds);
+        let mut burst = Burst::new([20, 10].map(Duration::from_secs).into());
+        assert_eq!(burst.due(), None);
+
+        burst.sent_at(start);
+        assert!(!burst.take_at(seconds(5)));
+        assert!(burst.take_at(seconds(10)));
+        assert_eq!(burst.due(), Some(seconds(20)));
+        // Later sends don't start it over
+        burst.sent_at(seconds(15));
+        assert!(burst.take_at(seconds(21)));
+        assert_eq!(burst.due(), None);
+
+        burst.arm();
+        burst.sent_at(seconds(60));
+        assert_eq!(burst.due(), Some(seconds(70)));
+        burst.stop();
+        assert!(!burst.take_at(seconds(70)));
+    }
+
+    #[test]
+    fn merges_requests_in_the_window() {
+        let start = Instant::now();
+        let millis = |millis| start + Duration::from_millis(millis);
+        let mut debounce = Debounce::new(Duration::from_secs(2));
+
+        let taken: Vec<_> = [0, 500, 1999, 2000, 3000, 4500]
+            .into_iter()
+            .map(|at| debounce.take_at(millis(at)))
+            .collect();
+        assert_eq!(taken, [true, false, false, true, false, true]);
+    }
+
+    #[test]
+    fn checks_the_charging_state_between_cycles() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let deadline = start + 60 * second;
+        let mut check = ChargeCheck {
+            interval: 10 * second,
+            next: start + 10 * second,
+        };
+
+        let checked: Vec<_> = (1..=6)
+            .map(|tens| check.take_at(start + tens * 10 * second, deadline))
+            .collect();
+        // The check at 60s would coincide with the cycle
+        assert_eq!(checked, [true, true, true, true, true, false]);
+        assert!(!check.take_at(start + 51 * second, deadline));
+        assert_eq!(check.due(start + 64 * second), None);
+        assert_eq!(check.due(start + 66 * second), Some(start + 60 * second));
+    }
+}
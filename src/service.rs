@@ -0,0 +1,272 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Name of the systemd unit and the scheduled task
+const NAME: &str = "vrc_volta";
+
+/// Registers volta to start in the background when the user logs in, with
+/// `config` if one was passed, as a systemd user unit on Linux and a
+/// scheduled task on Windows. Returns a description of what was registered.
+pub fn install(config: Option<&Path>) -> Result<String> {
+    let executable = std::env::current_exe().context("Failed to find the volta executable")?;
+    let config = config
+        .map(|config| {
+            config
+                .canonicalize()
+                .with_context(|| format!("Failed to find the config file {config:?}"))
+        })
+        .transpose()?;
+
+    platform::install(&executable, config.as_deref())
+}
+
+pub fn uninstall() -> Result<String> {
+    platform::uninstall()
+}
+
+/// Describes whether volta is registered and running
+pub fn status() -> Result<String> {
+    platform::status()
+}
+
+/// Tells systemd that volta started once it read the levels for the first
+/// time, when it runs as a `Type=notify` unit
+pub fn notify_ready() {
+    #[cfg(target_os = "linux")]
+    if let Some(socket) = std::env::var_os("NOTIFY_SOCKET") {
+        if let Err(error) = platform::notify(&socket, "READY=1") {
+            tracing::warn!("Failed to notify systemd that volta is ready: {error:#}");
+        }
+    }
+}
+
+/// Runs a command, failing with its output if it fails
+#[cfg(any(target_os = "linux", windows))]
+fn run(program: &str, args: &[&str]) -> Result<String> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run {program}"))?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "`{program} {}` failed: {}",
+            args.join(" "),
+            if stderr.trim().is_empty() {
+                &stdout
+            } else {
+                stderr.trim()
+            }
+        );
+    }
+
+    Ok(stdout)
+}
+
+/// The arguments volta is started with
+#[cfg(any(target_os = "linux", windows))]
+fn arguments(config: Option<&Path>) -> Vec<std::path::PathBuf> {
+    match config {
+        Some(config) => vec!["--config".into(), config.to_path_buf()],
+        None => Vec::new(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::{arguments, run, NAME};
+    use anyhow::{Context, Result};
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn unit_path() -> Result<PathBuf> {
+        let config = dirs::config_dir().context("Failed to find the config directory")?;
+        Ok(config.join("systemd/user").join(format!("{NAME}.service")))
+    }
+
+    pub fn install(executable: &Path, config: Option<&Path>) -> Result<String> {
+        let path = unit_path()?;
+        fs::create_dir_all(path.parent().expect("the unit is in a directory"))?;
+        fs::write(&path, super::unit(executable, config))
+            .with_context(|| format!("Failed to write {path:?}"))?;
+        run("systemctl", &["--user", "daemon-reload"])?;
+        run("systemctl", &["--user", "enable", "--now", NAME])?;
+
+        Ok(format!(
+            "Installed and started the systemd user unit {}",
+            path.display()
+        ))
+    }
+
+    pub fn uninstall() -> Result<String> {
+        let path = unit_path()?;
+        if !path.exists() {
+            return Ok(String::from("volta isn't installed"));
+        }
+        run("systemctl", &["--user", "disable", "--now", NAME])?;
+        fs::remove_file(&path).with_context(|| format!("Failed to remove {path:?}"))?;
+        run("systemctl", &["--user", "daemon-reload"])?;
+
+        Ok(format!("Stopped and removed {}", path.display()))
+    }
+
+    pub fn status() -> Result<String> {
+        let path = unit_path()?;
+        if !path.exists() {
+            return Ok(String::from("volta isn't installed"));
+        }
+        // `is-active` fails while the unit isn't running, but still prints
+        // its state
+        let state = run("systemctl", &["--user", "is-active", NAME])
+            .unwrap_or_else(|_| String::from("inactive"));
+
+        Ok(format!("Installed as {}, {state}", path.display()))
+    }
+
+    pub fn notify(socket: &OsStr, state: &str) -> Result<()> {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let socket = socket.as_bytes();
+        let address = match socket.strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(OsStr::from_bytes(socket))?,
+        };
+        UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)?;
+
+        Ok(())
+    }
+
+    /// Quotes an argument for `ExecStart`
+    fn quote(argument: &Path) -> String {
+        let argument = argument
+            .to_string_lossy()
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        format!("\"{argument}\"")
+    }
+
+    pub(super) fn exec_start(executable: &Path, config: Option<&Path>) -> String {
+        std::iter::once(executable.to_path_buf())
+            .chain(arguments(config))
+            .map(|argument| quote(&argument))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// The systemd user unit starting volta once the user's session is up. volta
+/// resets the parameters and flushes its log on the SIGTERM systemd stops it
+/// with, as on Ctrl-C.
+#[cfg(target_os = "linux")]
+fn unit(executable: &Path, config: Option<&Path>) -> String {
+    format!(
+        "[Unit]\n\
+         Description=VRChat OSC battery levels\n\
+         \n\
+         [Service]\n\
+         Type=notify\n\
+         # Readiness waits for the headset, which may not be connected yet\n\
+         TimeoutStartSec=infinity\n\
+         ExecStart={}\n\
+         Restart=on-failure\n\
+         RestartSec=10\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        platform::exec_start(executable, config)
+    )
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::{arguments, run, NAME};
+    use anyhow::Result;
+    use std::path::Path;
+
+    pub fn install(executable: &Path, config: Option<&Path>) -> Result<String> {
+        let command = std::iter::once(executable.to_path_buf())
+            .chain(arguments(config))
+            .map(|argument| format!("\"{}\"", argument.display()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        run(
+            "schtasks",
+            &[
+                "/Create", "/TN", NAME, "/TR", &command, "/SC", "ONLOGON", "/RL", "LIMITED", "/F",
+            ],
+        )?;
+        run("schtasks", &["/Run", "/TN", NAME])?;
+
+        Ok(format!(
+            "Installed and started the scheduled task {NAME}, which starts volta when you log in"
+        ))
+    }
+
+    pub fn uninstall() -> Result<String> {
+        if run("schtasks", &["/Query", "/TN", NAME]).is_err() {
+            return Ok(String::from("volta isn't installed"));
+        }
+        // Ending the task kills volta rather than stopping it, as a task runs
+        // without a console to send Ctrl-C to, so the last levels stay on
+        // the avatar
+        let _ = run("schtasks", &["/End", "/TN", NAME]);
+        run("schtasks", &["/Delete", "/TN", NAME, "/F"])?;
+
+        Ok(format!(
+            "Stopped and removed the scheduled task {NAME}. Windows ended volta without \
+             letting it reset the parameters, so the avatar shows the last levels until it's reloaded"
+        ))
+    }
+
+    pub fn status() -> Result<String> {
+        let Ok(task) = run("schtasks", &["/Query", "/TN", NAME, "/FO", "LIST"]) else {
+            return Ok(String::from("volta isn't installed"));
+        };
+        let state = task
+            .lines()
+            .find_map(|line| line.strip_prefix("Status:"))
+            .map_or("unknown", str::trim);
+
+        Ok(format!("Installed as the scheduled task {NAME}, {state}"))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod platform {
+    use anyhow::{bail, Result};
+    use std::path::Path;
+
+    pub fn install(_: &Path, _: Option<&Path>) -> Result<String> {
+        bail!("Installing volta as a service is only supported on Linux and Windows")
+    }
+
+    pub fn uninstall() -> Result<String> {
+        install(Path::new(""), None)
+    }
+
+    pub fn status() -> Result<String> {
+        install(Path::new(""), None)
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_the_unit_with_the_config() {
+        let unit = unit(
+            Path::new("/opt/volta/vrc_volta"),
+            Some(Path::new("/home/me/My Config/volta.toml")),
+        );
+
+        assert!(unit.contains("Type=notify\n"));
+        assert!(unit.contains(
+            "ExecStart=\"/opt/volta/vrc_volta\" \"--config\" \"/home/me/My Config/volta.toml\"\n"
+        ));
+    }
+}
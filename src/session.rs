@@ -0,0 +1,181 @@
+use crate::adb;
+use crate::error::VoltaError;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A long-lived `adb shell` which runs one command after the other, saving
+/// a new shell transport for every command. Each command's error output
+/// follows its output after a line with a marker, and ends with a line with
+/// the marker and its exit code.
+#[derive(Debug)]
+pub struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<Vec<u8>>,
+    timeout: Duration,
+    commands: u64,
+}
+
+impl Session {
+    /// Starts the shell with `command`, e.g. `adb -s <serial> shell`
+    pub fn open(mut command: Command, timeout: Duration) -> Result<Self, VoltaError> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|source| VoltaError::AdbIo {
+                action: "Failed to start the adb shell session",
+                source,
+            })?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut line = Vec::new();
+            match stdout.read_until(b'\n', &mut line) {
+                Ok(1..) if sender.send(line).is_ok() => {}
+                _ => return,
+            }
+        });
+
+        Ok(Self {
+            child,
+            stdin,
+            lines,
+            timeout,
+            commands: 0,
+        })
+    }
+
+    /// Runs `command`, returning its output, error output and exit code.
+    /// Fails if the session ended or hangs, after which it can't be used
+    /// anymore.
+    pub fn run(&mut self, command: &str) -> Result<(String, String, i32), VoltaError> {
+        self.commands += 1;
+        let marker = format!("volta-end-{}-{}", std::process::id(), self.commands);
+        // The output goes to the session's output through fd 3 while the
+        // error output is caught, and the markers go on lines of their own
+        // even if the output doesn't end with a newline
+        writeln!(
+            self.stdin,
+            "{{ volta_stderr=$({{ {command}; }} 2>&1 1>&3); }} 3>&1; \\
+             printf '\\n{marker}-stderr\\n%s\\n{marker} %d\\n' \"$volta_stderr\" $?"
+        )
+        .and_then(|()| self.stdin.flush())
+        .map_err(|source| VoltaError::AdbIo {
+            action: "The adb shell session ended",
+            source,
+        })?;
+
+        let deadline = Instant::now() + self.timeout;
+        let stderr_marker = format!("{marker}-stderr\n");
+        let mut output = Vec::new();
+        let mut stderr = None;
+        let code = loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let line = match self.lines.recv_timeout(timeout) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(VoltaError::AdbTimeout {
+                        command: format!("shell {command}"),
+                        timeout: self.timeout,
+                    })
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(VoltaError::AdbFailed {
+                        command: format!("shell {command}"),
+                        message: String::from("the shell session ended"),
+                    })
+                }
+            };
+            if line == stderr_marker.as_bytes() {
+                stderr = Some(Vec::new());
+                continue;
+            }
+            match (line.strip_prefix(marker.as_bytes()), &mut stderr) {
+                (Some(code), Some(_)) => {
+                    break String::from_utf8_lossy(code).trim().parse().unwrap_or(-1)
+                }
+                (_, Some(stderr)) => stderr.extend_from_slice(&line),
+                (_, None) => output.extend_from_slice(&line),
+            }
+        };
+        output.pop();
+        let stderr = stderr.unwrap_or_default();
+
+        Ok((
+            adb::decode(&output),
+            adb::decode(&stderr).trim().to_string(),
+            code,
+        ))
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        // `sh` stands in for the shell on the headset
+        Session::open(Command::new("sh"), Duration::from_secs(2)).unwrap()
+    }
+
+    #[test]
+    fn runs_commands_one_after_the_other() {
+        let mut session = session();
+
+        assert_eq!(
+            session.run("printf 'level: 85\\n'").unwrap(),
+            (String::from("level: 85\n"), String::new(), 0)
+        );
+        assert_eq!(
+            session.run("printf 'no newline'").unwrap(),
+            (String::from("no newline"), String::new(), 0)
+        );
+        let (output, stderr, code) = session.run("cat /nonexistent").unwrap();
+        assert_eq!((output.as_str(), code), ("", 1));
+        assert!(stderr.contains("/nonexistent"), "{stderr}");
+    }
+
+    #[test]
+    fn keeps_the_error_output_apart() {
+        let mut session = session();
+
+        assert_eq!(
+            session
+                .run("echo level: 85; echo 'not found' >&2; echo level: 80; exit 3")
+                .unwrap(),
+            (
+                String::from("level: 85\nlevel: 80\n"),
+                String::from("not found"),
+                3
+            )
+        );
+        // The session goes on after a command's error output
+        assert_eq!(
+            session.run("echo again").unwrap(),
+            (String::from("again\n"), String::new(), 0)
+        );
+    }
+
+    #[test]
+    fn fails_once_the_shell_ended() {
+        let mut session = session();
+
+        // The commands run in a subshell, so it's killed instead of exited
+        assert!(session.run("kill -9 $$").is_err());
+        assert!(session.run("echo again").is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::estimate::Estimates;
+use crate::osc::Parameters;
+use crate::source::BatteryLevels;
+use crate::stages::Stages;
+use crate::threshold::LowBattery;
+use anyhow::Result;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info, warn};
+
+/// Everything known about the batteries after a successful reading
+#[derive(Clone, Debug)]
+pub struct BatteryReport {
+    pub levels: BatteryLevels,
+    pub low: LowBattery,
+    /// The levels quantized into stages, when stages are sent
+    pub stages: Option<Stages>,
+    /// How long the batteries are estimated to last, when estimates are sent
+    pub estimates: Option<Estimates>,
+    /// Fraction of the headset's storage which is free, when it's sent
+    pub storage: Option<f32>,
+    /// When the levels were read
+    pub timestamp: SystemTime,
+    /// How long ago the levels were read, which only grows while the report
+    /// is published again because reading the levels failed
+    pub age: Duration,
+}
+
+impl BatteryReport {
+    pub fn new(levels: BatteryLevels, low: LowBattery) -> Self {
+        Self {
+            levels,
+            low,
+            stages: None,
+            estimates: None,
+            storage: None,
+            timestamp: SystemTime::now(),
+            age: Duration::ZERO,
+        }
+    }
+
+    /// Whole minutes since the levels were read
+    pub fn age_minutes(&self) -> u64 {
+        self.age.as_secs() / 60
+    }
+}
+
+/// What the sinks publish which can change while volta runs, when the config
+/// file is edited
+pub struct Settings {
+    pub parameters: Parameters,
+    pub chatbox_format: String,
+}
+
+/// An output the battery levels are published to after every reading
+pub trait Sink {
+    /// Name of the sink for logs
+    fn name(&self) -> &'static str;
+
+    fn publish(&mut self, report: &BatteryReport) -> Result<()>;
+
+    /// Called instead of `publish` after reading the levels failed, with the
+    /// last report whose age grew since. Sinks which don't handle it keep
+    /// showing the last levels.
+    fn publish_stale(&mut self, _report: &BatteryReport) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called when the avatar changed, for sinks whose values are lost with it
+    fn avatar_changed(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before the cycle of a requested refresh, for sinks which only
+    /// send what changed, to send everything then
+    fn resend(&mut self) {}
+
+    /// Called when the config file was edited, for sinks which apply the new
+    /// settings
+    fn reconfigure(&mut self, _settings: &Settings) {}
+
+    /// Called once when volta stops
+    fn shutdown(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Stands in for a sink with this name in `--dry-run` mode, logging what it
+/// would publish instead of writing or connecting anywhere
+pub struct DryRun(pub &'static str);
+
+impl Sink for DryRun {
+    fn name(&self) -> &'static str {
+        self.0
+    }
+
+    fn publish(&mut self, report: &BatteryReport) -> Result<()> {
+        info!("Would publish to {}: {}", self.0, report.levels.to_json());
+        Ok(())
+    }
+
+    fn publish_stale(&mut self, report: &BatteryReport) -> Result<()> {
+        info!(
+            "Would publish the levels from {} min ago to {}",
+            report.age_minutes(),
+            self.0
+        );
+        Ok(())
+    }
+}
+
+/// Publishes to every sink, where an error only affects its own sink. Only
+/// the first of a sink's consecutive errors is logged as a warning, so one
+/// which stays broken doesn't flood the log.
+pub struct Sinks {
+    sinks: Vec<(Box<dyn Sink>, bool)>,
+}
+
+impl Sinks {
+    pub fn new(sinks: Vec<Box<dyn Sink>>) -> Self {
+        Self {
+            sinks: sinks.into_iter().map(|sink| (sink, false)).collect(),
+        }
+    }
+
+    pub fn publish(&mut self, report: &BatteryReport) {
+        self.each(|sink| sink.publish(report));
+    }
+
+    pub fn publish_stale(&mut self, report: &BatteryReport) {
+        self.each(|sink| sink.publish_stale(report));
+    }
+
+    pub fn avatar_changed(&mut self) {
+        self.each(|sink| sink.avatar_changed());
+    }
+
+    pub fn resend(&mut self) {
+        for (sink, _) in &mut self.sinks {
+            sink.resend();
+        }
+    }
+
+    pub fn reconfigure(&mut self, settings: &Settings) {
+        for (sink, _) in &mut self.sinks {
+            sink.reconfigure(settings);
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        self.each(|sink| sink.shutdown());
+    }
+
+    fn each(&mut self, mut action: impl FnMut(&mut dyn Sink) -> Result<()>) {
+        for (sink, failing) in &mut self.sinks {
+            match action(sink.as_mut()) {
+                Ok(()) if *failing => {
+                    info!("Publishing to {} works again", sink.name());
+                    *failing = false;
+                }
+                Ok(()) => {}
+                Err(error) if !*failing => {
+                    warn!("Failed to publish to {}: {error:#}", sink.name());
+                    *failing = true;
+                }
+                Err(error) => debug!("Failed to publish to {}: {error:#}", sink.name()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod fixture {
+    use super::BatteryReport;
+    use crate::adb::mock::MockAdb;
+    use crate::source::{self, ControllerScale, Device, HeadsetSource};
+    use crate::threshold::Thresholds;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// The report of a charging Pico at 85% whose left controller is
+    /// disconnected and whose right one is at 40%, read from the recorded
+    /// dumps in `tests/fixtures`
+    pub fn report() -> BatteryReport {
+        let adb = MockAdb::default()
+            .with(
+                "shell dumpsys battery",
+                include_str!("../tests/fixtures/battery.txt"),
+            )
+            .with(
+                "shell dumpsys pxrcontrollerservice",
+                include_str!("../tests/fixtures/pxrcontrollerservice_left_missing.txt"),
+            );
+        let levels = source::create(
+            &Device::Pico,
+            None,
+            adb,
+            ControllerScale::Auto,
+            HeadsetSource::Auto,
+            false,
+            false,
+        )
+        .get_levels()
+        .unwrap();
+        let low = Thresholds::new((20, 10), (20, 10)).update(&levels);
+
+        BatteryReport {
+            levels,
+            low,
+            stages: None,
+            estimates: None,
+            storage: None,
+            timestamp: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            age: Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Fails while `failing` is set and counts what it published
+    struct FlakySink {
+        failing: Rc<RefCell<bool>>,
+        published: Rc<RefCell<u32>>,
+    }
+
+    impl Sink for FlakySink {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn publish(&mut self, _: &BatteryReport) -> Result<()> {
+            if *self.failing.borrow() {
+                bail!("broken");
+            }
+            *self.published.borrow_mut() += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failing_sink_does_not_stop_others() {
+        let published = Rc::new(RefCell::new(0));
+        let failing = Rc::new(RefCell::new(true));
+        let mut sinks = Sinks::new(vec![
+            Box::new(FlakySink {
+                failing: failing.clone(),
+                published: published.clone(),
+            }),
+            Box::new(FlakySink {
+                failing: Rc::new(RefCell::new(false)),
+                published: published.clone(),
+            }),
+        ]);
+
+        sinks.publish(&fixture::report());
+        assert_eq!(*published.borrow(), 1);
+
+        *failing.borrow_mut() = false;
+        sinks.publish(&fixture::report());
+        assert_eq!(*published.borrow(), 3);
+    }
+}
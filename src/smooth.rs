@@ -0,0 +1,118 @@
+use crate::source::BatteryLevels;
+use std::collections::HashMap;
+use tracing::debug;
+
+/// Smooths every level with an exponential moving average, so a reading
+/// flapping between two percentages settles between them. A device's average
+/// starts over when it shows up again after being absent.
+pub struct Smoother {
+    /// Weight of the newest reading, from 0.0 (exclusive) to 1.0, where 1.0
+    /// doesn't smooth at all
+    alpha: f32,
+    averages: HashMap<String, f32>,
+}
+
+impl Smoother {
+    pub fn new(alpha: f32) -> Self {
+        Self {
+            alpha,
+            averages: HashMap::new(),
+        }
+    }
+
+    /// Replaces the levels with their averages
+    pub fn smooth(&mut self, levels: &mut BatteryLevels) {
+        let mut seen = HashMap::new();
+        let mut smooth = |name: String, level: &mut f32| {
+            let average = match self.averages.get(&name) {
+                Some(average) => average + self.alpha * (*level - average),
+                None => *level,
+            };
+            debug!("Smoothed the {name} level from {} to {average}", *level);
+            *level = average;
+            seen.insert(name, average);
+        };
+
+        if let Some(level) = &mut levels.headset {
+            smooth(String::from("headset"), level);
+        }
+        if let Some(level) = &mut levels.left_controller {
+            smooth(String::from("left controller"), level);
+        }
+        if let Some(level) = &mut levels.right_controller {
+            smooth(String::from("right controller"), level);
+        }
+        for (name, level) in &mut levels.trackers {
+            smooth(format!("tracker {name}"), level);
+        }
+        for device in &mut levels.steamvr {
+            smooth(format!("SteamVR device {}", device.name), &mut device.level);
+        }
+
+        self.averages = seen;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(right_controller: Option<f32>) -> BatteryLevels {
+        BatteryLevels {
+            headset: Some(0.5),
+            right_controller,
+            ..Default::default()
+        }
+    }
+
+    fn smooth(smoother: &mut Smoother, right_controller: Option<f32>) -> Option<f32> {
+        let mut levels = levels(right_controller);
+        smoother.smooth(&mut levels);
+        levels.right_controller
+    }
+
+    #[test]
+    fn settles_between_flapping_readings() {
+        let mut smoother = Smoother::new(0.3);
+        let smoothed: Vec<_> = (0..20)
+            .map(|cycle| smooth(&mut smoother, Some([0.04, 0.03][cycle % 2])).unwrap())
+            .collect();
+
+        for level in &smoothed[10..] {
+            assert!((0.034..0.037).contains(level), "{smoothed:?}");
+        }
+    }
+
+    #[test]
+    fn settles_between_flapping_steps() {
+        // A Pico controller flapping between steps 3 and 4 of 5
+        let mut smoother = Smoother::new(0.3);
+        let smoothed: Vec<_> = (0..20)
+            .map(|cycle| smooth(&mut smoother, Some([0.8, 0.6][cycle % 2])).unwrap())
+            .collect();
+
+        for level in &smoothed[10..] {
+            assert!((0.68..0.72).contains(level), "{smoothed:?}");
+        }
+    }
+
+    #[test]
+    fn tracks_a_drop() {
+        let mut smoother = Smoother::new(0.5);
+        for cycle in 0..10 {
+            let level = 0.5 - cycle as f32 * 0.01;
+            let smoothed = smooth(&mut smoother, Some(level)).unwrap();
+            // Lags behind by at most one cycle's drop
+            assert!(smoothed - level <= 0.01 + 1e-6, "{cycle}: {smoothed}");
+        }
+    }
+
+    #[test]
+    fn starts_over_after_reconnecting() {
+        let mut smoother = Smoother::new(0.5);
+        smooth(&mut smoother, Some(0.8));
+        smooth(&mut smoother, None);
+
+        assert_eq!(smooth(&mut smoother, Some(0.2)), Some(0.2));
+    }
+}
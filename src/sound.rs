@@ -0,0 +1,140 @@
+use crate::threshold::{Alert, Severity};
+use anyhow::{ensure, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// How long a device's sound isn't played again for the same threshold
+const COOLDOWN: Duration = Duration::from_secs(10 * 60);
+
+/// Sound files played when a battery gets low or critical
+#[derive(Clone, Debug, Default)]
+pub struct Sounds {
+    pub low: Option<PathBuf>,
+    pub critical: Option<PathBuf>,
+}
+
+impl Sounds {
+    fn of(&self, severity: Severity) -> Option<&PathBuf> {
+        match severity {
+            Severity::Low => self.low.as_ref(),
+            Severity::Critical => self.critical.as_ref(),
+        }
+    }
+}
+
+/// Remembers when each device's threshold last played a sound, as a level
+/// hovering around a threshold can cross it again and again
+#[derive(Default)]
+struct Cooldown {
+    played: HashMap<(&'static str, Severity), Instant>,
+}
+
+impl Cooldown {
+    /// Whether the alert's sound may play at `now`, which starts its cooldown
+    fn take(&mut self, alert: &Alert, now: Instant) -> bool {
+        let key = (alert.device, alert.severity);
+        if self
+            .played
+            .get(&key)
+            .is_some_and(|played| now.duration_since(*played) < COOLDOWN)
+        {
+            return false;
+        }
+        self.played.insert(key, now);
+        true
+    }
+}
+
+/// Plays a sound for alerts on a background thread, so decoding and playing
+/// a file can't hold up the loop
+pub struct Player {
+    alerts: Sender<Alert>,
+}
+
+impl Player {
+    pub fn spawn(sounds: Sounds) -> Result<Self> {
+        ensure!(
+            cfg!(feature = "sound"),
+            "volta was built without sound support, build it with `--features sound`"
+        );
+
+        let (alerts, receiver) = mpsc::channel::<Alert>();
+        thread::spawn(move || {
+            let mut cooldown = Cooldown::default();
+            for alert in receiver {
+                let Some(path) = sounds.of(alert.severity) else {
+                    continue;
+                };
+                if !cooldown.take(&alert, Instant::now()) {
+                    debug!("Not playing the sound for `{alert}` again yet");
+                    continue;
+                }
+                if let Err(error) = play(path) {
+                    warn!("Failed to play {}: {error:#}", path.display());
+                }
+            }
+        });
+
+        Ok(Self { alerts })
+    }
+
+    /// Plays the alert's sound if it has one, on a best-effort basis
+    pub fn notify(&self, alert: &Alert) {
+        let _ = self.alerts.send(alert.clone());
+    }
+}
+
+/// Plays a WAV or Ogg Vorbis file on the default output device until it ends
+#[cfg(feature = "sound")]
+fn play(path: &Path) -> Result<()> {
+    use anyhow::Context;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    let file = File::open(path).context("Failed to open the file")?;
+    let source = rodio::Decoder::new(BufReader::new(file)).context("Failed to decode the file")?;
+    let (_stream, handle) =
+        rodio::OutputStream::try_default().context("Failed to open the audio device")?;
+    let sink = rodio::Sink::try_new(&handle).context("Failed to open the audio device")?;
+    sink.append(source);
+    sink.sleep_until_end();
+
+    Ok(())
+}
+
+#[cfg(not(feature = "sound"))]
+fn play(_path: &Path) -> Result<()> {
+    unreachable!("the player isn't spawned without sound support")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(device: &'static str, severity: Severity) -> Alert {
+        Alert {
+            device,
+            severity,
+            percent: 9.0,
+            threshold: 10,
+        }
+    }
+
+    #[test]
+    fn plays_once_per_cooldown() {
+        let mut cooldown = Cooldown::default();
+        let start = Instant::now();
+        let critical = alert("Headset", Severity::Critical);
+
+        assert!(cooldown.take(&critical, start));
+        assert!(!cooldown.take(&critical, start + Duration::from_secs(5 * 60)));
+        // Other devices and thresholds have cooldowns of their own
+        assert!(cooldown.take(&alert("Headset", Severity::Low), start));
+        assert!(cooldown.take(&alert("Left controller", Severity::Critical), start));
+        assert!(cooldown.take(&critical, start + COOLDOWN));
+    }
+}
@@ -0,0 +1,1492 @@
+use crate::adb::AdbRunner;
+use crate::error::VoltaError;
+use crate::parse::{self, Role};
+use crate::profile::Profile;
+use crate::worker;
+use clap::ValueEnum;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Scale of readings which are already percentages
+const PERCENT_SCALE: u8 = 100;
+
+/// Pico controllers report their battery in steps of 0 to 5
+const PICO_CONTROLLER_SCALE: u8 = 5;
+
+/// How many dumps in a row have to show percentages before the scale is
+/// inferred for good, so a single glitched reading can't settle it
+const PERCENT_DUMPS: u8 = 2;
+
+/// Delay before reading a failed dump again, doubling with every attempt
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The kernel's battery percentage, which stays accurate while charging
+const SYSFS_CAPACITY: &str = "/sys/class/power_supply/battery/capacity";
+
+/// Where Pico headsets report their charge cycles, which `dumpsys battery`
+/// leaves out
+const SYSFS_CYCLES: &str = "/sys/class/power_supply/battery/cycle_count";
+
+/// How long the charge cycles read apart from `dumpsys battery` are kept, as
+/// they only change about once a day
+const CYCLES_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Battery levels normalized to 0.0 to 1.0
+#[derive(Clone, Debug, Default)]
+pub struct BatteryLevels {
+    /// `None` for a headset without a battery, which only SteamVR reports
+    pub headset: Option<f32>,
+    pub left_controller: Option<f32>,
+    pub right_controller: Option<f32>,
+    pub headset_charging: bool,
+    /// Degrees Celsius, if the headset reports it
+    pub headset_temperature: Option<f32>,
+    /// Volts, if the headset reports it
+    pub headset_voltage: Option<f32>,
+    /// Android's code of the battery's health, if the headset reports it
+    pub headset_health: Option<u8>,
+    /// Charge cycles of the battery, if the headset reports them
+    pub headset_cycles: Option<u32>,
+    /// Whether the headset is awake, if its sleep is detected
+    pub headset_awake: Option<bool>,
+    /// Other devices the headset lists next to the controllers, like motion
+    /// trackers, by name
+    pub trackers: Vec<(String, f32)>,
+    /// Devices read from SteamVR which have no parameter of their own, like
+    /// Vive trackers
+    pub steamvr: Vec<TrackedDevice>,
+}
+
+/// A device SteamVR reports a battery for, named by its role or serial
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackedDevice {
+    pub name: String,
+    pub level: f32,
+    pub charging: bool,
+}
+
+impl BatteryLevels {
+    /// The headset's levels of this reading along with the controllers and
+    /// trackers of `other`, for readings of the headset on its own
+    pub fn with_controllers_of(self, other: &BatteryLevels) -> Self {
+        Self {
+            left_controller: other.left_controller,
+            right_controller: other.right_controller,
+            trackers: other.trackers.clone(),
+            ..self
+        }
+    }
+
+    /// The levels as a JSON object, rounded to the precision the headset
+    /// reports so the f32 values don't print as 0.8500000238
+    pub fn to_json(&self) -> serde_json::Value {
+        let round = |value: f32, scale: f64| (f64::from(value) * scale).round() / scale;
+        let level = |level: f32| round(level, 100.0);
+
+        serde_json::json!({
+            "headset": self.headset.map(level),
+            "headset_charging": self.headset_charging,
+            "headset_temperature": self.headset_temperature.map(|temperature| round(temperature, 10.0)),
+            "headset_voltage": self.headset_voltage.map(|voltage| round(voltage, 1000.0)),
+            "headset_health": self.headset_health.and_then(parse::health_name),
+            "headset_cycle_count": self.headset_cycles,
+            "headset_awake": self.headset_awake,
+            "left_controller": self.left_controller.map(level),
+            "left_connected": self.left_controller.is_some(),
+            "right_controller": self.right_controller.map(level),
+            "right_connected": self.right_controller.is_some(),
+            "trackers": self
+                .trackers
+                .iter()
+                .map(|(name, tracker)| (name.clone(), level(*tracker).into()))
+                .collect::<serde_json::Map<_, _>>(),
+            "steamvr": self
+                .steamvr
+                .iter()
+                .map(|device| {
+                    let status = serde_json::json!({
+                        "level": level(device.level),
+                        "charging": device.charging,
+                    });
+                    (device.name.clone(), status)
+                })
+                .collect::<serde_json::Map<_, _>>(),
+        })
+    }
+}
+
+pub trait BatterySource {
+    fn get_levels(&self) -> Result<BatteryLevels, VoltaError>;
+
+    /// Reads only the headset, leaving the controllers and trackers out.
+    /// Sources which can't read it on its own read everything.
+    fn get_headset_levels(&self) -> Result<BatteryLevels, VoltaError> {
+        self.get_levels()
+    }
+
+    /// Reads only the controllers and trackers, keeping the headset's levels
+    /// of `last`. Sources which can't read them on their own read everything.
+    fn get_controller_levels(&self, _last: &BatteryLevels) -> Result<BatteryLevels, VoltaError> {
+        self.get_levels()
+    }
+
+    /// Reads only whether the headset is charging, which is far quicker than
+    /// reading its level. `None` for sources which can't read it on its own.
+    fn get_headset_charging(&self) -> Option<Result<bool, VoltaError>> {
+        None
+    }
+
+    /// Forgets what the readings showed about the headset's firmware, as
+    /// the headset which reconnected may run another, e.g. after an update
+    fn reconnected(&self) {}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Device {
+    Pico,
+    Quest,
+    /// Pick the backend from the headset's manufacturer
+    Auto,
+    /// Read the controllers with the device profile of this name
+    Profile(String),
+}
+
+impl FromStr for Device {
+    type Err = String;
+
+    fn from_str(device: &str) -> Result<Self, Self::Err> {
+        if let Some(name) = device.strip_prefix("profile:") {
+            return match name.is_empty() {
+                true => Err(String::from("`profile:` needs the profile's name")),
+                false => Ok(Device::Profile(name.to_string())),
+            };
+        }
+        match device.to_lowercase().as_str() {
+            "pico" => Ok(Device::Pico),
+            "quest" => Ok(Device::Quest),
+            "auto" => Ok(Device::Auto),
+            _ => Err(format!(
+                "invalid device `{device}`, expected `pico`, `quest`, `auto` or `profile:<name>`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Device::Pico => f.write_str("pico"),
+            Device::Quest => f.write_str("quest"),
+            Device::Auto => f.write_str("auto"),
+            Device::Profile(name) => write!(f, "profile:{name}"),
+        }
+    }
+}
+
+/// Where the battery levels are read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SourceKind {
+    /// The headset over adb
+    Adb,
+    /// SteamVR, for PCVR headsets and lighthouse tracked devices
+    #[value(name = "steamvr")]
+    SteamVr,
+    /// The headset over adb, plus the other devices SteamVR tracks
+    Both,
+}
+
+/// Where the headset level is read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum HeadsetSource {
+    /// `dumpsys battery`, which some Pico firmware caches while charging
+    Dumpsys,
+    /// The kernel's battery capacity in sysfs
+    Sysfs,
+    /// sysfs, falling back to `dumpsys battery` where it can't be read
+    Auto,
+}
+
+impl HeadsetSource {
+    /// The strategies to try, in order
+    fn strategies(self) -> &'static [HeadsetSource] {
+        match self {
+            HeadsetSource::Dumpsys => &[HeadsetSource::Dumpsys],
+            HeadsetSource::Sysfs => &[HeadsetSource::Sysfs],
+            HeadsetSource::Auto => &[HeadsetSource::Sysfs, HeadsetSource::Dumpsys],
+        }
+    }
+}
+
+/// Reads the headset level with the strategies of a [`HeadsetSource`],
+/// remembering once `auto` found sysfs unreadable, e.g. for lack of
+/// permission, so it isn't asked for every cycle
+pub struct HeadsetReader {
+    source: HeadsetSource,
+    sysfs_unreadable: Cell<bool>,
+    /// Whether the charge cycles are read from sysfs or `dumpsys
+    /// batterystats` when `dumpsys battery` lacks them
+    cycles_elsewhere: bool,
+    /// The charge cycles read that way, and when
+    cycles: Cell<Option<(Instant, Option<u32>)>>,
+}
+
+impl HeadsetReader {
+    pub fn new(source: HeadsetSource) -> Self {
+        Self {
+            source,
+            sysfs_unreadable: Cell::new(false),
+            cycles_elsewhere: false,
+            cycles: Cell::new(None),
+        }
+    }
+
+    /// Reads the charge cycles from sysfs or `dumpsys batterystats` when
+    /// `dumpsys battery` lacks them, like on Pico headsets
+    pub fn with_cycles_elsewhere(self) -> Self {
+        Self {
+            cycles_elsewhere: true,
+            ..self
+        }
+    }
+
+    /// The charge cycles from `battery`, or read apart from it every
+    /// [`CYCLES_INTERVAL`] if that's set
+    fn cycles(&self, adb: &impl AdbRunner, battery: &str) -> Option<u32> {
+        let cycles = parse::parse_headset_cycles(battery);
+        if cycles.is_some() || !self.cycles_elsewhere {
+            return cycles;
+        }
+        if let Some((read, cycles)) = self.cycles.get() {
+            if read.elapsed() < CYCLES_INTERVAL {
+                return cycles;
+            }
+        }
+
+        let cycles = adb
+            .shell(&["cat", SYSFS_CYCLES])
+            .ok()
+            .and_then(|cycles| cycles.trim().parse().ok())
+            .or_else(|| {
+                adb.shell(&["dumpsys", "batterystats"])
+                    .ok()
+                    .and_then(|dump| parse::parse_headset_cycles(&dump))
+            });
+        debug!("Read the headset's charge cycles {cycles:?}");
+        self.cycles.set(Some((Instant::now(), cycles)));
+        cycles
+    }
+
+    /// Tries the strategies in order, `battery` being the output of
+    /// `dumpsys battery` which is read for the charging state anyway
+    fn level(&self, adb: &impl AdbRunner, battery: &str) -> Result<u8, VoltaError> {
+        let mut result = Err(VoltaError::Unreadable(String::from(
+            "No headset level strategy",
+        )));
+        for &strategy in self.source.strategies() {
+            result = match strategy {
+                HeadsetSource::Sysfs if self.sysfs_unreadable.get() => continue,
+                HeadsetSource::Sysfs => self.sysfs_level(adb),
+                _ => dumpsys_level(battery),
+            };
+            match &result {
+                Ok(level) => {
+                    debug!("Read the headset level {level} from {strategy:?}");
+                    break;
+                }
+                Err(error) => {
+                    debug!("Failed to read the headset level from {strategy:?}: {error:#}")
+                }
+            }
+        }
+
+        result
+    }
+
+    fn sysfs_level(&self, adb: &impl AdbRunner) -> Result<u8, VoltaError> {
+        let capacity = adb.shell(&["cat", SYSFS_CAPACITY])?;
+        let capacity = capacity.trim();
+        capacity.parse().map_err(|_| {
+            // adb answered, so this is how the headset answers every time
+            if self.source == HeadsetSource::Auto {
+                info!("{SYSFS_CAPACITY} can't be read, reading the headset level from `dumpsys battery`");
+                self.sysfs_unreadable.set(true);
+            }
+            VoltaError::Unreadable(format!(
+                "{SYSFS_CAPACITY} contains `{capacity}`, not a percentage"
+            ))
+        })
+    }
+}
+
+/// Scale the Pico controllers report their battery on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ControllerScale {
+    /// Steps of 0 to 5, like the Pico Neo 3
+    #[value(name = "5")]
+    Steps,
+    /// Percentages, like some newer firmware
+    #[value(name = "100")]
+    Percent,
+    /// Infer the scale from the readings
+    Auto,
+}
+
+/// Resolves the controller scale, remembering it once the readings showed
+/// the controllers report percentages
+pub struct Scale {
+    setting: ControllerScale,
+    inferred: Cell<Option<u8>>,
+    /// Dumps in a row whose readings could only be percentages
+    percent_dumps: Cell<u8>,
+    warned: Cell<bool>,
+}
+
+impl Scale {
+    pub fn new(setting: ControllerScale) -> Self {
+        Self {
+            setting,
+            inferred: Cell::new(None),
+            percent_dumps: Cell::new(0),
+            warned: Cell::new(false),
+        }
+    }
+
+    /// Returns the scale of `readings`, the controller levels of one dump
+    fn of(&self, readings: &[u8]) -> u8 {
+        match self.setting {
+            ControllerScale::Steps => return PICO_CONTROLLER_SCALE,
+            ControllerScale::Percent => return PERCENT_SCALE,
+            ControllerScale::Auto => {}
+        }
+        if let Some(scale) = self.inferred.get() {
+            return scale;
+        }
+
+        // A step reading never exceeds 5, so anything above has to be a
+        // percentage, unless it's beyond 100 and thus a glitch either way
+        let percentages = (PICO_CONTROLLER_SCALE + 1)..=PERCENT_SCALE;
+        if readings.iter().any(|reading| percentages.contains(reading)) {
+            let dumps = self.percent_dumps.get() + 1;
+            self.percent_dumps.set(dumps);
+            if dumps >= PERCENT_DUMPS {
+                info!("The controllers report percentages, using a scale of 0 to 100");
+                self.inferred.set(Some(PERCENT_SCALE));
+            }
+            return PERCENT_SCALE;
+        }
+        self.percent_dumps.set(0);
+
+        if !readings.is_empty() && !self.warned.replace(true) {
+            warn!(
+                "Controller readings {readings:?} could be steps or percentages, assuming steps of 0 to 5, \
+                 pass --controller-scale to choose"
+            );
+        }
+        PICO_CONTROLLER_SCALE
+    }
+}
+
+/// Creates the battery source for `device`, asking the headset for its
+/// manufacturer when the device is `auto`, and reading the controllers with
+/// `profile` when it's set, which it has to be for a `profile:` device.
+/// Readings beyond their scale are clamped if `clamp` is set, and otherwise
+/// fail the reading for the headset and leave the device out for the rest.
+/// With `detect_sleep`, only the headset is read while it's asleep.
+pub fn create<A: AdbRunner + 'static>(
+    device: &Device,
+    profile: Option<&Profile>,
+    adb: A,
+    scale: ControllerScale,
+    headset: HeadsetSource,
+    clamp: bool,
+    detect_sleep: bool,
+) -> Box<dyn BatterySource> {
+    let headset = || HeadsetReader::new(headset);
+    let sleep = || detect_sleep.then(Sleep::default);
+    let pico = |adb| {
+        Box::new(PicoSource {
+            adb,
+            scale: Scale::new(scale),
+            bluetooth: Cell::new(false),
+            headset: headset().with_cycles_elsewhere(),
+            clamp,
+            sleep: sleep(),
+        })
+    };
+    let quest = |adb| {
+        Box::new(QuestSource {
+            adb,
+            headset: headset(),
+            clamp,
+            sleep: sleep(),
+        })
+    };
+
+    if let Some(profile) = profile {
+        return Box::new(ProfileSource {
+            adb,
+            profile: profile.clone(),
+            headset: headset(),
+            clamp,
+            sleep: sleep(),
+        });
+    }
+    match device {
+        Device::Pico => pico(adb),
+        Device::Quest => quest(adb),
+        Device::Profile(name) => {
+            warn!("The device profile `{name}` wasn't loaded, using the Pico backend");
+            pico(adb)
+        }
+        Device::Auto => match get_manufacturer(&adb) {
+            Ok(manufacturer) if is_quest(&manufacturer) => {
+                info!("Detected a {manufacturer} headset, using the Quest backend");
+                quest(adb)
+            }
+            Ok(manufacturer) => {
+                info!("Detected a {manufacturer} headset, using the Pico backend");
+                pico(adb)
+            }
+            Err(error) => {
+                warn!("Failed to detect the headset, using the Pico backend: {error:#}");
+                pico(adb)
+            }
+        },
+    }
+}
+
+fn is_quest(manufacturer: &str) -> bool {
+    let manufacturer = manufacturer.to_lowercase();
+    manufacturer.contains("oculus") || manufacturer.contains("meta")
+}
+
+/// Whether the headset was awake at the last reading and the levels read
+/// while it last was. Asleep, the controller service stops updating and
+/// querying it keeps waking the headset over wireless adb, so only the headset
+/// is read and the controllers keep their last levels.
+#[derive(Default)]
+struct Sleep {
+    asleep: Cell<bool>,
+    last: RefCell<Option<BatteryLevels>>,
+}
+
+impl Sleep {
+    /// Reads whether the headset is awake, logging when that changes
+    fn check(&self, adb: &impl AdbRunner) -> bool {
+        let awake = is_awake(adb);
+        if self.asleep.replace(!awake) == awake {
+            match awake {
+                true => info!("The headset woke up"),
+                false => info!("The headset is asleep, reading only its battery"),
+            }
+        }
+
+        awake
+    }
+
+    /// Reads the headset on its own with `headset` while it's asleep, and
+    /// everything with `read` otherwise
+    fn read(
+        &self,
+        adb: &impl AdbRunner,
+        headset: Headset,
+        read: impl FnOnce() -> Result<BatteryLevels, VoltaError>,
+    ) -> Result<BatteryLevels, VoltaError> {
+        if !self.check(adb) {
+            let headset = BatteryLevels {
+                headset_awake: Some(false),
+                ..headset()?
+            };
+            return Ok(match &*self.last.borrow() {
+                Some(last) => headset.with_controllers_of(last),
+                None => headset,
+            });
+        }
+
+        let levels = BatteryLevels {
+            headset_awake: Some(true),
+            ..read()?
+        };
+        *self.last.borrow_mut() = Some(levels.clone());
+        Ok(levels)
+    }
+}
+
+/// Reads the headset's levels, or hands over the ones read last when only the
+/// controllers are read
+type Headset<'a> = &'a dyn Fn() -> Result<BatteryLevels, VoltaError>;
+
+/// Reads everything with `read`, or only the headset with `headset` while
+/// it's asleep if its sleep is detected
+fn read_awake(
+    adb: &impl AdbRunner,
+    sleep: Option<&Sleep>,
+    headset: Headset,
+    read: impl FnOnce() -> Result<BatteryLevels, VoltaError>,
+) -> Result<BatteryLevels, VoltaError> {
+    match sleep {
+        Some(sleep) => sleep.read(adb, headset, read),
+        None => read(),
+    }
+}
+
+/// Reads the headset on its own, along with whether it's awake if its sleep
+/// is detected
+fn read_headset(
+    adb: &impl AdbRunner,
+    headset: &HeadsetReader,
+    clamp: bool,
+    sleep: Option<&Sleep>,
+) -> Result<BatteryLevels, VoltaError> {
+    let awake = sleep.map(|sleep| sleep.check(adb));
+    Ok(BatteryLevels {
+        headset_awake: awake,
+        ..get_headset(adb, headset, clamp)?
+    })
+}
+
+/// Whether the headset is charging, from only the lines of `dumpsys battery`
+/// which tell
+fn get_charging(adb: &impl AdbRunner) -> Result<bool, VoltaError> {
+    let battery = adb
+        .shell(&[
+            "dumpsys", "battery", "|", "grep", "-e", "powered:", "-e", "status:",
+        ])
+        .map_err(|error| error.reading("Failed to get the headset's charging state"))?;
+    Ok(parse::parse_headset_charging(&battery))
+}
+
+/// Whether the headset is awake, taking it to be if that can't be read, so
+/// headsets which don't report it are read in full
+fn is_awake(adb: &impl AdbRunner) -> bool {
+    match adb
+        .shell(&["dumpsys", "power", "|", "grep", "mWakefulness="])
+        .and_then(|power| parse::parse_awake(&power))
+    {
+        Ok(awake) => awake,
+        Err(error) => {
+            debug!("Failed to read whether the headset is awake: {error:#}");
+            true
+        }
+    }
+}
+
+pub struct PicoSource<A> {
+    adb: A,
+    scale: Scale,
+    /// Set once `pxrcontrollerservice` turned out to be missing, as it won't
+    /// show up while the firmware runs
+    bluetooth: Cell<bool>,
+    headset: HeadsetReader,
+    clamp: bool,
+    sleep: Option<Sleep>,
+}
+
+impl<A: AdbRunner> BatterySource for PicoSource<A> {
+    fn get_levels(&self) -> Result<BatteryLevels, VoltaError> {
+        let headset = || get_headset(&self.adb, &self.headset, self.clamp);
+        read_awake(&self.adb, self.sleep.as_ref(), &headset, || {
+            read_levels(
+                &self.adb,
+                &self.scale,
+                &self.bluetooth,
+                self.clamp,
+                &headset,
+            )
+        })
+    }
+
+    fn get_controller_levels(&self, last: &BatteryLevels) -> Result<BatteryLevels, VoltaError> {
+        let headset = || Ok(last.clone());
+        read_awake(&self.adb, self.sleep.as_ref(), &headset, || {
+            read_levels(
+                &self.adb,
+                &self.scale,
+                &self.bluetooth,
+                self.clamp,
+                &headset,
+            )
+        })
+    }
+
+    fn get_headset_levels(&self) -> Result<BatteryLevels, VoltaError> {
+        read_headset(&self.adb, &self.headset, self.clamp, self.sleep.as_ref())
+    }
+
+    fn get_headset_charging(&self) -> Option<Result<bool, VoltaError>> {
+        Some(get_charging(&self.adb))
+    }
+
+    /// Checks for `pxrcontrollerservice` again, which the new firmware may
+    /// have, e.g. after an mDNS retarget found another headset
+    fn reconnected(&self) {
+        self.bluetooth.set(false);
+    }
+}
+
+pub struct QuestSource<A> {
+    adb: A,
+    headset: HeadsetReader,
+    clamp: bool,
+    sleep: Option<Sleep>,
+}
+
+impl<A: AdbRunner> BatterySource for QuestSource<A> {
+    fn get_levels(&self) -> Result<BatteryLevels, VoltaError> {
+        let headset = || get_headset(&self.adb, &self.headset, self.clamp);
+        read_awake(&self.adb, self.sleep.as_ref(), &headset, || {
+            self.read(&headset)
+        })
+    }
+
+    fn get_controller_levels(&self, last: &BatteryLevels) -> Result<BatteryLevels, VoltaError> {
+        let headset = || Ok(last.clone());
+        read_awake(&self.adb, self.sleep.as_ref(), &headset, || {
+            self.read(&headset)
+        })
+    }
+
+    fn get_headset_levels(&self) -> Result<BatteryLevels, VoltaError> {
+        read_headset(&self.adb, &self.headset, self.clamp, self.sleep.as_ref())
+    }
+
+    fn get_headset_charging(&self) -> Option<Result<bool, VoltaError>> {
+        Some(get_charging(&self.adb))
+    }
+}
+
+impl<A: AdbRunner> QuestSource<A> {
+    fn read(&self, headset: Headset) -> Result<BatteryLevels, VoltaError> {
+        let adb = &self.adb;
+        let (headset, controllers) = concurrently(
+            || timed("headset query", headset),
+            || timed("controller query", || get_remote_service_dump(adb)),
+        );
+        let headset = headset?;
+        let (left_controller, right_controller) =
+            parse::parse_quest_controller_levels(&controllers?)?;
+        let level = |name, reading| device_level(name, reading, PERCENT_SCALE, self.clamp);
+
+        Ok(BatteryLevels {
+            left_controller: left_controller.and_then(|reading| level("left controller", reading)),
+            right_controller: right_controller
+                .and_then(|reading| level("right controller", reading)),
+            ..headset
+        })
+    }
+}
+
+/// Reads the controllers with a device profile, and the headset the way every
+/// Android based headset reports it
+pub struct ProfileSource<A> {
+    adb: A,
+    profile: Profile,
+    headset: HeadsetReader,
+    clamp: bool,
+    sleep: Option<Sleep>,
+}
+
+impl<A: AdbRunner> BatterySource for ProfileSource<A> {
+    fn get_levels(&self) -> Result<BatteryLevels, VoltaError> {
+        let headset = || get_headset(&self.adb, &self.headset, self.clamp);
+        read_awake(&self.adb, self.sleep.as_ref(), &headset, || {
+            self.read(&headset)
+        })
+    }
+
+    fn get_controller_levels(&self, last: &BatteryLevels) -> Result<BatteryLevels, VoltaError> {
+        let headset = || Ok(last.clone());
+        read_awake(&self.adb, self.sleep.as_ref(), &headset, || {
+            self.read(&headset)
+        })
+    }
+
+    fn get_headset_levels(&self) -> Result<BatteryLevels, VoltaError> {
+        read_headset(&self.adb, &self.headset, self.clamp, self.sleep.as_ref())
+    }
+
+    fn get_headset_charging(&self) -> Option<Result<bool, VoltaError>> {
+        Some(get_charging(&self.adb))
+    }
+}
+
+impl<A: AdbRunner> ProfileSource<A> {
+    fn read(&self, headset: Headset) -> Result<BatteryLevels, VoltaError> {
+        let adb = &self.adb;
+        let command: Vec<&str> = self.profile.command.iter().map(String::as_str).collect();
+        let (headset, controllers) = concurrently(
+            || timed("headset query", headset),
+            || timed("controller query", || adb.shell(&command)),
+        );
+        let headset = headset?;
+        let controllers =
+            controllers.map_err(|error| error.reading("Failed to get controller batteries"))?;
+        let (left_controller, right_controller) = self.profile.parse(&controllers)?;
+        let level = |name, reading| device_level(name, reading, self.profile.scale, self.clamp);
+
+        Ok(BatteryLevels {
+            left_controller: left_controller.and_then(|reading| level("left controller", reading)),
+            right_controller: right_controller
+                .and_then(|reading| level("right controller", reading)),
+            ..headset
+        })
+    }
+}
+
+/// Reads the Pico controllers, along with the headset with `headset`, over
+/// Bluetooth once `bluetooth` is set
+fn read_levels(
+    adb: &impl AdbRunner,
+    scale: &Scale,
+    bluetooth: &Cell<bool>,
+    clamp: bool,
+    headset: Headset,
+) -> Result<BatteryLevels, VoltaError> {
+    let latched = bluetooth.get();
+    let (headset, controllers) = concurrently(
+        || timed("headset query", headset),
+        || {
+            timed("controller query", || match latched {
+                true => get_bluetooth_dump(adb),
+                false => get_controller_service_dump(adb),
+            })
+        },
+    );
+    let headset = headset?;
+    // The headset level is still worth sending when only the controllers fail
+    let skip = |error: VoltaError| warn!("{error:#}, sending the headset alone");
+    let mut controllers = match controllers {
+        Ok(controllers) => controllers,
+        Err(error) => {
+            skip(error);
+            return Ok(headset);
+        }
+    };
+    if !latched && parse::is_missing_service(&controllers) {
+        info!("The headset has no pxrcontrollerservice, reading the controllers over Bluetooth");
+        bluetooth.set(true);
+        controllers = match get_bluetooth_dump(adb) {
+            Ok(controllers) => controllers,
+            Err(error) => {
+                skip(error);
+                return Ok(headset);
+            }
+        };
+    }
+    if bluetooth.get() {
+        // The battery profile reports percentages
+        let (left_controller, right_controller) = parse::parse_bluetooth_controllers(&controllers);
+        let level = |name, reading| device_level(name, reading, PERCENT_SCALE, clamp);
+        return Ok(BatteryLevels {
+            left_controller: left_controller.and_then(|reading| level("left controller", reading)),
+            right_controller: right_controller
+                .and_then(|reading| level("right controller", reading)),
+            ..headset
+        });
+    }
+    let controllers = parse::parse_controllers(&controllers);
+    let left_controller = parse::level(&controllers, &Role::Left);
+    let right_controller = parse::level(&controllers, &Role::Right);
+    let trackers = parse::tracker_levels(&controllers);
+    // Trackers are listed by the same service and report on the same scale
+    let scale = scale.of(&[left_controller, right_controller]
+        .into_iter()
+        .flatten()
+        .chain(trackers.iter().map(|(_, reading)| *reading))
+        .collect::<Vec<_>>());
+    let level = |name: &str, reading| device_level(name, reading, scale, clamp);
+
+    Ok(BatteryLevels {
+        left_controller: left_controller.and_then(|reading| level("left controller", reading)),
+        right_controller: right_controller.and_then(|reading| level("right controller", reading)),
+        trackers: trackers
+            .into_iter()
+            .filter_map(|(name, reading)| level(&name, reading).map(|level| (name, level)))
+            .collect(),
+        ..headset
+    })
+}
+
+/// Converts a raw reading on a scale of 0 to `scale` to a level. A reading
+/// beyond the scale, e.g. from a firmware glitch, is clamped if `clamp` is
+/// set and fails otherwise, so it can never send garbage to VRChat.
+fn normalize(name: &str, reading: u8, scale: u8, clamp: bool) -> Result<f32, VoltaError> {
+    if reading > scale {
+        if !clamp {
+            return Err(VoltaError::OutOfScale {
+                name: name.to_string(),
+                reading,
+                scale,
+            });
+        }
+        warn!("The {name} reported `{reading}`, beyond its scale of 0 to {scale}, clamping it");
+        return Ok(1.0);
+    }
+
+    Ok(f32::from(reading) / f32::from(scale))
+}
+
+/// Like [`normalize`] for a controller or tracker, which is left out of the
+/// reading with a warning rather than failing it when beyond its scale
+fn device_level(name: &str, reading: u8, scale: u8, clamp: bool) -> Option<f32> {
+    normalize(name, reading, scale, clamp)
+        .inspect_err(|error| warn!("{error}"))
+        .ok()
+}
+
+/// Converts a level back to the whole percentage, which is exact for every
+/// level read from a headset since they're all integer readings
+pub fn percent(level: f32) -> i32 {
+    (level * 100.0).round() as i32
+}
+
+/// Runs both queries at the same time, so over wireless adb a cycle takes as
+/// long as the slower one rather than both together
+fn concurrently<T: Send, U: Send>(
+    first: impl FnOnce() -> T,
+    second: impl FnOnce() -> U + Send,
+) -> (T, U) {
+    thread::scope(|scope| {
+        let second = scope.spawn(second);
+        let first = first();
+        let second = second
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        (first, second)
+    })
+}
+
+fn timed<T>(name: &str, query: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = query();
+    debug!("{name} took {:?}", start.elapsed());
+    result
+}
+
+/// Reads the headset level, charging state, temperature, voltage and health,
+/// which every Android based headset reports through `dumpsys battery`, with
+/// the level from the first of `headset`'s strategies which works. The
+/// controllers are left disconnected.
+fn get_headset(
+    adb: &impl AdbRunner,
+    headset: &HeadsetReader,
+    clamp: bool,
+) -> Result<BatteryLevels, VoltaError> {
+    let battery = get_battery_dump(adb)?;
+    let level = headset.level(adb, &battery)?;
+
+    Ok(BatteryLevels {
+        headset: Some(normalize("headset", level, PERCENT_SCALE, clamp)?),
+        left_controller: None,
+        right_controller: None,
+        headset_charging: parse::parse_headset_charging(&battery),
+        headset_temperature: parse::parse_headset_temperature(&battery),
+        headset_voltage: parse::parse_headset_voltage(&battery),
+        headset_health: parse::parse_headset_health(&battery),
+        headset_cycles: headset.cycles(adb, &battery),
+        headset_awake: None,
+        trackers: Vec::new(),
+        steamvr: Vec::new(),
+    })
+}
+
+fn dumpsys_level(battery: &str) -> Result<u8, VoltaError> {
+    parse::parse_headset_level(battery)
+}
+
+pub fn get_battery_dump(adb: &impl AdbRunner) -> Result<String, VoltaError> {
+    dump(adb, "battery").map_err(|error| error.reading("Failed to get headset battery"))
+}
+
+pub fn get_controller_service_dump(adb: &impl AdbRunner) -> Result<String, VoltaError> {
+    dump(adb, "pxrcontrollerservice")
+        .map_err(|error| error.reading("Failed to get controller batteries"))
+}
+
+fn get_bluetooth_dump(adb: &impl AdbRunner) -> Result<String, VoltaError> {
+    dump(adb, "bluetooth_manager")
+        .map_err(|error| error.reading("Failed to get controller batteries over Bluetooth"))
+}
+
+fn get_remote_service_dump(adb: &impl AdbRunner) -> Result<String, VoltaError> {
+    dump(adb, "OVRRemoteService")
+        .map_err(|error| error.reading("Failed to get controller batteries"))
+}
+
+/// Reads a dump, reading it again after a growing delay while adb fails or
+/// returns nothing, which is often a one-off hiccup over wireless adb. Dumps
+/// which fail to parse aren't read again, as they'd fail the same way.
+fn dump(adb: &impl AdbRunner, service: &'static str) -> Result<String, VoltaError> {
+    let mut delay = FIRST_RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        match read_dump(adb, service) {
+            // There's no point in retrying without adb, or while it hangs
+            Err(error) if attempt < adb.retries() && error.is_retryable() => {
+                attempt += 1;
+                debug!("Failed to read dumpsys {service}, retrying in {delay:?}: {error:#}");
+                // A stopping volta doesn't wait for the retry
+                if !worker::sleep(delay) {
+                    return Err(error);
+                }
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn read_dump(adb: &impl AdbRunner, service: &'static str) -> Result<String, VoltaError> {
+    let dump = adb.shell(&["dumpsys", service])?;
+    if dump.trim().is_empty() {
+        return Err(VoltaError::DumpEmpty(service));
+    }
+
+    Ok(dump)
+}
+
+fn get_manufacturer(adb: &impl AdbRunner) -> Result<String, VoltaError> {
+    let manufacturer = adb
+        .shell(&["getprop", "ro.product.manufacturer"])
+        .map_err(|error| error.reading("Failed to get headset manufacturer"))?;
+    let manufacturer = manufacturer.trim();
+    if manufacturer.is_empty() {
+        return Err(VoltaError::Unreadable(String::from(
+            "Headset reported no manufacturer",
+        )));
+    }
+
+    Ok(manufacturer.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb::mock::MockAdb;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Reads a Pico headset and its controllers once, trying
+    /// `pxrcontrollerservice` first like a fresh `PicoSource`
+    fn get_levels(
+        adb: &impl AdbRunner,
+        scale: &Scale,
+        headset: &HeadsetReader,
+        clamp: bool,
+    ) -> Result<BatteryLevels, VoltaError> {
+        let bluetooth = Cell::new(false);
+        read_levels(adb, scale, &bluetooth, clamp, &|| {
+            get_headset(adb, headset, clamp)
+        })
+    }
+
+    #[test]
+    fn gets_pico_levels() {
+        let adb = MockAdb::default()
+            .with(
+                "shell dumpsys battery",
+                include_str!("../tests/fixtures/battery.txt"),
+            )
+            .with(
+                "shell dumpsys pxrcontrollerservice",
+                include_str!("../tests/fixtures/pxrcontrollerservice_left_missing.txt"),
+            );
+        let levels = get_levels(
+            &adb,
+            &Scale::new(ControllerScale::Auto),
+            &HeadsetReader::new(HeadsetSource::Auto),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(levels.headset, Some(0.85));
+        assert_eq!(levels.left_controller, None);
+        assert_eq!(levels.right_controller, Some(0.4));
+        assert!(levels.headset_charging);
+    }
+
+    #[test]
+    fn reads_the_controllers_with_a_profile() {
+        let adb = MockAdb::default()
+            .with(
+                "shell dumpsys battery",
+                include_str!("../tests/fixtures/battery.txt"),
+            )
+            .with(
+                "shell dumpsys wvrcontrollerservice",
+                include_str!("../tests/fixtures/vive_focus_controllers_right_missing.txt"),
+            );
+        let profile = crate::profile::find("vive_focus", &Default::default()).unwrap();
+        let device = "profile:vive_focus".parse().unwrap();
+        let levels = create(
+            &device,
+            Some(&profile),
+            adb,
+            ControllerScale::Auto,
+            HeadsetSource::Dumpsys,
+            false,
+            false,
+        )
+        .get_levels()
+        .unwrap();
+
+        assert_eq!(levels.headset, Some(0.85));
+        assert_eq!(levels.left_controller, Some(1.0));
+        assert_eq!(levels.right_controller, None);
+        assert_eq!(device.to_string(), "profile:vive_focus");
+        assert!("profile:".parse::<Device>().is_err());
+    }
+
+    #[test]
+    fn reads_only_the_controllers() {
+        let adb = MockAdb::default().with(
+            "shell dumpsys pxrcontrollerservice",
+            include_str!("../tests/fixtures/pxrcontrollerservice.txt"),
+        );
+        let source = create(
+            &Device::Pico,
+            None,
+            adb,
+            ControllerScale::Auto,
+            HeadsetSource::Dumpsys,
+            false,
+            false,
+        );
+        let last = BatteryLevels {
+            headset_charging: true,
+            ..crate::fake::parse_values("0.5,-,-").unwrap()
+        };
+
+        let levels = source.get_controller_levels(&last).unwrap();
+        assert_eq!(levels.headset, Some(0.5));
+        assert!(levels.headset_charging);
+        assert_eq!(levels.left_controller, Some(0.8));
+        assert_eq!(levels.right_controller, Some(0.6));
+    }
+
+    #[test]
+    fn reads_only_the_headset_while_asleep() {
+        let power = |wakefulness| format!("  mWakefulness={wakefulness}\n");
+        let awake = pico_adb(include_str!("../tests/fixtures/pxrcontrollerservice.txt"))
+            .with("shell dumpsys power | grep mWakefulness=", &power("Awake"));
+        // Asleep, the controller service isn't queried at all
+        let asleep = MockAdb::default()
+            .with(
+                "shell dumpsys battery",
+                include_str!("../tests/fixtures/battery_discharging.txt"),
+            )
+            .with("shell dumpsys power | grep mWakefulness=", &power("Asleep"));
+        let sleep = Sleep::default();
+        let headset = HeadsetReader::new(HeadsetSource::Dumpsys);
+        let read = |adb: &MockAdb| {
+            let full = || get_levels(adb, &Scale::new(ControllerScale::Auto), &headset, false);
+            sleep
+                .read(adb, &|| get_headset(adb, &headset, false), full)
+                .unwrap()
+        };
+
+        let levels = read(&awake);
+        assert_eq!(levels.headset_awake, Some(true));
+        let controllers = (levels.left_controller, levels.right_controller);
+
+        let levels = read(&asleep);
+        assert_eq!(levels.headset_awake, Some(false));
+        assert_eq!(levels.headset, Some(0.42));
+        assert_eq!(
+            (levels.left_controller, levels.right_controller),
+            controllers
+        );
+    }
+
+    #[test]
+    fn gets_tracker_levels() {
+        let adb = pico_adb(include_str!(
+            "../tests/fixtures/pxrcontrollerservice_trackers.txt"
+        ));
+        let levels = get_levels(
+            &adb,
+            &Scale::new(ControllerScale::Auto),
+            &HeadsetReader::new(HeadsetSource::Auto),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(levels.left_controller, Some(0.8));
+        assert_eq!(levels.right_controller, Some(0.6));
+        assert_eq!(
+            levels.trackers,
+            [
+                (String::from("tracker0"), 1.0),
+                (String::from("tracker1"), 0.4)
+            ]
+        );
+    }
+
+    fn pico_adb(controllers: &str) -> MockAdb {
+        MockAdb::default()
+            .with(
+                "shell dumpsys battery",
+                include_str!("../tests/fixtures/battery.txt"),
+            )
+            .with("shell dumpsys pxrcontrollerservice", controllers)
+    }
+
+    #[test]
+    fn infers_step_scale() {
+        let adb = pico_adb(include_str!("../tests/fixtures/pxrcontrollerservice.txt"));
+        let levels = get_levels(
+            &adb,
+            &Scale::new(ControllerScale::Auto),
+            &HeadsetReader::new(HeadsetSource::Auto),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(levels.left_controller, Some(0.8));
+        assert_eq!(levels.right_controller, Some(0.6));
+    }
+
+    #[test]
+    fn infers_and_remembers_percent_scale() {
+        let scale = Scale::new(ControllerScale::Auto);
+        let adb = pico_adb(include_str!(
+            "../tests/fixtures/pxrcontrollerservice_percent.txt"
+        ));
+        for _ in 0..PERCENT_DUMPS {
+            let levels = get_levels(
+                &adb,
+                &scale,
+                &HeadsetReader::new(HeadsetSource::Auto),
+                false,
+            )
+            .unwrap();
+
+            assert_eq!(levels.left_controller, Some(0.8));
+            assert_eq!(levels.right_controller, Some(0.45));
+        }
+
+        // Low percentages look like steps, but the inferred scale sticks
+        let adb = pico_adb(include_str!("../tests/fixtures/pxrcontrollerservice.txt"));
+        let levels = get_levels(
+            &adb,
+            &scale,
+            &HeadsetReader::new(HeadsetSource::Auto),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(levels.left_controller, Some(0.04));
+        assert_eq!(levels.right_controller, Some(0.03));
+    }
+
+    #[test]
+    fn infers_percent_scale_only_from_several_dumps() {
+        let scale = Scale::new(ControllerScale::Auto);
+        let steps = pico_adb(include_str!("../tests/fixtures/pxrcontrollerservice.txt"));
+        let glitch = |battery| {
+            pico_adb(
+                &include_str!("../tests/fixtures/pxrcontrollerservice.txt")
+                    .replace("battery: 4\n", &format!("battery: {battery}\n")),
+            )
+        };
+
+        // A single percentage doesn't settle the scale
+        let levels = get_levels(
+            &glitch(60),
+            &scale,
+            &HeadsetReader::new(HeadsetSource::Auto),
+            false,
+        )
+        .unwrap();
+        assert_eq!(levels.left_controller, Some(0.6));
+        let levels = get_levels(
+            &steps,
+            &scale,
+            &HeadsetReader::new(HeadsetSource::Auto),
+            false,
+        )
+        .unwrap();
+        assert_eq!(levels.left_controller, Some(0.8));
+
+        // Neither do readings beyond any scale
+        for _ in 0..PERCENT_DUMPS {
+            let levels = get_levels(
+                &glitch(255),
+                &scale,
+                &HeadsetReader::new(HeadsetSource::Auto),
+                true,
+            )
+            .unwrap();
+            assert_eq!(levels.left_controller, Some(1.0));
+        }
+        let levels = get_levels(
+            &steps,
+            &scale,
+            &HeadsetReader::new(HeadsetSource::Auto),
+            false,
+        )
+        .unwrap();
+        assert_eq!(levels.right_controller, Some(0.6));
+    }
+
+    #[test]
+    fn clamps_readings_beyond_the_scale() {
+        let adb = pico_adb(include_str!(
+            "../tests/fixtures/pxrcontrollerservice_percent.txt"
+        ));
+        let scale = Scale::new(ControllerScale::Steps);
+        let levels =
+            get_levels(&adb, &scale, &HeadsetReader::new(HeadsetSource::Auto), true).unwrap();
+
+        assert_eq!(levels.left_controller, Some(1.0));
+        assert_eq!(levels.right_controller, Some(1.0));
+
+        // Without clamping only the controllers are left out
+        let levels = get_levels(
+            &adb,
+            &scale,
+            &HeadsetReader::new(HeadsetSource::Auto),
+            false,
+        )
+        .unwrap();
+        assert_eq!(levels.headset, Some(0.85));
+        assert_eq!(levels.left_controller, None);
+        assert_eq!(levels.right_controller, None);
+    }
+
+    #[test]
+    fn reads_the_charge_cycles_elsewhere() {
+        let scale = Scale::new(ControllerScale::Auto);
+        let cycles = |adb: &MockAdb, headset: &HeadsetReader| {
+            get_levels(adb, &scale, headset, false)
+                .unwrap()
+                .headset_cycles
+        };
+        let controllers = include_str!("../tests/fixtures/pxrcontrollerservice.txt");
+        let sysfs = pico_adb(controllers).with(&format!("shell cat {SYSFS_CYCLES}"), "231\n");
+        let batterystats = pico_adb(controllers).with(
+            "shell dumpsys batterystats",
+            "Statistics since last charge:\n  Battery cycle count: 17\n",
+        );
+
+        let headset = HeadsetReader::new(HeadsetSource::Dumpsys);
+        assert_eq!(cycles(&sysfs, &headset), None);
+        let headset = || HeadsetReader::new(HeadsetSource::Dumpsys).with_cycles_elsewhere();
+        assert_eq!(cycles(&sysfs, &headset()), Some(231));
+        assert_eq!(cycles(&batterystats, &headset()), Some(17));
+        // Kept rather than read every cycle
+        let headset = headset();
+        assert_eq!(cycles(&sysfs, &headset), Some(231));
+        assert_eq!(cycles(&batterystats, &headset), Some(231));
+    }
+
+    #[test]
+    fn treats_empty_dump_as_disconnected() {
+        let adb = MockAdb::default().with("shell dumpsys battery", " \n");
+        let error = get_levels(
+            &adb,
+            &Scale::new(ControllerScale::Auto),
+            &HeadsetReader::new(HeadsetSource::Dumpsys),
+            false,
+        )
+        .unwrap_err();
+
+        assert!(matches!(error.root(), VoltaError::DumpEmpty(_)));
+    }
+
+    #[test]
+    fn sends_the_headset_when_only_the_controllers_fail() {
+        let scale = Scale::new(ControllerScale::Auto);
+        let headset = HeadsetReader::new(HeadsetSource::Auto);
+        for adb in [
+            pico_adb(" \n"),
+            MockAdb::default().with(
+                "shell dumpsys battery",
+                include_str!("../tests/fixtures/battery.txt"),
+            ),
+        ] {
+            let levels = get_levels(&adb, &scale, &headset, false).unwrap();
+            assert_eq!(levels.headset, Some(0.85));
+            assert_eq!(levels.left_controller, None);
+            assert_eq!(levels.right_controller, None);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_bluetooth() {
+        let bluetooth = include_str!("../tests/fixtures/bluetooth_manager.txt");
+        let adb = pico_adb("Can't find service: pxrcontrollerservice\n")
+            .with("shell dumpsys bluetooth_manager", bluetooth);
+        let scale = Scale::new(ControllerScale::Steps);
+        let levels = get_levels(
+            &adb,
+            &scale,
+            &HeadsetReader::new(HeadsetSource::Auto),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(levels.left_controller, Some(0.85));
+        assert_eq!(levels.right_controller, Some(0.4));
+    }
+
+    #[test]
+    fn checks_for_the_controller_service_again_after_reconnecting() {
+        let bluetooth = include_str!("../tests/fixtures/bluetooth_manager.txt");
+        let adb = pico_adb("Can't find service: pxrcontrollerservice\n")
+            .with("shell dumpsys bluetooth_manager", bluetooth);
+        let source = PicoSource {
+            adb,
+            scale: Scale::new(ControllerScale::Steps),
+            bluetooth: Cell::new(false),
+            headset: HeadsetReader::new(HeadsetSource::Dumpsys),
+            clamp: false,
+            sleep: None,
+        };
+        source.get_levels().unwrap();
+        assert!(source.bluetooth.get());
+
+        // The firmware the headset reconnected with has the service
+        let source = PicoSource {
+            adb: pico_adb(include_str!("../tests/fixtures/pxrcontrollerservice.txt")),
+            ..source
+        };
+        // Until then it's still read over Bluetooth, which it doesn't answer
+        assert_eq!(source.get_levels().unwrap().left_controller, None);
+        source.reconnected();
+        assert!(!source.bluetooth.get());
+        assert_eq!(source.get_levels().unwrap().left_controller, Some(0.8));
+    }
+
+    fn sysfs_adb(capacity: &str) -> MockAdb {
+        pico_adb(include_str!("../tests/fixtures/pxrcontrollerservice.txt"))
+            .with(&format!("shell cat {SYSFS_CAPACITY}"), capacity)
+    }
+
+    fn headset_level(adb: &MockAdb, source: HeadsetSource) -> Result<f32, VoltaError> {
+        let scale = Scale::new(ControllerScale::Auto);
+        get_levels(adb, &scale, &HeadsetReader::new(source), false)
+            .map(|levels| levels.headset.unwrap())
+    }
+
+    #[test]
+    fn prefers_sysfs_headset_level() {
+        // dumpsys battery reports 85%
+        let adb = sysfs_adb("92\n");
+
+        assert_eq!(headset_level(&adb, HeadsetSource::Auto).unwrap(), 0.92);
+        assert_eq!(headset_level(&adb, HeadsetSource::Sysfs).unwrap(), 0.92);
+        assert_eq!(headset_level(&adb, HeadsetSource::Dumpsys).unwrap(), 0.85);
+    }
+
+    #[test]
+    fn falls_back_to_dumpsys_headset_level() {
+        for capacity in [
+            "cat: /sys/class/power_supply/battery/capacity: Permission denied\n",
+            "",
+        ] {
+            let adb = sysfs_adb(capacity);
+
+            assert_eq!(headset_level(&adb, HeadsetSource::Auto).unwrap(), 0.85);
+            assert!(headset_level(&adb, HeadsetSource::Sysfs).is_err());
+        }
+    }
+
+    #[test]
+    fn stops_reading_sysfs_once_its_unreadable() {
+        let scale = Scale::new(ControllerScale::Auto);
+        let headset = HeadsetReader::new(HeadsetSource::Auto);
+        let levels = |adb| get_levels(&adb, &scale, &headset, false).unwrap().headset;
+
+        // adb failing says nothing about sysfs
+        assert_eq!(
+            levels(pico_adb(include_str!(
+                "../tests/fixtures/pxrcontrollerservice.txt"
+            ))),
+            Some(0.85)
+        );
+        assert_eq!(levels(sysfs_adb("92\n")), Some(0.92));
+        assert_eq!(
+            levels(sysfs_adb(
+                "cat: /sys/class/power_supply/battery/capacity: Permission denied\n"
+            )),
+            Some(0.85)
+        );
+        assert_eq!(levels(sysfs_adb("92\n")), Some(0.85));
+    }
+
+    #[test]
+    fn fails_when_adb_fails() {
+        let scale = Scale::new(ControllerScale::Auto);
+        assert!(get_levels(
+            &MockAdb::default(),
+            &scale,
+            &HeadsetReader::new(HeadsetSource::Auto),
+            false
+        )
+        .is_err());
+    }
+
+    /// Fails the first dumps it's asked for, then answers like `adb`
+    struct Flaky {
+        adb: MockAdb,
+        failures: AtomicU32,
+    }
+
+    impl AdbRunner for Flaky {
+        fn run(&self, args: &[&str]) -> Result<String, VoltaError> {
+            let failing =
+                self.failures
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |failures| {
+                        failures.checked_sub(1)
+                    });
+            match failing {
+                Ok(_) => Err(VoltaError::AdbFailed {
+                    command: args.join(" "),
+                    message: String::from("device offline"),
+                }),
+                Err(_) => self.adb.run(args),
+            }
+        }
+
+        fn retries(&self) -> u32 {
+            1
+        }
+    }
+
+    #[test]
+    fn retries_failed_dumps_but_not_malformed_ones() {
+        let scale = Scale::new(ControllerScale::Auto);
+        let flaky = |adb| Flaky {
+            adb,
+            failures: AtomicU32::new(1),
+        };
+
+        let adb = flaky(pico_adb(include_str!(
+            "../tests/fixtures/pxrcontrollerservice.txt"
+        )));
+        assert!(get_levels(
+            &adb,
+            &scale,
+            &HeadsetReader::new(HeadsetSource::Dumpsys),
+            false
+        )
+        .is_ok());
+
+        let adb = flaky(pico_adb("handler: left\nbattery: full\n"));
+        let start = Instant::now();
+        let levels = get_levels(
+            &adb,
+            &scale,
+            &HeadsetReader::new(HeadsetSource::Dumpsys),
+            false,
+        )
+        .unwrap();
+        assert_eq!(levels.left_controller, None);
+        // Only the failure to read was retried, after a second
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
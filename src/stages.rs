@@ -0,0 +1,94 @@
+use crate::source::BatteryLevels;
+
+/// The levels quantized into stages from 0 to one less than the count
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stages {
+    pub headset: Option<u8>,
+    pub left_controller: Option<u8>,
+    pub right_controller: Option<u8>,
+}
+
+/// Quantizes the levels into stages, keeping each device at its previous
+/// stage while its level stays within the hysteresis of that stage
+pub struct Stager {
+    count: u8,
+    /// Fraction of 0.0 to 1.0 a level may cross a boundary by before its
+    /// stage changes
+    hysteresis: f32,
+    last: Option<Stages>,
+}
+
+impl Stager {
+    /// Quantizes into `count` stages with a hysteresis in percentage points
+    pub fn new(count: u8, hysteresis: f32) -> Self {
+        Self {
+            count,
+            hysteresis: hysteresis / 100.0,
+            last: None,
+        }
+    }
+
+    pub fn update(&mut self, levels: &BatteryLevels) -> Stages {
+        let last = self.last;
+        let stage = |level, previous| quantize(level, self.count, self.hysteresis, previous);
+        let stages = Stages {
+            headset: levels
+                .headset
+                .map(|level| stage(level, last.and_then(|last| last.headset))),
+            left_controller: levels
+                .left_controller
+                .map(|level| stage(level, last.and_then(|last| last.left_controller))),
+            right_controller: levels
+                .right_controller
+                .map(|level| stage(level, last.and_then(|last| last.right_controller))),
+        };
+        self.last = Some(stages);
+
+        stages
+    }
+}
+
+/// Maps a level to one of `count` equally wide stages, where 0% is stage 0
+/// and 100% the top stage. A level within `hysteresis` beyond the bounds of
+/// the `previous` stage stays in it.
+pub fn quantize(level: f32, count: u8, hysteresis: f32, previous: Option<u8>) -> u8 {
+    let width = 1.0 / f32::from(count);
+    let stage = ((level * f32::from(count)) as u8).min(count - 1);
+
+    match previous {
+        Some(previous) if previous < count && previous != stage => {
+            let lower = f32::from(previous) * width - hysteresis;
+            let upper = f32::from(previous + 1) * width + hysteresis;
+            match level >= lower && level < upper {
+                true => previous,
+                false => stage,
+            }
+        }
+        _ => stage,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantizes_into_equal_stages() {
+        let stages: Vec<_> = [0.0, 0.19, 0.2, 0.5, 0.79, 0.8, 1.0]
+            .into_iter()
+            .map(|level| quantize(level, 5, 0.0, None))
+            .collect();
+
+        assert_eq!(stages, [0, 0, 1, 2, 3, 4, 4]);
+    }
+
+    #[test]
+    fn keeps_stage_within_hysteresis() {
+        // 40% is the boundary between stage 1 and 2
+        assert_eq!(quantize(0.41, 5, 0.02, Some(1)), 1);
+        assert_eq!(quantize(0.39, 5, 0.02, Some(2)), 2);
+        assert_eq!(quantize(0.43, 5, 0.02, Some(1)), 2);
+        assert_eq!(quantize(0.37, 5, 0.02, Some(2)), 1);
+        assert_eq!(quantize(0.41, 5, 0.0, Some(1)), 2);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::chatbox;
+use crate::sink::{BatteryReport, Sink};
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+/// Writes the latest battery levels to a file, e.g. for an OBS text source.
+/// Files ending in `.json` get a JSON document, any other file the levels
+/// rendered with the format.
+pub struct StatusFile {
+    path: PathBuf,
+    format: String,
+}
+
+impl StatusFile {
+    pub fn new(path: PathBuf, format: String) -> Self {
+        Self { path, format }
+    }
+
+    fn contents(&self, report: &BatteryReport) -> Result<String> {
+        if !self.is_json() {
+            return Ok(chatbox::render(&self.format, &report.levels));
+        }
+
+        let mut status = report.levels.to_json();
+        status["timestamp"] = report
+            .timestamp
+            .duration_since(UNIX_EPOCH)?
+            .as_secs()
+            .into();
+        status["data_age"] = report.age_minutes().into();
+        Ok(serde_json::to_string_pretty(&status)?)
+    }
+
+    fn is_json(&self) -> bool {
+        self.path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+    }
+}
+
+impl Sink for StatusFile {
+    fn name(&self) -> &'static str {
+        "the status file"
+    }
+
+    /// Replaces the file with the levels
+    fn publish(&mut self, report: &BatteryReport) -> Result<()> {
+        // Write next to the file and rename it over the old one, so readers
+        // never see a half written file
+        let mut temporary = OsString::from(&self.path);
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        fs::write(&temporary, self.contents(report)?)
+            .with_context(|| format!("Failed to write {temporary:?}"))?;
+        fs::rename(&temporary, &self.path)
+            .with_context(|| format!("Failed to replace {:?}", self.path))
+    }
+
+    /// Keeps the last levels, with their age in the JSON document
+    fn publish_stale(&mut self, report: &BatteryReport) -> Result<()> {
+        self.publish(report)
+    }
+
+    /// Deletes the file so stale levels don't linger after volta stopped
+    fn shutdown(&mut self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+            result => result.with_context(|| format!("Failed to remove {:?}", self.path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::fixture;
+    use serde_json::Value;
+
+    fn status_file(name: &str, format: &str) -> StatusFile {
+        let path = std::env::temp_dir().join(format!("vrc_volta-{}-{name}", std::process::id()));
+        StatusFile::new(path, format.to_string())
+    }
+
+    #[test]
+    fn writes_text() {
+        let mut status_file = status_file("status.txt", "{headset}% {left} {right}");
+        status_file.publish(&fixture::report()).unwrap();
+
+        assert_eq!(fs::read_to_string(&status_file.path).unwrap(), "85% - 40");
+        status_file.shutdown().unwrap();
+        assert!(!status_file.path.exists());
+    }
+
+    #[test]
+    fn writes_json() {
+        let mut status_file = status_file("status.json", "");
+        status_file.publish(&fixture::report()).unwrap();
+
+        let status: Value =
+            serde_json::from_str(&fs::read_to_string(&status_file.path).unwrap()).unwrap();
+        status_file.shutdown().unwrap();
+        assert_eq!(status["headset"], 0.85);
+        assert_eq!(status["headset_charging"], true);
+        assert_eq!(status["left_connected"], false);
+        assert_eq!(status["timestamp"], 1_700_000_000);
+        assert_eq!(status["data_age"], 0);
+    }
+}
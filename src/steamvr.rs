@@ -0,0 +1,304 @@
+use crate::error::VoltaError;
+use crate::source::{BatteryLevels, BatterySource, TrackedDevice};
+use anyhow::Result;
+use std::cell::Cell;
+use tracing::warn;
+
+/// What SteamVR uses a device for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Headset,
+    LeftHand,
+    RightHand,
+    /// Any other device with a battery, like a tracker
+    Other,
+}
+
+/// The battery of one device SteamVR tracks
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reading {
+    pub role: Role,
+    pub serial: String,
+    /// 0.0 to 1.0
+    pub level: f32,
+    pub charging: bool,
+}
+
+impl Reading {
+    /// Name of the device for its parameter address, the hand it's held in
+    /// for controllers and the serial without punctuation otherwise, so
+    /// `LHR-1A2B3C4D` is sent to `BatterySteamVRLHR1A2B3C4D`
+    fn name(&self) -> String {
+        match self.role {
+            Role::Headset => String::from("Headset"),
+            Role::LeftHand => String::from("LeftHand"),
+            Role::RightHand => String::from("RightHand"),
+            Role::Other => self
+                .serial
+                .chars()
+                .filter(char::is_ascii_alphanumeric)
+                .collect(),
+        }
+    }
+
+    fn device(&self) -> TrackedDevice {
+        TrackedDevice {
+            name: self.name(),
+            level: self.level,
+            charging: self.charging,
+        }
+    }
+}
+
+/// Maps the readings onto the levels when SteamVR is the only source: the
+/// headset and the controllers get their usual parameters, every other device
+/// its own
+pub fn levels(readings: &[Reading]) -> BatteryLevels {
+    let find = |role| readings.iter().find(|reading| reading.role == role);
+    // Most PC headsets have no battery
+    let headset = find(Role::Headset);
+
+    BatteryLevels {
+        headset: headset.map(|reading| reading.level),
+        left_controller: find(Role::LeftHand).map(|reading| reading.level),
+        right_controller: find(Role::RightHand).map(|reading| reading.level),
+        headset_charging: headset.is_some_and(|reading| reading.charging),
+        headset_temperature: None,
+        headset_voltage: None,
+        headset_health: None,
+        headset_cycles: None,
+        headset_awake: None,
+        trackers: Vec::new(),
+        steamvr: readings
+            .iter()
+            .filter(|reading| reading.role == Role::Other)
+            .map(Reading::device)
+            .collect(),
+    }
+}
+
+/// The devices to send next to the levels read over adb. The headset is the
+/// one adb reads, and the controllers are sent as `LeftHand` and `RightHand`
+/// so they don't collide with the headset's own controllers.
+pub fn devices(readings: &[Reading]) -> Vec<TrackedDevice> {
+    readings
+        .iter()
+        .filter(|reading| reading.role != Role::Headset)
+        .map(Reading::device)
+        .collect()
+}
+
+/// A connection to SteamVR, made on the first reading and retried on every
+/// reading after while SteamVR isn't running
+pub struct SteamVr {
+    #[cfg(feature = "steamvr")]
+    context: std::cell::RefCell<Option<openvr::Context>>,
+}
+
+impl SteamVr {
+    pub fn new() -> Result<Self> {
+        #[cfg(feature = "steamvr")]
+        return Ok(Self {
+            context: std::cell::RefCell::new(None),
+        });
+
+        #[cfg(not(feature = "steamvr"))]
+        anyhow::bail!(
+            "volta was built without SteamVR support, build it with `--features steamvr`"
+        );
+    }
+
+    /// Reads the battery of every connected device which reports one
+    #[cfg(feature = "steamvr")]
+    pub fn readings(&self) -> Result<Vec<Reading>> {
+        use anyhow::Context;
+        use openvr::{property, TrackedControllerRole, TrackedDeviceClass, TrackedDeviceIndex};
+
+        let mut context = self.context.borrow_mut();
+        if context.is_none() {
+            // volta opens no other context and never calls OpenVR after
+            // dropping this one
+            let opened = unsafe { openvr::init(openvr::ApplicationType::Background) }
+                .context("Failed to connect to SteamVR, is it running?")?;
+            tracing::info!("Connected to SteamVR");
+            *context = Some(opened);
+        }
+        let system = context
+            .as_ref()
+            .expect("connected above")
+            .system()
+            .context("Failed to get the SteamVR system")?;
+
+        let mut readings = Vec::new();
+        for index in 0..openvr::MAX_TRACKED_DEVICE_COUNT as u32 {
+            let index = TrackedDeviceIndex(index);
+            if !system.is_tracked_device_connected(index) {
+                continue;
+            }
+            // Lighthouses and devices without a battery don't report a level
+            let Ok(level) = system
+                .float_tracked_device_property(index, property::DeviceBatteryPercentage_Float)
+            else {
+                continue;
+            };
+            let role = match system.tracked_device_class(index) {
+                TrackedDeviceClass::HMD => Role::Headset,
+                TrackedDeviceClass::Controller => {
+                    match system.get_controller_role_for_tracked_device_index(index) {
+                        Some(TrackedControllerRole::LeftHand) => Role::LeftHand,
+                        Some(TrackedControllerRole::RightHand) => Role::RightHand,
+                        _ => Role::Other,
+                    }
+                }
+                TrackedDeviceClass::TrackingReference => continue,
+                _ => Role::Other,
+            };
+            let serial = system
+                .string_tracked_device_property(index, property::SerialNumber_String)
+                .map(|serial| serial.to_string_lossy().into_owned())
+                .unwrap_or_else(|_| format!("Device{}", index.0));
+
+            readings.push(Reading {
+                role,
+                serial,
+                level: level.clamp(0.0, 1.0),
+                charging: system
+                    .bool_tracked_device_property(index, property::DeviceIsCharging_Bool)
+                    .unwrap_or(false),
+            });
+        }
+
+        Ok(readings)
+    }
+
+    #[cfg(not(feature = "steamvr"))]
+    pub fn readings(&self) -> Result<Vec<Reading>> {
+        unreachable!("SteamVr can't be created without the steamvr feature")
+    }
+}
+
+/// Reads every battery from SteamVR
+pub struct SteamVrSource(pub SteamVr);
+
+impl BatterySource for SteamVrSource {
+    fn get_levels(&self) -> Result<BatteryLevels, VoltaError> {
+        let readings = self
+            .0
+            .readings()
+            .map_err(|error| VoltaError::SteamVr(format!("{error:#}")))?;
+        Ok(levels(&readings))
+    }
+}
+
+/// Reads the headset and its controllers over adb and adds the devices
+/// SteamVR tracks. While SteamVR isn't running, the adb levels are sent on
+/// their own.
+pub struct CombinedSource {
+    adb: Box<dyn BatterySource>,
+    steamvr: SteamVr,
+    failing: Cell<bool>,
+}
+
+impl CombinedSource {
+    pub fn new(adb: Box<dyn BatterySource>, steamvr: SteamVr) -> Self {
+        Self {
+            adb,
+            steamvr,
+            failing: Cell::new(false),
+        }
+    }
+
+    /// Adds the SteamVR devices to `levels` read over adb
+    fn with_steamvr(&self, mut levels: BatteryLevels) -> BatteryLevels {
+        match self.steamvr.readings() {
+            Ok(readings) => {
+                self.failing.set(false);
+                levels.steamvr = devices(&readings);
+            }
+            Err(error) if !self.failing.replace(true) => {
+                warn!("Failed to read the SteamVR batteries: {error:#}");
+            }
+            Err(_) => {}
+        }
+
+        levels
+    }
+}
+
+impl BatterySource for CombinedSource {
+    fn get_levels(&self) -> Result<BatteryLevels, VoltaError> {
+        Ok(self.with_steamvr(self.adb.get_levels()?))
+    }
+
+    /// SteamVR is read every time, as it's local and quick
+    fn get_headset_levels(&self) -> Result<BatteryLevels, VoltaError> {
+        Ok(self.with_steamvr(self.adb.get_headset_levels()?))
+    }
+
+    fn get_controller_levels(&self, last: &BatteryLevels) -> Result<BatteryLevels, VoltaError> {
+        Ok(self.with_steamvr(self.adb.get_controller_levels(last)?))
+    }
+
+    fn get_headset_charging(&self) -> Option<Result<bool, VoltaError>> {
+        self.adb.get_headset_charging()
+    }
+
+    fn reconnected(&self) {
+        self.adb.reconnected();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn readings() -> Vec<Reading> {
+        let reading = |role, serial: &str, level, charging| Reading {
+            role,
+            serial: serial.to_string(),
+            level,
+            charging,
+        };
+
+        vec![
+            reading(Role::Headset, "PA7L10MGH4030", 0.9, true),
+            reading(Role::LeftHand, "LHR-AAAAAAAA", 0.5, false),
+            reading(Role::RightHand, "LHR-BBBBBBBB", 0.25, false),
+            reading(Role::Other, "LHR-1A2B3C4D", 0.75, true),
+        ]
+    }
+
+    #[test]
+    fn maps_roles_onto_the_levels() {
+        let levels = levels(&readings());
+
+        assert_eq!(levels.headset, Some(0.9));
+        assert!(levels.headset_charging);
+        assert_eq!(levels.left_controller, Some(0.5));
+        assert_eq!(levels.right_controller, Some(0.25));
+        assert_eq!(
+            levels.steamvr,
+            [TrackedDevice {
+                name: String::from("LHR1A2B3C4D"),
+                level: 0.75,
+                charging: true,
+            }]
+        );
+
+        // No battery in the headset
+        let levels = super::levels(&readings()[1..]);
+        assert_eq!(levels.headset, None);
+        assert!(!levels.headset_charging);
+        assert_eq!(levels.left_controller, Some(0.5));
+    }
+
+    #[test]
+    fn names_controllers_by_hand_next_to_adb() {
+        let names: Vec<_> = devices(&readings())
+            .into_iter()
+            .map(|device| device.name)
+            .collect();
+
+        assert_eq!(names, ["LeftHand", "RightHand", "LHR1A2B3C4D"]);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::adb::AdbRunner;
+use anyhow::{bail, ensure, Context, Result};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// How much of the headset's data partition is used, in KiB
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Storage {
+    pub used: f64,
+    pub total: f64,
+}
+
+impl Storage {
+    /// Fraction of the partition which is free, from 0.0 to 1.0
+    pub fn free(&self) -> f32 {
+        ((self.total - self.used) / self.total).clamp(0.0, 1.0) as f32
+    }
+}
+
+/// Reads the headset's storage with `df /data`, or with `dumpsys diskstats`
+/// on firmware whose `df` fails or prints something else
+pub fn read(adb: &impl AdbRunner) -> Result<Storage> {
+    let df = adb
+        .shell(&["df", "/data"])
+        .map_err(anyhow::Error::from)
+        .and_then(|output| parse_df(&output).context("Failed to parse the output of df"));
+    df.or_else(|error| {
+        debug!("{error:#}, reading dumpsys diskstats instead");
+        let output = adb.shell(&["dumpsys", "diskstats"])?;
+        parse_diskstats(&output).context("Failed to parse dumpsys diskstats")
+    })
+}
+
+/// Parses the data partition of `dumpsys diskstats`, e.g.
+/// `Data-Free: 49543812K / 115112252K total = 43% free`
+pub fn parse_diskstats(output: &str) -> Result<Storage> {
+    let line = output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Data-Free:"))
+        .context("diskstats lists no Data-Free")?;
+    let (free, rest) = line
+        .split_once('/')
+        .with_context(|| format!("expected free / total in `{line}`"))?;
+    let total = rest.split_whitespace().next().unwrap_or_default();
+    let (free, total) = (size(free.trim())?, size(total)?);
+    ensure!(total > 0.0, "the partition is empty");
+
+    Ok(Storage {
+        used: total - free,
+        total,
+    })
+}
+
+/// Parses the output of `df` for a single partition, in the format of
+/// toybox and busybox in KiB, or of the old toolbox with sizes like `25.0G`
+pub fn parse_df(output: &str) -> Result<Storage> {
+    let mut lines = output.lines().filter(|line| !line.trim().is_empty());
+    let header = lines.next().context("df printed nothing")?;
+    // busybox wraps the columns onto the next line after a long filesystem
+    let fields: Vec<&str> = lines.flat_map(str::split_whitespace).collect();
+
+    let (total, used) = if header.to_lowercase().contains("1k-blocks") {
+        // Filesystem, 1K-blocks, Used, Available, Use%, Mounted on
+        ensure!(fields.len() >= 6, "expected 6 columns in `{output}`");
+        let columns = &fields[fields.len() - 5..];
+        (kib(columns[0], 1.0)?, kib(columns[1], 1.0)?)
+    } else if header.contains("Size") {
+        // Filesystem, Size, Used, Free, Blksize
+        ensure!(fields.len() >= 5, "expected 5 columns in `{output}`");
+        (size(fields[1])?, size(fields[2])?)
+    } else {
+        bail!("unknown df header `{header}`");
+    };
+    ensure!(total > 0.0, "the partition is empty");
+
+    Ok(Storage { used, total })
+}
+
+fn kib(value: &str, multiplier: f64) -> Result<f64> {
+    let number: f64 = value
+        .parse()
+        .with_context(|| format!("`{value}` is not a size"))?;
+    Ok(number * multiplier)
+}
+
+/// Parses a size with a unit suffix, e.g. `25.0G`, into KiB
+fn size(value: &str) -> Result<f64> {
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((index, 'K')) => (&value[..index], 1.0),
+        Some((index, 'M')) => (&value[..index], 1024.0),
+        Some((index, 'G')) => (&value[..index], 1024.0 * 1024.0),
+        Some((index, 'T')) => (&value[..index], 1024.0 * 1024.0 * 1024.0),
+        // Bytes without a suffix
+        _ => (value, 1.0 / 1024.0),
+    };
+    kib(number, multiplier)
+}
+
+/// Reads the storage every `interval` on a thread of its own, so a slow or
+/// failing `df` never holds up the battery levels
+pub struct StorageReader {
+    free: Receiver<f32>,
+}
+
+impl StorageReader {
+    pub fn spawn<A: AdbRunner + Send + 'static>(adb: A, interval: Duration) -> Self {
+        let (sender, free) = mpsc::channel();
+        thread::spawn(move || {
+            let mut failing = false;
+            loop {
+                match read(&adb) {
+                    Ok(storage) => {
+                        debug!("Headset storage {:.0}% free", storage.free() * 100.0);
+                        failing = false;
+                        if sender.send(storage.free()).is_err() {
+                            return;
+                        }
+                    }
+                    // Only the first failure in a row is worth a warning
+                    Err(error) if !failing => {
+                        warn!("Failed to read the headset storage: {error:#}");
+                        failing = true;
+                    }
+                    Err(error) => debug!("Failed to read the headset storage: {error:#}"),
+                }
+                thread::sleep(interval);
+            }
+        });
+        Self { free }
+    }
+
+    /// The fraction free read last, if it was read since the last call
+    pub fn take(&self) -> Option<f32> {
+        self.free.try_iter().last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adb::mock::MockAdb;
+
+    fn free(output: &str) -> f32 {
+        parse_df(output).unwrap().free()
+    }
+
+    #[test]
+    fn parses_toybox() {
+        let free = free(include_str!("../tests/fixtures/df_toybox.txt"));
+        assert!((free - 0.77).abs() < 0.01, "{free}");
+    }
+
+    #[test]
+    fn parses_busybox_with_a_wrapped_line() {
+        let free = free(include_str!("../tests/fixtures/df_busybox.txt"));
+        assert!((free - 0.15).abs() < 0.01, "{free}");
+    }
+
+    #[test]
+    fn parses_toolbox_sizes() {
+        let free = free(include_str!("../tests/fixtures/df_toolbox.txt"));
+        assert!((free - 0.2).abs() < 0.01, "{free}");
+    }
+
+    #[test]
+    fn fails_on_unknown_output() {
+        assert!(parse_df("").is_err());
+        assert!(parse_df("df: /data: Permission denied").is_err());
+        assert!(parse_df("Filesystem 1K-blocks Used Available Use% Mounted on\n").is_err());
+    }
+
+    #[test]
+    fn reads_through_adb() {
+        let adb = MockAdb::default().with(
+            "shell df /data",
+            include_str!("../tests/fixtures/df_toybox.txt"),
+        );
+        assert_eq!(read(&adb).unwrap().total, 240476756.0);
+    }
+
+    #[test]
+    fn falls_back_to_diskstats() {
+        let diskstats = include_str!("../tests/fixtures/diskstats.txt");
+        let storage = parse_diskstats(diskstats).unwrap();
+        assert!((storage.free() - 0.43).abs() < 0.01, "{storage:?}");
+        assert!(parse_diskstats("Latency: 3ms [512B Data Write]\n").is_err());
+
+        for df in ["df: /data: Permission denied", ""] {
+            let adb = MockAdb::default()
+                .with("shell df /data", df)
+                .with("shell dumpsys diskstats", diskstats);
+            assert_eq!(read(&adb).unwrap().total, 115112252.0);
+        }
+        // Without df at all
+        let adb = MockAdb::default().with("shell dumpsys diskstats", diskstats);
+        assert_eq!(read(&adb).unwrap(), storage);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::sink::{BatteryReport, Sink};
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::json;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+/// How long a device has to drain before its drain rate is worth reporting,
+/// as the levels only change by a percent every few minutes
+const MIN_DRAINING: Duration = Duration::from_secs(5 * 60);
+
+/// What one device's battery did over the session, as percentages
+struct Usage {
+    name: &'static str,
+    /// The warning threshold, below which the time spent is counted
+    warn: u8,
+    first: Option<f32>,
+    /// The last reading and when it was read, `None` while disconnected so
+    /// the time in between isn't counted
+    last: Option<(SystemTime, f32)>,
+    /// The level read last, which stays after the device disconnects
+    end: Option<f32>,
+    /// Percentage points lost while not charging, and the time they were
+    /// lost over
+    used: f32,
+    draining: Duration,
+    below_warning: Duration,
+}
+
+impl Usage {
+    fn new(name: &'static str, warn: u8) -> Self {
+        Self {
+            name,
+            warn,
+            first: None,
+            last: None,
+            end: None,
+            used: 0.0,
+            draining: Duration::ZERO,
+            below_warning: Duration::ZERO,
+        }
+    }
+
+    fn update(&mut self, time: SystemTime, percent: Option<f32>, charging: bool) {
+        let Some(percent) = percent else {
+            self.last = None;
+            return;
+        };
+        if let Some((last_time, last)) = self.last {
+            let elapsed = time.duration_since(last_time).unwrap_or_default();
+            if last <= f32::from(self.warn) {
+                self.below_warning += elapsed;
+            }
+            // Rises are charging or a fresh battery, which used nothing
+            if !charging && percent <= last {
+                self.used += last - percent;
+                self.draining += elapsed;
+            }
+        }
+        self.first.get_or_insert(percent);
+        self.last = Some((time, percent));
+        self.end = Some(percent);
+    }
+
+    /// Percentage points per hour while draining, `None` until the device
+    /// drained for long enough
+    fn drain(&self) -> Option<f32> {
+        (self.draining >= MIN_DRAINING && self.used > 0.0)
+            .then(|| self.used / (self.draining.as_secs_f32() / 3600.0))
+    }
+
+    fn line(&self) -> String {
+        let (Some(first), Some(end)) = (self.first, self.end) else {
+            return format!("{:<17} never connected", self.name);
+        };
+        let drain = self
+            .drain()
+            .map_or(String::from("-"), |drain| format!("{drain:.1}%/h"));
+        format!(
+            "{:<17} {:>5.0}% {:>5.0}% {:>5.0}% {:>9} min {:>9}",
+            self.name,
+            first,
+            end,
+            self.used,
+            self.below_warning.as_secs() / 60,
+            drain
+        )
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let round = |value: f32| (f64::from(value) * 10.0).round() / 10.0;
+        json!({
+            "start": self.first.map(round),
+            "end": self.end.map(round),
+            "used": self.first.map(|_| round(self.used)),
+            "minutes_below_warning": self.below_warning.as_secs() / 60,
+            "drain_per_hour": self.drain().map(round),
+        })
+    }
+}
+
+/// Keeps track of how much battery every device used while volta ran, and
+/// logs it as a table when volta stops. With `path`, the session is also
+/// appended to it as a line of JSON.
+pub struct SessionSummary {
+    start: SystemTime,
+    path: Option<PathBuf>,
+    devices: [Usage; 3],
+}
+
+impl SessionSummary {
+    /// A session starting now, counting the time below the warning
+    /// thresholds `warn_headset` and `warn_controller`
+    pub fn new(path: Option<PathBuf>, warn_headset: u8, warn_controller: u8) -> Self {
+        Self {
+            start: SystemTime::now(),
+            path,
+            devices: [
+                Usage::new("headset", warn_headset),
+                Usage::new("left controller", warn_controller),
+                Usage::new("right controller", warn_controller),
+            ],
+        }
+    }
+
+    /// The summary as the lines of a table
+    fn table(&self, end: SystemTime) -> Vec<String> {
+        let minutes = end.duration_since(self.start).unwrap_or_default().as_secs() / 60;
+        let mut lines = vec![
+            format!("Session summary, {}h {:02}min:", minutes / 60, minutes % 60),
+            format!(
+                "{:<17} {:>6} {:>6} {:>6} {:>13} {:>9}",
+                "device", "start", "end", "used", "below warning", "drain"
+            ),
+        ];
+        lines.extend(self.devices.iter().map(Usage::line));
+        lines
+    }
+
+    fn record(&self, end: SystemTime) -> String {
+        let timestamp = |time: SystemTime| {
+            DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+        };
+        let mut record = json!({
+            "start": timestamp(self.start),
+            "end": timestamp(end),
+            "minutes": end.duration_since(self.start).unwrap_or_default().as_secs() / 60,
+        });
+        for device in &self.devices {
+            record[device.name.replace(' ', "_")] = device.to_json();
+        }
+        record.to_string()
+    }
+}
+
+impl Sink for SessionSummary {
+    fn name(&self) -> &'static str {
+        "the session summary"
+    }
+
+    fn publish(&mut self, report: &BatteryReport) -> Result<()> {
+        let levels = &report.levels;
+        let percent = |level: Option<f32>| level.map(|level| level * 100.0);
+        let [headset, left, right] = &mut self.devices;
+        headset.update(
+            report.timestamp,
+            percent(levels.headset),
+            levels.headset_charging,
+        );
+        // The controllers report no charging state, so rises are all there is
+        left.update(report.timestamp, percent(levels.left_controller), false);
+        right.update(report.timestamp, percent(levels.right_controller), false);
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        let end = SystemTime::now();
+        for line in self.table(end) {
+            info!("{line}");
+        }
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {path:?}"))?;
+        file.write_all(format!("{}\n", self.record(end)).as_bytes())
+            .with_context(|| format!("Failed to append to {path:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::fixture;
+    use serde_json::Value;
+
+    /// A session of the fixture report at `(minute, headset, right, charging)`
+    fn session(readings: &[(u64, f32, Option<f32>, bool)]) -> (SessionSummary, SystemTime) {
+        let mut summary = SessionSummary::new(None, 20, 20);
+        let start = summary.start;
+        let mut report = fixture::report();
+        for &(minute, headset, right_controller, charging) in readings {
+            report.timestamp = start + Duration::from_secs(minute * 60);
+            report.levels.headset = Some(headset);
+            report.levels.headset_charging = charging;
+            report.levels.right_controller = right_controller;
+            summary.publish(&report).unwrap();
+        }
+        let end = start + Duration::from_secs(readings.last().map_or(0, |reading| reading.0) * 60);
+        (summary, end)
+    }
+
+    fn record(summary: &SessionSummary, end: SystemTime) -> Value {
+        serde_json::from_str(&summary.record(end)).unwrap()
+    }
+
+    #[test]
+    fn sums_up_what_every_device_used() {
+        let (summary, end) = session(&[
+            (0, 0.5, Some(0.4), false),
+            (30, 0.35, Some(0.2), false),
+            (60, 0.2, Some(0.2), false),
+            (90, 0.1, Some(0.2), false),
+        ]);
+        let record = record(&summary, end);
+
+        assert_eq!(record["minutes"], 90);
+        assert_eq!(record["headset"]["start"], 50.0);
+        assert_eq!(record["headset"]["end"], 10.0);
+        assert_eq!(record["headset"]["used"], 40.0);
+        assert_eq!(record["headset"]["drain_per_hour"], 26.7);
+        assert_eq!(record["headset"]["minutes_below_warning"], 30);
+        assert_eq!(record["right_controller"]["minutes_below_warning"], 60);
+
+        let table = summary.table(end);
+        assert_eq!(table[0], "Session summary, 1h 30min:");
+        assert_eq!(
+            table[2],
+            "headset              50%    10%    40%        30 min   26.7%/h"
+        );
+    }
+
+    #[test]
+    fn leaves_out_devices_which_never_connected() {
+        let (summary, end) = session(&[(0, 0.5, None, false), (30, 0.45, None, false)]);
+        let record = record(&summary, end);
+
+        assert_eq!(record["left_controller"]["start"], Value::Null);
+        assert_eq!(record["left_controller"]["used"], Value::Null);
+        assert_eq!(record["left_controller"]["drain_per_hour"], Value::Null);
+        assert_eq!(summary.table(end)[3], "left controller   never connected");
+    }
+
+    #[test]
+    fn counts_nothing_used_while_charging() {
+        let (summary, end) = session(&[
+            (0, 0.3, Some(0.2), true),
+            (30, 0.6, Some(1.0), true),
+            (60, 0.9, Some(1.0), true),
+        ]);
+        let record = record(&summary, end);
+
+        assert_eq!(record["headset"]["start"], 30.0);
+        assert_eq!(record["headset"]["end"], 90.0);
+        assert_eq!(record["headset"]["used"], 0.0);
+        assert_eq!(record["headset"]["drain_per_hour"], Value::Null);
+        // A fresh battery in the controller
+        assert_eq!(record["right_controller"]["used"], 0.0);
+        assert_eq!(record["right_controller"]["drain_per_hour"], Value::Null);
+    }
+
+    #[test]
+    fn skips_the_time_a_controller_was_disconnected() {
+        let (summary, end) = session(&[
+            (0, 0.5, Some(0.5), false),
+            (10, 0.5, Some(0.45), false),
+            (60, 0.5, None, false),
+            (120, 0.5, Some(0.4), false),
+            (130, 0.5, Some(0.35), false),
+        ]);
+        let record = record(&summary, end);
+
+        assert_eq!(record["right_controller"]["used"], 10.0);
+        assert_eq!(record["right_controller"]["drain_per_hour"], 30.0);
+        assert_eq!(record["right_controller"]["end"], 35.0);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::source::BatteryLevels;
+use std::fmt::{self, Display};
+use tracing::warn;
+
+/// Percentage points a level has to rise above the threshold before the low
+/// state clears, so it doesn't flicker while the reading hovers around it
+const HYSTERESIS: f32 = 5.0;
+
+/// How low a battery is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Low,
+    Critical,
+}
+
+/// A device's battery dropped to a threshold, reported once per crossing
+#[derive(Clone, Debug)]
+pub struct Alert {
+    pub device: &'static str,
+    pub severity: Severity,
+    /// The level which crossed the threshold, as a percentage
+    pub percent: f32,
+    pub threshold: u8,
+}
+
+impl Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Low => "low",
+            Severity::Critical => "critical",
+        };
+        write!(
+            f,
+            "{} battery {severity} at {:.0}%",
+            self.device, self.percent
+        )
+    }
+}
+
+/// Tracks whether a device's battery is at or below a threshold
+struct Threshold {
+    severity: Severity,
+    percent: u8,
+    crossed: bool,
+}
+
+impl Threshold {
+    fn new(severity: Severity, percent: u8) -> Self {
+        Self {
+            severity,
+            percent,
+            crossed: false,
+        }
+    }
+
+    /// Updates the state with a level as a percentage and returns whether the
+    /// level just crossed the threshold
+    fn update(&mut self, percent: f32) -> bool {
+        let threshold = f32::from(self.percent);
+
+        if !self.crossed && percent <= threshold {
+            self.crossed = true;
+            return true;
+        }
+        if self.crossed && percent > threshold + HYSTERESIS {
+            self.crossed = false;
+        }
+
+        false
+    }
+}
+
+/// The low and critical thresholds of a device
+struct Device {
+    name: &'static str,
+    low: Threshold,
+    critical: Threshold,
+}
+
+impl Device {
+    fn new(name: &'static str, low: u8, critical: u8) -> Self {
+        Self {
+            name,
+            low: Threshold::new(Severity::Low, low),
+            critical: Threshold::new(Severity::Critical, critical),
+        }
+    }
+
+    /// Updates both thresholds with a normalized level and returns whether
+    /// the battery is low, along with an alert for the most severe threshold
+    /// the level just crossed
+    fn update(&mut self, level: f32) -> (bool, Option<Alert>) {
+        let percent = level * 100.0;
+        let low = self.low.update(percent);
+        let critical = self.critical.update(percent);
+
+        let crossed = match (low, critical) {
+            (_, true) => Some(&self.critical),
+            (true, false) => Some(&self.low),
+            (false, false) => None,
+        };
+        let alert = crossed.map(|threshold| Alert {
+            device: self.name,
+            severity: threshold.severity,
+            percent,
+            threshold: threshold.percent,
+        });
+        if let Some(alert) = &alert {
+            warn!("{alert}");
+        }
+
+        (self.low.crossed, alert)
+    }
+}
+
+/// The low battery thresholds of every device
+pub struct Thresholds {
+    headset: Device,
+    left_controller: Device,
+    right_controller: Device,
+    alerts: Vec<Alert>,
+}
+
+impl Thresholds {
+    /// Creates the thresholds from the low and critical percentages of the
+    /// headset and of the controllers
+    pub fn new(headset: (u8, u8), controller: (u8, u8)) -> Self {
+        Self {
+            headset: Device::new("Headset", headset.0, headset.1),
+            left_controller: Device::new("Left controller", controller.0, controller.1),
+            right_controller: Device::new("Right controller", controller.0, controller.1),
+            alerts: Vec::new(),
+        }
+    }
+
+    /// Changes the percentages of the thresholds, keeping whether they're
+    /// crossed until the next update
+    pub fn set_percentages(&mut self, headset: (u8, u8), controller: (u8, u8)) {
+        for (device, (low, critical)) in [
+            (&mut self.headset, headset),
+            (&mut self.left_controller, controller),
+            (&mut self.right_controller, controller),
+        ] {
+            device.low.percent = low;
+            device.critical.percent = critical;
+        }
+    }
+
+    /// Updates every threshold, controllers which aren't connected keep their
+    /// state and have no low state to report, and a headset without a battery
+    /// is never low
+    pub fn update(&mut self, levels: &BatteryLevels) -> LowBattery {
+        let mut update = |device: &mut Device, level: f32| {
+            let (low, alert) = device.update(level);
+            self.alerts.extend(alert);
+            low
+        };
+
+        LowBattery {
+            headset: levels
+                .headset
+                .is_some_and(|level| update(&mut self.headset, level)),
+            left_controller: levels
+                .left_controller
+                .map(|level| update(&mut self.left_controller, level)),
+            right_controller: levels
+                .right_controller
+                .map(|level| update(&mut self.right_controller, level)),
+        }
+    }
+
+    /// Returns the alerts of the thresholds crossed since the last call
+    pub fn take_alerts(&mut self) -> Vec<Alert> {
+        std::mem::take(&mut self.alerts)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LowBattery {
+    pub headset: bool,
+    pub left_controller: Option<bool>,
+    pub right_controller: Option<bool>,
+}
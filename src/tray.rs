@@ -0,0 +1,232 @@
+// The icon is only drawn on Windows with the tray feature, but tested everywhere
+#![cfg_attr(not(all(feature = "tray", windows)), allow(dead_code))]
+
+use crate::chatbox;
+use crate::listener::Event;
+use crate::sink::BatteryReport;
+use anyhow::Result;
+use std::sync::mpsc::{Receiver, Sender};
+
+/// Width and height of the icon in pixels
+const ICON_SIZE: usize = 32;
+
+/// Text shown when hovering over the icon
+fn tooltip(report: &BatteryReport) -> String {
+    let mut tooltip = chatbox::render(
+        "volta\nHeadset {headset}%\nLeft {left}%\nRight {right}%",
+        &report.levels,
+    )
+    .replace("-%", "disconnected");
+    if report.levels.headset_charging {
+        tooltip = tooltip.replacen('%', "%, charging", 1);
+    }
+
+    tooltip
+}
+
+/// RGBA pixels of a battery filled up to the headset level, or the lowest
+/// controller's for a headset without a battery, in amber while any device
+/// is low
+fn icon_pixels(report: Option<&BatteryReport>) -> Vec<u8> {
+    const OUTLINE: [u8; 4] = [235, 235, 235, 255];
+    const NORMAL: [u8; 4] = [76, 175, 80, 255];
+    const LOW: [u8; 4] = [255, 160, 0, 255];
+
+    let (level, low) = report.map_or((0.0, false), |report| {
+        let low = &report.low;
+        let low = low.headset
+            || low.left_controller.unwrap_or(false)
+            || low.right_controller.unwrap_or(false);
+        let levels = &report.levels;
+        let controllers = [levels.left_controller, levels.right_controller];
+        let lowest = controllers.into_iter().flatten().reduce(f32::min);
+        (levels.headset.or(lowest).unwrap_or(0.0), low)
+    });
+    // The body spans columns 2 to 27 and rows 8 to 23, the terminal sticks
+    // out to the right
+    let filled = 4 + (level.clamp(0.0, 1.0) * 22.0).round() as usize;
+    let fill = if low { LOW } else { NORMAL };
+
+    let mut pixels = vec![0; ICON_SIZE * ICON_SIZE * 4];
+    for (index, pixel) in pixels.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (index % ICON_SIZE, index / ICON_SIZE);
+        let body = (2..28).contains(&x) && (8..24).contains(&y);
+        let border = body && !((4..26).contains(&x) && (10..22).contains(&y));
+        let terminal = (28..31).contains(&x) && (12..20).contains(&y);
+        if border || terminal {
+            pixel.copy_from_slice(&OUTLINE);
+        } else if body && x < filled {
+            pixel.copy_from_slice(&fill);
+        }
+    }
+
+    pixels
+}
+
+/// An icon in the notification area showing the levels of every report,
+/// with a menu to read the levels right away, pause sending them and quit
+pub struct Tray {
+    #[cfg(all(feature = "tray", windows))]
+    inner: windows::Tray,
+}
+
+impl Tray {
+    /// Shows the icon. `reports` are shown as they come in and the menu sends
+    /// its `events`.
+    pub fn new(reports: Receiver<BatteryReport>, events: Sender<Event>) -> Result<Self> {
+        #[cfg(all(feature = "tray", windows))]
+        return Ok(Self {
+            inner: windows::Tray::new(reports, events)?,
+        });
+
+        #[cfg(not(all(feature = "tray", windows)))]
+        {
+            let _ = (reports, events);
+            anyhow::bail!(
+                "volta was built without tray support, build it on Windows with `--features tray`"
+            );
+        }
+    }
+
+    /// Handles the icon until the reports stop, which they do once volta
+    /// stopped
+    pub fn run(self) {
+        #[cfg(all(feature = "tray", windows))]
+        self.inner.run();
+    }
+}
+
+#[cfg(all(feature = "tray", windows))]
+mod windows {
+    use super::{icon_pixels, tooltip, ICON_SIZE};
+    use crate::listener::Event;
+    use crate::sink::BatteryReport;
+    use anyhow::{Context, Result};
+    use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+    use std::time::Duration;
+    use tracing::info;
+    use tray_icon::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+    use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+    use windows_sys::Win32::System::Console::FreeConsole;
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        DispatchMessageW, PeekMessageW, TranslateMessage, MSG, PM_REMOVE,
+    };
+
+    /// How often the window messages and the menu are handled
+    const PUMP_INTERVAL: Duration = Duration::from_millis(50);
+
+    pub struct Tray {
+        reports: Receiver<BatteryReport>,
+        events: Sender<Event>,
+        icon: TrayIcon,
+        refresh: MenuItem,
+        pause: CheckMenuItem,
+        quit: MenuItem,
+    }
+
+    impl Tray {
+        pub fn new(reports: Receiver<BatteryReport>, events: Sender<Event>) -> Result<Self> {
+            let refresh = MenuItem::new("Refresh now", true, None);
+            let pause = CheckMenuItem::new("Pause sending", true, false, None);
+            let quit = MenuItem::new("Quit", true, None);
+            let menu = Menu::new();
+            menu.append_items(&[&refresh, &pause, &PredefinedMenuItem::separator(), &quit])?;
+
+            let icon = TrayIconBuilder::new()
+                .with_menu(Box::new(menu))
+                .with_tooltip("volta\nReading the battery levels...")
+                .with_icon(icon(None)?)
+                .build()
+                .context("Failed to create the tray icon")?;
+            // The icon replaces the console window, which closes unless a
+            // terminal volta was started from keeps it open
+            unsafe { FreeConsole() };
+
+            Ok(Self {
+                reports,
+                events,
+                icon,
+                refresh,
+                pause,
+                quit,
+            })
+        }
+
+        pub fn run(self) {
+            loop {
+                pump_messages();
+                while let Ok(event) = MenuEvent::receiver().try_recv() {
+                    let event = if event.id == self.refresh.id() {
+                        Event::Refresh
+                    } else if event.id == self.pause.id() {
+                        Event::Pause(self.pause.is_checked())
+                    } else if event.id == self.quit.id() {
+                        info!("Quitting from the tray");
+                        crate::worker::request_stop();
+                        let _ = self.icon.set_tooltip(Some("volta\nStopping..."));
+                        Event::Shutdown
+                    } else {
+                        continue;
+                    };
+                    let _ = self.events.send(event);
+                }
+
+                match self.reports.recv_timeout(PUMP_INTERVAL) {
+                    Ok(report) => {
+                        let _ = self.icon.set_tooltip(Some(tooltip(&report)));
+                        if let Ok(icon) = icon(Some(&report)) {
+                            let _ = self.icon.set_icon(Some(icon));
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        }
+    }
+
+    fn icon(report: Option<&BatteryReport>) -> Result<Icon> {
+        Icon::from_rgba(icon_pixels(report), ICON_SIZE as u32, ICON_SIZE as u32)
+            .context("Failed to draw the tray icon")
+    }
+
+    /// Handles the window messages of the icon and its menu, which Windows
+    /// delivers to the thread that created them
+    fn pump_messages() {
+        let mut message: MSG = unsafe { std::mem::zeroed() };
+        while unsafe { PeekMessageW(&mut message, std::ptr::null_mut(), 0, 0, PM_REMOVE) } != 0 {
+            unsafe {
+                TranslateMessage(&message);
+                DispatchMessageW(&message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::fixture;
+
+    #[test]
+    fn lists_the_levels() {
+        assert_eq!(
+            tooltip(&fixture::report()),
+            "volta\nHeadset 85%, charging\nLeft disconnected\nRight 40%"
+        );
+    }
+
+    #[test]
+    fn tints_the_icon_while_low() {
+        let mut report = fixture::report();
+        let fill = |report: &BatteryReport| {
+            // A pixel inside the body near its left end, filled at 85%
+            let index = (16 * ICON_SIZE + 5) * 4;
+            icon_pixels(Some(report))[index..index + 4].to_vec()
+        };
+
+        let normal = fill(&report);
+        report.low.headset = true;
+        assert_ne!(fill(&report), normal);
+    }
+}
@@ -0,0 +1,75 @@
+use sysinfo::{ProcessesToUpdate, System};
+use tracing::info;
+
+/// Process names of VRChat on Windows and under Proton, and of its Linux
+/// builds
+pub const DEFAULT_PROCESS_NAMES: [&str; 2] = ["VRChat.exe", "VRChat"];
+
+/// Whether VRChat runs, compared to the previous check
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Presence {
+    Absent,
+    /// VRChat started since the previous check, or runs at the first one
+    Started,
+    Running,
+}
+
+/// Watches for a VRChat process by name
+pub struct Vrchat {
+    names: Vec<String>,
+    system: System,
+    running: Option<bool>,
+}
+
+impl Vrchat {
+    /// Watches for a process with any of `names`, ignoring case
+    pub fn new(names: Vec<String>) -> Self {
+        Self {
+            names,
+            system: System::new(),
+            running: None,
+        }
+    }
+
+    /// Looks for the process, logging when it starts or stops
+    pub fn check(&mut self) -> Presence {
+        self.system.refresh_processes(ProcessesToUpdate::All, true);
+        let running = self
+            .system
+            .processes()
+            .values()
+            .any(|process| matches(&process.name().to_string_lossy(), &self.names));
+
+        match (self.running.replace(running), running) {
+            (Some(true), true) => Presence::Running,
+            (_, true) => {
+                info!("VRChat is running");
+                Presence::Started
+            }
+            (Some(false), false) => Presence::Absent,
+            (_, false) => {
+                info!("Waiting for VRChat to start");
+                Presence::Absent
+            }
+        }
+    }
+}
+
+fn matches(process: &str, names: &[String]) -> bool {
+    names.iter().any(|name| name.eq_ignore_ascii_case(process))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_names_ignoring_case() {
+        let names: Vec<_> = DEFAULT_PROCESS_NAMES.map(String::from).into();
+
+        assert!(matches("VRChat.exe", &names));
+        assert!(matches("vrchat.EXE", &names));
+        assert!(matches("VRChat", &names));
+        assert!(!matches("VRChatLauncher.exe", &names));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::listener::Event;
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use std::any::Any;
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+/// Restarts allowed within [`WINDOW`] before volta gives up
+const MAX_RESTARTS: usize = 5;
+const WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Wait before the first restart, which doubles with every further one in
+/// the window
+const BACKOFF: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    /// Set once volta is asked to stop, for the waits deep in adb and the
+    /// sources which have no events to watch
+    static ref STOPPING: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+}
+
+/// Ends every [`sleep`], now and later, as volta stops
+pub fn request_stop() {
+    let (stopping, changed) = &*STOPPING;
+    *stopping.lock().unwrap() = true;
+    changed.notify_all();
+}
+
+pub fn stopping() -> bool {
+    *STOPPING.0.lock().unwrap()
+}
+
+/// Sleeps for `duration`, returning false right away once volta is asked to
+/// stop
+pub fn sleep(duration: Duration) -> bool {
+    let (stopping, changed) = &*STOPPING;
+    let (stopping, _) = changed
+        .wait_timeout_while(stopping.lock().unwrap(), duration, |stopping| !*stopping)
+        .unwrap();
+    !*stopping
+}
+
+/// Counts the restarts of the last [`WINDOW`]
+#[derive(Debug, Default)]
+pub struct Restarts {
+    times: VecDeque<Instant>,
+}
+
+impl Restarts {
+    /// Records a restart at `now`, returning how long to wait before it, or
+    /// `None` if there were too many already
+    pub fn record(&mut self, now: Instant) -> Option<Duration> {
+        while let Some(&oldest) = self.times.front() {
+            match now.duration_since(oldest) >= WINDOW {
+                true => self.times.pop_front(),
+                false => break,
+            };
+        }
+        if self.times.len() >= MAX_RESTARTS {
+            return None;
+        }
+        self.times.push_back(now);
+        let doublings = u32::try_from(self.times.len() - 1).unwrap_or(u32::MAX);
+        Some(BACKOFF.saturating_mul(2u32.saturating_pow(doublings)))
+    }
+}
+
+/// Runs `work` on a worker thread and starts it again after it panics,
+/// backing off in between. `state` outlives the restarts, and a shutdown on
+/// the `events` it holds ends the wait before a restart right away.
+pub fn supervise<S: Send>(
+    state: &mut S,
+    events: fn(&S) -> &Receiver<Event>,
+    mut work: impl FnMut(&mut S) -> Result<()> + Send,
+) -> Result<()> {
+    let mut restarts = Restarts::default();
+    loop {
+        let outcome = thread::scope(|scope| {
+            thread::Builder::new()
+                .name(String::from("poll"))
+                .spawn_scoped(scope, || work(state))
+                .map(|worker| worker.join())
+        })?;
+        let payload = match outcome {
+            Ok(result) => return result,
+            Err(payload) => payload,
+        };
+
+        error!("The polling panicked: {}", message(payload.as_ref()));
+        let Some(backoff) = restarts.record(Instant::now()) else {
+            bail!(
+                "The polling panicked {MAX_RESTARTS} times within {} minutes, giving up",
+                WINDOW.as_secs() / 60
+            );
+        };
+        warn!("Restarting the polling in {backoff:?}");
+        if !wait(events(state), backoff) {
+            return Ok(());
+        }
+    }
+}
+
+/// Waits for `backoff`, returning false if volta is asked to stop meanwhile
+fn wait(events: &Receiver<Event>, backoff: Duration) -> bool {
+    let deadline = Instant::now() + backoff;
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match events.recv_timeout(timeout) {
+            Ok(Event::Shutdown) => return false,
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(timeout);
+                return true;
+            }
+        }
+    }
+}
+
+/// The message a panic was raised with
+fn message(payload: &(dyn Any + Send)) -> &str {
+    match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        _ => "unknown cause",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn backs_off_then_gives_up() {
+        let mut restarts = Restarts::default();
+        let start = Instant::now();
+
+        let backoffs: Vec<_> = (0..6)
+            .map(|minute| restarts.record(start + Duration::from_secs(minute * 60)))
+            .collect();
+        assert_eq!(
+            backoffs,
+            [1, 2, 4, 8, 16]
+                .map(|secs| Some(Duration::from_secs(secs)))
+                .into_iter()
+                .chain([None])
+                .collect::<Vec<_>>()
+        );
+        // The first restart left the window
+        assert_eq!(
+            restarts.record(start + Duration::from_secs(10 * 60)),
+            Some(Duration::from_secs(16))
+        );
+    }
+
+    #[test]
+    fn restarts_after_a_panic() {
+        let (_events_sender, events) = mpsc::channel();
+        let mut state = (events, 0);
+
+        let result = supervise(
+            &mut state,
+            |(events, _)| events,
+            |(_, runs)| {
+                *runs += 1;
+                match runs {
+                    1 => panic!("first run"),
+                    _ => Ok(()),
+                }
+            },
+        );
+        assert!(result.is_ok());
+        assert_eq!(state.1, 2);
+    }
+
+    #[test]
+    fn stops_during_the_backoff() {
+        let (events_sender, events) = mpsc::channel();
+        events_sender.send(Event::Shutdown).unwrap();
+        let mut state = (events, 0);
+
+        let result = supervise(
+            &mut state,
+            |(events, _)| events,
+            |(_, runs)| {
+                *runs += 1;
+                panic!("run {runs}")
+            },
+        );
+        assert!(result.is_ok());
+        assert_eq!(state.1, 1);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::threshold::{Alert, Severity};
+use serde::Serialize;
+use std::net::{Ipv4Addr, UdpSocket};
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_PORT: u16 = 42069;
+
+/// Seconds a notification stays visible
+const TIMEOUT: f32 = 5.0;
+
+/// A notification in the format of XSOverlay's UDP notification API
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    /// 1 is a popup notification
+    message_type: i32,
+    index: i32,
+    timeout: f32,
+    height: f32,
+    opacity: f32,
+    volume: f32,
+    audio_path: &'static str,
+    title: String,
+    content: String,
+    use_base64_icon: bool,
+    icon: &'static str,
+    source_app: &'static str,
+}
+
+impl From<&Alert> for Notification {
+    fn from(alert: &Alert) -> Self {
+        let (title, audio_path, icon) = match alert.severity {
+            Severity::Low => ("Battery low", "default", "warning"),
+            Severity::Critical => ("Battery critical", "warning", "error"),
+        };
+
+        Self {
+            message_type: 1,
+            index: 0,
+            timeout: TIMEOUT,
+            height: 100.0,
+            opacity: 1.0,
+            volume: 0.7,
+            audio_path,
+            title: title.to_string(),
+            content: format!("{} battery at {:.0}%", alert.device, alert.percent),
+            use_base64_icon: false,
+            icon,
+            source_app: "VRC Volta",
+        }
+    }
+}
+
+/// Sends alerts to XSOverlay running on this machine
+pub struct XsOverlay {
+    /// `None` in `--dry-run` mode
+    socket: Option<UdpSocket>,
+    port: u16,
+    failed: bool,
+}
+
+impl XsOverlay {
+    pub fn new(port: u16) -> std::io::Result<Self> {
+        Ok(Self {
+            socket: Some(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?),
+            port,
+            failed: false,
+        })
+    }
+
+    /// Logs the notifications it would send instead of sending them
+    pub fn dry_run(port: u16) -> Self {
+        Self {
+            socket: None,
+            port,
+            failed: false,
+        }
+    }
+
+    /// Sends a notification for the alert, only the first failure of the
+    /// session is reported as XSOverlay usually just isn't running
+    pub fn notify(&mut self, alert: &Alert) {
+        let payload =
+            serde_json::to_vec(&Notification::from(alert)).expect("notifications always serialize");
+
+        let Some(socket) = &self.socket else {
+            info!(
+                "Would notify XSOverlay on port {}: {}",
+                self.port,
+                String::from_utf8_lossy(&payload)
+            );
+            return;
+        };
+        match socket.send_to(&payload, (Ipv4Addr::LOCALHOST, self.port)) {
+            Ok(_) => {}
+            Err(error) if !self.failed => {
+                warn!("Failed to notify XSOverlay on port {}: {error}", self.port);
+                self.failed = true;
+            }
+            Err(error) => debug!("Failed to notify XSOverlay: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn alert(severity: Severity) -> Alert {
+        Alert {
+            device: "Left controller",
+            severity,
+            percent: 19.6,
+            threshold: 20,
+        }
+    }
+
+    #[test]
+    fn low_alert_payload() {
+        let payload = serde_json::to_value(Notification::from(&alert(Severity::Low))).unwrap();
+
+        assert_eq!(
+            payload,
+            json!({
+                "messageType": 1,
+                "index": 0,
+                "timeout": 5.0,
+                "height": 100.0,
+                "opacity": 1.0,
+                "volume": 0.7f32,
+                "audioPath": "default",
+                "title": "Battery low",
+                "content": "Left controller battery at 20%",
+                "useBase64Icon": false,
+                "icon": "warning",
+                "sourceApp": "VRC Volta",
+            })
+        );
+    }
+
+    #[test]
+    fn critical_alert_payload() {
+        let payload = serde_json::to_value(Notification::from(&alert(Severity::Critical))).unwrap();
+
+        assert_eq!(payload["title"], Value::from("Battery critical"));
+        assert_eq!(payload["icon"], Value::from("error"));
+    }
+}
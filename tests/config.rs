@@ -0,0 +1,83 @@
+use std::fs;
+use std::process::{Command, Output};
+
+fn volta(args: &[&str]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_vrc_volta"));
+    for (variable, _) in std::env::vars() {
+        if variable.starts_with("VOLTA_") {
+            command.env_remove(variable);
+        }
+    }
+    command.args(args).output().unwrap()
+}
+
+#[test]
+fn writes_a_config_whose_defaults_volta_reads() {
+    let dir = std::env::temp_dir().join(format!("vrc_volta-{}-init", std::process::id()));
+    let path = dir.join("volta.toml");
+    let path = path.to_str().unwrap();
+
+    assert!(volta(&["config", "init", "--path", path]).status.success());
+    assert!(!volta(&["config", "init", "--path", path]).status.success());
+    assert!(volta(&["config", "init", "--path", path, "--force"])
+        .status
+        .success());
+
+    // Set every key to the default it's commented out at
+    let contents = fs::read_to_string(path).unwrap();
+    let uncommented: String = contents
+        .lines()
+        .take_while(|line| !line.contains("[[device]]"))
+        .map(|line| match line.strip_prefix("# ") {
+            Some(key) if key.contains(" = ") && key.starts_with(char::is_lowercase) => key,
+            _ => line,
+        })
+        .map(|line| format!("{line}\n"))
+        .collect();
+    fs::write(path, uncommented).unwrap();
+
+    let output = volta(&["--config", path, "config", "show"]);
+    fs::remove_dir_all(&dir).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(!stderr.contains("Ignoring unknown config key"), "{stderr}");
+    let shown = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        shown.contains("interval = \"60s\" # config file\n"),
+        "{shown}"
+    );
+    assert!(shown.contains("retries = 3 # config file\n"), "{shown}");
+}
+
+#[test]
+fn shows_the_addresses_with_the_prefix() {
+    let output = volta(&["--prefix", "Mine", "config", "show"]);
+    let shown = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{shown}");
+    assert!(
+        shown.contains("headset_param = \"/avatar/parameters/MineBatteryLevelHeadset\""),
+        "{shown}"
+    );
+}
+
+#[test]
+fn replaces_a_broken_config() {
+    let dir = std::env::temp_dir().join(format!("vrc_volta-{}-broken", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("volta.toml");
+    fs::write(&path, "retries = \"many\"\n").unwrap();
+    let path = path.to_str().unwrap();
+
+    assert!(!volta(&["--config", path, "config", "show"])
+        .status
+        .success());
+    let output = volta(&["--config", path, "config", "init", "--force"]);
+    let contents = fs::read_to_string(path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!contents.contains("\"many\""), "{contents}");
+}
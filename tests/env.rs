@@ -0,0 +1,121 @@
+use serde_json::Value;
+use std::fs;
+use std::process::{Command, Output};
+
+/// A config file setting the headset, left and right controller levels
+const LEVELS_CONFIG: &str = "headset_param = \"/avatar/parameters/HeadsetConfig\"\n\
+                             left_param = \"/avatar/parameters/LeftConfig\"\n\
+                             right_param = \"/avatar/parameters/RightConfig\"\n";
+
+/// Runs `volta params` with `args` and `env`, and a config file of `config`
+fn params(config: &str, args: &[&str], env: &[(&str, &str)]) -> Output {
+    let path = std::env::temp_dir().join(format!(
+        "vrc_volta-{}-{:?}-env.toml",
+        std::process::id(),
+        std::thread::current().id()
+    ));
+    fs::write(&path, config).unwrap();
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_vrc_volta"));
+    for (variable, _) in std::env::vars() {
+        if variable.starts_with("VOLTA_") {
+            command.env_remove(variable);
+        }
+    }
+    let output = command
+        .arg("--config")
+        .arg(&path)
+        .args(args)
+        .arg("params")
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    output
+}
+
+/// The addresses of the headset, left and right controller levels volta would
+/// send with `config`, `args` and `env`
+fn level_addresses(config: &str, args: &[&str], env: &[(&str, &str)]) -> Vec<String> {
+    let output = params(config, args, env);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let params: Value = serde_json::from_slice(&output.stdout).unwrap();
+    params["parameters"].as_array().unwrap()[..3]
+        .iter()
+        .map(|param| param["input"]["address"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn prefers_the_command_line_then_the_environment_then_the_config() {
+    let addresses = level_addresses(
+        LEVELS_CONFIG,
+        &["--headset-param", "/avatar/parameters/HeadsetCli"],
+        &[
+            ("VOLTA_HEADSET_PARAM", "/avatar/parameters/HeadsetEnv"),
+            ("VOLTA_LEFT_PARAM", "/avatar/parameters/LeftEnv"),
+        ],
+    );
+
+    assert_eq!(
+        addresses,
+        [
+            "/avatar/parameters/HeadsetCli",
+            "/avatar/parameters/LeftEnv",
+            "/avatar/parameters/RightConfig"
+        ]
+    );
+}
+
+#[test]
+fn prefixes_only_the_addresses_left_at_their_default() {
+    let addresses = level_addresses(
+        "left_param = \"/avatar/parameters/LeftConfig\"\n",
+        &[
+            "--prefix",
+            "Quest_",
+            "--headset-param",
+            "/avatar/parameters/HeadsetCli",
+        ],
+        &[],
+    );
+
+    assert_eq!(
+        addresses,
+        [
+            "/avatar/parameters/HeadsetCli",
+            "/avatar/parameters/LeftConfig",
+            "/avatar/parameters/Quest_BatteryLevelControllerRight"
+        ]
+    );
+    assert_eq!(
+        level_addresses("prefix = \"Config_\"\n", &[], &[])[0],
+        "/avatar/parameters/Config_BatteryLevelHeadset"
+    );
+}
+
+#[test]
+fn rejects_prefixes_which_arent_parameter_names() {
+    for prefix in ["", "Quest-", "/avatar/parameters/"] {
+        let output = params("", &["--prefix", prefix], &[]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{prefix}");
+        assert!(
+            stderr.contains("is not a parameter name prefix"),
+            "{stderr}"
+        );
+    }
+
+    let output = params("prefix = \"Quest 3\"\n", &[], &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("Invalid config value for `prefix`"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,67 @@
+use rosc::{decoder, OscPacket, OscType};
+use std::net::UdpSocket;
+use std::process::Command;
+use std::time::Duration;
+
+/// Every message in a packet, flattening bundles
+fn messages(packet: OscPacket) -> Vec<(String, Vec<OscType>)> {
+    match packet {
+        OscPacket::Message(message) => vec![(message.addr, message.args)],
+        OscPacket::Bundle(bundle) => bundle.content.into_iter().flat_map(messages).collect(),
+    }
+}
+
+#[test]
+fn replays_the_dumps_into_the_receiver() {
+    let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_vrc_volta"));
+    for (variable, _) in std::env::vars() {
+        if variable.starts_with("VOLTA_") {
+            command.env_remove(variable);
+        }
+    }
+    let output = command
+        .args(["--replay", "tests/fixtures/replay", "--once"])
+        .args(["--controller-scale", "5"])
+        .arg("--receiver")
+        .arg(receiver.local_addr().unwrap().to_string())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mut buffer = [0; rosc::decoder::MTU];
+    let size = receiver.recv(&mut buffer).unwrap();
+    let (_, packet) = decoder::decode_udp(&buffer[..size]).unwrap();
+    let messages = messages(packet);
+    let value = |address: &str| {
+        messages
+            .iter()
+            .find(|(addr, _)| addr == address)
+            .map(|(_, args)| args.clone())
+    };
+
+    assert_eq!(
+        value("/avatar/parameters/BatteryLevelHeadset"),
+        Some(vec![OscType::Float(0.85)])
+    );
+    assert_eq!(
+        value("/avatar/parameters/BatteryLevelControllerLeft"),
+        Some(vec![OscType::Float(0.8)])
+    );
+    assert_eq!(
+        value("/avatar/parameters/BatteryLevelControllerRight"),
+        Some(vec![OscType::Float(0.6)])
+    );
+    assert_eq!(
+        value("/avatar/parameters/BatteryChargingHeadset"),
+        Some(vec![OscType::Bool(true)])
+    );
+}